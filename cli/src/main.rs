@@ -1,5 +1,27 @@
 use colored::*;
 use daemon::daemon_connection::DaemonConnection;
+use daemon::export::{ExportFormat, SettingsExport};
+use daemon::fan_calibration::FanCalibrationStatus;
+use daemon::fan_control::{
+    FanCurveInterpolation, FanFailsafe, FanLimits, FanMode, FanSpeedUnit, FanTempSource,
+};
+use daemon::fan_health::FanHealthSettings;
+use daemon::fan_pid::FanPidSettings;
+use daemon::gpu_controller::{ClocksTable, OdFanControls, PowerProfile};
+use daemon::group::{GpuGroup, GroupMatcher, GroupSetting};
+use daemon::hooks::{Hook, HookEvent, HookStatus};
+use daemon::idle::IdleSettings;
+use daemon::power_guard::PowerGuardSettings;
+use daemon::power_source::PowerSource;
+use daemon::process_watcher::{ProcessMatcher, ProcessRule};
+use daemon::schedule::{parse_time, parse_weekdays, Schedule, ScheduleAction};
+use daemon::DaemonError;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -7,6 +29,299 @@ enum ConfigOpt {
     Show,
     AllowOnlineUpdating,
     DisallowOnlineUpdating,
+    /// Sets how often the fan control loop runs
+    FanInterval {
+        /// Milliseconds between fan control loop ticks
+        interval_ms: u64,
+    },
+    /// Lists the previous configs that are kept as backups
+    Backups,
+    /// Switches back to a previous config by its number as printed in `lact-cli config backups`
+    Restore {
+        index: usize,
+    },
+}
+
+#[derive(StructOpt)]
+enum ProfileOpt {
+    /// Lists the profiles of a GPU
+    List {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+    },
+    /// Saves the current settings as a new profile, which becomes the active one
+    Create {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        name: String,
+    },
+    Rename {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        name: String,
+        new_name: String,
+    },
+    /// Deletes a profile, leaving the current settings as they are
+    Delete {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        name: String,
+    },
+    /// Applies the settings of a profile. Later changes are saved to the active profile.
+    Activate {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        name: String,
+    },
+    /// Switches to a profile whenever the laptop runs on the given power source
+    PowerSource {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        /// ac or battery
+        source: String,
+        /// Leave out to stop switching on this power source
+        name: Option<String>,
+    },
+}
+
+#[derive(StructOpt)]
+enum RuleOpt {
+    /// Lists the process rules and which of them match right now
+    List,
+    /// Switches a GPU to a profile while a matching process runs. Exactly one of the patterns has to be given.
+    Add {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        profile: String,
+        /// File name or full path of the executable
+        #[structopt(long)]
+        exe: Option<String>,
+        /// Text that the command line contains, e.g. the name of a game run through Wine
+        #[structopt(long)]
+        cmdline: Option<String>,
+        /// Text that the cgroup path contains, e.g. the ID of a flatpak app
+        #[structopt(long)]
+        cgroup: Option<String>,
+    },
+    /// Removes a rule by its number as printed in `lact-cli rule list`
+    Remove { index: usize },
+}
+
+#[derive(StructOpt)]
+struct ScheduleArgs {
+    /// GPU ID as printed in `lact-cli gpus`
+    gpu_id: u32,
+    /// Time of day as HH:MM
+    time: String,
+    /// Weekdays like mon-fri or sat,sun, every day by default
+    #[structopt(long, default_value = "*")]
+    days: String,
+    /// Activates a profile
+    #[structopt(long)]
+    profile: Option<String>,
+    /// Sets the power cap (W)
+    #[structopt(long)]
+    power_cap: Option<i64>,
+    /// Replaces the fan curve with temperature:speed% points
+    #[structopt(long)]
+    fan_curve: Vec<String>,
+    /// Sets the power profile (auto, low or high)
+    #[structopt(long)]
+    power_profile: Option<String>,
+}
+
+impl ScheduleArgs {
+    fn to_schedule(&self) -> Result<Schedule, String> {
+        let (hour, minute) =
+            parse_time(&self.time).ok_or_else(|| format!("Invalid time {}", self.time))?;
+        let weekdays =
+            parse_weekdays(&self.days).ok_or_else(|| format!("Invalid weekdays {}", self.days))?;
+
+        let action = match (
+            &self.profile,
+            self.power_cap,
+            self.fan_curve.is_empty(),
+            &self.power_profile,
+        ) {
+            (Some(name), None, true, None) => ScheduleAction::Profile(name.clone()),
+            (None, Some(cap), true, None) => ScheduleAction::PowerCap(cap),
+            (None, None, false, None) => {
+                let mut curve = BTreeMap::new();
+
+                for point in &self.fan_curve {
+                    let (temp, speed) = parse_curve_point(point).ok_or_else(|| {
+                        format!("Invalid curve point {}, expected temperature:speed", point)
+                    })?;
+                    curve.insert(temp, speed);
+                }
+
+                ScheduleAction::FanCurve(curve)
+            }
+            (None, None, true, Some(profile)) => ScheduleAction::PowerProfile(
+                PowerProfile::from_str(profile)
+                    .map_err(|_| format!("Invalid power profile {}", profile))?,
+            ),
+            _ => return Err(
+                "Exactly one of --profile, --power-cap, --fan-curve or --power-profile is required"
+                    .to_string(),
+            ),
+        };
+
+        Ok(Schedule {
+            gpu_id: self.gpu_id,
+            hour,
+            minute,
+            weekdays,
+            action,
+        })
+    }
+}
+
+#[derive(StructOpt)]
+enum ScheduleOpt {
+    /// Lists the schedules and which of them are in effect
+    List,
+    /// Applies settings at a time of day
+    Add(ScheduleArgs),
+    /// Replaces a schedule by its number as printed in `lact-cli schedule list`
+    Edit {
+        index: usize,
+        #[structopt(flatten)]
+        schedule: ScheduleArgs,
+    },
+    /// Removes a schedule by its number as printed in `lact-cli schedule list`
+    Remove { index: usize },
+}
+
+#[derive(StructOpt)]
+enum HookOpt {
+    /// Lists the hooks and how they went the last time they ran
+    List,
+    /// Runs a shell command when an event happens, with the details in LACT_* environment variables
+    Add {
+        /// profile-switched, temperature-threshold, fan-fault, settings-applied, gpu-added or gpu-removed
+        event: String,
+        command: String,
+        /// Only runs for this GPU ID as printed in `lact-cli gpus`, for all GPUs by default
+        #[structopt(long)]
+        gpu_id: Option<u32>,
+        /// Seconds until the command gets killed
+        #[structopt(long, default_value = "10")]
+        timeout: u64,
+        /// Threshold (°C) of temperature-threshold hooks, which run when it's crossed in either direction
+        #[structopt(long)]
+        temperature: Option<i64>,
+    },
+    /// Removes a hook by its number as printed in `lact-cli hook list`
+    Remove { index: usize },
+}
+
+#[derive(StructOpt)]
+struct GroupSettingArgs {
+    /// Turns manual fan control on or off
+    #[structopt(long)]
+    fan_control: Option<bool>,
+    /// Replaces the fan curve with temperature:speed% points
+    #[structopt(long)]
+    fan_curve: Vec<String>,
+    /// Sets the power cap (W)
+    #[structopt(long)]
+    power_cap: Option<i64>,
+    /// Sets the power profile (auto, low or high)
+    #[structopt(long)]
+    power_profile: Option<String>,
+    /// Sets the clockspeed (MHz) of the highest GPU power state
+    #[structopt(long)]
+    gpu_max_clock: Option<i64>,
+    /// Sets the voltage (mV) of the highest GPU power state, together with --gpu-max-clock
+    #[structopt(long, requires = "gpu-max-clock")]
+    gpu_max_voltage: Option<i64>,
+    /// Sets the clockspeed (MHz) of the highest VRAM power state
+    #[structopt(long)]
+    vram_max_clock: Option<i64>,
+}
+
+impl GroupSettingArgs {
+    fn to_settings(&self) -> Result<Vec<GroupSetting>, String> {
+        let mut settings = Vec::new();
+
+        if let Some(enabled) = self.fan_control {
+            settings.push(GroupSetting::FanControl(enabled));
+        }
+        if !self.fan_curve.is_empty() {
+            let mut curve = BTreeMap::new();
+
+            for point in &self.fan_curve {
+                let (temp, speed) = parse_curve_point(point).ok_or_else(|| {
+                    format!("Invalid curve point {}, expected temperature:speed", point)
+                })?;
+                curve.insert(temp, speed);
+            }
+
+            settings.push(GroupSetting::FanCurve(curve));
+        }
+        if let Some(cap) = self.power_cap {
+            settings.push(GroupSetting::PowerCap(cap));
+        }
+        if let Some(profile) = &self.power_profile {
+            settings.push(GroupSetting::PowerProfile(
+                PowerProfile::from_str(profile)
+                    .map_err(|_| format!("Invalid power profile {}", profile))?,
+            ));
+        }
+        if let Some(clock) = self.gpu_max_clock {
+            settings.push(GroupSetting::GpuMaxClock(clock, self.gpu_max_voltage));
+        }
+        if let Some(clock) = self.vram_max_clock {
+            settings.push(GroupSetting::VramMaxClock(clock));
+        }
+
+        match settings.is_empty() {
+            true => Err("No settings given".to_string()),
+            false => Ok(settings),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+enum GroupOpt {
+    /// Lists the groups with their members and settings
+    List,
+    /// Creates a group of GPUs that get the same settings
+    Create {
+        name: String,
+        /// Includes the GPUs of a card or GPU model, e.g. "Radeon RX 6800"
+        #[structopt(long)]
+        model: Option<String>,
+        /// Includes the GPUs whose card or GPU vendor contains this, e.g. "Sapphire"
+        #[structopt(long)]
+        vendor: Option<String>,
+        /// Includes these GPU IDs as printed in `lact-cli gpus`
+        #[structopt(long)]
+        ids: Vec<u32>,
+    },
+    /// Deletes a group, leaving the settings of its GPUs as they are
+    Delete { name: String },
+    /// Sets and applies settings of a group, or overrides them for one of its GPUs
+    Set {
+        name: String,
+        /// Only overrides the settings for this GPU ID as printed in `lact-cli gpus`
+        #[structopt(long)]
+        gpu_id: Option<u32>,
+        #[structopt(flatten)]
+        settings: GroupSettingArgs,
+    },
+    /// Removes a setting of a group, or an override of one of its GPUs
+    Clear {
+        name: String,
+        /// fan-control, fan-curve, power-cap, power-profile, gpu-max-clock or vram-max-clock
+        setting: String,
+        /// Removes the override of this GPU ID, which gets the group's setting again
+        #[structopt(long)]
+        gpu_id: Option<u32>,
+    },
+    /// Applies the settings of a group to all of its GPUs again
+    Apply { name: String },
 }
 
 #[derive(StructOpt)]
@@ -16,6 +331,144 @@ enum CurveOpt {
         /// Specify a GPU ID as printed in `lact-cli gpus`. By default, all GPUs are printed.
         gpu_id: Option<u32>,
     },
+    /// Replaces the fan curve with the given points
+    Set {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        /// Points as `temperature:speed`, e.g. `40:20 60:50 80:100`
+        #[structopt(required = true)]
+        points: Vec<String>,
+    },
+    /// Changes how the curve is interpolated and validated. Unspecified values are left unchanged.
+    Interpolation {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        /// One of `step`, `linear` or `smooth`
+        mode: Option<String>,
+        /// Reject curves where the speed drops as the temperature rises
+        #[structopt(long)]
+        non_decreasing: Option<bool>,
+        /// Unit of the curve speeds, `percent` or `rpm`. The curve is converted using the fan calibration.
+        #[structopt(long)]
+        unit: Option<String>,
+    },
+    /// Measures the fan speed over the whole PWM range, which allows fan curves in RPM
+    Calibrate {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+    },
+    /// Changes how the fan speed follows the curve. Unspecified values are left unchanged.
+    Smoothing {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        /// Degrees (°C) the temperature has to drop before the fan slows down
+        #[structopt(long)]
+        hysteresis: Option<i64>,
+        /// Maximum fan speed increase in %/s, 0 for unlimited
+        #[structopt(long)]
+        ramp_up: Option<f64>,
+        /// Maximum fan speed decrease in %/s, 0 for unlimited
+        #[structopt(long)]
+        ramp_down: Option<f64>,
+        /// Seconds of temperature readings to average
+        #[structopt(long)]
+        average_window: Option<u64>,
+        /// Seconds the temperature has to stay low before the fan slows down
+        #[structopt(long)]
+        spin_down_delay: Option<u64>,
+    },
+    /// Selects how the fan speed is chosen while manual fan control is enabled
+    Mode {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        /// One of `curve`, `static`, `rpm` or `pid`
+        mode: String,
+        /// Fan speed in % for `static`, in RPM for `rpm`, or the target temperature (°C) for `pid`
+        value: Option<f64>,
+        /// Proportional gain of `pid` in PWM per °C
+        #[structopt(long)]
+        kp: Option<f64>,
+        /// Integral gain of `pid` in PWM per °C per second
+        #[structopt(long)]
+        ki: Option<f64>,
+        /// Derivative gain of `pid` in PWM per °C/s
+        #[structopt(long)]
+        kd: Option<f64>,
+        /// Lowest PWM (0-255) used by `pid`
+        #[structopt(long)]
+        min_pwm: Option<i64>,
+        /// Highest PWM (0-255) used by `pid`
+        #[structopt(long)]
+        max_pwm: Option<i64>,
+    },
+    /// Changes the fan speed limits and zero RPM mode. Unspecified values are left unchanged.
+    Limits {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        /// Minimum fan speed in % while the fan is spinning
+        #[structopt(long)]
+        min_speed: Option<f64>,
+        /// Maximum fan speed in %
+        #[structopt(long)]
+        max_speed: Option<f64>,
+        /// Stop the fan below the zero RPM threshold
+        #[structopt(long)]
+        zero_rpm: Option<bool>,
+        /// Temperature (°C) below which the fan is stopped in zero RPM mode
+        #[structopt(long)]
+        zero_rpm_threshold: Option<i64>,
+        /// Fan speed in % applied when the fan starts from standstill
+        #[structopt(long)]
+        kick_speed: Option<f64>,
+        /// Seconds the start-up speed is applied for
+        #[structopt(long)]
+        kick_duration: Option<u64>,
+        /// What to do when fan control fails: `automatic` or `full-speed`
+        #[structopt(long)]
+        failsafe: Option<String>,
+    },
+    /// Changes how stalled and failing fans are detected. Unspecified values are left unchanged.
+    Health {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        /// Watch the fan speed
+        #[structopt(long)]
+        enabled: Option<bool>,
+        /// Seconds the fan can stand still while it should spin
+        #[structopt(long)]
+        stall_time: Option<u64>,
+        /// Fraction (0-1) the fan can be slower than calibrated
+        #[structopt(long)]
+        underspeed_tolerance: Option<f64>,
+        /// Seconds the fan can be slower than calibrated
+        #[structopt(long)]
+        underspeed_time: Option<u64>,
+        /// Long-term speed ratio (0-1) below which the fan is reported as worn
+        #[structopt(long)]
+        wear_threshold: Option<f64>,
+        /// Hand the fan back to the failsafe when it fails
+        #[structopt(long)]
+        stop_fan_control: Option<bool>,
+        /// Power cap in W applied while the fan is failing, 0 to leave the power cap unchanged
+        #[structopt(long)]
+        power_cap: Option<i64>,
+    },
+    /// Selects the temperature sensor used as the fan curve input
+    TempSource {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        /// One of `edge`, `junction`, `memory`, `max` or `weighted`
+        source: String,
+        /// Weight of the edge temperature when using `weighted`
+        #[structopt(long, default_value = "1")]
+        edge_weight: f64,
+        /// Weight of the junction temperature when using `weighted`
+        #[structopt(long, default_value = "1")]
+        junction_weight: f64,
+        /// Weight of the memory temperature when using `weighted`
+        #[structopt(long, default_value = "1")]
+        memory_weight: f64,
+    },
 }
 
 #[derive(StructOpt)]
@@ -36,6 +489,146 @@ enum Opt {
     Config(ConfigOpt),
     /// Fan curve control
     Curve(CurveOpt),
+    /// Named sets of GPU settings
+    Profile(ProfileOpt),
+    /// Switching profiles automatically while certain processes run
+    Rule(RuleOpt),
+    /// Applying settings at certain times of the day
+    Schedule(ScheduleOpt),
+    /// Running commands when something happens
+    Hook(HookOpt),
+    /// Applying the same settings to several GPUs
+    Group(GroupOpt),
+    /// Clocks and voltages, showing only what the GPU supports. Setting any of them needs a GPU ID.
+    Clocks {
+        /// Specify a GPU ID as printed in `lact-cli gpus`. By default, all GPUs are printed.
+        gpu_id: Option<u32>,
+        /// Sets the clockspeed (MHz) of the lowest GPU power state
+        #[structopt(long)]
+        gpu_min_clock: Option<i64>,
+        /// Sets the clockspeed (MHz) of the lowest VRAM power state
+        #[structopt(long)]
+        vram_min_clock: Option<i64>,
+        /// Sets the voltage offset (mV), negative values undervolt
+        #[structopt(long, allow_hyphen_values = true)]
+        voltage_offset: Option<i64>,
+    },
+    /// Overdrive fan settings of RDNA3 and newer. Shows the current settings when no options are given.
+    OdFan {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        /// Sets a value as name=value, such as acoustic_limit_rpm_threshold=2000
+        #[structopt(long)]
+        set: Vec<String>,
+        /// Sets a fan curve point as point:temperature:speed%, such as 1:45:30
+        #[structopt(long)]
+        curve_point: Vec<String>,
+        /// Restores the defaults before setting anything else
+        #[structopt(long)]
+        reset: bool,
+    },
+    /// Lowers the power cap while the GPU is too hot or its fan too fast. Unspecified values are left unchanged.
+    PowerGuard {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        #[structopt(long)]
+        enabled: Option<bool>,
+        /// Temperature (°C) of the hottest sensor at which the power cap is lowered
+        #[structopt(long)]
+        max_temp: Option<i64>,
+        /// Fan speed in % of the maximum RPM at which the power cap is lowered, 0 to ignore the fan speed
+        #[structopt(long)]
+        max_fan_speed: Option<f64>,
+        /// Degrees (°C) the temperature has to drop below the limit before the power cap is raised again
+        #[structopt(long)]
+        hysteresis: Option<i64>,
+        /// W the power cap is lowered by at once
+        #[structopt(long)]
+        step: Option<i64>,
+        /// Seconds between lowering steps
+        #[structopt(long)]
+        step_interval: Option<u64>,
+        /// W the power cap is raised by at once
+        #[structopt(long)]
+        restore_step: Option<i64>,
+        /// Seconds it has to stay cool before each raising step
+        #[structopt(long)]
+        restore_interval: Option<u64>,
+    },
+    /// Low power settings for when the GPU isn't used. Shows the current settings when no options are given.
+    Idle {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        #[structopt(long)]
+        enabled: Option<bool>,
+        /// Load (%) at or below which the GPU counts as idle
+        #[structopt(long)]
+        idle_threshold: Option<u8>,
+        /// Load (%) above which the previous settings are restored
+        #[structopt(long)]
+        busy_threshold: Option<u8>,
+        /// Seconds the GPU has to be idle before switching to low power
+        #[structopt(long)]
+        idle_time: Option<u64>,
+        /// Power cap (W) while idle, 0 to keep the current one
+        #[structopt(long)]
+        power_cap: Option<i64>,
+        /// Forces the lowest performance level while idle
+        #[structopt(long)]
+        force_low: Option<bool>,
+        /// Fan curve points as temperature:speed% used while idle, if fan control is enabled
+        #[structopt(long)]
+        fan_curve: Vec<String>,
+        /// Keeps the regular fan settings while idle
+        #[structopt(long)]
+        no_fan_curve: bool,
+    },
+    /// Saves the settings of a GPU, or one of its profiles, to a file that can be imported on another machine
+    Export {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        /// File to write, printed if not given
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+        /// Exports a profile instead of the current settings
+        #[structopt(long)]
+        profile: Option<String>,
+        /// `json` or `toml`, taken from the file extension by default
+        #[structopt(long)]
+        format: Option<String>,
+    },
+    /// Applies exported settings to a GPU after checking that its clocks and power limits allow them
+    Import {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Adds the settings as a new profile instead of applying them
+        #[structopt(long)]
+        profile: Option<String>,
+        /// Imports the settings even if they don't fit the GPU, leaving out what it rejects
+        #[structopt(long)]
+        force: bool,
+        /// `json` or `toml`, taken from the file extension by default
+        #[structopt(long)]
+        format: Option<String>,
+    },
+    /// Rhai script that sets the fan PWM, power cap or profile on every tick. Shows the current script when no options are given.
+    ///
+    /// The script gets the `stats` of the GPU, the local `time` with hour, minute and weekday (0 is Monday),
+    /// and a `state` map it can keep values in. It returns a map with any of `fan_pwm` (0-255),
+    /// `power_cap` (W) and `profile`, e.g. `#{ fan_pwm: max(stats.junction_temp, stats.mem_temp) * 2 }`.
+    /// What it leaves out, or everything if it fails, is controlled automatically.
+    Script {
+        /// GPU ID as printed in `lact-cli gpus`
+        gpu_id: u32,
+        /// File to load the script from
+        #[structopt(long, parse(from_os_str))]
+        file: Option<PathBuf>,
+        /// Removes the script
+        #[structopt(long, conflicts_with = "file")]
+        clear: bool,
+    },
 }
 
 fn main() {
@@ -81,6 +674,495 @@ fn main() {
                 print_info(&d, gpu_id);
             }
         }
+        Opt::Clocks {
+            gpu_id,
+            gpu_min_clock,
+            vram_min_clock,
+            voltage_offset,
+        } => {
+            if gpu_min_clock.is_some() || vram_min_clock.is_some() || voltage_offset.is_some() {
+                let gpu_id = match gpu_id {
+                    Some(gpu_id) => gpu_id,
+                    None => {
+                        eprintln!("Setting the clocks needs a GPU ID");
+                        return;
+                    }
+                };
+
+                // The values only take effect together when they're committed
+                let result = gpu_min_clock
+                    .map_or(Ok(()), |clock| d.set_gpu_min_clock(gpu_id, clock))
+                    .and_then(|_| {
+                        vram_min_clock.map_or(Ok(()), |clock| d.set_vram_min_clock(gpu_id, clock))
+                    })
+                    .and_then(|_| {
+                        voltage_offset.map_or(Ok(()), |offset| d.set_voltage_offset(gpu_id, offset))
+                    })
+                    .and_then(|_| d.commit_gpu_power_states(gpu_id));
+
+                match result {
+                    Ok(()) => println!("{}", "Applied the clocks".green()),
+                    Err(e) => eprintln!("Failed to set the clocks: {}", e),
+                }
+            }
+
+            let mut gpu_ids: Vec<u32> = Vec::new();
+
+            if let Some(gpu_id) = gpu_id {
+                gpu_ids.push(gpu_id);
+            } else {
+                for (gpu_id, _) in d.get_gpus().unwrap() {
+                    gpu_ids.push(gpu_id);
+                }
+            }
+
+            for gpu_id in gpu_ids {
+                print_clocks(&d, gpu_id);
+            }
+        }
+        Opt::PowerGuard {
+            gpu_id,
+            enabled,
+            max_temp,
+            max_fan_speed,
+            hysteresis,
+            step,
+            step_interval,
+            restore_step,
+            restore_interval,
+        } => {
+            let mut settings = d.get_gpu_info(gpu_id).unwrap().power_guard;
+
+            if let Some(enabled) = enabled {
+                settings.enabled = enabled;
+            }
+            if let Some(max_temp) = max_temp {
+                settings.max_temp = max_temp;
+            }
+            if let Some(max_fan_speed) = max_fan_speed {
+                settings.max_fan_speed = match max_fan_speed {
+                    speed if speed > 0.0 => Some(speed),
+                    _ => None,
+                };
+            }
+            if let Some(hysteresis) = hysteresis {
+                settings.hysteresis = hysteresis;
+            }
+            if let Some(step) = step {
+                settings.step = step;
+            }
+            if let Some(step_interval) = step_interval {
+                settings.step_interval = step_interval;
+            }
+            if let Some(restore_step) = restore_step {
+                settings.restore_step = restore_step;
+            }
+            if let Some(restore_interval) = restore_interval {
+                settings.restore_interval = restore_interval;
+            }
+
+            if let Err(e) = d.set_power_guard(gpu_id, settings.clone()) {
+                eprintln!("Failed to set the power guard: {}", e);
+                return;
+            }
+            print_power_guard_settings(&settings);
+        }
+        Opt::Idle {
+            gpu_id,
+            enabled,
+            idle_threshold,
+            busy_threshold,
+            idle_time,
+            power_cap,
+            force_low,
+            fan_curve,
+            no_fan_curve,
+        } => {
+            let mut settings = d.get_gpu_info(gpu_id).unwrap().idle;
+            let changed = enabled.is_some()
+                || idle_threshold.is_some()
+                || busy_threshold.is_some()
+                || idle_time.is_some()
+                || power_cap.is_some()
+                || force_low.is_some()
+                || !fan_curve.is_empty()
+                || no_fan_curve;
+
+            if let Some(enabled) = enabled {
+                settings.enabled = enabled;
+            }
+            if let Some(idle_threshold) = idle_threshold {
+                settings.idle_threshold = idle_threshold;
+            }
+            if let Some(busy_threshold) = busy_threshold {
+                settings.busy_threshold = busy_threshold;
+            }
+            if let Some(idle_time) = idle_time {
+                settings.idle_time = idle_time;
+            }
+            if let Some(power_cap) = power_cap {
+                settings.power_cap = match power_cap {
+                    cap if cap > 0 => Some(cap),
+                    _ => None,
+                };
+            }
+            if let Some(force_low) = force_low {
+                settings.force_low = force_low;
+            }
+            if !fan_curve.is_empty() {
+                let mut curve = BTreeMap::new();
+
+                for point in &fan_curve {
+                    match parse_curve_point(point) {
+                        Some((temp, speed)) => {
+                            curve.insert(temp, speed);
+                        }
+                        None => {
+                            eprintln!("Invalid curve point {}, expected temperature:speed", point);
+                            return;
+                        }
+                    }
+                }
+
+                settings.fan_curve = Some(curve);
+            }
+            if no_fan_curve {
+                settings.fan_curve = None;
+            }
+
+            if changed {
+                if let Err(e) = d.set_idle_settings(gpu_id, settings.clone()) {
+                    eprintln!("Failed to set the idle settings: {}", e);
+                    return;
+                }
+            }
+            print_idle_settings(&settings);
+        }
+        Opt::OdFan {
+            gpu_id,
+            set,
+            curve_point,
+            reset,
+        } => {
+            if reset {
+                if let Err(e) = d.reset_od_fan_controls(gpu_id) {
+                    eprintln!("Failed to reset the overdrive fan settings: {}", e);
+                    return;
+                }
+            }
+            for value in &set {
+                let (name, value) = match parse_od_fan_value(value) {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("Invalid setting {}, expected name=value", value);
+                        return;
+                    }
+                };
+
+                if let Err(e) = d.set_od_fan_value(gpu_id, name, value) {
+                    eprintln!("Failed to set {}: {}", name, e);
+                    return;
+                }
+            }
+            for point in &curve_point {
+                let (num, temperature, speed) = match parse_od_fan_curve_point(point) {
+                    Some(point) => point,
+                    None => {
+                        eprintln!(
+                            "Invalid curve point {}, expected point:temperature:speed",
+                            point
+                        );
+                        return;
+                    }
+                };
+
+                if let Err(e) = d.set_od_fan_curve_point(gpu_id, num, temperature, speed) {
+                    eprintln!("Failed to set the fan curve point {}: {}", num, e);
+                    return;
+                }
+            }
+
+            match d.get_gpu_info(gpu_id).unwrap().od_fan_controls {
+                Some(controls) => print_od_fan_controls(&controls),
+                None => println!("{}", "Overdrive fan settings not available".cyan()),
+            }
+        }
+        Opt::Export {
+            gpu_id,
+            file,
+            profile,
+            format,
+        } => {
+            let format = match parse_export_format(format, file.as_ref()) {
+                Ok(format) => format,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+
+            let text = match d
+                .export_settings(gpu_id, profile)
+                .map_err(|e| e.to_string())
+                .and_then(|export| export.to_string(format))
+            {
+                Ok(text) => text,
+                Err(e) => {
+                    eprintln!("Failed to export the settings: {}", e);
+                    return;
+                }
+            };
+
+            match file {
+                Some(path) => match fs::write(&path, text) {
+                    Ok(()) => println!("Exported the settings to {}", path.to_string_lossy()),
+                    Err(e) => eprintln!("Failed to write {}: {}", path.to_string_lossy(), e),
+                },
+                None => print!("{}", text),
+            }
+        }
+        Opt::Import {
+            gpu_id,
+            file,
+            profile,
+            force,
+            format,
+        } => {
+            let format = match parse_export_format(format, Some(&file)) {
+                Ok(format) => format,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+
+            let export = match fs::read_to_string(&file)
+                .map_err(|e| e.to_string())
+                .and_then(|text| SettingsExport::parse(&text, format))
+            {
+                Ok(export) => export,
+                Err(e) => {
+                    eprintln!("Failed to read {}: {}", file.to_string_lossy(), e);
+                    return;
+                }
+            };
+
+            println!("{} {}", "Made for:".yellow(), export.model);
+            if let Some(name) = &export.profile {
+                println!("{} {}", "Exported profile:".yellow(), name);
+            }
+
+            match d.import_settings(gpu_id, export, profile.clone(), force) {
+                Ok(()) => match profile {
+                    Some(_) => print_profiles(&d, gpu_id),
+                    None => println!("{}", "Applied the settings".green()),
+                },
+                Err(DaemonError::IncompatibleSettings(problems)) => {
+                    eprintln!("{}", "The settings don't fit this GPU:".red());
+                    for problem in problems {
+                        eprintln!("   {}", problem);
+                    }
+                    eprintln!("Use --force to import them anyway");
+                }
+                Err(e) => eprintln!("Failed to import the settings: {}", e),
+            }
+        }
+        Opt::Script {
+            gpu_id,
+            file,
+            clear,
+        } => {
+            let script = match file {
+                Some(path) => match fs::read_to_string(&path) {
+                    Ok(source) => Some(source),
+                    Err(e) => {
+                        eprintln!("Failed to read {}: {}", path.to_string_lossy(), e);
+                        return;
+                    }
+                },
+                None => None,
+            };
+
+            if script.is_some() || clear {
+                if let Err(e) = d.set_script(gpu_id, script) {
+                    eprintln!("Failed to set the script: {}", e);
+                    return;
+                }
+            }
+
+            match d.get_gpu_info(gpu_id).unwrap().script {
+                Some(source) => println!("{}", source.trim_end()),
+                None => println!("{}", "No script".yellow()),
+            }
+        }
+        Opt::Profile(profile_opt) => {
+            let (gpu_id, result) = match profile_opt {
+                ProfileOpt::List { gpu_id } => (gpu_id, Ok(())),
+                ProfileOpt::Create { gpu_id, name } => (gpu_id, d.create_profile(gpu_id, &name)),
+                ProfileOpt::Rename {
+                    gpu_id,
+                    name,
+                    new_name,
+                } => (gpu_id, d.rename_profile(gpu_id, &name, &new_name)),
+                ProfileOpt::Delete { gpu_id, name } => (gpu_id, d.delete_profile(gpu_id, &name)),
+                ProfileOpt::Activate { gpu_id, name } => {
+                    (gpu_id, d.activate_profile(gpu_id, &name))
+                }
+                ProfileOpt::PowerSource {
+                    gpu_id,
+                    source,
+                    name,
+                } => match PowerSource::from_str(&source) {
+                    Some(source) => (gpu_id, d.set_power_source_profile(gpu_id, source, name)),
+                    None => {
+                        eprintln!("Unknown power source {}", source);
+                        return;
+                    }
+                },
+            };
+
+            match result {
+                Ok(()) => print_profiles(&d, gpu_id),
+                Err(e) => eprintln!("Failed to change the profiles: {}", e),
+            }
+        }
+        Opt::Rule(rule_opt) => {
+            let result = match rule_opt {
+                RuleOpt::List => Ok(()),
+                RuleOpt::Add {
+                    gpu_id,
+                    profile,
+                    exe,
+                    cmdline,
+                    cgroup,
+                } => {
+                    let matcher = match (exe, cmdline, cgroup) {
+                        (Some(exe), None, None) => ProcessMatcher::Exe(exe),
+                        (None, Some(cmdline), None) => ProcessMatcher::Cmdline(cmdline),
+                        (None, None, Some(cgroup)) => ProcessMatcher::Cgroup(cgroup),
+                        _ => {
+                            eprintln!("Exactly one of --exe, --cmdline or --cgroup is required");
+                            return;
+                        }
+                    };
+
+                    d.add_process_rule(ProcessRule {
+                        gpu_id,
+                        matcher,
+                        profile,
+                    })
+                }
+                RuleOpt::Remove { index } => d.remove_process_rule(index),
+            };
+
+            match result {
+                Ok(()) => print_process_rules(&d),
+                Err(e) => eprintln!("Failed to change the process rules: {}", e),
+            }
+        }
+        Opt::Schedule(schedule_opt) => {
+            let result = match schedule_opt {
+                ScheduleOpt::List => Ok(()),
+                ScheduleOpt::Add(args) => match args.to_schedule() {
+                    Ok(schedule) => d.set_schedule(None, schedule),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                },
+                ScheduleOpt::Edit { index, schedule } => match schedule.to_schedule() {
+                    Ok(schedule) => d.set_schedule(Some(index), schedule),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                },
+                ScheduleOpt::Remove { index } => d.remove_schedule(index),
+            };
+
+            match result {
+                Ok(()) => print_schedules(&d),
+                Err(e) => eprintln!("Failed to change the schedules: {}", e),
+            }
+        }
+        Opt::Hook(hook_opt) => {
+            let result = match hook_opt {
+                HookOpt::List => Ok(()),
+                HookOpt::Add {
+                    event,
+                    command,
+                    gpu_id,
+                    timeout,
+                    temperature,
+                } => match HookEvent::from_str(&event) {
+                    Some(event) => d.add_hook(Hook {
+                        event,
+                        gpu_id,
+                        command,
+                        timeout,
+                        temperature,
+                    }),
+                    None => {
+                        eprintln!("Unknown event {}", event);
+                        return;
+                    }
+                },
+                HookOpt::Remove { index } => d.remove_hook(index),
+            };
+
+            match result {
+                Ok(()) => print_hooks(&d),
+                Err(e) => eprintln!("Failed to change the hooks: {}", e),
+            }
+        }
+        Opt::Group(group_opt) => {
+            let result = match group_opt {
+                GroupOpt::List => Ok(()),
+                GroupOpt::Create {
+                    name,
+                    model,
+                    vendor,
+                    ids,
+                } => {
+                    let matcher = match (model, vendor, ids.is_empty()) {
+                        (Some(model), None, true) => GroupMatcher::Model(model),
+                        (None, Some(vendor), true) => GroupMatcher::Vendor(vendor),
+                        (None, None, false) => GroupMatcher::Ids(ids),
+                        _ => {
+                            eprintln!("Exactly one of --model, --vendor or --ids is required");
+                            return;
+                        }
+                    };
+
+                    d.create_group(GpuGroup::new(&name, matcher))
+                }
+                GroupOpt::Delete { name } => d.delete_group(&name),
+                GroupOpt::Set {
+                    name,
+                    gpu_id,
+                    settings,
+                } => match settings.to_settings() {
+                    Ok(settings) => settings
+                        .into_iter()
+                        .try_for_each(|setting| d.set_group_setting(&name, gpu_id, setting)),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                },
+                GroupOpt::Clear {
+                    name,
+                    setting,
+                    gpu_id,
+                } => d.clear_group_setting(&name, gpu_id, &setting),
+                GroupOpt::Apply { name } => d.apply_group(&name),
+            };
+
+            match result {
+                Ok(()) => print_groups(&d),
+                Err(e) => eprintln!("Failed to change the groups: {}", e),
+            }
+        }
         Opt::Curve(curve) => match curve {
             CurveOpt::Status { gpu_id } => {
                 let mut gpu_ids: Vec<u32> = Vec::new();
@@ -97,11 +1179,328 @@ fn main() {
                     print_fan_curve(&d, gpu_id);
                 }
             }
+            CurveOpt::Set { gpu_id, points } => {
+                let mut curve = BTreeMap::new();
+
+                for point in &points {
+                    match parse_curve_point(point) {
+                        Some((temp, speed)) => {
+                            curve.insert(temp, speed);
+                        }
+                        None => {
+                            eprintln!("Invalid curve point {}, expected temperature:speed", point);
+                            return;
+                        }
+                    }
+                }
+
+                let options = d.get_fan_control(gpu_id).unwrap().curve_options;
+
+                match d.set_fan_curve(gpu_id, curve, options) {
+                    Ok(()) => print_fan_curve(&d, gpu_id),
+                    Err(e) => eprintln!("Failed to set fan curve: {}", e),
+                }
+            }
+            CurveOpt::Interpolation {
+                gpu_id,
+                mode,
+                non_decreasing,
+                unit,
+            } => {
+                let fan_control = d.get_fan_control(gpu_id).unwrap();
+                let mut curve = fan_control.curve;
+                let mut options = fan_control.curve_options;
+
+                if let Some(mode) = mode {
+                    options.interpolation = match mode.as_str() {
+                        "step" => FanCurveInterpolation::Step,
+                        "linear" => FanCurveInterpolation::Linear,
+                        "smooth" => FanCurveInterpolation::Smooth,
+                        _ => {
+                            eprintln!("Unknown interpolation {}", mode);
+                            return;
+                        }
+                    };
+                }
+                if let Some(non_decreasing) = non_decreasing {
+                    options.non_decreasing = non_decreasing;
+                }
+                if let Some(unit) = unit {
+                    let unit = match unit.as_str() {
+                        "percent" => FanSpeedUnit::Percent,
+                        "rpm" => FanSpeedUnit::Rpm,
+                        _ => {
+                            eprintln!("Unknown unit {}", unit);
+                            return;
+                        }
+                    };
+
+                    if unit != options.unit {
+                        match &fan_control.calibration {
+                            Some(calibration) => {
+                                curve = calibration.convert_curve(&curve, options.unit, unit);
+                            }
+                            None => {
+                                eprintln!("The fan has to be calibrated first with `lact-cli curve calibrate`");
+                                return;
+                            }
+                        }
+                        options.unit = unit;
+                    }
+                }
+
+                match d.set_fan_curve(gpu_id, curve, options) {
+                    Ok(()) => print_fan_curve(&d, gpu_id),
+                    Err(e) => eprintln!("Failed to set fan curve options: {}", e),
+                }
+            }
+            CurveOpt::Calibrate { gpu_id } => {
+                if let Err(e) = d.start_fan_calibration(gpu_id) {
+                    eprintln!("Failed to start the fan calibration: {}", e);
+                    return;
+                }
+
+                println!("Calibrating the fan, this takes a few minutes");
+
+                loop {
+                    thread::sleep(Duration::from_secs(1));
+
+                    match d.get_gpu_stats(gpu_id).unwrap().fan_calibration {
+                        FanCalibrationStatus::Running(progress) => {
+                            print!("\r{} {:.0}%", "Progress:".yellow(), progress * 100.0);
+                            io::stdout().flush().unwrap();
+                        }
+                        status => {
+                            println!();
+                            println!("{} {}", "Fan calibration".yellow(), status);
+                            break;
+                        }
+                    }
+                }
+            }
+            CurveOpt::Smoothing {
+                gpu_id,
+                hysteresis,
+                ramp_up,
+                ramp_down,
+                average_window,
+                spin_down_delay,
+            } => {
+                let mut smoothing = d.get_fan_control(gpu_id).unwrap().smoothing;
+
+                if let Some(hysteresis) = hysteresis {
+                    smoothing.hysteresis = hysteresis;
+                }
+                if let Some(ramp_up) = ramp_up {
+                    smoothing.ramp_up_rate = ramp_up;
+                }
+                if let Some(ramp_down) = ramp_down {
+                    smoothing.ramp_down_rate = ramp_down;
+                }
+                if let Some(average_window) = average_window {
+                    smoothing.temp_average_window = average_window;
+                }
+                if let Some(spin_down_delay) = spin_down_delay {
+                    smoothing.spin_down_delay = spin_down_delay;
+                }
+
+                d.set_fan_smoothing(gpu_id, smoothing).unwrap();
+                print_fan_curve(&d, gpu_id);
+            }
+            CurveOpt::Mode {
+                gpu_id,
+                mode,
+                value,
+                kp,
+                ki,
+                kd,
+                min_pwm,
+                max_pwm,
+            } => {
+                let mode = match (mode.as_str(), value) {
+                    ("curve", _) => FanMode::Curve,
+                    ("static", Some(speed)) => FanMode::Static(speed),
+                    ("rpm", Some(rpm)) => FanMode::TargetRpm(rpm as i64),
+                    ("pid", target_temp) => {
+                        // Unspecified values are taken from the current PID settings
+                        let mut settings = match d.get_fan_control(gpu_id).unwrap().mode {
+                            FanMode::Pid(settings) => settings,
+                            _ => FanPidSettings::default(),
+                        };
+
+                        if let Some(target_temp) = target_temp {
+                            settings.target_temp = target_temp;
+                        }
+                        if let Some(kp) = kp {
+                            settings.kp = kp;
+                        }
+                        if let Some(ki) = ki {
+                            settings.ki = ki;
+                        }
+                        if let Some(kd) = kd {
+                            settings.kd = kd;
+                        }
+                        if let Some(min_pwm) = min_pwm {
+                            settings.min_pwm = min_pwm;
+                        }
+                        if let Some(max_pwm) = max_pwm {
+                            settings.max_pwm = max_pwm;
+                        }
+
+                        if let Err(e) = settings.validate() {
+                            eprintln!("Invalid PID settings: {}", e);
+                            return;
+                        }
+
+                        FanMode::Pid(settings)
+                    }
+                    ("static", None) | ("rpm", None) => {
+                        eprintln!("The {} fan mode needs a value", mode);
+                        return;
+                    }
+                    _ => {
+                        eprintln!("Unknown fan mode {}", mode);
+                        return;
+                    }
+                };
+
+                match d.set_fan_mode(gpu_id, mode) {
+                    Ok(()) => print_fan_curve(&d, gpu_id),
+                    Err(e) => eprintln!("Failed to set fan mode: {}", e),
+                }
+            }
+            CurveOpt::Limits {
+                gpu_id,
+                min_speed,
+                max_speed,
+                zero_rpm,
+                zero_rpm_threshold,
+                kick_speed,
+                kick_duration,
+                failsafe,
+            } => {
+                let mut limits = d.get_fan_control(gpu_id).unwrap().limits;
+
+                if let Some(min_speed) = min_speed {
+                    limits.min_speed = min_speed;
+                }
+                if let Some(max_speed) = max_speed {
+                    limits.max_speed = max_speed;
+                }
+                if let Some(zero_rpm) = zero_rpm {
+                    limits.zero_rpm = zero_rpm;
+                }
+                if let Some(zero_rpm_threshold) = zero_rpm_threshold {
+                    limits.zero_rpm_threshold = zero_rpm_threshold;
+                }
+                if let Some(kick_speed) = kick_speed {
+                    limits.kick_speed = kick_speed;
+                }
+                if let Some(kick_duration) = kick_duration {
+                    limits.kick_duration = kick_duration;
+                }
+                if let Some(failsafe) = failsafe {
+                    limits.failsafe = match failsafe.as_str() {
+                        "automatic" => FanFailsafe::Automatic,
+                        "full-speed" => FanFailsafe::FullSpeed,
+                        _ => {
+                            eprintln!("Unknown failsafe {}", failsafe);
+                            return;
+                        }
+                    };
+                }
+
+                d.set_fan_limits(gpu_id, limits).unwrap();
+                print_fan_curve(&d, gpu_id);
+            }
+            CurveOpt::Health {
+                gpu_id,
+                enabled,
+                stall_time,
+                underspeed_tolerance,
+                underspeed_time,
+                wear_threshold,
+                stop_fan_control,
+                power_cap,
+            } => {
+                let mut settings = d.get_fan_control(gpu_id).unwrap().health_settings;
+
+                if let Some(enabled) = enabled {
+                    settings.enabled = enabled;
+                }
+                if let Some(stall_time) = stall_time {
+                    settings.stall_time = stall_time;
+                }
+                if let Some(underspeed_tolerance) = underspeed_tolerance {
+                    settings.underspeed_tolerance = underspeed_tolerance;
+                }
+                if let Some(underspeed_time) = underspeed_time {
+                    settings.underspeed_time = underspeed_time;
+                }
+                if let Some(wear_threshold) = wear_threshold {
+                    settings.wear_threshold = wear_threshold;
+                }
+                if let Some(stop_fan_control) = stop_fan_control {
+                    settings.action.stop_fan_control = stop_fan_control;
+                }
+                if let Some(power_cap) = power_cap {
+                    settings.action.power_cap = match power_cap {
+                        0 => None,
+                        cap => Some(cap),
+                    };
+                }
+
+                if let Err(e) = d.set_fan_health_settings(gpu_id, settings) {
+                    eprintln!("Failed to set the fan health settings: {}", e);
+                    return;
+                }
+                print_fan_curve(&d, gpu_id);
+            }
+            CurveOpt::TempSource {
+                gpu_id,
+                source,
+                edge_weight,
+                junction_weight,
+                memory_weight,
+            } => {
+                let source = match source.as_str() {
+                    "edge" => FanTempSource::Edge,
+                    "junction" => FanTempSource::Junction,
+                    "memory" => FanTempSource::Memory,
+                    "max" => FanTempSource::Max,
+                    "weighted" => FanTempSource::Weighted {
+                        edge: edge_weight,
+                        junction: junction_weight,
+                        memory: memory_weight,
+                    },
+                    _ => {
+                        eprintln!("Unknown temperature source {}", source);
+                        return;
+                    }
+                };
+
+                d.set_fan_temp_source(gpu_id, source).unwrap();
+                print_fan_curve(&d, gpu_id);
+            }
         },
         Opt::Config(config_opt) => match config_opt {
             ConfigOpt::Show => print_config(&d),
             ConfigOpt::AllowOnlineUpdating => enable_online_update(&d),
             ConfigOpt::DisallowOnlineUpdating => disable_online_update(&d),
+            ConfigOpt::FanInterval { interval_ms } => {
+                let mut config = d.get_config().unwrap();
+                config.fan_control_interval = Some(interval_ms);
+                d.set_config(config).unwrap();
+            }
+            ConfigOpt::Backups => print_config_backups(&d),
+            ConfigOpt::Restore { index } => match d.restore_config_backup(index) {
+                Ok(()) => println!(
+                    "{} {}",
+                    "Restored config backup".green(),
+                    format!("#{}", index).bold()
+                ),
+                Err(e) => eprintln!("Failed to restore the config: {}", e),
+            },
         },
     }
 }
@@ -121,31 +1520,563 @@ fn enable_online_update(d: &DaemonConnection) {
 fn print_config(d: &DaemonConnection) {
     let config = d.get_config().unwrap();
 
+    if let Some(e) = d.get_config_load_error().unwrap() {
+        println!(
+            "{} {}",
+            "The config failed to load, changes are not saved:".red(),
+            e.bold()
+        );
+    }
+
     println!(
         "{} {:?}",
         "Online PCI DB updating:".purple(),
         config.allow_online_update
     );
+    println!(
+        "{} {}{}",
+        "Fan control interval:".purple(),
+        config
+            .fan_control_interval
+            .unwrap_or(daemon::fan_scheduler::DEFAULT_INTERVAL_MS)
+            .to_string()
+            .bold(),
+        "ms".bold()
+    );
 }
 
 fn print_fan_curve(d: &DaemonConnection, gpu_id: u32) {
     let fan_control = d.get_fan_control(gpu_id).unwrap();
 
+    if let Some(fault) = &fan_control.fault {
+        println!(
+            "{} {}",
+            "Fan control failed:".red(),
+            fault.to_string().bold()
+        );
+    }
+
     if fan_control.enabled {
-        println!("{}", "Fan curve:".yellow());
+        println!(
+            "{} {}",
+            "Fan mode:".yellow(),
+            fan_control.mode.to_string().bold()
+        );
+        if let Some((min, max)) = fan_control.speed_range {
+            println!(
+                "{} {}-{} {}{}",
+                "Fan speed range:".yellow(),
+                min.to_string().bold(),
+                max.to_string().bold(),
+                "RPM".bold(),
+                match fan_control.supports_fan_target {
+                    true => "",
+                    false => " (RPM targets are approximated by adjusting the PWM)",
+                }
+            );
+        }
+
+        if let Some(status) = &fan_control.status {
+            if let Some(temp) = status.temp {
+                println!(
+                    "{} {}{}",
+                    "Fan control temperature:".yellow(),
+                    format!("{:.1}", temp).bold(),
+                    "C°".bold()
+                );
+            }
+            if let Some(pwm) = status.pwm {
+                println!(
+                    "{} {}{}",
+                    "Fan control PWM:".yellow(),
+                    pwm.to_string().bold(),
+                    "/255".bold()
+                );
+            }
+        }
+
+        println!(
+            "{} {}{}",
+            "Fan curve:".yellow(),
+            fan_control.curve_options.interpolation.to_string().bold(),
+            match fan_control.curve_options.non_decreasing {
+                true => ", non-decreasing",
+                false => "",
+            }
+        );
+
+        let unit = fan_control.curve_options.unit;
 
         for (temp, fan_speed) in fan_control.curve {
+            // Show the speed in the other unit as well when the fan was calibrated
+            let converted = match (&fan_control.calibration, unit) {
+                (Some(calibration), FanSpeedUnit::Percent) => {
+                    format!(" (~{} RPM)", calibration.percent_to_rpm(fan_speed).round())
+                }
+                (Some(calibration), FanSpeedUnit::Rpm) => {
+                    format!(" (~{}%)", calibration.rpm_to_percent(fan_speed).round())
+                }
+                (None, _) => String::new(),
+            };
+
             println!(
-                "{}{}: {}{}",
+                "{}{}: {}{}{}",
                 temp.to_string().yellow(),
                 "C°".yellow(),
                 fan_speed.round().to_string().bold(),
-                "%".bold()
+                unit.to_string().bold(),
+                converted
             );
         }
     } else {
         println!("{}", "Automatic fan control used".yellow());
     }
+
+    println!(
+        "{} {}",
+        "Temperature source:".yellow(),
+        fan_control.temp_source.to_string().bold()
+    );
+
+    match &fan_control.calibration {
+        Some(calibration) => println!(
+            "{} {}-{} {}, {} {}, {} {}",
+            "Fan calibration:".yellow(),
+            "0".bold(),
+            calibration.max_rpm().to_string().bold(),
+            "RPM".bold(),
+            "start PWM".yellow(),
+            calibration.start_pwm.to_string().bold(),
+            "stop PWM".yellow(),
+            calibration.stop_pwm.to_string().bold()
+        ),
+        None => println!("{} {}", "Fan calibration:".yellow(), "none".bold()),
+    }
+
+    print_fan_limits(&fan_control.limits);
+    print_fan_health_settings(&fan_control.health_settings);
+
+    let smoothing = fan_control.smoothing;
+    println!(
+        "{} {}{}",
+        "Hysteresis:".yellow(),
+        smoothing.hysteresis.to_string().bold(),
+        "C°".bold()
+    );
+    println!(
+        "{} {}",
+        "Ramp up rate:".yellow(),
+        format_ramp_rate(smoothing.ramp_up_rate).bold()
+    );
+    println!(
+        "{} {}",
+        "Ramp down rate:".yellow(),
+        format_ramp_rate(smoothing.ramp_down_rate).bold()
+    );
+    println!(
+        "{} {}{}",
+        "Temperature averaging window:".yellow(),
+        smoothing.temp_average_window.to_string().bold(),
+        "s".bold()
+    );
+    println!(
+        "{} {}{}",
+        "Spin down delay:".yellow(),
+        smoothing.spin_down_delay.to_string().bold(),
+        "s".bold()
+    );
+}
+
+fn print_fan_health_settings(settings: &FanHealthSettings) {
+    if !settings.enabled {
+        println!("{} {}", "Fan health check:".yellow(), "disabled".bold());
+        return;
+    }
+
+    println!(
+        "{} {} {}{}, {} {}{} {} {}{}, {} {}",
+        "Fan health check:".yellow(),
+        "stall after".yellow(),
+        settings.stall_time.to_string().bold(),
+        "s".bold(),
+        "underspeed".yellow(),
+        format!("{:.0}", settings.underspeed_tolerance * 100.0).bold(),
+        "%".bold(),
+        "below calibration for".yellow(),
+        settings.underspeed_time.to_string().bold(),
+        "s".bold(),
+        "wear below".yellow(),
+        format!("{:.0}%", settings.wear_threshold * 100.0).bold()
+    );
+    println!(
+        "{} {}{}",
+        "On fan failure:".yellow(),
+        match settings.action.stop_fan_control {
+            true => "stop fan control",
+            false => "keep fan control",
+        }
+        .bold(),
+        match settings.action.power_cap {
+            Some(cap) => format!(", power cap {}W", cap),
+            None => String::new(),
+        }
+        .bold()
+    );
+}
+
+fn print_profiles(d: &DaemonConnection, gpu_id: u32) {
+    let (names, active) = d.get_profiles(gpu_id).unwrap();
+
+    if names.is_empty() {
+        println!("{}", "No profiles".yellow());
+    }
+
+    for name in names {
+        match active.as_deref() == Some(name.as_str()) {
+            true => println!("{} {}", name.bold(), "(active)".green()),
+            false => println!("{}", name),
+        }
+    }
+
+    let gpu_info = d.get_gpu_info(gpu_id).unwrap();
+    for source in &[PowerSource::Ac, PowerSource::Battery] {
+        if let Some(name) = gpu_info.power_source_profiles.get(*source) {
+            print!("{} {}", format!("On {} power:", source).blue(), name.bold());
+            match gpu_info.power_source == Some(*source) {
+                true => println!(" {}", "(current)".green()),
+                false => println!(),
+            }
+        }
+    }
+}
+
+fn print_process_rules(d: &DaemonConnection) {
+    let (rules, matches) = d.get_process_rules().unwrap();
+
+    if rules.is_empty() {
+        println!("{}", "No process rules".yellow());
+    }
+
+    for (index, rule) in rules.iter().enumerate() {
+        print!(
+            "{} GPU {}: {} -> {}",
+            format!("#{}", index).bold(),
+            rule.gpu_id,
+            rule.matcher,
+            rule.profile.bold()
+        );
+
+        match matches.iter().find(|rule_match| rule_match.index == index) {
+            Some(rule_match) => println!(
+                " {}",
+                format!("(matched by {} [{}])", rule_match.process, rule_match.pid).green()
+            ),
+            None => println!(),
+        }
+    }
+}
+
+fn print_hooks(d: &DaemonConnection) {
+    let (hooks, results) = d.get_hooks().unwrap();
+
+    if hooks.is_empty() {
+        println!("{}", "No hooks".yellow());
+    }
+
+    for (index, hook) in hooks.iter().enumerate() {
+        println!("{} {}", format!("#{}", index).bold(), hook);
+
+        if let Some(result) = results.get(&index) {
+            let status = result.status.to_string();
+            let status = match result.status {
+                HookStatus::Exited(0) => status.green(),
+                HookStatus::Running => status.normal(),
+                _ => status.red(),
+            };
+            let ago = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_secs().saturating_sub(result.started))
+                .unwrap_or_default();
+
+            println!(
+                "   Last run {}s ago for GPU {}: {} after {}ms",
+                ago, result.gpu_id, status, result.duration
+            );
+            for line in result.output.lines() {
+                println!("   {}", line.dimmed());
+            }
+        }
+    }
+}
+
+fn parse_export_format(
+    format: Option<String>,
+    file: Option<&PathBuf>,
+) -> Result<ExportFormat, String> {
+    match (format, file) {
+        (Some(format), _) => {
+            ExportFormat::from_str(&format).ok_or_else(|| format!("Unknown format {}", format))
+        }
+        (None, Some(path)) => Ok(ExportFormat::from_path(path)),
+        (None, None) => Ok(ExportFormat::Json),
+    }
+}
+
+fn print_config_backups(d: &DaemonConnection) {
+    let backups = d.get_config_backups().unwrap();
+
+    if backups.is_empty() {
+        println!("{}", "No config backups".yellow());
+    }
+
+    for backup in backups {
+        let ago = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs().saturating_sub(backup.created))
+            .unwrap_or_default();
+
+        print!(
+            "{} replaced {}",
+            format!("#{}", backup.index).bold(),
+            format_age(ago)
+        );
+
+        match backup.error {
+            Some(e) => println!(" {}", format!("(can't be restored: {})", e).red()),
+            None => println!(),
+        }
+    }
+}
+
+fn format_age(secs: u64) -> String {
+    match secs {
+        secs if secs < 60 => format!("{}s ago", secs),
+        secs if secs < 60 * 60 => format!("{}min ago", secs / 60),
+        secs if secs < 24 * 60 * 60 => format!("{}h ago", secs / (60 * 60)),
+        secs => format!("{} days ago", secs / (24 * 60 * 60)),
+    }
+}
+
+fn print_groups(d: &DaemonConnection) {
+    let groups = d.get_groups().unwrap();
+
+    if groups.is_empty() {
+        println!("{}", "No groups".yellow());
+    }
+
+    for (group, members) in groups {
+        let members: Vec<String> = members.iter().map(|id| id.to_string()).collect();
+        println!(
+            "{} {} {}",
+            group.name.bold(),
+            format!("({})", group.matcher).yellow(),
+            match members.is_empty() {
+                true => "no GPUs".red(),
+                false => format!("GPUs {}", members.join(", ")).green(),
+            }
+        );
+
+        for setting in &group.settings {
+            println!("   {}", setting);
+        }
+
+        let mut overrides: Vec<_> = group.overrides.iter().collect();
+        overrides.sort_by_key(|(id, _)| **id);
+
+        for (id, settings) in overrides {
+            for setting in settings {
+                println!("   {} {}", format!("GPU {}:", id).yellow(), setting);
+            }
+        }
+    }
+}
+
+fn print_schedules(d: &DaemonConnection) {
+    let (schedules, applied) = d.get_schedules().unwrap();
+
+    if schedules.is_empty() {
+        println!("{}", "No schedules".yellow());
+    }
+
+    for (index, schedule) in schedules.iter().enumerate() {
+        print!("{} {}", format!("#{}", index).bold(), schedule);
+
+        match applied.contains(schedule) {
+            true => println!(" {}", "(in effect)".green()),
+            false => println!(),
+        }
+    }
+}
+
+fn print_power_guard_settings(settings: &PowerGuardSettings) {
+    if !settings.enabled {
+        println!("{} {}", "Power guard:".yellow(), "disabled".bold());
+        return;
+    }
+
+    println!(
+        "{} {} {}{}{}",
+        "Power guard:".yellow(),
+        "lowers the power cap at".yellow(),
+        settings.max_temp.to_string().bold(),
+        "C°".bold(),
+        match settings.max_fan_speed {
+            Some(speed) => format!(" or {}% fan speed", speed),
+            None => String::new(),
+        }
+        .bold()
+    );
+    println!(
+        "{} {}{} {} {}{}",
+        "Lowering:".yellow(),
+        settings.step.to_string().bold(),
+        "W".bold(),
+        "every".yellow(),
+        settings.step_interval.to_string().bold(),
+        "s".bold()
+    );
+    println!(
+        "{} {}{} {} {}{} {} {}{}",
+        "Restoring:".yellow(),
+        settings.restore_step.to_string().bold(),
+        "W".bold(),
+        "every".yellow(),
+        settings.restore_interval.to_string().bold(),
+        "s".bold(),
+        "once below".yellow(),
+        (settings.max_temp - settings.hysteresis).to_string().bold(),
+        "C°".bold()
+    );
+}
+
+fn print_idle_settings(settings: &IdleSettings) {
+    if !settings.enabled {
+        println!("{} {}", "Idle:".yellow(), "disabled".bold());
+        return;
+    }
+
+    println!(
+        "{} {} {}{} {} {}{}",
+        "Idle:".yellow(),
+        "low power after".yellow(),
+        settings.idle_time.to_string().bold(),
+        "s".bold(),
+        "at or below".yellow(),
+        settings.idle_threshold.to_string().bold(),
+        "% load".bold()
+    );
+    println!(
+        "{} {}{}",
+        "Restoring above:".yellow(),
+        settings.busy_threshold.to_string().bold(),
+        "% load".bold()
+    );
+    println!(
+        "{} {}",
+        "Power cap:".yellow(),
+        match settings.power_cap {
+            Some(cap) => format!("{}W", cap),
+            None => "unchanged".to_string(),
+        }
+        .bold()
+    );
+    println!(
+        "{} {}",
+        "Performance level:".yellow(),
+        match settings.force_low {
+            true => "low",
+            false => "unchanged",
+        }
+        .bold()
+    );
+    println!(
+        "{} {}",
+        "Fan curve:".yellow(),
+        match &settings.fan_curve {
+            Some(curve) => curve
+                .iter()
+                .map(|(temp, speed)| format!("{}°C {}%", temp, speed))
+                .collect::<Vec<String>>()
+                .join(", "),
+            None => "unchanged".to_string(),
+        }
+        .bold()
+    );
+}
+
+fn parse_curve_point(point: &str) -> Option<(i64, f64)> {
+    let mut parts = point.splitn(2, ':');
+
+    let temp = parts.next()?.trim().parse().ok()?;
+    let speed = parts.next()?.trim().trim_end_matches('%').parse().ok()?;
+
+    Some((temp, speed))
+}
+
+fn parse_od_fan_value(value: &str) -> Option<(&str, i64)> {
+    let mut parts = value.splitn(2, '=');
+
+    let name = parts.next()?.trim();
+    let value = parts.next()?.trim().parse().ok()?;
+
+    Some((name, value))
+}
+
+fn parse_od_fan_curve_point(point: &str) -> Option<(u32, i64, i64)> {
+    let mut parts = point.splitn(3, ':');
+
+    let num = parts.next()?.trim().parse().ok()?;
+    let temperature = parts.next()?.trim().parse().ok()?;
+    let speed = parts.next()?.trim().trim_end_matches('%').parse().ok()?;
+
+    Some((num, temperature, speed))
+}
+
+fn print_fan_limits(limits: &FanLimits) {
+    println!(
+        "{} {}{}-{}{}",
+        "Speed limits:".yellow(),
+        limits.min_speed.to_string().bold(),
+        "%".bold(),
+        limits.max_speed.to_string().bold(),
+        "%".bold()
+    );
+    if limits.zero_rpm {
+        println!(
+            "{} {}{}",
+            "Zero RPM below:".yellow(),
+            limits.zero_rpm_threshold.to_string().bold(),
+            "C°".bold()
+        );
+    } else {
+        println!("{} {}", "Zero RPM:".yellow(), "disabled".bold());
+    }
+    println!(
+        "{} {}{} {} {}{}",
+        "Start-up kick:".yellow(),
+        limits.kick_speed.to_string().bold(),
+        "%".bold(),
+        "for".yellow(),
+        limits.kick_duration.to_string().bold(),
+        "s".bold()
+    );
+    println!(
+        "{} {}",
+        "On failure:".yellow(),
+        match limits.failsafe {
+            FanFailsafe::Automatic => "automatic fan control",
+            FanFailsafe::FullSpeed => "full speed",
+        }
+        .bold()
+    );
+}
+
+fn format_ramp_rate(rate: f64) -> String {
+    if rate > 0.0 {
+        format!("{}%/s", rate)
+    } else {
+        String::from("unlimited")
+    }
 }
 
 fn print_info(d: &DaemonConnection, gpu_id: u32) {
@@ -172,6 +2103,157 @@ fn print_info(d: &DaemonConnection, gpu_id: u32) {
         gpu_info.vram_size.to_string().bold()
     );
     println!("{} {}", "Link Speed:".blue(), gpu_info.link_speed.bold());
+    if let Some(controls) = gpu_info.od_fan_controls {
+        let names: Vec<&str> = controls
+            .values
+            .keys()
+            .map(|name| name.as_str())
+            .chain(controls.curve.as_ref().map(|_| "fan_curve"))
+            .collect();
+
+        println!(
+            "{} {}",
+            "Overdrive fan controls:".blue(),
+            names.join(", ").bold()
+        );
+    }
+    if let Some(source) = gpu_info.power_source {
+        println!("{} {}", "Power source:".blue(), source.to_string().bold());
+    }
+}
+
+fn print_clocks(d: &DaemonConnection, gpu_id: u32) {
+    let gpu_info = d.get_gpu_info(gpu_id).unwrap();
+
+    match gpu_info.clocks_table {
+        Some(ClocksTable::Old(clocks_table)) => {
+            println!("{}", "GPU power levels:".cyan());
+            for (num, (clockspeed, voltage)) in &clocks_table.gpu_power_levels {
+                println!(
+                    "{}: {}{} {}{}",
+                    num.to_string().cyan(),
+                    clockspeed.to_string().bold(),
+                    "MHz".bold(),
+                    voltage.to_string().bold(),
+                    "mV".bold()
+                );
+            }
+            println!("{}", "VRAM power levels:".cyan());
+            for (num, (clockspeed, voltage)) in &clocks_table.mem_power_levels {
+                println!(
+                    "{}: {}{} {}{}",
+                    num.to_string().cyan(),
+                    clockspeed.to_string().bold(),
+                    "MHz".bold(),
+                    voltage.to_string().bold(),
+                    "mV".bold()
+                );
+            }
+            print_range("GPU clock range:", clocks_table.gpu_clocks_range, "MHz");
+            print_range("VRAM clock range:", clocks_table.mem_clocks_range, "MHz");
+            print_range("Voltage range:", clocks_table.voltage_range, "mV");
+        }
+        Some(ClocksTable::New(clocks_table)) => {
+            println!(
+                "{} {}-{}{}",
+                "GPU clocks:".cyan(),
+                clocks_table.current_gpu_clocks.0.to_string().bold(),
+                clocks_table.current_gpu_clocks.1.to_string().bold(),
+                "MHz".bold()
+            );
+            match clocks_table.current_min_mem_clock {
+                Some(min_mem_clock) => println!(
+                    "{} {}-{}{}",
+                    "VRAM clocks:".cyan(),
+                    min_mem_clock.to_string().bold(),
+                    clocks_table.current_max_mem_clock.to_string().bold(),
+                    "MHz".bold()
+                ),
+                None => println!(
+                    "{} {}{}",
+                    "VRAM max clock:".cyan(),
+                    clocks_table.current_max_mem_clock.to_string().bold(),
+                    "MHz".bold()
+                ),
+            }
+            if let Some(vddc_curve) = clocks_table.vddc_curve {
+                println!("{}", "Voltage curve:".cyan());
+                for (num, (clockspeed, voltage)) in vddc_curve.iter().enumerate() {
+                    println!(
+                        "{}: {}{} {}{}",
+                        num.to_string().cyan(),
+                        clockspeed.to_string().bold(),
+                        "MHz".bold(),
+                        voltage.to_string().bold(),
+                        "mV".bold()
+                    );
+                }
+            }
+            if let Some(voltage_offset) = clocks_table.voltage_offset {
+                println!(
+                    "{} {}{}",
+                    "Voltage offset:".cyan(),
+                    voltage_offset.to_string().bold(),
+                    "mV".bold()
+                );
+            }
+            print_range("GPU clock range:", clocks_table.gpu_clocks_range, "MHz");
+            print_range("VRAM clock range:", clocks_table.mem_clocks_range, "MHz");
+            if clocks_table.vddc_curve.is_some() {
+                print_range("Voltage range:", clocks_table.voltage_range, "mV");
+            }
+            if let Some(voltage_offset_range) = clocks_table.voltage_offset_range {
+                print_range("Voltage offset range:", voltage_offset_range, "mV");
+            }
+        }
+        None => println!("{}", "Clocks table not available".cyan()),
+    }
+}
+
+fn print_od_fan_controls(controls: &OdFanControls) {
+    for (name, value) in &controls.values {
+        println!(
+            "{} {}",
+            format!("{}:", name).cyan(),
+            value.value.to_string().bold()
+        );
+    }
+    if let Some(curve) = &controls.curve {
+        println!("{}", "Fan curve:".cyan());
+        for (num, (temperature, speed)) in curve.points.iter().enumerate() {
+            println!(
+                "{}: {}{} {}{}",
+                num.to_string().cyan(),
+                temperature.to_string().bold(),
+                "C°".bold(),
+                speed.to_string().bold(),
+                "%".bold()
+            );
+        }
+    }
+    for (name, value) in &controls.values {
+        if let Some(range) = value.range {
+            print_range(&format!("{} range:", name), range, "");
+        }
+    }
+    if let Some(curve) = &controls.curve {
+        if let Some(range) = curve.temperature_range {
+            print_range("Fan curve temperature range:", range, "C°");
+        }
+        if let Some(range) = curve.speed_range {
+            print_range("Fan curve speed range:", range, "%");
+        }
+    }
+}
+
+fn print_range(name: &str, range: (i64, i64), unit: &str) {
+    println!(
+        "{} {}-{}{}",
+        name.cyan(),
+        range.0.to_string().bold(),
+        range.1.to_string().bold(),
+        unit.bold()
+    );
 }
 
 fn print_stats(d: &DaemonConnection, gpu_id: u32) {
@@ -189,6 +2271,22 @@ fn print_stats(d: &DaemonConnection, gpu_id: u32) {
         gpu_stats.gpu_temp.unwrap_or_default().to_string().bold(),
         "°C".bold(),
     );
+    if let Some(junction_temp) = gpu_stats.junction_temp {
+        println!(
+            "{} {}{}",
+            "Junction temperature:".green(),
+            junction_temp.to_string().bold(),
+            "°C".bold(),
+        );
+    }
+    if let Some(mem_temp) = gpu_stats.mem_temp {
+        println!(
+            "{} {}{}",
+            "Memory temperature:".green(),
+            mem_temp.to_string().bold(),
+            "°C".bold(),
+        );
+    }
     println!(
         "{} {}/{}{}",
         "Fan Speed:".green(),
@@ -200,6 +2298,83 @@ fn print_stats(d: &DaemonConnection, gpu_id: u32) {
             .bold(),
         "RPM".bold(),
     );
+    if let Some(fan_pwm) = gpu_stats.fan_pwm {
+        println!(
+            "{} {}{} ({}%)",
+            "Fan PWM:".green(),
+            fan_pwm.to_string().bold(),
+            "/255".bold(),
+            (fan_pwm as f64 / 255.0 * 100.0).round()
+        );
+    }
+    if let Some(fan_health) = &gpu_stats.fan_health {
+        match &fan_health.fault {
+            Some(fault) => println!("{} {}", "Fan health:".green(), fault.to_string().red()),
+            None => println!("{} {}", "Fan health:".green(), "ok".bold()),
+        }
+    }
+    if let Some(idle) = &gpu_stats.idle {
+        match idle.low_power {
+            true => println!(
+                "{} {} (load {}%)",
+                "Idle:".green(),
+                "low power".bold(),
+                idle.busy_percent
+            ),
+            false if idle.idle_for > 0 => println!(
+                "{} {}{}",
+                "Idle:".green(),
+                idle.idle_for.to_string().bold(),
+                "s".bold()
+            ),
+            false => (),
+        }
+    }
+    if let Some(script) = &gpu_stats.script {
+        match (&script.output, &script.error) {
+            (Some(output), _) => {
+                let mut outputs = Vec::new();
+                if let Some(pwm) = output.fan_pwm {
+                    outputs.push(format!("PWM {}", pwm));
+                }
+                if let Some(cap) = output.power_cap {
+                    outputs.push(format!("{}W", cap));
+                }
+                if let Some(profile) = &output.profile {
+                    outputs.push(format!("profile {}", profile));
+                }
+                if outputs.is_empty() {
+                    outputs.push("automatic".to_string());
+                }
+
+                println!("{} {}", "Script:".green(), outputs.join(", ").bold());
+            }
+            (None, error) => println!(
+                "{} {}",
+                "Script failed:".red(),
+                error.clone().unwrap_or_default()
+            ),
+        }
+    }
+    if let Some(rule_match) = &gpu_stats.process_rule {
+        println!(
+            "{} {} {}",
+            "Process rule:".green(),
+            rule_match.rule.profile.bold(),
+            format!(
+                "(rule #{}, {} [{}])",
+                rule_match.index, rule_match.process, rule_match.pid
+            )
+        );
+    }
+    if let FanCalibrationStatus::Running(progress) = gpu_stats.fan_calibration {
+        println!(
+            "{} {}{}",
+            "Fan calibration:".green(),
+            format!("{:.0}", progress * 100.0).bold(),
+            "%".bold()
+        );
+    }
     println!(
         "{} {}{}",
         "GPU Clock:".green(),
@@ -227,4 +2402,17 @@ fn print_stats(d: &DaemonConnection, gpu_id: u32) {
         gpu_stats.power_cap.unwrap_or_default().to_string().bold(),
         "W".bold(),
     );
+    if let Some(power_guard) = &gpu_stats.power_guard {
+        if let Some(reason) = &power_guard.reason {
+            println!(
+                "{} {}{} instead of {}{}, {}",
+                "Power guard:".green(),
+                power_guard.effective_cap.to_string().bold(),
+                "W".bold(),
+                power_guard.configured_cap.to_string().bold(),
+                "W".bold(),
+                reason.to_string().red()
+            );
+        }
+    }
 }
@@ -1,15 +1,69 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::fan_calibration::FanCalibration;
+use crate::fan_control::{
+    validate_curve, FanCurveOptions, FanLimits, FanMode, FanSmoothing, FanTempSource,
+};
+use crate::fan_health::FanHealthSettings;
 use crate::gpu_controller::PowerProfile;
+use crate::group::GpuGroup;
+use crate::hooks::Hook;
+use crate::idle::IdleSettings;
+use crate::power_guard::PowerGuardSettings;
+use crate::power_source::{PowerSource, PowerSourceProfiles};
+use crate::process_watcher::ProcessRule;
+use crate::schedule::{Schedule, ScheduleAction};
+
+/// Version of the config layout, configs of older versions get migrated when they're loaded
+pub const CONFIG_VERSION: u32 = 1;
+
+type Migration = fn(&mut Map<String, Value>, &Path) -> Result<(), String>;
+
+/// Migrations of the config layout, the one at index `n` migrates version `n` to `n + 1`
+const MIGRATIONS: &[Migration] = &[migrate_unversioned];
+
+/// Previous configs that are kept next to the config, `lact.json.1` being the newest
+pub const MAX_BACKUPS: usize = 5;
+
+/// Saves in quick succession, like when applying several settings at once, share a backup
+const BACKUP_INTERVAL: Duration = Duration::from_secs(300);
+
+/// A previous config, as listed by `Config::list_backups`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigBackup {
+    pub index: usize,
+    pub created: u64,          // Unix timestamp of when it was replaced
+    pub error: Option<String>, // Why it can't be restored
+}
 
 #[derive(Debug)]
 pub enum ConfigError {
     IoError(io::Error),
     ParseError(serde_json::Error),
+    MigrationError(String),
+    NotLoaded(String), // The config file failed to load, so it isn't written over
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::IoError(e) => write!(f, "{}", e),
+            ConfigError::ParseError(e) => write!(f, "invalid config: {}", e),
+            ConfigError::MigrationError(msg) => write!(f, "failed to migrate the config: {}", msg),
+            ConfigError::NotLoaded(msg) => write!(
+                f,
+                "not writing over the config that failed to load ({}), fix it and restart the daemon or restore a backup",
+                msg
+            ),
+        }
+    }
 }
 
 impl From<io::Error> for ConfigError {
@@ -44,14 +98,135 @@ impl PartialEq for GpuIdentifier {
 pub struct GpuConfig {
     pub fan_control_enabled: bool,
     pub fan_curve: BTreeMap<i64, f64>,
+    #[serde(default)]
+    pub fan_curve_options: FanCurveOptions,
+    #[serde(default)]
+    pub fan_smoothing: FanSmoothing,
+    #[serde(default)]
+    pub fan_temp_source: FanTempSource,
+    #[serde(default)]
+    pub fan_limits: FanLimits,
+    #[serde(default)]
+    pub fan_mode: FanMode,
+    #[serde(default)]
+    pub fan_calibration: Option<FanCalibration>,
+    #[serde(default)]
+    pub fan_health: FanHealthSettings,
     pub power_cap: i64,
+    #[serde(default)]
+    pub power_guard: PowerGuardSettings,
+    #[serde(default)]
+    pub idle: IdleSettings,
+    #[serde(default)]
+    pub script: Option<String>, // Rhai control script, see `script::Script`
     pub power_profile: PowerProfile,
     pub gpu_max_clock: i64,
     pub gpu_max_voltage: Option<i64>,
     pub vram_max_clock: i64,
+    #[serde(default)]
+    pub vddc_curve: BTreeMap<u32, (i64, i64)>, //<point, (clockspeed, voltage)>
+    #[serde(default)]
+    pub gpu_min_clock: Option<i64>,
+    #[serde(default)]
+    pub vram_min_clock: Option<i64>,
+    #[serde(default)]
+    pub voltage_offset: Option<i64>,
+    #[serde(default)]
+    pub od_fan_values: BTreeMap<String, i64>, //<gpu_od/fan_ctrl file name, value>
+    #[serde(default)]
+    pub od_fan_curve: BTreeMap<u32, (i64, i64)>, //<point, (temperature, speed)>
 }
 
 impl GpuConfig {
+    /// Replaces the settings that are out of range with their defaults, returning what was wrong.
+    /// Clocks and the power cap depend on the GPU, so they're only checked when they get applied.
+    pub fn validate(&mut self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let defaults = GpuConfig::new();
+
+        let max_rpm = self
+            .fan_calibration
+            .as_ref()
+            .map(|calibration| calibration.max_rpm());
+        if let Err(e) = validate_curve(&self.fan_curve, &self.fan_curve_options, max_rpm) {
+            problems.push(format!("fan curve: {}", e));
+            self.fan_curve = defaults.fan_curve;
+            self.fan_curve_options = defaults.fan_curve_options;
+        }
+
+        let limits = &self.fan_limits;
+        let percent = 0.0..=100.0;
+        if !percent.contains(&limits.min_speed)
+            || !percent.contains(&limits.max_speed)
+            || !percent.contains(&limits.kick_speed)
+            || limits.min_speed > limits.max_speed
+        {
+            problems.push(format!(
+                "fan limits: the speeds {}-{}% and start-up speed {}% are outside of 0-100%",
+                limits.min_speed, limits.max_speed, limits.kick_speed
+            ));
+            self.fan_limits = defaults.fan_limits;
+        }
+
+        let smoothing = &self.fan_smoothing;
+        if smoothing.hysteresis < 0
+            || smoothing.ramp_up_rate < 0.0
+            || smoothing.ramp_down_rate < 0.0
+        {
+            problems.push("fan smoothing: the hysteresis and rates can't be negative".to_string());
+            self.fan_smoothing = defaults.fan_smoothing;
+        }
+
+        let mode = match &self.fan_mode {
+            FanMode::Static(speed) if !percent.contains(speed) => {
+                Err(format!("the speed {}% is outside of 0-100%", speed))
+            }
+            FanMode::TargetRpm(rpm) if *rpm < 0 => Err(format!("the speed {}RPM is negative", rpm)),
+            FanMode::Pid(settings) => settings.validate(),
+            _ => Ok(()),
+        };
+        if let Err(e) = mode {
+            problems.push(format!("fan mode: {}", e));
+            self.fan_mode = defaults.fan_mode;
+        }
+
+        let health = &self.fan_health;
+        if !(0.0..1.0).contains(&health.underspeed_tolerance)
+            || !(0.0..=1.0).contains(&health.wear_threshold)
+        {
+            problems.push(
+                "fan health: the underspeed tolerance and wear threshold are outside of 0-1"
+                    .to_string(),
+            );
+            self.fan_health = defaults.fan_health;
+        }
+
+        if let Err(e) = self.power_guard.validate() {
+            problems.push(format!("power guard: {}", e));
+            self.power_guard = defaults.power_guard;
+        }
+
+        if let Err(e) = self.idle.validate() {
+            problems.push(format!("idle settings: {}", e));
+            self.idle = defaults.idle;
+        }
+
+        // -1 means that the power cap was never changed
+        if self.power_cap == 0 || self.power_cap < -1 {
+            problems.push(format!("power cap: {}W is invalid", self.power_cap));
+            self.power_cap = defaults.power_cap;
+        }
+
+        // 0 means that the clock was never changed
+        if self.gpu_max_clock < 0 || self.vram_max_clock < 0 {
+            problems.push("clocks: the clockspeeds can't be negative".to_string());
+            self.gpu_max_clock = defaults.gpu_max_clock;
+            self.vram_max_clock = defaults.vram_max_clock;
+        }
+
+        problems
+    }
+
     pub fn new() -> Self {
         let mut fan_curve: BTreeMap<i64, f64> = BTreeMap::new();
         fan_curve.insert(20, 0f64);
@@ -62,22 +237,63 @@ impl GpuConfig {
 
         GpuConfig {
             fan_curve,
+            fan_curve_options: FanCurveOptions::default(),
+            fan_smoothing: FanSmoothing::default(),
+            fan_temp_source: FanTempSource::default(),
+            fan_limits: FanLimits::default(),
+            fan_mode: FanMode::default(),
+            fan_calibration: None,
+            fan_health: FanHealthSettings::default(),
             fan_control_enabled: false,
             power_cap: -1,
+            power_guard: PowerGuardSettings::default(),
+            idle: IdleSettings::default(),
+            script: None,
             power_profile: PowerProfile::Auto,
             gpu_max_clock: 0,
             gpu_max_voltage: None,
             vram_max_clock: 0,
+            vddc_curve: BTreeMap::new(),
+            gpu_min_clock: None,
+            vram_min_clock: None,
+            voltage_offset: None,
+            od_fan_values: BTreeMap::new(),
+            od_fan_curve: BTreeMap::new(),
         }
     }
 }
 
+/// Named sets of settings of a GPU, one of which can be active
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GpuProfiles {
+    pub profiles: BTreeMap<String, GpuConfig>,
+    pub active: Option<String>, // Follows the changes made to the GPU settings
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
+    #[serde(default)]
+    pub version: u32, // See `CONFIG_VERSION`
     pub gpu_configs: HashMap<u32, (GpuIdentifier, GpuConfig)>,
+    #[serde(default)]
+    pub gpu_profiles: HashMap<u32, GpuProfiles>,
+    #[serde(default)]
+    pub power_source_profiles: HashMap<u32, PowerSourceProfiles>,
+    #[serde(default)]
+    pub groups: Vec<GpuGroup>,
+    #[serde(default)]
+    pub hooks: Vec<Hook>,
+    #[serde(default)]
+    pub process_rules: Vec<ProcessRule>,
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
     pub allow_online_update: Option<bool>,
+    #[serde(default)]
+    pub fan_control_interval: Option<u64>, // Milliseconds between fan control loop ticks
     pub config_path: PathBuf,
     pub group: String,
+    #[serde(skip)]
+    pub load_error: Option<String>, // Why the config file couldn't be loaded, it isn't saved over while set
 }
 
 impl Config {
@@ -85,34 +301,979 @@ impl Config {
         let gpu_configs: HashMap<u32, (GpuIdentifier, GpuConfig)> = HashMap::new();
 
         Config {
+            version: CONFIG_VERSION,
             gpu_configs,
+            gpu_profiles: HashMap::new(),
+            power_source_profiles: HashMap::new(),
+            groups: Vec::new(),
+            hooks: Vec::new(),
+            process_rules: Vec::new(),
+            schedules: Vec::new(),
             allow_online_update: None,
+            fan_control_interval: None,
             config_path: config_path.clone(),
             group: String::from("wheel"),
+            load_error: None,
         }
     }
 
+    /// Reads a config, migrating it from the layouts of older versions and resetting invalid settings.
+    /// Fields it doesn't know, like the ones of newer versions, are ignored.
     pub fn read_from_file(path: &PathBuf) -> Result<Self, ConfigError> {
         let json = fs::read_to_string(path)?;
+        let mut value: Value = serde_json::from_str(&json)?;
+
+        let config = match value.as_object_mut() {
+            Some(config) => config,
+            None => {
+                return Err(ConfigError::MigrationError(
+                    "the config isn't a JSON object".to_string(),
+                ))
+            }
+        };
+
+        let version = match config.get("version") {
+            Some(version) => version.as_u64().ok_or_else(|| {
+                ConfigError::MigrationError(format!("invalid config version {}", version))
+            })?,
+            None => 0,
+        };
+
+        if version > CONFIG_VERSION as u64 {
+            log::warn!(
+                "The config is from a newer version of LACT (layout {} instead of {}), settings this version doesn't know will be lost when it's saved",
+                version,
+                CONFIG_VERSION
+            );
+        }
+
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            log::info!("Migrating the config from layout {} to {}", from, from + 1);
+            migration(config, path).map_err(ConfigError::MigrationError)?;
+        }
+        config.insert("version".to_string(), CONFIG_VERSION.into());
+
+        let mut config: Config = serde_json::from_value(value)?;
+
+        for problem in config.validate() {
+            log::warn!(
+                "Invalid config {}, using the default instead: {}",
+                path.to_string_lossy(),
+                problem
+            );
+        }
+
+        Ok(config)
+    }
+
+    /// Replaces the invalid settings with their defaults and drops the invalid entries,
+    /// returning what was wrong
+    pub fn validate(&mut self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        for (id, (_, gpu_config)) in self.gpu_configs.iter_mut() {
+            for problem in gpu_config.validate() {
+                problems.push(format!("GPU {}: {}", id, problem));
+            }
+        }
+
+        for (id, gpu_profiles) in self.gpu_profiles.iter_mut() {
+            for (name, profile) in gpu_profiles.profiles.iter_mut() {
+                for problem in profile.validate() {
+                    problems.push(format!("profile {} of GPU {}: {}", name, id, problem));
+                }
+            }
+        }
+
+        self.groups.retain(|group| {
+            match group.validate().and_then(|_| {
+                group
+                    .settings
+                    .iter()
+                    .try_for_each(|setting| setting.validate())
+            }) {
+                Ok(()) => true,
+                Err(e) => {
+                    problems.push(format!("group {}: {}", group.name, e));
+                    false
+                }
+            }
+        });
+
+        self.hooks.retain(|hook| match hook.validate() {
+            Ok(()) => true,
+            Err(e) => {
+                problems.push(format!("hook {}: {}", hook, e));
+                false
+            }
+        });
+
+        self.process_rules.retain(|rule| match rule.validate() {
+            Ok(()) => true,
+            Err(e) => {
+                problems.push(format!("process rule: {}", e));
+                false
+            }
+        });
+
+        self.schedules.retain(|schedule| match schedule.validate() {
+            Ok(()) => true,
+            Err(e) => {
+                problems.push(format!("schedule {}: {}", schedule, e));
+                false
+            }
+        });
+
+        if self.fan_control_interval == Some(0) {
+            problems.push("the fan control interval can't be 0ms".to_string());
+            self.fan_control_interval = None;
+        }
+
+        problems
+    }
+
+    /// Copies a config that can't be read next to it, so that it doesn't get lost when a new one is saved
+    pub fn back_up_broken(path: &Path) -> io::Result<PathBuf> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or_default();
+
+        let backup_path = sibling_path(path, &format!(".broken-{}", timestamp));
+
+        fs::copy(path, &backup_path)?;
+        Ok(backup_path)
+    }
+
+    /// Stores the current settings of a GPU, updating its active profile as well
+    pub fn set_gpu_config(&mut self, id: u32, identifier: GpuIdentifier, config: GpuConfig) {
+        if let Some(gpu_profiles) = self.gpu_profiles.get_mut(&id) {
+            if let Some(active) = &gpu_profiles.active {
+                gpu_profiles.profiles.insert(active.clone(), config.clone());
+            }
+        }
+
+        self.gpu_configs.insert(id, (identifier, config));
+    }
+
+    /// Saves the current settings of a GPU as a new profile, which becomes the active one
+    pub fn create_profile(&mut self, id: u32, name: &str) -> Result<(), String> {
+        let name = validate_profile_name(name)?;
+        let config = match self.gpu_configs.get(&id) {
+            Some((_, config)) => config.clone(),
+            None => return Err(format!("there are no settings for GPU {}", id)),
+        };
+
+        let gpu_profiles = self.gpu_profiles.entry(id).or_default();
+
+        if gpu_profiles.profiles.contains_key(&name) {
+            return Err(format!("the profile {} already exists", name));
+        }
+
+        gpu_profiles.profiles.insert(name.clone(), config);
+        gpu_profiles.active = Some(name);
 
-        Ok(serde_json::from_str::<Config>(&json)?)
+        Ok(())
     }
 
+    /// Adds a profile with the given settings, without activating it
+    pub fn add_profile(&mut self, id: u32, name: &str, config: GpuConfig) -> Result<(), String> {
+        let name = validate_profile_name(name)?;
+        let gpu_profiles = self.gpu_profiles.entry(id).or_default();
+
+        if gpu_profiles.profiles.contains_key(&name) {
+            return Err(format!("the profile {} already exists", name));
+        }
+
+        gpu_profiles.profiles.insert(name, config);
+
+        Ok(())
+    }
+
+    pub fn rename_profile(&mut self, id: u32, name: &str, new_name: &str) -> Result<(), String> {
+        let new_name = validate_profile_name(new_name)?;
+        let gpu_profiles = self.gpu_profiles.entry(id).or_default();
+
+        if gpu_profiles.profiles.contains_key(&new_name) {
+            return Err(format!("the profile {} already exists", new_name));
+        }
+
+        let config = gpu_profiles
+            .profiles
+            .remove(name)
+            .ok_or_else(|| format!("there is no profile named {}", name))?;
+        gpu_profiles.profiles.insert(new_name.clone(), config);
+
+        if gpu_profiles.active.as_deref() == Some(name) {
+            gpu_profiles.active = Some(new_name.clone());
+        }
+
+        if let Some(power_source_profiles) = self.power_source_profiles.get_mut(&id) {
+            for source in &[PowerSource::Ac, PowerSource::Battery] {
+                if power_source_profiles
+                    .get(*source)
+                    .map(|profile| profile.as_str())
+                    == Some(name)
+                {
+                    power_source_profiles.set(*source, Some(new_name.clone()));
+                }
+            }
+        }
+        for rule in &mut self.process_rules {
+            if rule.gpu_id == id && rule.profile == name {
+                rule.profile = new_name.clone();
+            }
+        }
+        for schedule in &mut self.schedules {
+            if let ScheduleAction::Profile(profile) = &mut schedule.action {
+                if schedule.gpu_id == id && profile == name {
+                    *profile = new_name.clone();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes a profile. The settings of the GPU stay as they are, even if the profile was active.
+    pub fn delete_profile(&mut self, id: u32, name: &str) -> Result<(), String> {
+        if let Some(power_source_profiles) = self.power_source_profiles.get(&id) {
+            for source in &[PowerSource::Ac, PowerSource::Battery] {
+                if power_source_profiles
+                    .get(*source)
+                    .map(|profile| profile.as_str())
+                    == Some(name)
+                {
+                    return Err(format!("the profile {} is used on {} power", name, source));
+                }
+            }
+        }
+        if self
+            .process_rules
+            .iter()
+            .any(|rule| rule.gpu_id == id && rule.profile == name)
+        {
+            return Err(format!("the profile {} is used by a process rule", name));
+        }
+        if self.schedules.iter().any(|schedule| {
+            schedule.gpu_id == id && schedule.action == ScheduleAction::Profile(name.to_string())
+        }) {
+            return Err(format!("the profile {} is used by a schedule", name));
+        }
+
+        let gpu_profiles = self.gpu_profiles.entry(id).or_default();
+
+        if gpu_profiles.profiles.remove(name).is_none() {
+            return Err(format!("there is no profile named {}", name));
+        }
+
+        if gpu_profiles.active.as_deref() == Some(name) {
+            gpu_profiles.active = None;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_profile(&self, id: u32, name: &str) -> Option<GpuConfig> {
+        self.gpu_profiles
+            .get(&id)
+            .and_then(|gpu_profiles| gpu_profiles.profiles.get(name))
+            .cloned()
+    }
+
+    /// Marks a profile as active, the settings have to be applied to the GPU separately
+    pub fn set_active_profile(&mut self, id: u32, name: &str) -> Result<(), String> {
+        let gpu_profiles = self.gpu_profiles.entry(id).or_default();
+
+        if !gpu_profiles.profiles.contains_key(name) {
+            return Err(format!("there is no profile named {}", name));
+        }
+
+        gpu_profiles.active = Some(name.to_string());
+
+        Ok(())
+    }
+
+    pub fn clear_active_profile(&mut self, id: u32) {
+        if let Some(gpu_profiles) = self.gpu_profiles.get_mut(&id) {
+            gpu_profiles.active = None;
+        }
+    }
+
+    /// Sets the profile a GPU switches to on the given power source, or stops switching with `None`
+    pub fn set_power_source_profile(
+        &mut self,
+        id: u32,
+        source: PowerSource,
+        profile: Option<String>,
+    ) -> Result<(), String> {
+        if let Some(name) = &profile {
+            if self.get_profile(id, name).is_none() {
+                return Err(format!("there is no profile named {}", name));
+            }
+        }
+
+        self.power_source_profiles
+            .entry(id)
+            .or_default()
+            .set(source, profile);
+
+        Ok(())
+    }
+
+    pub fn add_process_rule(&mut self, rule: ProcessRule) -> Result<(), String> {
+        rule.validate()?;
+
+        if self.get_profile(rule.gpu_id, &rule.profile).is_none() {
+            return Err(format!("there is no profile named {}", rule.profile));
+        }
+
+        self.process_rules.push(rule);
+
+        Ok(())
+    }
+
+    pub fn remove_process_rule(&mut self, index: usize) -> Result<ProcessRule, String> {
+        match index < self.process_rules.len() {
+            true => Ok(self.process_rules.remove(index)),
+            false => Err(format!("there is no process rule #{}", index)),
+        }
+    }
+
+    pub fn create_group(&mut self, group: GpuGroup) -> Result<(), String> {
+        group.validate()?;
+
+        if self.groups.iter().any(|other| other.name == group.name) {
+            return Err(format!("there already is a group named {}", group.name));
+        }
+
+        self.groups.push(group);
+        Ok(())
+    }
+
+    pub fn delete_group(&mut self, name: &str) -> Result<GpuGroup, String> {
+        match self.groups.iter().position(|group| group.name == name) {
+            Some(index) => Ok(self.groups.remove(index)),
+            None => Err(format!("there is no group named {}", name)),
+        }
+    }
+
+    pub fn get_group_mut(&mut self, name: &str) -> Result<&mut GpuGroup, String> {
+        self.groups
+            .iter_mut()
+            .find(|group| group.name == name)
+            .ok_or_else(|| format!("there is no group named {}", name))
+    }
+
+    pub fn add_hook(&mut self, hook: Hook) -> Result<(), String> {
+        hook.validate()?;
+        self.hooks.push(hook);
+        Ok(())
+    }
+
+    pub fn remove_hook(&mut self, index: usize) -> Result<Hook, String> {
+        match index < self.hooks.len() {
+            true => Ok(self.hooks.remove(index)),
+            false => Err(format!("there is no hook #{}", index)),
+        }
+    }
+
+    /// Adds a schedule, or replaces the one at the given index
+    pub fn set_schedule(&mut self, index: Option<usize>, schedule: Schedule) -> Result<(), String> {
+        schedule.validate()?;
+
+        if let ScheduleAction::Profile(name) = &schedule.action {
+            if self.get_profile(schedule.gpu_id, name).is_none() {
+                return Err(format!("there is no profile named {}", name));
+            }
+        }
+
+        match index {
+            Some(index) => match self.schedules.get_mut(index) {
+                Some(current) => *current = schedule,
+                None => return Err(format!("there is no schedule #{}", index)),
+            },
+            None => self.schedules.push(schedule),
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_schedule(&mut self, index: usize) -> Result<Schedule, String> {
+        match index < self.schedules.len() {
+            true => Ok(self.schedules.remove(index)),
+            false => Err(format!("there is no schedule #{}", index)),
+        }
+    }
+
+    /// Writes the config without ever leaving a partially written one behind,
+    /// keeping the previous one as a backup
     pub fn save(&self) -> Result<(), ConfigError> {
+        if let Some(e) = &self.load_error {
+            return Err(ConfigError::NotLoaded(e.clone()));
+        }
+
         let json = serde_json::to_string_pretty(self)?;
-        log::info!("saving {}", json.to_string());
 
-        Ok(fs::write(&self.config_path, &json.to_string())?)
+        if fs::read_to_string(&self.config_path).map_or(false, |current| current == json) {
+            return Ok(());
+        }
+
+        log::debug!(
+            "Saving the config to {}",
+            self.config_path.to_string_lossy()
+        );
+
+        if let Err(e) = back_up(&self.config_path, false) {
+            log::warn!("Failed to back up the previous config: {}", e);
+        }
+
+        // A crash while writing leaves the temporary file behind instead of a broken config
+        let temp_path = sibling_path(&self.config_path, ".tmp");
+        {
+            let mut file = fs::File::create(&temp_path)?;
+            file.write_all(json.as_bytes())?;
+
+            if let Ok(metadata) = fs::metadata(&self.config_path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+
+            file.sync_all()?;
+        }
+        fs::rename(&temp_path, &self.config_path)?;
+
+        // The rename is only on the disk once the directory is
+        if let Some(dir) = self.config_path.parent() {
+            if let Err(e) = fs::File::open(dir).and_then(|dir| dir.sync_all()) {
+                log::warn!("Failed to sync {}: {}", dir.to_string_lossy(), e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The previous configs kept next to the given one, newest first
+    pub fn list_backups(path: &Path) -> Vec<ConfigBackup> {
+        (1..=MAX_BACKUPS)
+            .filter_map(|index| {
+                let backup_path = sibling_path(path, &format!(".{}", index));
+                let created = fs::metadata(&backup_path).and_then(|m| m.modified()).ok()?;
+
+                Some(ConfigBackup {
+                    index,
+                    created: created
+                        .duration_since(UNIX_EPOCH)
+                        .map(|created| created.as_secs())
+                        .unwrap_or_default(),
+                    error: Config::read_from_file(&backup_path)
+                        .err()
+                        .map(|e| e.to_string()),
+                })
+            })
+            .collect()
+    }
+
+    /// Reads a previous config without touching the current one
+    pub fn read_backup(path: &Path, index: usize) -> Result<Self, ConfigError> {
+        if index == 0 || index > MAX_BACKUPS {
+            return Err(ConfigError::IoError(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("there is no backup {}", index),
+            )));
+        }
+
+        let mut config = Config::read_from_file(&sibling_path(path, &format!(".{}", index)))?;
+        config.config_path = path.to_path_buf();
+
+        Ok(config)
+    }
+
+    /// Reads a previous config, backing up the current one so that restoring it can be undone
+    pub fn restore_backup(path: &Path, index: usize) -> Result<Self, ConfigError> {
+        let config = Config::read_backup(path, index)?;
+
+        back_up(path, true)?;
+        Ok(config)
+    }
+}
+
+/// `lact.json` with a suffix, like `lact.json.1`
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Copies the config to `lact.json.1`, moving the older backups up by one and dropping the oldest.
+/// Unless forced, nothing happens while the newest backup is recent.
+fn back_up(path: &Path, force: bool) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let newest = sibling_path(path, ".1");
+
+    if !force {
+        if let Ok(created) = fs::metadata(&newest).and_then(|m| m.modified()) {
+            if created
+                .elapsed()
+                .map_or(false, |elapsed| elapsed < BACKUP_INTERVAL)
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    for index in (1..MAX_BACKUPS).rev() {
+        let backup_path = sibling_path(path, &format!(".{}", index));
+
+        if backup_path.exists() {
+            fs::rename(&backup_path, sibling_path(path, &format!(".{}", index + 1)))?;
+        }
+    }
+
+    fs::copy(path, &newest)?;
+    Ok(())
+}
+
+/// Adds the fields missing in `object` from `defaults`
+fn fill_missing(object: &mut Value, defaults: &Value) -> Result<(), String> {
+    match (object.as_object_mut(), defaults.as_object()) {
+        (Some(object), Some(defaults)) => {
+            for (key, value) in defaults {
+                if !object.contains_key(key) {
+                    object.insert(key.clone(), value.clone());
+                }
+            }
+            Ok(())
+        }
+        _ => Err(format!("expected an object, found {}", object)),
+    }
+}
+
+/// The defaults of layout 1. Spelled out, as the defaults of later versions may change
+/// while the migration has to produce the same config.
+fn v1_defaults(path: &Path) -> (Value, Value) {
+    let defaults = json!({
+        "gpu_configs": {},
+        "gpu_profiles": {},
+        "power_source_profiles": {},
+        "groups": [],
+        "hooks": [],
+        "process_rules": [],
+        "schedules": [],
+        "allow_online_update": null,
+        "fan_control_interval": null,
+        "config_path": path,
+        "group": "wheel"
+    });
+
+    let gpu_defaults = json!({
+        "fan_control_enabled": false,
+        "fan_curve": {"20": 0.0, "40": 0.0, "60": 50.0, "80": 80.0, "100": 100.0},
+        "fan_curve_options": {"interpolation": "Linear", "non_decreasing": true, "unit": "Percent"},
+        "fan_smoothing": {
+            "hysteresis": 2,
+            "ramp_up_rate": 0.0,
+            "ramp_down_rate": 5.0,
+            "temp_average_window": 3,
+            "spin_down_delay": 5
+        },
+        "fan_temp_source": "Edge",
+        "fan_limits": {
+            "min_speed": 0.0,
+            "max_speed": 100.0,
+            "zero_rpm": false,
+            "zero_rpm_threshold": 50,
+            "kick_speed": 50.0,
+            "kick_duration": 2,
+            "failsafe": "Automatic"
+        },
+        "fan_mode": "Curve",
+        "fan_calibration": null,
+        "fan_health": {
+            "enabled": true,
+            "stall_time": 10,
+            "underspeed_tolerance": 0.3,
+            "underspeed_time": 30,
+            "wear_threshold": 0.85,
+            "action": {"stop_fan_control": true, "power_cap": null}
+        },
+        "power_cap": -1,
+        "power_guard": {
+            "enabled": false,
+            "max_temp": 90,
+            "max_fan_speed": null,
+            "hysteresis": 5,
+            "step": 10,
+            "step_interval": 5,
+            "restore_step": 5,
+            "restore_interval": 30
+        },
+        "idle": {
+            "enabled": false,
+            "idle_threshold": 5,
+            "busy_threshold": 20,
+            "idle_time": 300,
+            "power_cap": null,
+            "force_low": true,
+            "fan_curve": null
+        },
+        "script": null,
+        "power_profile": "Auto",
+        "gpu_max_clock": 0,
+        "gpu_max_voltage": null,
+        "vram_max_clock": 0,
+        "vddc_curve": {},
+        "gpu_min_clock": null,
+        "vram_min_clock": null,
+        "voltage_offset": null
+    });
+
+    (defaults, gpu_defaults)
+}
+
+/// Configs from before the layout had a version, where fields without a default could be missing
+fn migrate_unversioned(config: &mut Map<String, Value>, path: &Path) -> Result<(), String> {
+    let (defaults, gpu_defaults) = v1_defaults(path);
+
+    for (key, value) in defaults.as_object().unwrap() {
+        if !config.contains_key(key) {
+            config.insert(key.clone(), value.clone());
+        }
+    }
+
+    let gpu_configs = config
+        .get_mut("gpu_configs")
+        .and_then(|gpu_configs| gpu_configs.as_object_mut())
+        .ok_or("gpu_configs isn't a map")?;
+
+    for (id, entry) in gpu_configs.iter_mut() {
+        match entry.get_mut(1) {
+            Some(gpu_config) => {
+                fill_missing(gpu_config, &gpu_defaults).map_err(|e| format!("GPU {}: {}", id, e))?
+            }
+            None => return Err(format!("GPU {} has no settings", id)),
+        }
+    }
+
+    if let Some(gpu_profiles) = config
+        .get_mut("gpu_profiles")
+        .and_then(|gpu_profiles| gpu_profiles.as_object_mut())
+    {
+        for (id, gpu_profiles) in gpu_profiles.iter_mut() {
+            if let Some(profiles) = gpu_profiles
+                .get_mut("profiles")
+                .and_then(|profiles| profiles.as_object_mut())
+            {
+                for (name, profile) in profiles.iter_mut() {
+                    fill_missing(profile, &gpu_defaults)
+                        .map_err(|e| format!("profile {} of GPU {}: {}", name, id, e))?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_profile_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+
+    match name.is_empty() {
+        true => Err("the profile name can't be empty".to_string()),
+        false => Ok(name.to_string()),
     }
 }
 
-/*#[cfg(test)]
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::process_watcher::ProcessMatcher;
+
+    fn config() -> Config {
+        let mut config = Config::new(&PathBuf::from("/tmp/lact-test-config.json"));
+        config.gpu_configs.insert(
+            1,
+            (
+                GpuIdentifier {
+                    pci_id: String::from("0000:03:00.0"),
+                    card_model: None,
+                    gpu_model: None,
+                    path: PathBuf::from("/sys/class/drm/card0/device"),
+                },
+                GpuConfig::new(),
+            ),
+        );
+        config
+    }
+
+    #[test]
+    fn profiles_follow_gpu_settings() {
+        let mut config = config();
+        let identifier = config.gpu_configs[&1].0.clone();
+
+        config.create_profile(1, " quiet ").unwrap();
+        assert_eq!(config.gpu_profiles[&1].active.as_deref(), Some("quiet"));
+        assert!(config.create_profile(1, "quiet").is_err());
+        assert!(config.create_profile(1, "").is_err());
+
+        // Changes end up in the active profile only
+        let mut gpu_config = GpuConfig::new();
+        gpu_config.power_cap = 150;
+        config.set_gpu_config(1, identifier.clone(), gpu_config);
+        config.create_profile(1, "gaming").unwrap();
+
+        let mut gpu_config = GpuConfig::new();
+        gpu_config.power_cap = 250;
+        config.set_gpu_config(1, identifier, gpu_config);
+
+        assert_eq!(config.get_profile(1, "quiet").unwrap().power_cap, 150);
+        assert_eq!(config.get_profile(1, "gaming").unwrap().power_cap, 250);
+    }
+
+    #[test]
+    fn profiles_can_be_renamed_and_deleted() {
+        let mut config = config();
+
+        config.create_profile(1, "quiet").unwrap();
+        config.create_profile(1, "gaming").unwrap();
+
+        assert!(config.rename_profile(1, "quiet", "gaming").is_err());
+        config.rename_profile(1, "gaming", "fast").unwrap();
+        assert_eq!(config.gpu_profiles[&1].active.as_deref(), Some("fast"));
+
+        config.set_active_profile(1, "quiet").unwrap();
+        assert!(config.set_active_profile(1, "gaming").is_err());
+
+        config.delete_profile(1, "quiet").unwrap();
+        assert_eq!(config.gpu_profiles[&1].active, None);
+        assert!(config.delete_profile(1, "quiet").is_err());
+        assert!(config.get_profile(1, "fast").is_some());
+    }
+
+    #[test]
+    fn process_rules_follow_profiles() {
+        let mut config = config();
+        let rule = |profile: &str| ProcessRule {
+            gpu_id: 1,
+            matcher: ProcessMatcher::Exe("game".to_string()),
+            profile: profile.to_string(),
+        };
+
+        assert!(config.add_process_rule(rule("gaming")).is_err());
+        config.create_profile(1, "gaming").unwrap();
+        config.add_process_rule(rule("gaming")).unwrap();
+
+        config.rename_profile(1, "gaming", "fast").unwrap();
+        assert_eq!(config.process_rules[0].profile, "fast");
+
+        // Rules can't be left pointing at nothing
+        assert!(config.delete_profile(1, "fast").is_err());
+        assert_eq!(config.remove_process_rule(0).unwrap(), rule("fast"));
+        assert!(config.remove_process_rule(0).is_err());
+        config.delete_profile(1, "fast").unwrap();
+    }
+
+    #[test]
+    fn schedules_follow_profiles() {
+        let mut config = config();
+        let schedule = |action: ScheduleAction| Schedule {
+            gpu_id: 1,
+            hour: 22,
+            minute: 0,
+            weekdays: Vec::new(),
+            action,
+        };
+
+        assert!(config
+            .set_schedule(None, schedule(ScheduleAction::Profile("quiet".to_string())))
+            .is_err());
+        config.create_profile(1, "quiet").unwrap();
+        config
+            .set_schedule(None, schedule(ScheduleAction::Profile("quiet".to_string())))
+            .unwrap();
+        assert!(config.delete_profile(1, "quiet").is_err());
+
+        config.rename_profile(1, "quiet", "night").unwrap();
+        assert_eq!(
+            config.schedules[0].action,
+            ScheduleAction::Profile("night".to_string())
+        );
+
+        config
+            .set_schedule(Some(0), schedule(ScheduleAction::PowerCap(120)))
+            .unwrap();
+        assert!(config
+            .set_schedule(Some(1), schedule(ScheduleAction::PowerCap(120)))
+            .is_err());
+        config.delete_profile(1, "night").unwrap();
+    }
+
+    fn read_config(name: &str, json: &str) -> Result<Config, ConfigError> {
+        let path =
+            std::env::temp_dir().join(format!("lact-config-{}-{}.json", name, std::process::id()));
+        fs::write(&path, json).unwrap();
+
+        let config = Config::read_from_file(&path);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn migrates_unversioned_configs() {
+        // A config of the first versions, which didn't have power profiles and clocks
+        let config = read_config(
+            "unversioned",
+            r#"{
+                "gpu_configs": {
+                    "1": [
+                        {"pci_id": "0000:03:00.0", "card_model": null, "gpu_model": null, "path": "/sys/class/drm/card0/device"},
+                        {"fan_control_enabled": true, "fan_curve": {"40": 30.0, "80": 100.0}, "power_cap": 150}
+                    ]
+                },
+                "allow_online_update": null,
+                "config_path": "/etc/lact.json"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.group, "wheel");
+
+        let gpu_config = &config.gpu_configs[&1].1;
+        assert!(gpu_config.fan_control_enabled);
+        assert_eq!(gpu_config.fan_curve.len(), 2);
+        assert_eq!(gpu_config.power_cap, 150);
+        assert_eq!(gpu_config.power_profile, PowerProfile::Auto);
+        assert_eq!(gpu_config.gpu_max_clock, 0);
+    }
+
+    #[test]
+    fn v1_defaults_are_complete() {
+        let path = PathBuf::from("/etc/lact.json");
+        let (defaults, gpu_defaults) = v1_defaults(&path);
+
+        let config: Config = serde_json::from_value(defaults).unwrap();
+        assert_eq!(config.config_path, path);
+        serde_json::from_value::<GpuConfig>(gpu_defaults).unwrap();
+    }
+
+    #[test]
+    fn ignores_unknown_fields() {
+        let mut json = serde_json::to_value(config()).unwrap();
+        json["from_the_future"] = true.into();
+        json["gpu_configs"]["1"][1]["fan_wobble"] = 3.into();
+
+        let config = read_config("unknown-fields", &json.to_string()).unwrap();
+        assert_eq!(config.gpu_configs[&1].1.power_cap, -1);
+
+        assert!(read_config("broken", "{\"gpu_configs\": ").is_err());
+        assert!(read_config("not-an-object", "[]").is_err());
+    }
 
     #[test]
-    fn write_config() -> Result<(), ConfigError> {
-        let c = Config::new();
-        c.save(PathBuf::from("/tmp/config.json"))
+    fn keeps_backups_of_previous_configs() {
+        let dir = std::env::temp_dir().join(format!("lact-config-backups-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lact.json");
+
+        let mut config = Config::new(&path);
+        config.save().unwrap();
+        assert!(Config::list_backups(&path).is_empty());
+
+        config.fan_control_interval = Some(500);
+        config.save().unwrap();
+        config.fan_control_interval = Some(1000);
+        config.save().unwrap();
+
+        // Saves in quick succession share the backup of what was there before
+        let backups = Config::list_backups(&path);
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].error, None);
+        assert!(!dir.join("lact.json.tmp").exists());
+
+        let restored = Config::restore_backup(&path, 1).unwrap();
+        assert_eq!(restored.fan_control_interval, None);
+        assert_eq!(restored.config_path, path);
+        assert!(Config::restore_backup(&path, 3).is_err());
+
+        // Restoring can be undone
+        assert_eq!(Config::list_backups(&path).len(), 2);
+        assert_eq!(
+            Config::restore_backup(&path, 1)
+                .unwrap()
+                .fan_control_interval,
+            Some(1000)
+        );
+
+        fs::write(dir.join("lact.json.3"), "{").unwrap();
+        assert!(Config::list_backups(&path)[2].error.is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
     }
-}*/
+
+    #[test]
+    fn keeps_configs_that_failed_to_load() {
+        let path =
+            std::env::temp_dir().join(format!("lact-config-broken-{}.json", std::process::id()));
+        fs::write(&path, "{\"gpu_configs\": ").unwrap();
+
+        let mut config = Config::new(&path);
+        config.load_error = Some(Config::read_from_file(&path).unwrap_err().to_string());
+
+        assert!(matches!(config.save(), Err(ConfigError::NotLoaded(_))));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"gpu_configs\": ");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resets_invalid_settings() {
+        let mut config = config();
+        let gpu_config = &mut config.gpu_configs.get_mut(&1).unwrap().1;
+        gpu_config.fan_curve.insert(60, 150.0);
+        gpu_config.fan_limits.min_speed = 80.0;
+        gpu_config.fan_limits.max_speed = 40.0;
+        gpu_config.power_cap = 0;
+        gpu_config.vram_max_clock = 1000;
+        config.fan_control_interval = Some(0);
+
+        assert_eq!(config.validate().len(), 4);
+
+        let gpu_config = &config.gpu_configs[&1].1;
+        assert_eq!(gpu_config.fan_curve, GpuConfig::new().fan_curve);
+        assert_eq!(gpu_config.fan_limits.max_speed, 100.0);
+        assert_eq!(gpu_config.power_cap, -1);
+        assert_eq!(gpu_config.vram_max_clock, 1000);
+        assert_eq!(config.fan_control_interval, None);
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn power_source_profiles_follow_profiles() {
+        let mut config = config();
+
+        assert!(config
+            .set_power_source_profile(1, PowerSource::Battery, Some("saving".to_string()))
+            .is_err());
+        config.create_profile(1, "saving").unwrap();
+        config
+            .set_power_source_profile(1, PowerSource::Battery, Some("saving".to_string()))
+            .unwrap();
+        assert!(config.delete_profile(1, "saving").is_err());
+
+        config.rename_profile(1, "saving", "battery").unwrap();
+        assert_eq!(
+            config.power_source_profiles[&1].get(PowerSource::Battery),
+            Some(&"battery".to_string())
+        );
+        assert_eq!(config.power_source_profiles[&1].get(PowerSource::Ac), None);
+
+        config
+            .set_power_source_profile(1, PowerSource::Battery, None)
+            .unwrap();
+        config.delete_profile(1, "battery").unwrap();
+    }
+}
@@ -1,65 +1,92 @@
-use crate::config::Config;
-use crate::gpu_controller::{FanControlInfo, GpuStats};
+use crate::config::{Config, ConfigBackup};
+use crate::export::SettingsExport;
+use crate::fan_control::{FanCurveOptions, FanLimits, FanMode, FanSmoothing, FanTempSource};
+use crate::fan_health::FanHealthSettings;
+use crate::gpu_controller::{ClocksSettings, FanControlInfo, GpuStats};
 use crate::gpu_controller::{GpuInfo, PowerProfile};
-use crate::Daemon;
+use crate::group::{GpuGroup, GroupSetting};
+use crate::hooks::{Hook, HookResult};
+use crate::idle::IdleSettings;
+use crate::power_guard::PowerGuardSettings;
+use crate::power_source::PowerSource;
+use crate::process_watcher::{ProcessRule, ProcessRuleMatch};
+use crate::schedule::Schedule;
 use crate::DaemonError;
 use crate::{Action, DaemonResponse, SOCK_PATH};
+use nix::errno::Errno;
 use std::collections::{BTreeMap, HashMap};
+use std::os::unix::io::RawFd;
 
 #[derive(Clone, Copy)]
 pub struct DaemonConnection {}
 
-pub const BUFFER_SIZE: usize = 4096;
+/// Largest message accepted from the socket, so a bogus length can't make us allocate gigabytes
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
 
-impl DaemonConnection {
-    pub fn new() -> Result<Self, DaemonError> {
-        let addr = nix::sys::socket::SockAddr::Unix(
-            nix::sys::socket::UnixAddr::new_abstract(SOCK_PATH.as_bytes()).unwrap(),
-        );
-        let socket = nix::sys::socket::socket(
-            nix::sys::socket::AddressFamily::Unix,
-            nix::sys::socket::SockType::Stream,
-            nix::sys::socket::SockFlag::empty(),
-            None,
-        )
-        .expect("Creating socket failed");
-        nix::sys::socket::connect(socket, &addr).expect("Socket connect failed");
-
-        nix::unistd::write(socket, &bincode::serialize(&Action::CheckAlive).unwrap())
-            .expect("Writing check alive to socket failed");
-
-        nix::sys::socket::shutdown(socket, nix::sys::socket::Shutdown::Write)
-            .expect("Could not shut down");
-
-        let mut buffer = Vec::<u8>::new();
-        buffer.resize(BUFFER_SIZE, 0);
-        loop {
-            match nix::unistd::read(socket, &mut buffer) {
-                Ok(0) => {
-                    break;
-                }
-                Ok(n) => {
-                    assert!(n < buffer.len());
-                    if n < buffer.len() {
-                        buffer.resize(n, 0);
-                    }
-                    break;
-                }
-                Err(e) => {
-                    panic!("Error reading from socket: {}", e);
-                }
-            }
+/// Writes a message prefixed with its length, so the other side knows when it has all of it
+pub fn write_message(socket: RawFd, message: &[u8]) -> nix::Result<()> {
+    let mut buffer = (message.len() as u64).to_le_bytes().to_vec();
+    buffer.extend_from_slice(message);
+
+    let mut written = 0;
+    while written < buffer.len() {
+        match nix::unistd::write(socket, &buffer[written..]) {
+            Ok(n) => written += n,
+            Err(nix::Error::Sys(Errno::EINTR)) => (),
+            Err(e) => return Err(e),
         }
-        let result: Result<DaemonResponse, DaemonResponse> =
-            bincode::deserialize(&buffer).expect("failed to deserialize message");
+    }
 
-        match result {
+    Ok(())
+}
+
+/// Reads a message written by `write_message`
+pub fn read_message(socket: RawFd) -> nix::Result<Vec<u8>> {
+    let mut header = [0; 8];
+    read_exact(socket, &mut header)?;
+
+    let len = u64::from_le_bytes(header) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(nix::Error::Sys(Errno::EMSGSIZE));
+    }
+
+    log::trace!("Reading message of {} bytes", len);
+
+    let mut buffer = vec![0; len];
+    read_exact(socket, &mut buffer)?;
+
+    Ok(buffer)
+}
+
+fn read_exact(socket: RawFd, buffer: &mut [u8]) -> nix::Result<()> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match nix::unistd::read(socket, &mut buffer[read..]) {
+            // The other side hung up halfway through the message
+            Ok(0) => return Err(nix::Error::Sys(Errno::ECONNRESET)),
+            Ok(n) => read += n,
+            Err(nix::Error::Sys(Errno::EINTR)) => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+impl DaemonConnection {
+    pub fn new() -> Result<Self, DaemonError> {
+        match Self::exchange(&Action::CheckAlive)? {
             Ok(_) => Ok(DaemonConnection {}),
             Err(_) => Err(DaemonError::ConnectionFailed),
         }
     }
 
     fn send_action(&self, action: Action) -> Result<DaemonResponse, DaemonError> {
+        Self::exchange(&action)?
+    }
+
+    /// Sends an action to the daemon and reads back its response
+    fn exchange(action: &Action) -> Result<Result<DaemonResponse, DaemonError>, DaemonError> {
         let addr = nix::sys::socket::SockAddr::Unix(
             nix::sys::socket::UnixAddr::new_abstract(SOCK_PATH.as_bytes()).unwrap(),
         );
@@ -69,18 +96,24 @@ impl DaemonConnection {
             nix::sys::socket::SockFlag::empty(),
             None,
         )
-        .expect("Socket failed");
-        nix::sys::socket::connect(socket, &addr).expect("connect failed");
-
-        let b = bincode::serialize(&action).unwrap();
-        nix::unistd::write(socket, &b).expect("Writing action to socket failed");
+        .map_err(|_| DaemonError::ConnectionFailed)?;
 
-        nix::sys::socket::shutdown(socket, nix::sys::socket::Shutdown::Write)
-            .expect("Could not shut down");
+        let result = nix::sys::socket::connect(socket, &addr)
+            .and_then(|_| write_message(socket, &bincode::serialize(action).unwrap()))
+            .and_then(|_| read_message(socket));
 
-        let buffer = Daemon::read_buffer(socket);
+        let _ = nix::unistd::close(socket);
 
-        bincode::deserialize(&buffer).expect("failed to deserialize message")
+        match result {
+            Ok(buffer) => bincode::deserialize(&buffer).map_err(|e| {
+                log::error!("Failed to deserialize the response: {}", e);
+                DaemonError::ConnectionFailed
+            }),
+            Err(e) => {
+                log::error!("Failed to talk to the daemon: {}", e);
+                Err(DaemonError::ConnectionFailed)
+            }
+        }
     }
 
     pub fn get_gpu_stats(&self, gpu_id: u32) -> Result<GpuStats, DaemonError> {
@@ -118,8 +151,68 @@ impl DaemonConnection {
         }
     }
 
-    pub fn set_fan_curve(&self, gpu_id: u32, curve: BTreeMap<i64, f64>) -> Result<(), DaemonError> {
-        match self.send_action(Action::SetFanCurve(gpu_id, curve))? {
+    /// Starts calibrating the fan in the background, the progress is reported in the GPU stats
+    pub fn start_fan_calibration(&self, gpu_id: u32) -> Result<(), DaemonError> {
+        match self.send_action(Action::StartFanCalibration(gpu_id))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_fan_curve(
+        &self,
+        gpu_id: u32,
+        curve: BTreeMap<i64, f64>,
+        options: FanCurveOptions,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetFanCurve(gpu_id, curve, options))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_fan_smoothing(
+        &self,
+        gpu_id: u32,
+        smoothing: FanSmoothing,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetFanSmoothing(gpu_id, smoothing))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_fan_temp_source(
+        &self,
+        gpu_id: u32,
+        source: FanTempSource,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetFanTempSource(gpu_id, source))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_fan_limits(&self, gpu_id: u32, limits: FanLimits) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetFanLimits(gpu_id, limits))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_fan_health_settings(
+        &self,
+        gpu_id: u32,
+        settings: FanHealthSettings,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetFanHealthSettings(gpu_id, settings))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_fan_mode(&self, gpu_id: u32, mode: FanMode) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetFanMode(gpu_id, mode))? {
             DaemonResponse::OK => Ok(()),
             _ => unreachable!(),
         }
@@ -132,6 +225,28 @@ impl DaemonConnection {
         }
     }
 
+    pub fn set_power_guard(
+        &self,
+        gpu_id: u32,
+        settings: PowerGuardSettings,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetPowerGuard(gpu_id, settings))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_idle_settings(
+        &self,
+        gpu_id: u32,
+        settings: IdleSettings,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetIdleSettings(gpu_id, settings))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
     pub fn set_power_profile(&self, gpu_id: u32, profile: PowerProfile) -> Result<(), DaemonError> {
         match self.send_action(Action::SetPowerProfile(gpu_id, profile))? {
             DaemonResponse::OK => Ok(()),
@@ -158,6 +273,19 @@ impl DaemonConnection {
         }
     }
 
+    pub fn set_vddc_curve_point(
+        &self,
+        gpu_id: u32,
+        num: u32,
+        clockspeed: i64,
+        voltage: i64,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetVddcCurvePoint(gpu_id, num, clockspeed, voltage))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
     pub fn set_vram_max_clock(&self, gpu_id: u32, clockspeed: i64) -> Result<(), DaemonError> {
         match self.send_action(Action::SetVRAMMaxClock(gpu_id, clockspeed))? {
             DaemonResponse::OK => Ok(()),
@@ -165,6 +293,34 @@ impl DaemonConnection {
         }
     }
 
+    pub fn set_gpu_min_clock(&self, gpu_id: u32, clockspeed: i64) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetGPUMinClock(gpu_id, clockspeed))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_vram_min_clock(&self, gpu_id: u32, clockspeed: i64) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetVRAMMinClock(gpu_id, clockspeed))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_voltage_offset(&self, gpu_id: u32, offset: i64) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetVoltageOffset(gpu_id, offset))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn apply_clocks(&self, gpu_id: u32, settings: ClocksSettings) -> Result<(), DaemonError> {
+        match self.send_action(Action::ApplyClocks(gpu_id, settings))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
     pub fn commit_gpu_power_states(&self, gpu_id: u32) -> Result<(), DaemonError> {
         match self.send_action(Action::CommitGPUPowerStates(gpu_id))? {
             DaemonResponse::OK => Ok(()),
@@ -179,6 +335,33 @@ impl DaemonConnection {
         }
     }
 
+    pub fn set_od_fan_value(&self, gpu_id: u32, name: &str, value: i64) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetOdFanValue(gpu_id, name.to_string(), value))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_od_fan_curve_point(
+        &self,
+        gpu_id: u32,
+        num: u32,
+        temperature: i64,
+        speed: i64,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetOdFanCurvePoint(gpu_id, num, temperature, speed))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn reset_od_fan_controls(&self, gpu_id: u32) -> Result<(), DaemonError> {
+        match self.send_action(Action::ResetOdFanControls(gpu_id))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
     pub fn get_gpus(&self) -> Result<HashMap<u32, Option<String>>, DaemonError> {
         match self.send_action(Action::GetGpus)? {
             DaemonResponse::Gpus(gpus) => Ok(gpus),
@@ -198,8 +381,9 @@ impl DaemonConnection {
         )
         .expect("Socket failed");
         nix::sys::socket::connect(socket, &addr).expect("connect failed");
-        nix::unistd::write(socket, &mut &bincode::serialize(&Action::Shutdown).unwrap())
+        write_message(socket, &bincode::serialize(&Action::Shutdown).unwrap())
             .expect("Writing shutdown to socket failed");
+        let _ = nix::unistd::close(socket);
     }
 
     pub fn get_config(&self) -> Result<Config, DaemonError> {
@@ -215,4 +399,319 @@ impl DaemonConnection {
             _ => unreachable!(),
         }
     }
+
+    /// Why the config file couldn't be loaded, in which case the daemon doesn't save its changes
+    pub fn get_config_load_error(&self) -> Result<Option<String>, DaemonError> {
+        match self.send_action(Action::GetConfigLoadError)? {
+            DaemonResponse::ConfigLoadError(error) => Ok(error),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The previous configs, newest first
+    pub fn get_config_backups(&self) -> Result<Vec<ConfigBackup>, DaemonError> {
+        match self.send_action(Action::GetConfigBackups)? {
+            DaemonResponse::ConfigBackups(backups) => Ok(backups),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Switches back to a previous config, the current one becomes the newest backup
+    pub fn restore_config_backup(&self, index: usize) -> Result<(), DaemonError> {
+        match self.send_action(Action::RestoreConfigBackup(index))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the profile names and the active profile
+    pub fn get_profiles(&self, gpu_id: u32) -> Result<(Vec<String>, Option<String>), DaemonError> {
+        match self.send_action(Action::GetProfiles(gpu_id))? {
+            DaemonResponse::Profiles(names, active) => Ok((names, active)),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn create_profile(&self, gpu_id: u32, name: &str) -> Result<(), DaemonError> {
+        match self.send_action(Action::CreateProfile(gpu_id, name.to_string()))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn rename_profile(
+        &self,
+        gpu_id: u32,
+        name: &str,
+        new_name: &str,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::RenameProfile(
+            gpu_id,
+            name.to_string(),
+            new_name.to_string(),
+        ))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn delete_profile(&self, gpu_id: u32, name: &str) -> Result<(), DaemonError> {
+        match self.send_action(Action::DeleteProfile(gpu_id, name.to_string()))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn activate_profile(&self, gpu_id: u32, name: &str) -> Result<(), DaemonError> {
+        match self.send_action(Action::ActivateProfile(gpu_id, name.to_string()))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn get_process_rules(
+        &self,
+    ) -> Result<(Vec<ProcessRule>, Vec<ProcessRuleMatch>), DaemonError> {
+        match self.send_action(Action::GetProcessRules)? {
+            DaemonResponse::ProcessRules(rules, matches) => Ok((rules, matches)),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn add_process_rule(&self, rule: ProcessRule) -> Result<(), DaemonError> {
+        match self.send_action(Action::AddProcessRule(rule))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn remove_process_rule(&self, index: usize) -> Result<(), DaemonError> {
+        match self.send_action(Action::RemoveProcessRule(index))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn apply_process_rules(&self) -> Result<(), DaemonError> {
+        match self.send_action(Action::ApplyProcessRules)? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_script(&self, gpu_id: u32, script: Option<String>) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetScript(gpu_id, script))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn apply_script_profiles(&self) -> Result<(), DaemonError> {
+        match self.send_action(Action::ApplyScriptProfiles)? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The current settings of a GPU, or one of its profiles, for a file that can be imported elsewhere
+    pub fn export_settings(
+        &self,
+        gpu_id: u32,
+        profile: Option<String>,
+    ) -> Result<SettingsExport, DaemonError> {
+        match self.send_action(Action::ExportSettings(gpu_id, profile))? {
+            DaemonResponse::SettingsExport(export) => Ok(export),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Applies exported settings to a GPU, or adds them as a profile if a name is given.
+    /// Fails with the problems found unless they fit the GPU or `force` is set.
+    pub fn import_settings(
+        &self,
+        gpu_id: u32,
+        export: SettingsExport,
+        profile: Option<String>,
+        force: bool,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::ImportSettings(gpu_id, export, profile, force))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// All groups with the IDs of their members
+    pub fn get_groups(&self) -> Result<Vec<(GpuGroup, Vec<u32>)>, DaemonError> {
+        match self.send_action(Action::GetGroups)? {
+            DaemonResponse::Groups(groups) => Ok(groups),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn create_group(&self, group: GpuGroup) -> Result<(), DaemonError> {
+        match self.send_action(Action::CreateGroup(group))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn delete_group(&self, name: &str) -> Result<(), DaemonError> {
+        match self.send_action(Action::DeleteGroup(name.to_string()))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Sets a setting of the whole group, or overrides it for one of its GPUs, and applies it
+    pub fn set_group_setting(
+        &self,
+        name: &str,
+        gpu_id: Option<u32>,
+        setting: GroupSetting,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetGroupSetting(name.to_string(), gpu_id, setting))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn clear_group_setting(
+        &self,
+        name: &str,
+        gpu_id: Option<u32>,
+        kind: &str,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::ClearGroupSetting(
+            name.to_string(),
+            gpu_id,
+            kind.to_string(),
+        ))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn apply_group(&self, name: &str) -> Result<(), DaemonError> {
+        match self.send_action(Action::ApplyGroup(name.to_string()))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn get_hooks(&self) -> Result<(Vec<Hook>, HashMap<usize, HookResult>), DaemonError> {
+        match self.send_action(Action::GetHooks)? {
+            DaemonResponse::Hooks(hooks, results) => Ok((hooks, results)),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn add_hook(&self, hook: Hook) -> Result<(), DaemonError> {
+        match self.send_action(Action::AddHook(hook))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn remove_hook(&self, index: usize) -> Result<(), DaemonError> {
+        match self.send_action(Action::RemoveHook(index))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn get_schedules(&self) -> Result<(Vec<Schedule>, Vec<Schedule>), DaemonError> {
+        match self.send_action(Action::GetSchedules)? {
+            DaemonResponse::Schedules(schedules, applied) => Ok((schedules, applied)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Adds a schedule, or replaces the one at the index
+    pub fn set_schedule(
+        &self,
+        index: Option<usize>,
+        schedule: Schedule,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetSchedule(index, schedule))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn remove_schedule(&self, index: usize) -> Result<(), DaemonError> {
+        match self.send_action(Action::RemoveSchedule(index))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn apply_schedules(&self) -> Result<(), DaemonError> {
+        match self.send_action(Action::ApplySchedules)? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_power_source_profile(
+        &self,
+        gpu_id: u32,
+        source: PowerSource,
+        profile: Option<String>,
+    ) -> Result<(), DaemonError> {
+        match self.send_action(Action::SetPowerSourceProfile(gpu_id, source, profile))? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn apply_power_source(&self) -> Result<(), DaemonError> {
+        match self.send_action(Action::ApplyPowerSource)? {
+            DaemonResponse::OK => Ok(()),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
+    use std::thread;
+
+    #[test]
+    fn transfers_messages_larger_than_a_single_read() {
+        let (left, right) = socketpair(
+            AddressFamily::Unix,
+            SockType::Stream,
+            None,
+            SockFlag::empty(),
+        )
+        .unwrap();
+
+        let message: Vec<u8> = (0..1_000_000).map(|i| i as u8).collect();
+        let sent = message.clone();
+
+        let writer = thread::spawn(move || write_message(left, &sent).unwrap());
+
+        assert_eq!(read_message(right).unwrap(), message);
+
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn fails_on_truncated_messages() {
+        let (left, right) = socketpair(
+            AddressFamily::Unix,
+            SockType::Stream,
+            None,
+            SockFlag::empty(),
+        )
+        .unwrap();
+
+        nix::unistd::write(left, &100u64.to_le_bytes()).unwrap();
+        nix::unistd::write(left, &[1, 2, 3]).unwrap();
+        nix::unistd::close(left).unwrap();
+
+        assert!(read_message(right).is_err());
+    }
 }
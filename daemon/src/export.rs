@@ -0,0 +1,477 @@
+use std::{fmt, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::GpuConfig;
+use crate::fan_control::FanSpeedUnit;
+use crate::gpu_controller::{check_range, ClocksTable, GpuControllerError, GpuInfo, OdFanControls};
+
+/// Version of the file layout, files of newer versions are rejected
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    Toml,
+}
+
+impl ExportFormat {
+    pub fn from_str(format: &str) -> Option<Self> {
+        match format.to_lowercase().as_str() {
+            "json" => Some(ExportFormat::Json),
+            "toml" => Some(ExportFormat::Toml),
+            _ => None,
+        }
+    }
+
+    /// TOML for .toml files, JSON for anything else
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("toml") => ExportFormat::Toml,
+            _ => ExportFormat::Json,
+        }
+    }
+}
+
+/// The card the settings were made on
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GpuModel {
+    pub vendor_id: String,
+    pub model_id: String,
+    pub card_vendor: Option<String>,
+    pub card_model: Option<String>,
+    pub gpu_model: Option<String>,
+    pub vbios_version: Option<String>,
+    pub vram_size: u64, // MiB
+}
+
+impl GpuModel {
+    pub fn from_info(info: &GpuInfo) -> Self {
+        GpuModel {
+            vendor_id: info.vendor_id.clone(),
+            model_id: info.model_id.clone(),
+            card_vendor: info.vendor_data.card_vendor.clone(),
+            card_model: info.vendor_data.card_model.clone(),
+            gpu_model: info.vendor_data.gpu_model.clone(),
+            vbios_version: match info.vbios_version.is_empty() {
+                true => None,
+                false => Some(info.vbios_version.clone()),
+            },
+            vram_size: info.vram_size,
+        }
+    }
+}
+
+impl fmt::Display for GpuModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(vendor) = &self.card_vendor {
+            write!(f, "{} ", vendor)?;
+        }
+
+        match self.card_model.as_ref().or(self.gpu_model.as_ref()) {
+            Some(model) => write!(f, "{} ", model)?,
+            None => write!(f, "Unknown GPU ")?,
+        }
+
+        write!(f, "({}:{})", self.vendor_id, self.model_id)
+    }
+}
+
+/// The settings of one GPU, or one of its profiles, in a file that can be taken to another machine.
+/// Unlike the config it holds nothing specific to the machine, like GPU IDs and paths.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SettingsExport {
+    pub version: u32,
+    pub model: GpuModel,
+    #[serde(default)]
+    pub profile: Option<String>, // The name of the exported profile
+    pub settings: GpuConfig,
+}
+
+impl SettingsExport {
+    pub fn new(info: &GpuInfo, profile: Option<String>, settings: &GpuConfig) -> Self {
+        let mut settings = settings.clone();
+
+        // The calibration was measured on this card's fan
+        settings.fan_calibration = None;
+
+        SettingsExport {
+            version: EXPORT_VERSION,
+            model: GpuModel::from_info(info),
+            profile,
+            settings,
+        }
+    }
+
+    pub fn to_string(&self, format: ExportFormat) -> Result<String, String> {
+        match format {
+            ExportFormat::Json => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+            ExportFormat::Toml => {
+                // TOML has neither nulls nor integer keys, and the fan curve and voltage curve have those
+                let mut value = serde_json::to_value(self).map_err(|e| e.to_string())?;
+                remove_nulls(&mut value);
+
+                let value = toml::Value::try_from(value).map_err(|e| e.to_string())?;
+                toml::to_string_pretty(&value).map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    pub fn parse(text: &str, format: ExportFormat) -> Result<Self, String> {
+        let value: serde_json::Value = match format {
+            ExportFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string())?,
+            ExportFormat::Toml => {
+                let value: toml::Value = toml::from_str(text).map_err(|e| e.to_string())?;
+                serde_json::to_value(value).map_err(|e| e.to_string())?
+            }
+        };
+
+        match value.get("version").and_then(|version| version.as_u64()) {
+            Some(version) if version > EXPORT_VERSION as u64 => {
+                return Err(format!(
+                    "the file was made by a newer version of LACT (format {})",
+                    version
+                ))
+            }
+            Some(_) => (),
+            None => return Err("the file has no format version".to_string()),
+        }
+
+        let mut export: Self = serde_json::from_value(value).map_err(|e| e.to_string())?;
+
+        // Unlike compatibility problems these can't be forced through
+        let problems = export.settings.validate();
+        if !problems.is_empty() {
+            return Err(format!("invalid settings: {}", problems.join(", ")));
+        }
+
+        Ok(export)
+    }
+
+    /// Finds what the target GPU can't do, checking the clocks against its ranges
+    /// and the power cap against its limits. An empty list means the settings can be applied.
+    pub fn check_compatibility(&self, info: &GpuInfo, fan_calibrated: bool) -> Vec<String> {
+        let settings = &self.settings;
+        let mut problems = Vec::new();
+
+        let model = GpuModel::from_info(info);
+        if model.vendor_id != self.model.vendor_id || model.model_id != self.model.model_id {
+            problems.push(format!(
+                "the settings were made for a {}, not a {}",
+                self.model, model
+            ));
+        }
+
+        if settings.power_cap > 0 {
+            match info.power_cap_max {
+                Some(max) => push_error(
+                    &mut problems,
+                    check_range(
+                        "Power cap",
+                        settings.power_cap,
+                        (info.power_cap_min.unwrap_or(0), max),
+                        "W",
+                    ),
+                ),
+                None => problems.push("the power cap can't be changed".to_string()),
+            }
+        }
+
+        if settings.fan_curve_options.unit == FanSpeedUnit::Rpm && !fan_calibrated {
+            problems.push(
+                "the fan curve is in RPM, which needs the fan to be calibrated first".to_string(),
+            );
+        }
+
+        let changes_clocks = settings.gpu_max_clock != 0
+            || settings.vram_max_clock != 0
+            || settings.gpu_min_clock.is_some()
+            || settings.vram_min_clock.is_some()
+            || settings.voltage_offset.is_some()
+            || !settings.vddc_curve.is_empty();
+
+        if changes_clocks {
+            match &info.clocks_table {
+                Some(clocks_table) => check_clocks(settings, clocks_table, &mut problems),
+                None => problems.push(
+                    "the clocks can't be changed, overclocking might not be enabled".to_string(),
+                ),
+            }
+        }
+
+        if !settings.od_fan_values.is_empty() || !settings.od_fan_curve.is_empty() {
+            match &info.od_fan_controls {
+                Some(controls) => check_od_fan_controls(settings, controls, &mut problems),
+                None => problems.push("the overdrive fan settings can't be changed".to_string()),
+            }
+        }
+
+        problems
+    }
+}
+
+fn remove_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|_, value| !value.is_null());
+            map.values_mut().for_each(remove_nulls);
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(remove_nulls),
+        _ => (),
+    }
+}
+
+fn push_error(problems: &mut Vec<String>, result: Result<(), GpuControllerError>) {
+    match result {
+        Ok(()) => (),
+        Err(GpuControllerError::InvalidValue(msg)) => problems.push(msg),
+        Err(GpuControllerError::NotSupported) => {
+            problems.push("the voltage curve isn't supported".to_string())
+        }
+        Err(e) => problems.push(format!("{:?}", e)),
+    }
+}
+
+fn check_clocks(settings: &GpuConfig, clocks_table: &ClocksTable, problems: &mut Vec<String>) {
+    let unsupported = |name: &str| format!("setting the {} isn't supported", name);
+
+    match clocks_table {
+        ClocksTable::Old(clocks_table) => {
+            if settings.gpu_max_clock != 0 {
+                push_error(
+                    problems,
+                    check_range(
+                        "GPU clock",
+                        settings.gpu_max_clock,
+                        clocks_table.gpu_clocks_range,
+                        "MHz",
+                    ),
+                );
+            }
+            if let Some(voltage) = settings.gpu_max_voltage {
+                push_error(
+                    problems,
+                    check_range("GPU voltage", voltage, clocks_table.voltage_range, "mV"),
+                );
+            }
+            if settings.vram_max_clock != 0 {
+                push_error(
+                    problems,
+                    check_range(
+                        "VRAM clock",
+                        settings.vram_max_clock,
+                        clocks_table.mem_clocks_range,
+                        "MHz",
+                    ),
+                );
+            }
+            if settings.gpu_min_clock.is_some() {
+                problems.push(unsupported("GPU min clock"));
+            }
+            if settings.vram_min_clock.is_some() {
+                problems.push(unsupported("VRAM min clock"));
+            }
+            if settings.voltage_offset.is_some() {
+                problems.push(unsupported("voltage offset"));
+            }
+            if !settings.vddc_curve.is_empty() {
+                problems.push(unsupported("voltage curve"));
+            }
+        }
+        ClocksTable::New(clocks_table) => {
+            if settings.gpu_max_clock != 0 {
+                push_error(
+                    problems,
+                    check_range(
+                        "GPU clock",
+                        settings.gpu_max_clock,
+                        clocks_table.gpu_clocks_range,
+                        "MHz",
+                    ),
+                );
+
+                if let Some(voltage) = settings.gpu_max_voltage {
+                    push_error(
+                        problems,
+                        clocks_table.check_vddc_curve_point(2, settings.gpu_max_clock, voltage),
+                    );
+                }
+            }
+            if let Some(clockspeed) = settings.gpu_min_clock {
+                push_error(
+                    problems,
+                    check_range(
+                        "GPU min clock",
+                        clockspeed,
+                        clocks_table.gpu_clocks_range,
+                        "MHz",
+                    ),
+                );
+            }
+            if settings.vram_max_clock != 0 {
+                push_error(
+                    problems,
+                    check_range(
+                        "VRAM clock",
+                        settings.vram_max_clock,
+                        clocks_table.mem_clocks_range,
+                        "MHz",
+                    ),
+                );
+            }
+            if let Some(clockspeed) = settings.vram_min_clock {
+                match clocks_table.current_min_mem_clock {
+                    // The default minimum is usually below the overdrive range, like when setting it
+                    Some(current) if current != clockspeed => push_error(
+                        problems,
+                        check_range(
+                            "VRAM min clock",
+                            clockspeed,
+                            clocks_table.mem_clocks_range,
+                            "MHz",
+                        ),
+                    ),
+                    Some(_) => (),
+                    None => problems.push(unsupported("VRAM min clock")),
+                }
+            }
+            if let Some(offset) = settings.voltage_offset {
+                match (
+                    clocks_table.voltage_offset,
+                    clocks_table.voltage_offset_range,
+                ) {
+                    (Some(_), Some(range)) => {
+                        push_error(problems, check_range("Voltage offset", offset, range, "mV"))
+                    }
+                    (Some(_), None) => (),
+                    (None, _) => problems.push(unsupported("voltage offset")),
+                }
+            }
+            for (num, (clockspeed, voltage)) in &settings.vddc_curve {
+                push_error(
+                    problems,
+                    clocks_table.check_vddc_curve_point(*num as usize, *clockspeed, *voltage),
+                );
+            }
+        }
+    }
+}
+
+fn check_od_fan_controls(
+    settings: &GpuConfig,
+    controls: &OdFanControls,
+    problems: &mut Vec<String>,
+) {
+    for (name, value) in &settings.od_fan_values {
+        push_error(problems, controls.check_value(name, *value));
+    }
+
+    if !settings.od_fan_curve.is_empty() && controls.curve.is_none() {
+        problems.push("setting the overdrive fan curve isn't supported".to_string());
+        return;
+    }
+
+    for (num, (temperature, speed)) in &settings.od_fan_curve {
+        push_error(
+            problems,
+            controls.check_curve_point(*num as usize, *temperature, *speed),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fan_control::FanMode;
+    use crate::gpu_controller::ClocksTableNew;
+
+    fn info() -> GpuInfo {
+        let mut info = GpuInfo::default();
+        info.vendor_id = "1002".to_string();
+        info.model_id = "73BF".to_string();
+        info.vendor_data.card_model = Some("Radeon RX 6800".to_string());
+        info.power_cap_min = Some(0);
+        info.power_cap_max = Some(250);
+        info.clocks_table = Some(ClocksTable::New(ClocksTableNew {
+            gpu_clocks_range: (500, 2800),
+            mem_clocks_range: (674, 1075),
+            voltage_offset: Some(0),
+            voltage_offset_range: Some((-450, 0)),
+            ..ClocksTableNew::default()
+        }));
+        info
+    }
+
+    fn export() -> SettingsExport {
+        let mut settings = GpuConfig::new();
+        settings.power_cap = 200;
+        settings.gpu_max_clock = 2400;
+        settings.vram_max_clock = 1050;
+        settings.voltage_offset = Some(-50);
+
+        SettingsExport::new(&info(), Some("quiet".to_string()), &settings)
+    }
+
+    #[test]
+    fn round_trips_through_both_formats() {
+        let export = export();
+
+        for format in &[ExportFormat::Json, ExportFormat::Toml] {
+            let text = export.to_string(*format).unwrap();
+            let parsed = SettingsExport::parse(&text, *format).unwrap();
+
+            assert_eq!(parsed.model, export.model);
+            assert_eq!(parsed.profile, export.profile);
+            assert_eq!(parsed.settings.fan_curve, export.settings.fan_curve);
+            assert_eq!(parsed.settings.voltage_offset, Some(-50));
+            assert_eq!(parsed.settings.gpu_max_voltage, None);
+        }
+
+        let text = export
+            .to_string(ExportFormat::Json)
+            .unwrap()
+            .replace("\"version\": 1", "\"version\": 2");
+        assert!(SettingsExport::parse(&text, ExportFormat::Json).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let mut export = export();
+        export.settings.fan_mode = FanMode::Static(500.0);
+
+        let text = export.to_string(ExportFormat::Json).unwrap();
+        let err = SettingsExport::parse(&text, ExportFormat::Json).unwrap_err();
+        assert!(err.starts_with("invalid settings: "));
+        assert!(err.contains("500%"));
+    }
+
+    #[test]
+    fn checks_compatibility() {
+        let mut export = export();
+        assert!(export.check_compatibility(&info(), false).is_empty());
+
+        export.settings.power_cap = 300;
+        export.settings.gpu_max_clock = 3000;
+        export.settings.vram_min_clock = Some(100);
+        export.model.model_id = "744C".to_string();
+
+        assert_eq!(
+            export.check_compatibility(&info(), false),
+            vec![
+                "the settings were made for a Radeon RX 6800 (1002:744C), not a Radeon RX 6800 (1002:73BF)",
+                "Power cap 300W is outside of the allowed range 0-250W",
+                "GPU clock 3000MHz is outside of the allowed range 500-2800MHz",
+                "setting the VRAM min clock isn't supported",
+            ]
+        );
+
+        let mut info = info();
+        info.clocks_table = None;
+        export.settings.power_cap = -1;
+        export.model.model_id = "73BF".to_string();
+        assert_eq!(
+            export.check_compatibility(&info, false),
+            vec!["the clocks can't be changed, overclocking might not be enabled"]
+        );
+    }
+}
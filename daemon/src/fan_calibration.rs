@@ -0,0 +1,392 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fan_control::{curve_speed, FanCurveInterpolation, FanSpeedUnit};
+
+/// Measured relation between the PWM value and the fan speed of a cooler
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FanCalibration {
+    pub points: BTreeMap<i64, i64>, // <pwm, rpm>
+    pub start_pwm: i64,             // Lowest PWM that spins the fan up from standstill
+    pub stop_pwm: i64,              // Lowest PWM that keeps an already spinning fan going
+}
+
+impl FanCalibration {
+    pub fn max_rpm(&self) -> i64 {
+        self.points.values().copied().max().unwrap_or(0)
+    }
+
+    /// Expected fan speed for a PWM value, assuming the fan is already spinning
+    pub fn pwm_to_rpm(&self, pwm: f64) -> f64 {
+        if pwm < self.stop_pwm as f64 {
+            return 0.0;
+        }
+
+        let points: BTreeMap<i64, f64> = self
+            .points
+            .iter()
+            .map(|(pwm, rpm)| (*pwm, *rpm as f64))
+            .collect();
+
+        curve_speed(&points, pwm, FanCurveInterpolation::Linear).unwrap_or(0.0)
+    }
+
+    /// PWM value needed for a fan speed. Speeds that the fan can't run at that slowly
+    /// are mapped to the stop PWM, speeds above the measured maximum to full PWM.
+    pub fn rpm_to_pwm(&self, rpm: f64) -> f64 {
+        if rpm <= 0.0 {
+            return 0.0;
+        }
+
+        // The measured speed can dip between steps, so only the highest speed so far is used
+        let mut points: BTreeMap<i64, f64> = BTreeMap::new();
+        let mut max_rpm = 0;
+        for (pwm, point_rpm) in &self.points {
+            if *point_rpm > max_rpm {
+                max_rpm = *point_rpm;
+                points.insert(max_rpm, *pwm as f64);
+            }
+        }
+
+        curve_speed(&points, rpm, FanCurveInterpolation::Linear)
+            .map_or(255.0, |pwm| pwm.max(self.stop_pwm as f64))
+    }
+
+    pub fn percent_to_rpm(&self, percent: f64) -> f64 {
+        self.pwm_to_rpm(percent / 100.0 * 255.0)
+    }
+
+    pub fn rpm_to_percent(&self, rpm: f64) -> f64 {
+        self.rpm_to_pwm(rpm) / 255.0 * 100.0
+    }
+
+    /// Converts the speeds of a fan curve between percent and RPM
+    pub fn convert_curve(
+        &self,
+        curve: &BTreeMap<i64, f64>,
+        from: FanSpeedUnit,
+        to: FanSpeedUnit,
+    ) -> BTreeMap<i64, f64> {
+        curve
+            .iter()
+            .map(|(temp, speed)| {
+                let speed = match (from, to) {
+                    (FanSpeedUnit::Percent, FanSpeedUnit::Rpm) => {
+                        self.percent_to_rpm(*speed).round()
+                    }
+                    (FanSpeedUnit::Rpm, FanSpeedUnit::Percent) => {
+                        self.rpm_to_percent(*speed).round()
+                    }
+                    _ => *speed,
+                };
+
+                (*temp, speed)
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum FanCalibrationStatus {
+    Idle,
+    Running(f64), // Progress from 0 to 1
+    Finished(FanCalibration),
+    Failed(String),
+}
+
+impl Default for FanCalibrationStatus {
+    fn default() -> Self {
+        FanCalibrationStatus::Idle
+    }
+}
+
+impl fmt::Display for FanCalibrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FanCalibrationStatus::Idle => write!(f, "not running"),
+            FanCalibrationStatus::Running(progress) => {
+                write!(f, "running ({:.0}%)", progress * 100.0)
+            }
+            FanCalibrationStatus::Finished(calibration) => write!(
+                f,
+                "finished, up to {} RPM, starts at PWM {}, stops below PWM {}",
+                calibration.max_rpm(),
+                calibration.start_pwm,
+                calibration.stop_pwm
+            ),
+            FanCalibrationStatus::Failed(e) => write!(f, "failed: {}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CalibrationSettings {
+    pub pwm_step: i64,
+    pub sample_interval: Duration,
+    pub stable_samples: usize, // Consecutive readings that have to agree
+    pub max_samples: usize,    // Readings to take before giving up on the fan settling
+    pub tolerance: f64, // Allowed spread of the stable readings as a fraction of their average
+}
+
+impl Default for CalibrationSettings {
+    fn default() -> Self {
+        CalibrationSettings {
+            pwm_step: 16,
+            sample_interval: Duration::from_millis(500),
+            stable_samples: 4,
+            max_samples: 20,
+            tolerance: 0.03,
+        }
+    }
+}
+
+// Readings this close together are always considered stable, since the tachometer isn't more precise at low speeds
+const MIN_RPM_TOLERANCE: i64 = 30;
+
+/// The fan being calibrated
+pub trait CalibrationFan {
+    fn set_pwm(&mut self, pwm: i64) -> Result<(), String>;
+    fn read_rpm(&mut self) -> Result<i64, String>;
+    fn wait(&mut self, duration: Duration);
+}
+
+/// Steps the PWM up through its range recording the settled speed of every step,
+/// then back down to find where the fan stops. The fan is left at the last PWM value.
+pub fn calibrate<F: CalibrationFan>(
+    fan: &mut F,
+    settings: &CalibrationSettings,
+    progress: &dyn Fn(f64),
+) -> Result<FanCalibration, String> {
+    let mut steps: Vec<i64> = (0..255)
+        .step_by(settings.pwm_step.max(1) as usize)
+        .collect();
+    steps.push(255);
+
+    // The way down is at most as long as the way up
+    let total_steps = (steps.len() * 2) as f64;
+    let mut done_steps = 0;
+
+    // Make sure the fan starts from standstill, so that the start PWM can be detected
+    fan.set_pwm(0)?;
+    stable_rpm(fan, settings)?;
+
+    let mut points = BTreeMap::new();
+    let mut start_pwm = None;
+
+    for pwm in &steps {
+        fan.set_pwm(*pwm)?;
+        let rpm = stable_rpm(fan, settings)?;
+
+        if rpm > 0 && start_pwm.is_none() {
+            start_pwm = Some(*pwm);
+        }
+        points.insert(*pwm, rpm);
+
+        done_steps += 1;
+        progress(done_steps as f64 / total_steps);
+    }
+
+    let start_pwm = start_pwm.ok_or_else(|| String::from("the fan didn't spin at any speed"))?;
+
+    // Fans keep spinning below the PWM they need to start, so walk back down until it stops
+    let mut stop_pwm = 0;
+
+    for pwm in steps.iter().rev().skip(1) {
+        fan.set_pwm(*pwm)?;
+        let rpm = stable_rpm(fan, settings)?;
+
+        done_steps += 1;
+        progress(done_steps as f64 / total_steps);
+
+        if rpm == 0 {
+            break;
+        }
+        stop_pwm = *pwm;
+        // Use the speed of the spinning fan, the way up measured it stopped below the start PWM
+        points.insert(*pwm, rpm);
+    }
+
+    progress(1.0);
+
+    Ok(FanCalibration {
+        points,
+        start_pwm,
+        stop_pwm,
+    })
+}
+
+/// Waits for the fan speed to settle and returns the average of the settled readings
+fn stable_rpm<F: CalibrationFan>(
+    fan: &mut F,
+    settings: &CalibrationSettings,
+) -> Result<i64, String> {
+    let mut samples: VecDeque<i64> = VecDeque::new();
+
+    for _ in 0..settings.max_samples.max(1) {
+        fan.wait(settings.sample_interval);
+
+        samples.push_back(fan.read_rpm()?);
+        if samples.len() > settings.stable_samples {
+            samples.pop_front();
+        }
+
+        if samples.len() == settings.stable_samples && is_stable(&samples, settings.tolerance) {
+            break;
+        }
+    }
+
+    // If the fan never settled the latest readings are the best guess
+    Ok(samples.iter().sum::<i64>() / samples.len() as i64)
+}
+
+fn is_stable(samples: &VecDeque<i64>, tolerance: f64) -> bool {
+    let min = samples.iter().min().copied().unwrap_or(0);
+    let max = samples.iter().max().copied().unwrap_or(0);
+    let average = samples.iter().sum::<i64>() as f64 / samples.len() as f64;
+
+    max - min <= MIN_RPM_TOLERANCE || (max - min) as f64 <= average * tolerance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// A fan that needs PWM 60 to start, keeps spinning down to PWM 40 and runs at 10 RPM per PWM step.
+    /// The speed moves halfway towards its target on every wait and the fan stalls below 200 RPM.
+    struct SimulatedFan {
+        pwm: i64,
+        rpm: f64,
+    }
+
+    impl SimulatedFan {
+        fn target_rpm(&self) -> f64 {
+            let spinning = self.rpm > 0.0;
+
+            if self.pwm >= 60 || (spinning && self.pwm >= 40) {
+                self.pwm as f64 * 10.0
+            } else {
+                0.0
+            }
+        }
+    }
+
+    impl CalibrationFan for SimulatedFan {
+        fn set_pwm(&mut self, pwm: i64) -> Result<(), String> {
+            self.pwm = pwm;
+            Ok(())
+        }
+
+        fn read_rpm(&mut self) -> Result<i64, String> {
+            Ok(self.rpm.round() as i64)
+        }
+
+        fn wait(&mut self, _: Duration) {
+            let target = self.target_rpm();
+            self.rpm += (target - self.rpm) / 2.0;
+            if self.rpm < 200.0 && target == 0.0 {
+                self.rpm = 0.0;
+            }
+        }
+    }
+
+    fn calibration() -> FanCalibration {
+        let mut points = BTreeMap::new();
+        points.insert(0, 0);
+        points.insert(40, 400);
+        points.insert(64, 640);
+        points.insert(128, 1280);
+        points.insert(192, 1900); // Dips below the linear trend
+        points.insert(255, 2550);
+
+        FanCalibration {
+            points,
+            start_pwm: 64,
+            stop_pwm: 40,
+        }
+    }
+
+    #[test]
+    fn calibrate_simulated_fan() {
+        let mut fan = SimulatedFan {
+            pwm: 255,
+            rpm: 2550.0,
+        };
+        let last_progress = Cell::new(0.0);
+
+        let calibration = calibrate(&mut fan, &CalibrationSettings::default(), &|progress| {
+            assert!(progress >= last_progress.get());
+            last_progress.set(progress);
+        })
+        .unwrap();
+
+        assert_eq!(last_progress.get(), 1.0);
+        // The first step at or above 60 and the last step at or above 40
+        assert_eq!(calibration.start_pwm, 64);
+        assert_eq!(calibration.stop_pwm, 48);
+        assert_eq!(calibration.points.get(&0), Some(&0));
+        assert_eq!(calibration.points.get(&32), Some(&0));
+
+        // Settled within the tolerance of the real speed
+        for (pwm, rpm) in calibration.points.iter().filter(|(pwm, _)| **pwm >= 48) {
+            let expected = pwm * 10;
+            assert!(
+                (rpm - expected).abs() as f64 <= expected as f64 * 0.03 + MIN_RPM_TOLERANCE as f64,
+                "{} RPM at PWM {}",
+                rpm,
+                pwm
+            );
+        }
+    }
+
+    #[test]
+    fn calibrate_fails_for_stuck_fan() {
+        struct StuckFan;
+
+        impl CalibrationFan for StuckFan {
+            fn set_pwm(&mut self, _: i64) -> Result<(), String> {
+                Ok(())
+            }
+            fn read_rpm(&mut self) -> Result<i64, String> {
+                Ok(0)
+            }
+            fn wait(&mut self, _: Duration) {}
+        }
+
+        assert!(calibrate(&mut StuckFan, &CalibrationSettings::default(), &|_| ()).is_err());
+    }
+
+    #[test]
+    fn calibration_converts_between_units() {
+        let calibration = calibration();
+
+        assert_eq!(calibration.max_rpm(), 2550);
+        assert_eq!(calibration.pwm_to_rpm(96.0), 960.0);
+        assert_eq!(calibration.pwm_to_rpm(30.0), 0.0);
+        assert_eq!(calibration.rpm_to_pwm(960.0), 96.0);
+        assert_eq!(calibration.rpm_to_pwm(0.0), 0.0);
+        // Slower than the fan can spin
+        assert_eq!(calibration.rpm_to_pwm(100.0), 40.0);
+        assert_eq!(calibration.rpm_to_pwm(3000.0), 255.0);
+        assert_eq!(calibration.percent_to_rpm(100.0), 2550.0);
+
+        let curve: BTreeMap<i64, f64> = vec![(40, 0.0), (60, 50.0), (80, 100.0)]
+            .into_iter()
+            .collect();
+        let rpm_curve = calibration.convert_curve(&curve, FanSpeedUnit::Percent, FanSpeedUnit::Rpm);
+
+        assert_eq!(rpm_curve.get(&40), Some(&0.0));
+        assert_eq!(rpm_curve.get(&80), Some(&2550.0));
+
+        let percent_curve =
+            calibration.convert_curve(&rpm_curve, FanSpeedUnit::Rpm, FanSpeedUnit::Percent);
+        for (temp, speed) in &curve {
+            assert!((percent_curve[temp] - speed).abs() <= 1.0);
+        }
+    }
+}
@@ -0,0 +1,821 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fan_health::FanHealthFault;
+use crate::fan_pid::FanPidSettings;
+
+/// How the fan speed is chosen while manual fan control is enabled
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum FanMode {
+    Curve,
+    Static(f64),         // Fixed speed in %
+    TargetRpm(i64),      // Fixed speed in RPM
+    Pid(FanPidSettings), // Holds a temperature
+}
+
+impl Default for FanMode {
+    fn default() -> Self {
+        FanMode::Curve
+    }
+}
+
+impl fmt::Display for FanMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FanMode::Curve => write!(f, "curve"),
+            FanMode::Static(speed) => write!(f, "static {}%", speed),
+            FanMode::TargetRpm(rpm) => write!(f, "target {} RPM", rpm),
+            FanMode::Pid(settings) => write!(f, "target temperature {}", settings),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FanSmoothing {
+    pub hysteresis: i64,   // °C the temperature has to drop before the fan slows down
+    pub ramp_up_rate: f64, // %/s, 0 means unlimited
+    pub ramp_down_rate: f64, // %/s, 0 means unlimited
+    pub temp_average_window: u64, // Seconds of temperature readings to average
+    pub spin_down_delay: u64, // Seconds the temperature has to stay low before slowing down
+}
+
+impl Default for FanSmoothing {
+    fn default() -> Self {
+        FanSmoothing {
+            hysteresis: 2,
+            ramp_up_rate: 0.0,
+            ramp_down_rate: 5.0,
+            temp_average_window: 3,
+            spin_down_delay: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum FanTempSource {
+    Edge,
+    Junction,
+    Memory,
+    Max, // The hottest of all available sensors
+    Weighted {
+        edge: f64,
+        junction: f64,
+        memory: f64,
+    },
+}
+
+impl Default for FanTempSource {
+    fn default() -> Self {
+        FanTempSource::Edge
+    }
+}
+
+impl fmt::Display for FanTempSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FanTempSource::Edge => write!(f, "edge"),
+            FanTempSource::Junction => write!(f, "junction"),
+            FanTempSource::Memory => write!(f, "memory"),
+            FanTempSource::Max => write!(f, "max"),
+            FanTempSource::Weighted {
+                edge,
+                junction,
+                memory,
+            } => write!(
+                f,
+                "weighted (edge {}, junction {}, memory {})",
+                edge, junction, memory
+            ),
+        }
+    }
+}
+
+impl FanTempSource {
+    /// Picks the temperature the fan curve should use from the available sensor readings.
+    /// Sensors that aren't available are left out of the max and weighted modes.
+    pub fn select(
+        &self,
+        edge: Option<i64>,
+        junction: Option<i64>,
+        memory: Option<i64>,
+    ) -> Option<f64> {
+        match self {
+            FanTempSource::Edge => edge.map(|t| t as f64),
+            FanTempSource::Junction => junction.map(|t| t as f64),
+            FanTempSource::Memory => memory.map(|t| t as f64),
+            FanTempSource::Max => [edge, junction, memory]
+                .iter()
+                .filter_map(|t| *t)
+                .max()
+                .map(|t| t as f64),
+            FanTempSource::Weighted {
+                edge: edge_weight,
+                junction: junction_weight,
+                memory: memory_weight,
+            } => {
+                let readings = [
+                    (edge, edge_weight),
+                    (junction, junction_weight),
+                    (memory, memory_weight),
+                ];
+
+                let mut total = 0.0;
+                let mut total_weight = 0.0;
+
+                for (temp, weight) in readings.iter() {
+                    if let Some(temp) = temp {
+                        total += *temp as f64 * **weight;
+                        total_weight += **weight;
+                    }
+                }
+
+                if total_weight > 0.0 {
+                    Some(total / total_weight)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FanLimits {
+    pub min_speed: f64,          // % the fan never goes below while spinning
+    pub max_speed: f64,          // % the fan never goes above
+    pub zero_rpm: bool,          // Whether to stop the fan below `zero_rpm_threshold`
+    pub zero_rpm_threshold: i64, // °C
+    pub kick_speed: f64,         // % applied when the fan starts from standstill
+    pub kick_duration: u64,      // Seconds
+    #[serde(default)]
+    pub failsafe: FanFailsafe,
+}
+
+impl Default for FanLimits {
+    fn default() -> Self {
+        FanLimits {
+            min_speed: 0.0,
+            max_speed: 100.0,
+            zero_rpm: false,
+            zero_rpm_threshold: 50,
+            kick_speed: 50.0,
+            kick_duration: 2,
+            failsafe: FanFailsafe::default(),
+        }
+    }
+}
+
+/// How the fan speed is chosen between two points of the fan curve
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FanCurveInterpolation {
+    Step,   // Keep the speed of the lower point until the next point is reached
+    Linear, // Straight line between the points
+    Smooth, // Eased transition that flattens out at every point
+}
+
+impl Default for FanCurveInterpolation {
+    fn default() -> Self {
+        FanCurveInterpolation::Linear
+    }
+}
+
+impl fmt::Display for FanCurveInterpolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FanCurveInterpolation::Step => write!(f, "step"),
+            FanCurveInterpolation::Linear => write!(f, "linear"),
+            FanCurveInterpolation::Smooth => write!(f, "smooth"),
+        }
+    }
+}
+
+/// Unit of the fan speeds in a fan curve
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FanSpeedUnit {
+    Percent, // Of the maximum PWM
+    Rpm,     // Needs a fan calibration to be converted to PWM
+}
+
+impl Default for FanSpeedUnit {
+    fn default() -> Self {
+        FanSpeedUnit::Percent
+    }
+}
+
+impl fmt::Display for FanSpeedUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FanSpeedUnit::Percent => write!(f, "%"),
+            FanSpeedUnit::Rpm => write!(f, "RPM"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FanCurveOptions {
+    pub interpolation: FanCurveInterpolation,
+    pub non_decreasing: bool, // Reject curves where the speed drops as the temperature rises
+    #[serde(default)]
+    pub unit: FanSpeedUnit,
+}
+
+impl Default for FanCurveOptions {
+    fn default() -> Self {
+        FanCurveOptions {
+            interpolation: FanCurveInterpolation::default(),
+            non_decreasing: true,
+            unit: FanSpeedUnit::default(),
+        }
+    }
+}
+
+/// Checks that a fan curve can be used by the control loop.
+/// `max_rpm` is the calibrated maximum fan speed, which curves in RPM need.
+pub fn validate_curve(
+    curve: &BTreeMap<i64, f64>,
+    options: &FanCurveOptions,
+    max_rpm: Option<i64>,
+) -> Result<(), String> {
+    if curve.is_empty() {
+        return Err(String::from("the fan curve needs at least one point"));
+    }
+
+    let max_speed = match options.unit {
+        FanSpeedUnit::Percent => 100.0,
+        FanSpeedUnit::Rpm => match max_rpm {
+            Some(max_rpm) => max_rpm as f64,
+            None => {
+                return Err(String::from(
+                    "the fan has to be calibrated to use a fan curve in RPM",
+                ))
+            }
+        },
+    };
+
+    for (temp, speed) in curve {
+        if *temp < PLAUSIBLE_TEMP_RANGE.0 as i64 || *temp > PLAUSIBLE_TEMP_RANGE.1 as i64 {
+            return Err(format!(
+                "temperature {}°C is outside of the allowed range {}-{}°C",
+                temp, PLAUSIBLE_TEMP_RANGE.0, PLAUSIBLE_TEMP_RANGE.1
+            ));
+        }
+        if !speed.is_finite() || *speed < 0.0 || *speed > max_speed {
+            return Err(format!(
+                "speed {}{unit} at {}°C is outside of the allowed range 0-{}{unit}",
+                speed,
+                temp,
+                max_speed,
+                unit = options.unit
+            ));
+        }
+    }
+
+    if options.non_decreasing {
+        for ((_, &s_low), (&t_high, &s_high)) in curve.iter().zip(curve.iter().skip(1)) {
+            if s_high < s_low {
+                return Err(format!(
+                    "speed drops from {}{unit} to {}{unit} at {}°C",
+                    s_low,
+                    s_high,
+                    t_high,
+                    unit = options.unit
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Interpolates the fan speed percentage for the given temperature.
+/// Below the first point the speed of the first point is used, above the last point the speed of the last point.
+/// Returns `None` only if the curve is empty.
+pub fn curve_speed(
+    curve: &BTreeMap<i64, f64>,
+    temp: f64,
+    interpolation: FanCurveInterpolation,
+) -> Option<f64> {
+    let (&t_first, &s_first) = curve.iter().next()?;
+    let (&t_last, &s_last) = curve.iter().next_back()?;
+
+    if temp < t_first as f64 {
+        return Some(s_first);
+    }
+    if temp >= t_last as f64 {
+        return Some(s_last);
+    }
+
+    for ((&t_low, &s_low), (&t_high, &s_high)) in curve.iter().zip(curve.iter().skip(1)) {
+        if temp >= t_low as f64 && temp < t_high as f64 {
+            //The ratio of which speed to choose within the range of current lower and upper speeds
+            let speed_ratio = (temp - t_low as f64) / (t_high - t_low) as f64;
+
+            let speed_ratio = match interpolation {
+                FanCurveInterpolation::Step => 0.0,
+                FanCurveInterpolation::Linear => speed_ratio,
+                // Smoothstep, stays between the two points so it can't overshoot
+                FanCurveInterpolation::Smooth => {
+                    speed_ratio * speed_ratio * (3.0 - 2.0 * speed_ratio)
+                }
+            };
+
+            return Some(s_low + ((s_high - s_low) * speed_ratio));
+        }
+    }
+
+    Some(s_last)
+}
+
+/// Closed-loop adjustment of the fan speed percentage towards an RPM target,
+/// used when the card doesn't support `fan1_target`.
+pub fn rpm_step(current_speed: f64, target_rpm: i64, measured_rpm: i64, max_rpm: i64) -> f64 {
+    if max_rpm <= 0 {
+        return current_speed;
+    }
+
+    // Only correct half of the error each tick, so that the fan doesn't overshoot while it spins up
+    let error = (target_rpm - measured_rpm) as f64 / max_rpm as f64 * 100.0;
+
+    (current_speed + error * 0.5).max(0.0).min(100.0)
+}
+
+/// What to do with the fan when manual control fails
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum FanFailsafe {
+    Automatic, // Hand the fan back to the driver
+    FullSpeed,
+}
+
+impl Default for FanFailsafe {
+    fn default() -> Self {
+        FanFailsafe::Automatic
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum FanFault {
+    SensorReadFailed(String),
+    InvalidReading(String),
+    StaleSensor(String),
+    WriteFailed(String),
+    FanFailure(FanHealthFault),
+}
+
+impl fmt::Display for FanFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FanFault::SensorReadFailed(sensor) => write!(f, "failed to read {}", sensor),
+            FanFault::InvalidReading(reading) => {
+                write!(f, "implausible sensor reading {}", reading)
+            }
+            FanFault::StaleSensor(sensor) => write!(f, "{} stopped updating", sensor),
+            FanFault::WriteFailed(file) => write!(f, "failed to write to {}", file),
+            FanFault::FanFailure(fault) => write!(f, "{}", fault),
+        }
+    }
+}
+
+// Temperatures outside of this range can only come from a broken sensor
+const PLAUSIBLE_TEMP_RANGE: (f64, f64) = (-20.0, 150.0);
+// Sensors that haven't changed at all for this long are considered stale
+const STALE_SENSOR_TIME: Duration = Duration::from_secs(600);
+
+/// Checks the temperature readings fed into the fan control loop
+#[derive(Debug, Default)]
+pub struct SensorWatchdog {
+    last_readings: Vec<Option<i64>>,
+    unchanged: Duration,
+}
+
+impl SensorWatchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `raw_readings` are the unrounded values of all sensors that move on a working card,
+    /// as a steadily idling card can keep the same temperature for a long time.
+    pub fn check(
+        &mut self,
+        sensor: &str,
+        reading: Option<f64>,
+        raw_readings: Vec<Option<i64>>,
+        elapsed: Duration,
+    ) -> Result<f64, FanFault> {
+        let temp = reading.ok_or_else(|| FanFault::SensorReadFailed(sensor.to_string()))?;
+
+        if temp < PLAUSIBLE_TEMP_RANGE.0 || temp > PLAUSIBLE_TEMP_RANGE.1 {
+            return Err(FanFault::InvalidReading(format!("{} {}°C", sensor, temp)));
+        }
+
+        if raw_readings == self.last_readings {
+            self.unchanged += elapsed;
+
+            if self.unchanged >= STALE_SENSOR_TIME {
+                return Err(FanFault::StaleSensor(sensor.to_string()));
+            }
+        } else {
+            self.last_readings = raw_readings;
+            self.unchanged = Duration::default();
+        }
+
+        Ok(temp)
+    }
+}
+
+/// Keeps the state needed to smooth out the fan speed between control loop ticks
+#[derive(Debug, Default)]
+pub struct FanSmoother {
+    temps: VecDeque<f64>,
+    speed: Option<f64>,
+    setpoint_temp: f64, // The temperature at which the speed was last raised or held
+    spin_down_elapsed: Option<Duration>,
+    stopped: bool, // Whether the fan was last stopped
+    kick_remaining: Option<Duration>,
+}
+
+impl FanSmoother {
+    pub fn new() -> Self {
+        // The fan might be standing still when control starts, so it gets kicked on the first tick
+        FanSmoother {
+            stopped: true,
+            ..Default::default()
+        }
+    }
+
+    /// Adds a temperature reading and returns the average over the last `samples` readings
+    pub fn push_temp(&mut self, temp: f64, samples: usize) -> f64 {
+        self.temps.push_back(temp);
+
+        while self.temps.len() > samples.max(1) {
+            self.temps.pop_front();
+        }
+
+        self.temps.iter().sum::<f64>() / self.temps.len() as f64
+    }
+
+    /// Returns the fan speed percentage to apply, given the target speed from the curve
+    pub fn update(
+        &mut self,
+        smoothing: &FanSmoothing,
+        temp: f64,
+        target: f64,
+        elapsed: Duration,
+    ) -> f64 {
+        let current = match self.speed {
+            Some(speed) => speed,
+            None => {
+                self.speed = Some(target);
+                self.setpoint_temp = temp;
+                return target;
+            }
+        };
+
+        let target = if target < current {
+            if temp > self.setpoint_temp - smoothing.hysteresis as f64 {
+                self.spin_down_elapsed = None;
+                current
+            } else {
+                let spin_down_elapsed =
+                    self.spin_down_elapsed.get_or_insert(Duration::from_secs(0));
+                *spin_down_elapsed += elapsed;
+
+                if *spin_down_elapsed >= Duration::from_secs(smoothing.spin_down_delay) {
+                    target
+                } else {
+                    current
+                }
+            }
+        } else {
+            self.setpoint_temp = temp;
+            self.spin_down_elapsed = None;
+            target
+        };
+
+        let speed = if target > current {
+            match smoothing.ramp_up_rate {
+                rate if rate > 0.0 => target.min(current + rate * elapsed.as_secs_f64()),
+                _ => target,
+            }
+        } else {
+            match smoothing.ramp_down_rate {
+                rate if rate > 0.0 => target.max(current - rate * elapsed.as_secs_f64()),
+                _ => target,
+            }
+        };
+
+        self.speed = Some(speed);
+
+        speed
+    }
+
+    /// Applies the zero RPM mode, the start-up kick and the speed limits to the smoothed speed
+    pub fn apply_limits(
+        &mut self,
+        limits: &FanLimits,
+        hysteresis: i64,
+        temp: f64,
+        speed: f64,
+        elapsed: Duration,
+    ) -> f64 {
+        let threshold = limits.zero_rpm_threshold as f64;
+
+        // The fan starts once the threshold is reached, but only stops after dropping below it by the hysteresis
+        let stop = limits.zero_rpm
+            && ((self.stopped && temp < threshold)
+                || (!self.stopped && temp < threshold - hysteresis as f64));
+
+        // Only the zero RPM mode stops the fan, a 0% curve point still gets the minimum speed
+        let speed = if stop {
+            0.0
+        } else {
+            speed.max(limits.min_speed).min(limits.max_speed)
+        };
+
+        if speed > 0.0 {
+            if self.stopped && limits.kick_duration > 0 {
+                self.kick_remaining = Some(Duration::from_secs(limits.kick_duration));
+            }
+        } else {
+            self.kick_remaining = None;
+        }
+        self.stopped = speed <= 0.0;
+
+        match self.kick_remaining {
+            Some(remaining) => {
+                self.kick_remaining = remaining
+                    .checked_sub(elapsed)
+                    .filter(|r| *r > Duration::from_secs(0));
+                speed.max(limits.kick_speed.min(limits.max_speed))
+            }
+            None => speed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn curve_speed_interpolates() {
+        let curve: BTreeMap<i64, f64> =
+            vec![(20, 0.0), (40, 0.0), (60, 50.0), (80, 80.0), (100, 100.0)]
+                .into_iter()
+                .collect();
+        let linear = FanCurveInterpolation::Linear;
+
+        assert_eq!(curve_speed(&curve, 50.0, linear), Some(25.0));
+        assert_eq!(curve_speed(&curve, 70.0, linear), Some(65.0));
+        // Outside of the curve the closest point is used
+        assert_eq!(curve_speed(&curve, 10.0, linear), Some(0.0));
+        assert_eq!(curve_speed(&curve, 105.0, linear), Some(100.0));
+        assert_eq!(curve_speed(&BTreeMap::new(), 50.0, linear), None);
+    }
+
+    #[test]
+    fn curve_speed_step_and_smooth() {
+        let curve: BTreeMap<i64, f64> = vec![(30, 20.0), (50, 40.0), (60, 40.0), (90, 100.0)]
+            .into_iter()
+            .collect();
+        let step = FanCurveInterpolation::Step;
+        let smooth = FanCurveInterpolation::Smooth;
+
+        assert_eq!(curve_speed(&curve, 49.9, step), Some(20.0));
+        assert_eq!(curve_speed(&curve, 50.0, step), Some(40.0));
+        assert_eq!(curve_speed(&curve, 89.0, step), Some(40.0));
+        assert_eq!(curve_speed(&curve, 90.0, step), Some(100.0));
+
+        // The midpoint matches the linear curve, the points themselves are hit exactly
+        assert_eq!(curve_speed(&curve, 40.0, smooth), Some(30.0));
+        assert_eq!(curve_speed(&curve, 50.0, smooth), Some(40.0));
+        assert_eq!(curve_speed(&curve, 55.0, smooth), Some(40.0));
+        // Eased towards the points, but never outside of them
+        let near_start = curve_speed(&curve, 33.0, smooth).unwrap();
+        assert!(near_start > 20.0 && near_start < 23.0);
+        let near_end = curve_speed(&curve, 87.0, smooth).unwrap();
+        assert!(near_end > 94.0 && near_end < 100.0);
+
+        // Any number of points works, including a single one
+        let single: BTreeMap<i64, f64> = vec![(50, 60.0)].into_iter().collect();
+        assert_eq!(curve_speed(&single, 20.0, smooth), Some(60.0));
+        assert_eq!(curve_speed(&single, 80.0, step), Some(60.0));
+    }
+
+    #[test]
+    fn validate_curve_checks_points() {
+        let curve: BTreeMap<i64, f64> = vec![(20, 0.0), (60, 50.0), (80, 40.0)]
+            .into_iter()
+            .collect();
+
+        let mut options = FanCurveOptions {
+            non_decreasing: false,
+            ..FanCurveOptions::default()
+        };
+
+        assert!(validate_curve(&curve, &options, None).is_ok());
+
+        let over: BTreeMap<i64, f64> = vec![(20, 0.0), (80, 110.0)].into_iter().collect();
+        assert!(validate_curve(&over, &options, None).is_err());
+
+        let too_hot: BTreeMap<i64, f64> = vec![(200, 100.0)].into_iter().collect();
+        assert!(validate_curve(&too_hot, &options, None).is_err());
+
+        assert!(validate_curve(&BTreeMap::new(), &options, None).is_err());
+
+        options.non_decreasing = true;
+        assert_eq!(
+            validate_curve(&curve, &options, None),
+            Err(String::from("speed drops from 50% to 40% at 80°C"))
+        );
+
+        // Curves in RPM are limited by the calibrated speed
+        let rpm_options = FanCurveOptions {
+            unit: FanSpeedUnit::Rpm,
+            ..FanCurveOptions::default()
+        };
+        let rpm_curve: BTreeMap<i64, f64> = vec![(40, 800.0), (80, 2400.0)].into_iter().collect();
+
+        assert!(validate_curve(&rpm_curve, &rpm_options, None).is_err());
+        assert!(validate_curve(&rpm_curve, &rpm_options, Some(2000)).is_err());
+        assert!(validate_curve(&rpm_curve, &rpm_options, Some(2500)).is_ok());
+    }
+
+    #[test]
+    fn temp_source_selects_sensor() {
+        assert_eq!(
+            FanTempSource::Junction.select(Some(60), Some(75), Some(80)),
+            Some(75.0)
+        );
+        assert_eq!(FanTempSource::Memory.select(Some(60), Some(75), None), None);
+        assert_eq!(
+            FanTempSource::Max.select(Some(60), Some(75), Some(80)),
+            Some(80.0)
+        );
+        assert_eq!(FanTempSource::Max.select(Some(60), None, None), Some(60.0));
+
+        let weighted = FanTempSource::Weighted {
+            edge: 1.0,
+            junction: 3.0,
+            memory: 1.0,
+        };
+        assert_eq!(weighted.select(Some(60), Some(80), Some(70)), Some(74.0));
+        // Missing sensors are left out
+        assert_eq!(weighted.select(Some(60), Some(80), None), Some(75.0));
+    }
+
+    #[test]
+    fn smoother_averages_temperature() {
+        let mut smoother = FanSmoother::new();
+
+        assert_eq!(smoother.push_temp(60.0, 3), 60.0);
+        assert_eq!(smoother.push_temp(63.0, 3), 61.5);
+        assert_eq!(smoother.push_temp(66.0, 3), 63.0);
+        assert_eq!(smoother.push_temp(69.0, 3), 66.0);
+    }
+
+    #[test]
+    fn smoother_applies_hysteresis_and_spin_down_delay() {
+        let smoothing = FanSmoothing {
+            hysteresis: 3,
+            ramp_up_rate: 0.0,
+            ramp_down_rate: 0.0,
+            temp_average_window: 1,
+            spin_down_delay: 2,
+        };
+        let mut smoother = FanSmoother::new();
+
+        assert_eq!(smoother.update(&smoothing, 70.0, 65.0, SECOND), 65.0);
+        // Within the hysteresis
+        assert_eq!(smoother.update(&smoothing, 68.0, 60.0, SECOND), 65.0);
+        // Below the hysteresis, but the delay hasn't passed yet
+        assert_eq!(smoother.update(&smoothing, 66.0, 55.0, SECOND), 65.0);
+        assert_eq!(smoother.update(&smoothing, 66.0, 55.0, SECOND), 55.0);
+    }
+
+    #[test]
+    fn smoother_limits_ramp_rate() {
+        let smoothing = FanSmoothing {
+            hysteresis: 0,
+            ramp_up_rate: 10.0,
+            ramp_down_rate: 5.0,
+            temp_average_window: 1,
+            spin_down_delay: 0,
+        };
+        let mut smoother = FanSmoother::new();
+
+        assert_eq!(smoother.update(&smoothing, 50.0, 30.0, SECOND), 30.0);
+        assert_eq!(smoother.update(&smoothing, 80.0, 80.0, SECOND), 40.0);
+        assert_eq!(smoother.update(&smoothing, 80.0, 80.0, SECOND), 50.0);
+        assert_eq!(smoother.update(&smoothing, 40.0, 20.0, SECOND), 45.0);
+    }
+
+    #[test]
+    fn limits_clamp_speed_and_stop_fan() {
+        let limits = FanLimits {
+            min_speed: 20.0,
+            max_speed: 80.0,
+            zero_rpm: true,
+            zero_rpm_threshold: 50,
+            kick_speed: 60.0,
+            kick_duration: 2,
+            failsafe: FanFailsafe::Automatic,
+        };
+        let mut smoother = FanSmoother::new();
+
+        assert_eq!(smoother.apply_limits(&limits, 2, 60.0, 10.0, SECOND), 60.0);
+        // Kick is still active
+        assert_eq!(smoother.apply_limits(&limits, 2, 60.0, 10.0, SECOND), 60.0);
+        assert_eq!(smoother.apply_limits(&limits, 2, 60.0, 10.0, SECOND), 20.0);
+        assert_eq!(smoother.apply_limits(&limits, 2, 60.0, 95.0, SECOND), 80.0);
+        // Within the hysteresis below the threshold
+        assert_eq!(smoother.apply_limits(&limits, 2, 49.0, 30.0, SECOND), 30.0);
+        assert_eq!(smoother.apply_limits(&limits, 2, 47.0, 30.0, SECOND), 0.0);
+        assert_eq!(smoother.apply_limits(&limits, 2, 49.0, 30.0, SECOND), 0.0);
+        // Starting up again kicks the fan
+        assert_eq!(smoother.apply_limits(&limits, 2, 50.0, 30.0, SECOND), 60.0);
+    }
+
+    #[test]
+    fn limits_keep_min_speed_without_zero_rpm() {
+        let limits = FanLimits {
+            min_speed: 20.0,
+            zero_rpm: false,
+            kick_duration: 0,
+            ..Default::default()
+        };
+        let mut smoother = FanSmoother::new();
+
+        assert_eq!(smoother.apply_limits(&limits, 2, 30.0, 0.0, SECOND), 20.0);
+        assert_eq!(smoother.apply_limits(&limits, 2, 30.0, 50.0, SECOND), 50.0);
+        assert_eq!(smoother.apply_limits(&limits, 2, 30.0, 0.0, SECOND), 20.0);
+    }
+
+    #[test]
+    fn rpm_step_converges_on_target() {
+        // Simulated fan where the RPM is proportional to the speed
+        let max_rpm = 3000;
+        let mut speed = 0.0;
+
+        for _ in 0..20 {
+            let measured_rpm = (speed / 100.0 * max_rpm as f64) as i64;
+            speed = rpm_step(speed, 1500, measured_rpm, max_rpm);
+        }
+
+        assert!((speed - 50.0).abs() < 1.0);
+        assert_eq!(rpm_step(95.0, 3000, 0, max_rpm), 100.0);
+    }
+
+    #[test]
+    fn watchdog_detects_sensor_failures() {
+        let mut watchdog = SensorWatchdog::new();
+
+        assert_eq!(
+            watchdog.check("edge", Some(60.0), Vec::new(), Duration::default()),
+            Ok(60.0)
+        );
+        assert_eq!(
+            watchdog.check("edge", None, Vec::new(), Duration::default()),
+            Err(FanFault::SensorReadFailed(String::from("edge")))
+        );
+        assert!(watchdog
+            .check("edge", Some(511.0), Vec::new(), Duration::default())
+            .is_err());
+
+        let tick = Duration::from_secs(1);
+        let idle = || vec![Some(60000), Some(15000)];
+
+        // A steady temperature is fine as long as the other sensors move
+        for power in 0..STALE_SENSOR_TIME.as_secs() * 2 {
+            assert_eq!(
+                watchdog.check(
+                    "edge",
+                    Some(60.0),
+                    vec![Some(60000), Some(power as i64)],
+                    tick
+                ),
+                Ok(60.0)
+            );
+        }
+
+        for _ in 0..STALE_SENSOR_TIME.as_secs() {
+            assert_eq!(watchdog.check("edge", Some(60.0), idle(), tick), Ok(60.0));
+        }
+        assert_eq!(
+            watchdog.check("edge", Some(60.0), idle(), tick),
+            Err(FanFault::StaleSensor(String::from("edge")))
+        );
+
+        // Fan control takes over again once the sensors come back
+        assert_eq!(
+            watchdog.check("edge", Some(60.0), vec![Some(60125), Some(15000)], tick),
+            Ok(60.0)
+        );
+    }
+}
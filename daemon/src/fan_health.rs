@@ -0,0 +1,296 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fan_calibration::FanCalibration;
+
+/// What to do when the fan stalls or runs too slow
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FanFaultAction {
+    pub stop_fan_control: bool, // Hand the fan back to the failsafe configured in the fan limits
+    pub power_cap: Option<i64>, // W the power cap is lowered to until the fan recovers
+}
+
+impl Default for FanFaultAction {
+    fn default() -> Self {
+        FanFaultAction {
+            stop_fan_control: true,
+            power_cap: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FanHealthSettings {
+    pub enabled: bool,
+    pub stall_time: u64, // Seconds the fan can stand still while it should spin
+    pub underspeed_tolerance: f64, // Fraction the fan can be slower than calibrated
+    pub underspeed_time: u64, // Seconds the fan can be too slow
+    pub wear_threshold: f64, // Long-term speed ratio below which the fan is considered worn
+    pub action: FanFaultAction,
+}
+
+impl Default for FanHealthSettings {
+    fn default() -> Self {
+        FanHealthSettings {
+            enabled: true,
+            stall_time: 10,
+            underspeed_tolerance: 0.3,
+            underspeed_time: 30,
+            wear_threshold: 0.85,
+            action: FanFaultAction::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum FanHealthFault {
+    Stalled {
+        pwm: i64,
+    },
+    Underspeed {
+        expected_rpm: i64,
+        measured_rpm: i64,
+    },
+    Worn {
+        speed_ratio: f64,
+    },
+}
+
+impl FanHealthFault {
+    /// Worn fans still work, so they only get reported
+    pub fn needs_action(&self) -> bool {
+        !matches!(self, FanHealthFault::Worn { .. })
+    }
+}
+
+impl fmt::Display for FanHealthFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FanHealthFault::Stalled { pwm } => {
+                write!(f, "the fan doesn't spin at PWM {}", pwm)
+            }
+            FanHealthFault::Underspeed {
+                expected_rpm,
+                measured_rpm,
+            } => write!(
+                f,
+                "the fan runs at {} RPM instead of {} RPM",
+                measured_rpm, expected_rpm
+            ),
+            FanHealthFault::Worn { speed_ratio } => write!(
+                f,
+                "the fan only reaches {:.0}% of its calibrated speed, the bearings might be worn",
+                speed_ratio * 100.0
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FanHealth {
+    pub fault: Option<FanHealthFault>,
+    pub speed_ratio: Option<f64>, // Long-term average of the measured to the calibrated speed
+}
+
+// Without a calibration the fan is only expected to spin above this PWM
+const UNCALIBRATED_SPIN_PWM: i64 = 77; // 30%
+
+// Time constant of the long-term speed ratio, so that only a lasting slowdown counts as wear
+const WEAR_TIME_CONSTANT: Duration = Duration::from_secs(3600);
+// How long the fan has to be observed before the speed ratio is trusted
+const WEAR_MIN_OBSERVED: Duration = Duration::from_secs(600);
+
+/// Compares the measured fan speed against what the PWM value should produce
+pub struct FanHealthMonitor {
+    stalled_for: Duration,
+    underspeed_for: Duration,
+    speed_ratio: Option<f64>,
+    observed: Duration,
+}
+
+impl FanHealthMonitor {
+    pub fn new() -> Self {
+        FanHealthMonitor {
+            stalled_for: Duration::from_secs(0),
+            underspeed_for: Duration::from_secs(0),
+            speed_ratio: None,
+            observed: Duration::from_secs(0),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        settings: &FanHealthSettings,
+        pwm: i64,
+        measured_rpm: i64,
+        calibration: Option<&FanCalibration>,
+        elapsed: Duration,
+    ) -> FanHealth {
+        // A fan between the stop and start PWM only spins if it was already spinning
+        let spin_pwm = calibration.map_or(UNCALIBRATED_SPIN_PWM, |c| c.start_pwm.max(1));
+
+        if pwm >= spin_pwm && measured_rpm == 0 {
+            self.stalled_for += elapsed;
+        } else {
+            self.stalled_for = Duration::from_secs(0);
+        }
+
+        let mut underspeed = None;
+
+        if let Some(calibration) = calibration {
+            let expected_rpm = calibration.pwm_to_rpm(pwm as f64);
+
+            if expected_rpm > 0.0 && measured_rpm > 0 {
+                let ratio = measured_rpm as f64 / expected_rpm;
+
+                if ratio < 1.0 - settings.underspeed_tolerance {
+                    self.underspeed_for += elapsed;
+                    underspeed = Some(FanHealthFault::Underspeed {
+                        expected_rpm: expected_rpm.round() as i64,
+                        measured_rpm,
+                    });
+                } else {
+                    self.underspeed_for = Duration::from_secs(0);
+                }
+
+                let alpha = (elapsed.as_secs_f64() / WEAR_TIME_CONSTANT.as_secs_f64()).min(1.0);
+                self.speed_ratio = Some(match self.speed_ratio {
+                    Some(average) => average + (ratio - average) * alpha,
+                    None => ratio,
+                });
+                self.observed += elapsed;
+            } else {
+                self.underspeed_for = Duration::from_secs(0);
+            }
+        }
+
+        let fault = if self.stalled_for >= Duration::from_secs(settings.stall_time) {
+            Some(FanHealthFault::Stalled { pwm })
+        } else if self.underspeed_for >= Duration::from_secs(settings.underspeed_time)
+            && underspeed.is_some()
+        {
+            underspeed
+        } else {
+            match self.speed_ratio {
+                Some(speed_ratio)
+                    if self.observed >= WEAR_MIN_OBSERVED
+                        && speed_ratio < settings.wear_threshold =>
+                {
+                    Some(FanHealthFault::Worn { speed_ratio })
+                }
+                _ => None,
+            }
+        };
+
+        FanHealth {
+            fault,
+            speed_ratio: self.speed_ratio,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn calibration() -> FanCalibration {
+        let mut points = BTreeMap::new();
+        points.insert(0, 0);
+        points.insert(64, 640);
+        points.insert(255, 2550);
+
+        FanCalibration {
+            points,
+            start_pwm: 64,
+            stop_pwm: 48,
+        }
+    }
+
+    #[test]
+    fn detects_stalled_fan() {
+        let settings = FanHealthSettings::default();
+        let mut monitor = FanHealthMonitor::new();
+
+        // Spinning up takes a moment
+        for _ in 0..9 {
+            let health = monitor.update(&settings, 200, 0, None, SECOND);
+            assert_eq!(health.fault, None);
+        }
+        let health = monitor.update(&settings, 200, 0, None, SECOND);
+        assert_eq!(health.fault, Some(FanHealthFault::Stalled { pwm: 200 }));
+
+        // Recovers as soon as it spins
+        let health = monitor.update(&settings, 200, 1500, None, SECOND);
+        assert_eq!(health.fault, None);
+    }
+
+    #[test]
+    fn ignores_stopped_fan_at_low_pwm() {
+        let settings = FanHealthSettings::default();
+        let mut monitor = FanHealthMonitor::new();
+        let calibration = calibration();
+
+        // Zero RPM mode and the range where a stopped fan doesn't start
+        for pwm in [0, 50, 60].iter().cycle().take(60) {
+            let health = monitor.update(&settings, *pwm, 0, Some(&calibration), SECOND);
+            assert_eq!(health.fault, None);
+        }
+    }
+
+    #[test]
+    fn detects_underspeed() {
+        let settings = FanHealthSettings::default();
+        let mut monitor = FanHealthMonitor::new();
+        let calibration = calibration();
+
+        for _ in 0..29 {
+            let health = monitor.update(&settings, 128, 600, Some(&calibration), SECOND);
+            assert_eq!(health.fault, None);
+        }
+        let health = monitor.update(&settings, 128, 600, Some(&calibration), SECOND);
+        assert_eq!(
+            health.fault,
+            Some(FanHealthFault::Underspeed {
+                expected_rpm: 1280,
+                measured_rpm: 600
+            })
+        );
+        assert!(health.fault.unwrap().needs_action());
+
+        // Within the tolerance
+        let health = monitor.update(&settings, 128, 1100, Some(&calibration), SECOND);
+        assert_eq!(health.fault, None);
+    }
+
+    #[test]
+    fn tracks_wear_trend() {
+        let settings = FanHealthSettings::default();
+        let mut monitor = FanHealthMonitor::new();
+        let calibration = calibration();
+
+        // A healthy fan
+        for _ in 0..600 {
+            monitor.update(&settings, 255, 2550, Some(&calibration), SECOND);
+        }
+        let health = monitor.update(&settings, 255, 2550, Some(&calibration), SECOND);
+        assert_eq!(health.fault, None);
+        assert_eq!(health.speed_ratio, Some(1.0));
+
+        // Slowly losing speed, which is within the underspeed tolerance
+        let mut health = FanHealth::default();
+        for _ in 0..(3 * 3600) {
+            health = monitor.update(&settings, 255, 2000, Some(&calibration), SECOND);
+        }
+        match health.fault {
+            Some(FanHealthFault::Worn { speed_ratio }) => {
+                assert!(speed_ratio < 0.85);
+                assert!(!FanHealthFault::Worn { speed_ratio }.needs_action());
+            }
+            fault => panic!("Unexpected fault {:?}", fault),
+        }
+    }
+}
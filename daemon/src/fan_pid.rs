@@ -0,0 +1,224 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// Settings of the fan mode that holds a temperature with a PID controller
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FanPidSettings {
+    pub target_temp: f64, // °C
+    pub kp: f64,          // PWM per °C above the target
+    pub ki: f64,          // PWM per °C above the target per second
+    pub kd: f64,          // PWM per °C/s the temperature rises
+    pub min_pwm: i64,
+    pub max_pwm: i64,
+}
+
+impl Default for FanPidSettings {
+    fn default() -> Self {
+        FanPidSettings {
+            target_temp: 75.0,
+            kp: 16.0,
+            ki: 0.5,
+            kd: 5.0,
+            min_pwm: 0,
+            max_pwm: 255,
+        }
+    }
+}
+
+impl fmt::Display for FanPidSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}°C (P {}, I {}, D {}, PWM {}-{})",
+            self.target_temp, self.kp, self.ki, self.kd, self.min_pwm, self.max_pwm
+        )
+    }
+}
+
+impl FanPidSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=150.0).contains(&self.target_temp) {
+            return Err(format!(
+                "the target temperature of {}°C is outside of 0-150°C",
+                self.target_temp
+            ));
+        }
+        if self.kp < 0.0 || self.ki < 0.0 || self.kd < 0.0 {
+            return Err("the gains can't be negative".to_string());
+        }
+        if self.min_pwm < 0 || self.max_pwm > 255 || self.min_pwm > self.max_pwm {
+            return Err(format!(
+                "the PWM range {}-{} is outside of 0-255",
+                self.min_pwm, self.max_pwm
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// PID controller that turns the distance to the target temperature into a PWM value.
+/// The output rests at the minimum PWM while the card is cooler than the target, so the fan is as quiet as possible.
+pub struct FanPid {
+    integral: f64, // PWM above the minimum contributed by the integral term
+    last_temp: Option<f64>,
+}
+
+impl FanPid {
+    pub fn new() -> Self {
+        FanPid {
+            integral: 0.0,
+            last_temp: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = FanPid::new();
+    }
+
+    pub fn update(&mut self, settings: &FanPidSettings, temp: f64, elapsed: Duration) -> i64 {
+        let dt = elapsed.as_secs_f64();
+        let min = settings.min_pwm as f64;
+        let max = settings.max_pwm as f64;
+
+        let error = temp - settings.target_temp;
+
+        // Derivative on the measurement, so that changing the target doesn't kick the fan
+        let derivative = match self.last_temp {
+            Some(last_temp) if dt > 0.0 => (temp - last_temp) / dt,
+            _ => 0.0,
+        };
+        self.last_temp = Some(temp);
+
+        let proportional = settings.kp * error + settings.kd * derivative;
+
+        // Anti-windup: the integral only grows while the output isn't saturated in the same direction,
+        // and never covers more than the whole PWM range
+        let integral = (self.integral + settings.ki * error * dt)
+            .max(0.0)
+            .min(max - min);
+        let output = min + proportional + integral;
+
+        if !(output > max && error > 0.0 || output < min && error < 0.0) {
+            self.integral = integral;
+        }
+
+        let output = (min + proportional + self.integral).max(min).min(max);
+
+        output.round() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    /// A GPU heating up with its power draw and cooled by the ambient air, more so the faster the fan spins
+    struct SimulatedGpu {
+        temp: f64,
+        power: f64, // W
+    }
+
+    impl SimulatedGpu {
+        const AMBIENT: f64 = 30.0; // °C
+        const HEAT_CAPACITY: f64 = 300.0; // J/°C
+
+        fn step(&mut self, pwm: i64) {
+            // W/°C from the heatsink alone and from the fan at full speed
+            let conductance = 1.5 + 8.0 * pwm as f64 / 255.0;
+
+            self.temp +=
+                (self.power - conductance * (self.temp - Self::AMBIENT)) / Self::HEAT_CAPACITY;
+        }
+
+        // The sensor reports whole degrees
+        fn read_temp(&self) -> f64 {
+            self.temp.round()
+        }
+    }
+
+    fn run(gpu: &mut SimulatedGpu, pid: &mut FanPid, settings: &FanPidSettings, secs: u32) -> i64 {
+        let mut pwm = 0;
+
+        for _ in 0..secs {
+            pwm = pid.update(settings, gpu.read_temp(), SECOND);
+            assert!(pwm >= settings.min_pwm && pwm <= settings.max_pwm);
+            gpu.step(pwm);
+        }
+
+        pwm
+    }
+
+    #[test]
+    fn holds_target_temperature() {
+        let settings = FanPidSettings::default();
+        let mut pid = FanPid::new();
+        let mut gpu = SimulatedGpu {
+            temp: 40.0,
+            power: 30.0,
+        };
+
+        // Idling below the target keeps the fan at the minimum
+        assert_eq!(run(&mut gpu, &mut pid, &settings, 600), settings.min_pwm);
+
+        // Under full load the temperature settles at the target, after overshooting by a few degrees while the fan spins up
+        gpu.power = 250.0;
+        let mut max_temp: f64 = 0.0;
+        for _ in 0..1800 {
+            let pwm = pid.update(&settings, gpu.read_temp(), SECOND);
+            gpu.step(pwm);
+            max_temp = max_temp.max(gpu.temp);
+        }
+
+        assert!(max_temp < 81.0, "overshoot to {}°C", max_temp);
+        assert!((gpu.temp - 75.0).abs() < 1.0, "settled at {}°C", gpu.temp);
+    }
+
+    #[test]
+    fn respects_pwm_limits_without_windup() {
+        let settings = FanPidSettings {
+            min_pwm: 40,
+            max_pwm: 100,
+            ..FanPidSettings::default()
+        };
+        let mut pid = FanPid::new();
+        let mut gpu = SimulatedGpu {
+            temp: 40.0,
+            power: 250.0,
+        };
+
+        // The target can't be reached with the limited fan speed
+        assert_eq!(run(&mut gpu, &mut pid, &settings, 3600), 100);
+        assert!(gpu.temp > 80.0);
+
+        // Once the load is gone the fan slows down as soon as the card is below the target,
+        // instead of unwinding an integral that grew for an hour
+        gpu.power = 30.0;
+        while gpu.read_temp() > settings.target_temp {
+            run(&mut gpu, &mut pid, &settings, 1);
+        }
+        assert!(run(&mut gpu, &mut pid, &settings, 30) < 100);
+        assert_eq!(run(&mut gpu, &mut pid, &settings, 300), 40);
+    }
+
+    #[test]
+    fn validates_settings() {
+        assert!(FanPidSettings::default().validate().is_ok());
+        assert!(FanPidSettings {
+            min_pwm: 200,
+            max_pwm: 100,
+            ..FanPidSettings::default()
+        }
+        .validate()
+        .is_err());
+        assert!(FanPidSettings {
+            kp: -1.0,
+            ..FanPidSettings::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fan_control::FanFault;
+use crate::fan_health::{FanHealth, FanHealthFault};
+use crate::hooks::{HookEvent, HookRunner};
+use crate::hw_mon::{FanControlState, FanHealthState, HWMon, IdleState, ScriptState};
+use crate::idle::IdleStatus;
+use crate::power_guard::{PowerGuard, PowerGuardStatus};
+use crate::script::ScriptStatus;
+
+pub const DEFAULT_INTERVAL_MS: u64 = 1000;
+
+/// What the control loop computed for a GPU on its last tick
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FanControlStatus {
+    pub pwm: Option<i64>,
+    pub temp: Option<f64>,
+}
+
+/// Runs the fan control loop, fan health checks, power guard, idle policy and control scripts
+/// of all GPUs on a single thread
+pub struct FanScheduler {
+    status: Arc<RwLock<HashMap<u32, FanControlStatus>>>,
+    health: Arc<RwLock<HashMap<u32, FanHealth>>>,
+    power_guard: Arc<RwLock<HashMap<u32, PowerGuardStatus>>>,
+    idle: Arc<RwLock<HashMap<u32, IdleStatus>>>,
+    script: Arc<RwLock<HashMap<u32, ScriptStatus>>>,
+    hooks: HookRunner,
+    on_script_profile: Arc<dyn Fn() + Send + Sync>,
+    handle: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl FanScheduler {
+    /// `on_script_profile` gets called when a control script asks for a different profile,
+    /// which only the daemon can switch to
+    pub fn new<F>(hooks: HookRunner, on_script_profile: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        FanScheduler {
+            status: Arc::new(RwLock::new(HashMap::new())),
+            health: Arc::new(RwLock::new(HashMap::new())),
+            power_guard: Arc::new(RwLock::new(HashMap::new())),
+            idle: Arc::new(RwLock::new(HashMap::new())),
+            script: Arc::new(RwLock::new(HashMap::new())),
+            hooks,
+            on_script_profile: Arc::new(on_script_profile),
+            handle: None,
+        }
+    }
+
+    /// Starts ticking the given GPUs, stopping the previous loop first
+    pub fn start(&mut self, hw_mons: HashMap<u32, HWMon>, interval: Duration) {
+        self.stop();
+
+        log::info!(
+            "Starting the fan scheduler for {} GPUs with an interval of {:?}",
+            hw_mons.len(),
+            interval
+        );
+
+        let (sender, receiver) = mpsc::channel();
+        let published_status = self.status.clone();
+        let published_health = self.health.clone();
+        let published_power_guard = self.power_guard.clone();
+        let published_idle = self.idle.clone();
+        let published_script = self.script.clone();
+        let hooks = self.hooks.clone();
+        let on_script_profile = self.on_script_profile.clone();
+
+        let handle = thread::spawn(move || {
+            let mut states: HashMap<u32, FanControlState> = HashMap::new();
+            let mut health_states: HashMap<u32, FanHealthState> = HashMap::new();
+            let mut power_guards: HashMap<u32, PowerGuard> = HashMap::new();
+            let mut idle_states: HashMap<u32, IdleState> = HashMap::new();
+            let mut health_faults: HashMap<u32, FanHealthFault> = HashMap::new();
+            let mut script_states: HashMap<u32, ScriptState> = HashMap::new();
+            let mut last_tick = Instant::now();
+
+            // Worked on without holding the locks, which are only taken to publish them after each tick
+            let mut status: HashMap<u32, FanControlStatus> = HashMap::new();
+            let mut health: HashMap<u32, FanHealth> = HashMap::new();
+            let mut power_guard: HashMap<u32, PowerGuardStatus> = HashMap::new();
+            let mut idle: HashMap<u32, IdleStatus> = HashMap::new();
+            let mut script: HashMap<u32, ScriptStatus> = HashMap::new();
+
+            // Anything other than a timeout means that a stop was requested or the scheduler was dropped
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                let elapsed = last_tick.elapsed();
+                last_tick = Instant::now();
+
+                for (id, hw_mon) in &hw_mons {
+                    if let Some(temp) = hw_mon.get_gpu_temp() {
+                        hooks.check_temperature(*id, temp);
+                    }
+
+                    // First, so that the other policies work from what the script set.
+                    // The calibration needs the fan to itself.
+                    let script_state = script_states.entry(*id).or_insert_with(ScriptState::new);
+
+                    let script_status = match hw_mon.is_calibrating() {
+                        true => {
+                            hw_mon.restore_script(script_state);
+                            None
+                        }
+                        false => hw_mon.script_tick(script_state),
+                    };
+                    let profile = |status: Option<&ScriptStatus>| {
+                        status
+                            .and_then(|status| status.output.as_ref())
+                            .and_then(|output| output.profile.clone())
+                    };
+                    let new_profile = profile(script_status.as_ref());
+                    if new_profile.is_some() && new_profile != profile(script.get(id)) {
+                        on_script_profile();
+                    }
+
+                    match script_status {
+                        Some(status) => script.insert(*id, status),
+                        None => script.remove(id),
+                    };
+
+                    // Before the power guard, which then works from the idle power cap
+                    let idle_state = idle_states.entry(*id).or_insert_with(IdleState::new);
+
+                    match hw_mon.idle_tick(idle_state, elapsed) {
+                        Some(status) => idle.insert(*id, status),
+                        None => idle.remove(id),
+                    };
+
+                    let guard = power_guards.entry(*id).or_insert_with(PowerGuard::new);
+
+                    match hw_mon.power_guard_tick(guard, elapsed) {
+                        Some(status) => power_guard.insert(*id, status),
+                        None => power_guard.remove(id),
+                    };
+
+                    // The calibration stops the fan on purpose
+                    if hw_mon.is_calibrating() {
+                        health.remove(id);
+                    } else {
+                        // Fans are also watched when the firmware controls them
+                        let health_state =
+                            health_states.entry(*id).or_insert_with(FanHealthState::new);
+
+                        let fan_health = hw_mon.check_fan_health(health_state, elapsed);
+
+                        // Only new faults are reported, not every tick of a lasting one
+                        match fan_health
+                            .as_ref()
+                            .and_then(|fan_health| fan_health.fault.clone())
+                        {
+                            Some(fault) => {
+                                if health_faults.get(id) != Some(&fault) {
+                                    hooks.fire(
+                                        HookEvent::FanFault,
+                                        *id,
+                                        &[("LACT_FAULT", fault.to_string())],
+                                    );
+                                    health_faults.insert(*id, fault);
+                                }
+                            }
+                            None => {
+                                health_faults.remove(id);
+                            }
+                        }
+
+                        match fan_health {
+                            Some(fan_health) => health.insert(*id, fan_health),
+                            None => health.remove(id),
+                        };
+                    }
+
+                    // The calibration drives the fan by itself, the loop starts over afterwards
+                    if !hw_mon.is_fan_control_enabled()
+                        || hw_mon.is_calibrating()
+                        || script_state.controls_fan()
+                    {
+                        states.remove(id);
+                        status.remove(id);
+                        continue;
+                    }
+
+                    let state = states.entry(*id).or_insert_with(FanControlState::new);
+
+                    match hw_mon.fan_control_tick(state, interval, elapsed) {
+                        Ok(()) => {
+                            status.insert(
+                                *id,
+                                FanControlStatus {
+                                    pwm: state.get_last_pwm(),
+                                    temp: state.get_last_temp(),
+                                },
+                            );
+                        }
+                        // Only reported once, the failsafe stays until the sensors update again
+                        Err(fault @ FanFault::StaleSensor(_)) => {
+                            if !state.is_suspended() {
+                                hooks.fire(
+                                    HookEvent::FanFault,
+                                    *id,
+                                    &[("LACT_FAULT", fault.to_string())],
+                                );
+                                hw_mon.suspend_fan_control(state, fault);
+                            }
+                            status.remove(id);
+                        }
+                        Err(fault) => {
+                            hooks.fire(
+                                HookEvent::FanFault,
+                                *id,
+                                &[("LACT_FAULT", fault.to_string())],
+                            );
+                            hw_mon.fail_fan_control(fault);
+                            states.remove(id);
+                            status.remove(id);
+                        }
+                    }
+                }
+
+                *published_status.write().unwrap() = status.clone();
+                *published_health.write().unwrap() = health.clone();
+                *published_power_guard.write().unwrap() = power_guard.clone();
+                *published_idle.write().unwrap() = idle.clone();
+                *published_script.write().unwrap() = script.clone();
+            }
+
+            for (id, health_state) in &mut health_states {
+                hw_mons[id].restore_fan_protection(health_state);
+            }
+            for (id, guard) in &mut power_guards {
+                hw_mons[id].restore_power_guard(guard);
+            }
+            for (id, idle_state) in &mut idle_states {
+                hw_mons[id].restore_idle(idle_state);
+            }
+            for (id, script_state) in &mut script_states {
+                hw_mons[id].restore_script(script_state);
+            }
+
+            log::info!("Fan scheduler stopped");
+        });
+
+        self.handle = Some((sender, handle));
+    }
+
+    /// Stops the control loop and waits for it to finish the current tick
+    pub fn stop(&mut self) {
+        if let Some((sender, handle)) = self.handle.take() {
+            // The thread might already be gone, in which case there is nothing to stop
+            let _ = sender.send(());
+
+            if handle.join().is_err() {
+                log::error!("The fan scheduler thread panicked");
+            }
+        }
+
+        self.status.write().unwrap().clear();
+        self.health.write().unwrap().clear();
+        self.power_guard.write().unwrap().clear();
+        self.idle.write().unwrap().clear();
+        self.script.write().unwrap().clear();
+    }
+
+    pub fn get_status(&self, id: u32) -> Option<FanControlStatus> {
+        self.status.read().unwrap().get(&id).cloned()
+    }
+
+    pub fn get_health(&self, id: u32) -> Option<FanHealth> {
+        self.health.read().unwrap().get(&id).cloned()
+    }
+
+    pub fn get_power_guard(&self, id: u32) -> Option<PowerGuardStatus> {
+        self.power_guard.read().unwrap().get(&id).cloned()
+    }
+
+    pub fn get_idle(&self, id: u32) -> Option<IdleStatus> {
+        self.idle.read().unwrap().get(&id).cloned()
+    }
+
+    pub fn get_script(&self, id: u32) -> Option<ScriptStatus> {
+        self.script.read().unwrap().get(&id).cloned()
+    }
+}
+
+impl Drop for FanScheduler {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GpuConfig;
+    use crate::fan_control::FanMode;
+    use std::fs;
+    use std::path::Path;
+
+    /// Waits a bit for the expected value, as the file is empty while the next tick rewrites it
+    fn read_pwm(hwmon_path: &Path, expected: &str) -> String {
+        for _ in 0..50 {
+            match fs::read_to_string(hwmon_path.join("pwm1")) {
+                Ok(pwm) if pwm == expected => return pwm,
+                _ => thread::sleep(Duration::from_millis(2)),
+            }
+        }
+
+        fs::read_to_string(hwmon_path.join("pwm1")).unwrap()
+    }
+
+    #[test]
+    fn scheduler_ticks_and_stops() {
+        let hwmon_path = std::env::temp_dir().join(format!("lact-hwmon-{}", std::process::id()));
+        fs::create_dir_all(&hwmon_path).unwrap();
+        fs::write(hwmon_path.join("temp1_input"), "50000").unwrap();
+        fs::write(hwmon_path.join("pwm1_enable"), "2").unwrap();
+        fs::write(hwmon_path.join("pwm1"), "0").unwrap();
+
+        let mut config = GpuConfig::new();
+        config.fan_control_enabled = true;
+        config.fan_mode = FanMode::Static(50.0);
+
+        let hw_mon = HWMon::new(&hwmon_path, &config);
+        assert_eq!(
+            fs::read_to_string(hwmon_path.join("pwm1_enable")).unwrap(),
+            "1"
+        );
+
+        let mut hw_mons = HashMap::new();
+        hw_mons.insert(0, hw_mon.clone());
+
+        let mut scheduler = FanScheduler::new(HookRunner::new(Vec::new()), || ());
+        scheduler.start(hw_mons, Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(scheduler.get_status(0).unwrap().pwm, Some(127));
+        assert_eq!(read_pwm(&hwmon_path, "127"), "127");
+
+        // Another config, like that of a profile, is picked up by the running scheduler
+        config.fan_mode = FanMode::Static(100.0);
+        hw_mon.load_config(&config);
+        thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(scheduler.get_status(0).unwrap().pwm, Some(255));
+        assert_eq!(read_pwm(&hwmon_path, "255"), "255");
+
+        scheduler.stop();
+        assert!(scheduler.get_status(0).is_none());
+
+        // Nothing writes to the fan after the scheduler was stopped
+        fs::write(hwmon_path.join("pwm1"), "0").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(fs::read_to_string(hwmon_path.join("pwm1")).unwrap(), "0");
+
+        fs::remove_dir_all(&hwmon_path).unwrap();
+    }
+}
@@ -1,9 +1,21 @@
 use crate::config::{GpuConfig, GpuIdentifier};
+use crate::fan_calibration::{FanCalibration, FanCalibrationStatus};
+use crate::fan_control::{
+    validate_curve, FanCurveOptions, FanFault, FanLimits, FanMode, FanSmoothing, FanTempSource,
+};
+use crate::fan_health::{FanHealth, FanHealthSettings};
+use crate::fan_scheduler::FanControlStatus;
 use crate::hw_mon::{HWMon, HWMonError};
+use crate::idle::{IdleSettings, IdleStatus};
+use crate::power_guard::{PowerGuardSettings, PowerGuardStatus};
+use crate::power_source::{PowerSource, PowerSourceProfiles};
+use crate::process_watcher::ProcessRuleMatch;
+use crate::script::{Script, ScriptStatus};
 use pciid_parser::{PciDatabase, VendorData};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::io::Write;
 use std::num::ParseIntError;
 use std::path::PathBuf;
 use vulkano::instance::{Instance, InstanceExtensions, PhysicalDevice};
@@ -13,6 +25,8 @@ pub enum GpuControllerError {
     NotSupported,
     PermissionDenied,
     UnknownError,
+    InvalidValue(String),
+    NotApplied(String),
     ParseError(String),
 }
 
@@ -32,7 +46,31 @@ impl From<ParseIntError> for GpuControllerError {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) fn check_range(
+    name: &str,
+    value: i64,
+    range: (i64, i64),
+    unit: &str,
+) -> Result<(), GpuControllerError> {
+    if value < range.0 || value > range.1 {
+        return Err(GpuControllerError::InvalidValue(format!(
+            "{} {}{} is outside of the allowed range {}-{}{}",
+            name, value, unit, range.0, range.1, unit
+        )));
+    }
+
+    Ok(())
+}
+
+/// Replaces the expected value with the actual one if they don't match
+fn verify_value(name: &str, expected: &mut i64, actual: i64, mismatches: &mut Vec<String>) {
+    if *expected != actual {
+        mismatches.push(format!("{} is {} instead of {}", name, actual, expected));
+        *expected = actual;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum PowerProfile {
     Auto,
     Low,
@@ -85,32 +123,180 @@ pub struct ClocksTableOld {
 pub struct ClocksTableNew {
     pub current_gpu_clocks: (i64, i64),
     pub current_max_mem_clock: i64,
-    // pub vddc_curve: [(i64, i64); 3],
+    pub current_min_mem_clock: Option<i64>, // Only editable on Navi2x and newer
+    pub vddc_curve: Option<[(i64, i64); 3]>, //<(clockspeed, voltage)>, Vega20 and Navi10 only
+    pub voltage_offset: Option<i64>,        // Navi2x and newer, IN MILLIVOLTS
     pub gpu_clocks_range: (i64, i64),
     pub mem_clocks_range: (i64, i64),
-    // pub voltage_range: (i64, i64), //IN MILLIVOLTS
+    pub vddc_curve_clocks_range: [(i64, i64); 3],
+    pub vddc_curve_voltage_range: [(i64, i64); 3], //IN MILLIVOLTS
+    pub voltage_range: (i64, i64),                 //IN MILLIVOLTS
+    pub voltage_offset_range: Option<(i64, i64)>,  //IN MILLIVOLTS
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl ClocksTableNew {
+    pub fn check_vddc_curve_point(
+        &self,
+        num: usize,
+        clockspeed: i64,
+        voltage: i64,
+    ) -> Result<(), GpuControllerError> {
+        if self.vddc_curve.is_none() {
+            return Err(GpuControllerError::NotSupported);
+        }
+
+        let (clocks_range, voltage_range) = match (
+            self.vddc_curve_clocks_range.get(num),
+            self.vddc_curve_voltage_range.get(num),
+        ) {
+            (Some(clocks_range), Some(voltage_range)) => (clocks_range, voltage_range),
+            _ => {
+                return Err(GpuControllerError::InvalidValue(format!(
+                    "invalid voltage curve point {}",
+                    num
+                )))
+            }
+        };
+
+        check_range(
+            &format!("Voltage curve point {} clock", num),
+            clockspeed,
+            *clocks_range,
+            "MHz",
+        )?;
+        check_range(
+            &format!("Voltage curve point {} voltage", num),
+            voltage,
+            *voltage_range,
+            "mV",
+        )?;
+
+        Ok(())
+    }
+}
+
+/// The clocks page of the GUI, applied together by `GpuController::apply_clocks`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ClocksSettings {
+    pub gpu_clock: i64,
+    pub vram_clock: i64,
+    pub gpu_voltage: Option<i64>,
+    pub gpu_min_clock: Option<i64>,
+    pub vram_min_clock: Option<i64>,
+    pub voltage_offset: Option<i64>,
+    pub vddc_curve: Option<Vec<(i64, i64)>>, //<(clockspeed, voltage)>, replaces the min and max GPU clock
+}
+
+/// A single value overdrive fan setting, such as `acoustic_limit_rpm_threshold`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OdFanValue {
+    pub value: i64,
+    pub range: Option<(i64, i64)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OdFanCurve {
+    pub points: Vec<(i64, i64)>, //<(temperature, speed)>, IN °C AND %
+    pub temperature_range: Option<(i64, i64)>,
+    pub speed_range: Option<(i64, i64)>,
+}
+
+/// The overdrive fan settings in gpu_od/fan_ctrl, RDNA3 and newer
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OdFanControls {
+    pub values: BTreeMap<String, OdFanValue>, //<file name, value>
+    pub curve: Option<OdFanCurve>,
+}
+
+impl OdFanControls {
+    pub fn check_value(&self, name: &str, value: i64) -> Result<(), GpuControllerError> {
+        match self.values.get(name) {
+            Some(OdFanValue {
+                range: Some(range), ..
+            }) => check_range(name, value, *range, ""),
+            Some(_) => Ok(()),
+            None => Err(GpuControllerError::InvalidValue(format!(
+                "unknown overdrive fan setting {}",
+                name
+            ))),
+        }
+    }
+
+    pub fn check_curve_point(
+        &self,
+        num: usize,
+        temperature: i64,
+        speed: i64,
+    ) -> Result<(), GpuControllerError> {
+        let curve = self
+            .curve
+            .as_ref()
+            .ok_or(GpuControllerError::NotSupported)?;
+
+        if num >= curve.points.len() {
+            return Err(GpuControllerError::InvalidValue(format!(
+                "invalid fan curve point {}",
+                num
+            )));
+        }
+
+        if let Some(range) = curve.temperature_range {
+            check_range(
+                &format!("Fan curve point {} temperature", num),
+                temperature,
+                range,
+                "°C",
+            )?;
+        }
+        if let Some(range) = curve.speed_range {
+            check_range(&format!("Fan curve point {} speed", num), speed, range, "%")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct GpuStats {
     pub mem_used: Option<u64>,
     pub mem_total: Option<u64>,
     pub mem_freq: Option<i64>,
     pub gpu_freq: Option<i64>,
     pub gpu_temp: Option<i64>,
+    pub junction_temp: Option<i64>,
+    pub mem_temp: Option<i64>,
     pub power_avg: Option<i64>,
-    pub power_cap: Option<i64>,
+    pub power_cap: Option<i64>, // The cap in effect, which the power guard might have lowered
+    pub power_cap_min: Option<i64>,
     pub power_cap_max: Option<i64>,
+    pub power_guard: Option<PowerGuardStatus>, // Filled in by the daemon from the fan scheduler
     pub fan_speed: Option<i64>,
     pub max_fan_speed: Option<i64>,
+    pub fan_pwm: Option<i64>, // 0-255
+    pub fan_calibration: FanCalibrationStatus,
+    pub fan_health: Option<FanHealth>, // Filled in by the daemon from the fan scheduler
     pub voltage: Option<i64>,
     pub gpu_usage: Option<u8>,
+    pub idle: Option<IdleStatus>, // Filled in by the daemon from the fan scheduler
+    pub process_rule: Option<ProcessRuleMatch>, // Filled in by the daemon from the process watcher
+    pub script: Option<ScriptStatus>, // Filled in by the daemon from the fan scheduler
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FanControlInfo {
     pub enabled: bool,
     pub curve: BTreeMap<i64, f64>,
+    pub curve_options: FanCurveOptions,
+    pub smoothing: FanSmoothing,
+    pub temp_source: FanTempSource,
+    pub limits: FanLimits,
+    pub mode: FanMode,
+    pub speed_range: Option<(i64, i64)>, // fan1_min-fan1_max in RPM
+    pub supports_fan_target: bool,
+    pub fault: Option<FanFault>, // Set when the fan control loop had to stop
+    pub calibration: Option<FanCalibration>,
+    pub health_settings: FanHealthSettings,
+    pub status: Option<FanControlStatus>,
 }
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct VulkanInfo {
@@ -134,7 +320,14 @@ pub struct GpuInfo {
     pub power_profile: Option<PowerProfile>,
     pub clocks_table: Option<ClocksTable>,
     pub power_cap: Option<i64>,
+    pub power_cap_min: Option<i64>,
     pub power_cap_max: Option<i64>,
+    pub power_guard: PowerGuardSettings,
+    pub idle: IdleSettings,
+    pub script: Option<String>,
+    pub power_source: Option<PowerSource>, // Filled in by the daemon, `None` without a system battery
+    pub power_source_profiles: PowerSourceProfiles, // Filled in by the daemon from the config
+    pub od_fan_controls: Option<OdFanControls>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -162,16 +355,21 @@ impl GpuController {
     }
 
     pub fn load_config(&mut self, config: &GpuConfig) {
+        // The setters below update the values that actually got applied
+        self.config = config.clone();
+
         self.hw_mon = match fs::read_dir(self.hw_path.join("hwmon")) {
             Ok(mut path) => {
                 let path = path.next().unwrap().unwrap().path();
-                let hw_mon = HWMon::new(
-                    &path,
-                    config.fan_control_enabled,
-                    config.fan_curve.clone(),
-                    Some(config.power_cap),
-                );
-                Some(hw_mon)
+
+                // Kept when it's the same, as the fan scheduler shares it
+                match self.hw_mon.take() {
+                    Some(hw_mon) if hw_mon.get_path() == path => {
+                        hw_mon.load_config(config);
+                        Some(hw_mon)
+                    }
+                    _ => Some(HWMon::new(&path, config)),
+                }
             }
             _ => None,
         };
@@ -180,12 +378,91 @@ impl GpuController {
         {
             self.set_power_profile(config.power_profile.clone());
 
-            self.set_gpu_max_power_state(config.gpu_max_clock, config.gpu_max_voltage);
+            // 0 means that the clock was never changed
+            if config.gpu_max_clock != 0 {
+                self.set_gpu_max_power_state(config.gpu_max_clock, config.gpu_max_voltage);
+            }
+
+            if config.vram_max_clock != 0 {
+                self.set_vram_max_clockspeed(config.vram_max_clock);
+            }
+
+            if let Some(clockspeed) = config.gpu_min_clock {
+                self.set_gpu_min_clockspeed(clockspeed);
+            }
+
+            if let Some(clockspeed) = config.vram_min_clock {
+                self.set_vram_min_clockspeed(clockspeed);
+            }
+
+            if let Some(offset) = config.voltage_offset {
+                self.set_voltage_offset(offset);
+            }
 
-            self.set_vram_max_clockspeed(config.gpu_max_clock);
+            for (num, (clockspeed, voltage)) in &config.vddc_curve {
+                self.set_vddc_curve_point(*num, *clockspeed, *voltage);
+            }
 
             self.commit_gpu_power_states();
+
+            for (name, value) in &config.od_fan_values {
+                self.set_od_fan_value(name, *value);
+            }
+
+            for (num, (temperature, speed)) in &config.od_fan_curve {
+                self.set_od_fan_curve_point(*num, *temperature, *speed);
+            }
+        }
+    }
+
+    /// Switches to the settings of a profile, undoing the changes the profile doesn't make
+    pub fn apply_profile(&mut self, profile: &GpuConfig) {
+        let mut config = profile.clone();
+
+        // The calibration belongs to the fan rather than to a profile
+        config.fan_calibration = self.config.fan_calibration.clone();
+
+        // Loading the config only takes over the fan, it doesn't give it back
+        if self.config.fan_control_enabled && !config.fan_control_enabled {
+            if let Err(e) = self.stop_fan_control() {
+                log::error!("Failed to stop fan control: {:?}", e);
+            }
+        }
+
+        if config.power_cap < 0 {
+            if let Some(hw_mon) = &self.hw_mon {
+                if let Some(default_cap) = hw_mon.get_power_cap_default() {
+                    config.power_cap = default_cap;
+                }
+            }
+        }
+
+        let old_config = &self.config;
+        let clocks_changed = old_config.gpu_max_clock != 0
+            || old_config.vram_max_clock != 0
+            || old_config.gpu_min_clock.is_some()
+            || old_config.vram_min_clock.is_some()
+            || old_config.voltage_offset.is_some()
+            || !old_config.vddc_curve.is_empty();
+        let od_fan_changed =
+            !old_config.od_fan_values.is_empty() || !old_config.od_fan_curve.is_empty();
+
+        if clocks_changed {
+            if let Err(e) = self
+                .reset_gpu_power_states()
+                .and_then(|_| self.commit_gpu_power_states())
+            {
+                log::error!("Failed to reset the clocks: {:?}", e);
+            }
+        }
+
+        if od_fan_changed {
+            if let Err(e) = self.reset_od_fan_controls() {
+                log::error!("Failed to reset the overdrive fan settings: {:?}", e);
+            }
         }
+
+        self.load_config(&config);
     }
 
     pub fn get_config(&self) -> GpuConfig {
@@ -215,9 +492,15 @@ impl GpuController {
             Err(_) => None,
         };
 
+        info.od_fan_controls = self.get_od_fan_controls().ok();
+
         if let Some(hw_mon) = &self.hw_mon {
             info.power_cap = hw_mon.get_power_cap();
+            info.power_cap_min = hw_mon.get_power_cap_min();
             info.power_cap_max = hw_mon.get_power_cap_max();
+            info.power_guard = hw_mon.get_power_guard_settings();
+            info.idle = hw_mon.get_idle_settings();
+            info.script = hw_mon.get_script();
         }
 
         info
@@ -325,65 +608,26 @@ impl GpuController {
             power_profile: None,
             clocks_table: None,
             power_cap: None,
+            power_cap_min: None,
             power_cap_max: None,
+            power_guard: PowerGuardSettings::default(),
+            idle: IdleSettings::default(),
+            script: None,
+            power_source: None,
+            power_source_profiles: PowerSourceProfiles::default(),
+            od_fan_controls: None,
         }
     }
 
     pub fn get_stats(&self) -> Result<GpuStats, HWMonError> {
-        let mem_total = match fs::read_to_string(self.hw_path.join("mem_info_vram_total")) {
-            Ok(a) => Some(a.trim().parse::<u64>().unwrap() / 1024 / 1024),
-            Err(_) => None,
-        };
-
-        let mem_used = match fs::read_to_string(self.hw_path.join("mem_info_vram_used")) {
-            Ok(a) => Some(a.trim().parse::<u64>().unwrap() / 1024 / 1024),
-            Err(_) => None,
-        };
-
-        let gpu_usage = match fs::read_to_string(self.hw_path.join("gpu_busy_percent")) {
-            Ok(a) => Some(a.trim().parse::<u8>().unwrap()),
-            Err(_) => None,
-        };
-
-        let (
-            mem_freq,
-            gpu_freq,
-            gpu_temp,
-            power_avg,
-            power_cap,
-            power_cap_max,
-            fan_speed,
-            max_fan_speed,
-            voltage,
-        ) = match &self.hw_mon {
-            Some(hw_mon) => (
-                hw_mon.get_mem_freq(),
-                hw_mon.get_gpu_freq(),
-                hw_mon.get_gpu_temp(),
-                hw_mon.get_power_avg(),
-                hw_mon.get_power_cap(),
-                hw_mon.get_power_cap_max(),
-                hw_mon.get_fan_speed(),
-                hw_mon.get_fan_max_speed(),
-                hw_mon.get_voltage(),
-            ),
-            None => return Err(HWMonError::NoHWMon),
-        };
+        match &self.hw_mon {
+            Some(hw_mon) => Ok(hw_mon.get_stats()),
+            None => Err(HWMonError::NoHWMon),
+        }
+    }
 
-        Ok(GpuStats {
-            mem_total,
-            mem_used,
-            mem_freq,
-            gpu_freq,
-            gpu_temp,
-            power_avg,
-            power_cap,
-            power_cap_max,
-            fan_speed,
-            max_fan_speed,
-            voltage,
-            gpu_usage,
-        })
+    pub fn get_hw_mon(&self) -> Option<HWMon> {
+        self.hw_mon.clone()
     }
 
     pub fn start_fan_control(&mut self) -> Result<(), HWMonError> {
@@ -420,6 +664,19 @@ impl GpuController {
                     Ok(FanControlInfo {
                         enabled: control.0,
                         curve: control.1,
+                        curve_options: hw_mon.get_fan_curve_options(),
+                        smoothing: hw_mon.get_fan_smoothing(),
+                        temp_source: hw_mon.get_fan_temp_source(),
+                        limits: hw_mon.get_fan_limits(),
+                        mode: hw_mon.get_fan_mode(),
+                        speed_range: hw_mon
+                            .get_fan_max_speed()
+                            .map(|max| (hw_mon.get_fan_min_speed().unwrap_or(0), max)),
+                        supports_fan_target: hw_mon.supports_fan_target(),
+                        fault: hw_mon.get_fan_fault(),
+                        calibration: hw_mon.get_fan_calibration(),
+                        health_settings: hw_mon.get_fan_health_settings(),
+                        status: None, // Filled in by the daemon from the fan scheduler
                     })
                 }
                 None => Err(HWMonError::Unsupported),
@@ -428,11 +685,125 @@ impl GpuController {
         }
     }
 
-    pub fn set_fan_curve(&mut self, curve: BTreeMap<i64, f64>) -> Result<(), HWMonError> {
+    /// Replaces the fan curve and how it's interpolated, validating the curve against the new options
+    pub fn set_fan_curve(
+        &mut self,
+        curve: BTreeMap<i64, f64>,
+        options: FanCurveOptions,
+    ) -> Result<(), GpuControllerError> {
         match &self.hw_mon {
             Some(hw_mon) => {
+                let max_rpm = hw_mon.get_fan_calibration().map(|c| c.max_rpm());
+
+                validate_curve(&curve, &options, max_rpm)
+                    .map_err(GpuControllerError::InvalidValue)?;
+
                 hw_mon.set_fan_curve(curve.clone());
+                hw_mon.set_fan_curve_options(options.clone());
                 self.config.fan_curve = curve;
+                self.config.fan_curve_options = options;
+                Ok(())
+            }
+            None => Err(GpuControllerError::NotSupported),
+        }
+    }
+
+    pub fn start_fan_calibration(&self) -> Result<(), HWMonError> {
+        match &self.hw_mon {
+            Some(hw_mon) => hw_mon.start_fan_calibration(),
+            None => Err(HWMonError::NoHWMon),
+        }
+    }
+
+    /// Stores a fan calibration that finished in the background in the config.
+    /// Returns true if the config changed.
+    pub fn update_fan_calibration(&mut self) -> bool {
+        if let Some(hw_mon) = &self.hw_mon {
+            let calibration = hw_mon.get_fan_calibration();
+
+            if calibration != self.config.fan_calibration {
+                self.config.fan_calibration = calibration;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn set_fan_smoothing(&mut self, smoothing: FanSmoothing) -> Result<(), HWMonError> {
+        match &self.hw_mon {
+            Some(hw_mon) => {
+                hw_mon.set_fan_smoothing(smoothing.clone());
+                self.config.fan_smoothing = smoothing;
+                Ok(())
+            }
+            None => Err(HWMonError::NoHWMon),
+        }
+    }
+
+    pub fn set_fan_temp_source(&mut self, source: FanTempSource) -> Result<(), HWMonError> {
+        match &self.hw_mon {
+            Some(hw_mon) => {
+                hw_mon.set_fan_temp_source(source.clone());
+                self.config.fan_temp_source = source;
+                Ok(())
+            }
+            None => Err(HWMonError::NoHWMon),
+        }
+    }
+
+    pub fn set_fan_limits(&mut self, limits: FanLimits) -> Result<(), HWMonError> {
+        match &self.hw_mon {
+            Some(hw_mon) => {
+                hw_mon.set_fan_limits(limits.clone());
+                self.config.fan_limits = limits;
+                Ok(())
+            }
+            None => Err(HWMonError::NoHWMon),
+        }
+    }
+
+    pub fn set_fan_health_settings(
+        &mut self,
+        settings: FanHealthSettings,
+    ) -> Result<(), GpuControllerError> {
+        if !(0.0..1.0).contains(&settings.underspeed_tolerance) {
+            return Err(GpuControllerError::InvalidValue(
+                "the underspeed tolerance has to be between 0 and 1".to_string(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&settings.wear_threshold) {
+            return Err(GpuControllerError::InvalidValue(
+                "the wear threshold has to be between 0 and 1".to_string(),
+            ));
+        }
+
+        match &self.hw_mon {
+            Some(hw_mon) => {
+                if let Some(cap) = settings.action.power_cap {
+                    let max = hw_mon.get_power_cap_max().unwrap_or(i64::MAX);
+
+                    if cap <= 0 || cap > max {
+                        return Err(GpuControllerError::InvalidValue(format!(
+                            "the power cap has to be between 1 and {}W",
+                            max
+                        )));
+                    }
+                }
+
+                hw_mon.set_fan_health_settings(settings.clone());
+                self.config.fan_health = settings;
+                Ok(())
+            }
+            None => Err(GpuControllerError::NotSupported),
+        }
+    }
+
+    pub fn set_fan_mode(&mut self, mode: FanMode) -> Result<(), HWMonError> {
+        match &self.hw_mon {
+            Some(hw_mon) => {
+                hw_mon.set_fan_mode(mode.clone())?;
+                self.config.fan_mode = mode;
                 Ok(())
             }
             None => Err(HWMonError::NoHWMon),
@@ -442,7 +813,7 @@ impl GpuController {
     pub fn set_power_cap(&mut self, cap: i64) -> Result<(), HWMonError> {
         match &mut self.hw_mon {
             Some(hw_mon) => {
-                hw_mon.set_power_cap(cap).unwrap();
+                hw_mon.set_power_cap(cap)?;
                 self.config.power_cap = cap;
                 Ok(())
             }
@@ -450,6 +821,63 @@ impl GpuController {
         }
     }
 
+    pub fn set_power_guard(
+        &mut self,
+        settings: PowerGuardSettings,
+    ) -> Result<(), GpuControllerError> {
+        settings
+            .validate()
+            .map_err(GpuControllerError::InvalidValue)?;
+
+        match &self.hw_mon {
+            Some(hw_mon) => {
+                if hw_mon.get_power_cap().is_none() {
+                    return Err(GpuControllerError::NotSupported);
+                }
+
+                hw_mon.set_power_guard_settings(settings.clone());
+                self.config.power_guard = settings;
+                Ok(())
+            }
+            None => Err(GpuControllerError::NotSupported),
+        }
+    }
+
+    pub fn set_idle_settings(&mut self, settings: IdleSettings) -> Result<(), GpuControllerError> {
+        settings
+            .validate()
+            .map_err(GpuControllerError::InvalidValue)?;
+
+        match &self.hw_mon {
+            Some(hw_mon) => {
+                if hw_mon.get_busy_percent().is_none() {
+                    return Err(GpuControllerError::NotSupported);
+                }
+
+                hw_mon.set_idle_settings(settings.clone());
+                self.config.idle = settings;
+                Ok(())
+            }
+            None => Err(GpuControllerError::NotSupported),
+        }
+    }
+
+    /// Replaces the control script, which has to compile. `None` goes back to the regular settings.
+    pub fn set_script(&mut self, script: Option<String>) -> Result<(), GpuControllerError> {
+        if let Some(source) = &script {
+            Script::compile(source).map_err(GpuControllerError::InvalidValue)?;
+        }
+
+        match &self.hw_mon {
+            Some(hw_mon) => {
+                hw_mon.set_script(script.clone());
+                self.config.script = script;
+                Ok(())
+            }
+            None => Err(GpuControllerError::NotSupported),
+        }
+    }
+
     pub fn get_power_cap(&self) -> Result<(i64, i64), HWMonError> {
         match &self.hw_mon {
             Some(hw_mon) => {
@@ -493,11 +921,112 @@ impl GpuController {
         }
     }
 
+    fn get_od_fan_controls(&self) -> Result<OdFanControls, GpuControllerError> {
+        let mut controls = OdFanControls::default();
+
+        for entry in fs::read_dir(self.hw_path.join("gpu_od/fan_ctrl"))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let contents = fs::read_to_string(entry.path())?;
+
+            // Sections added by newer kernels are skipped until they're known
+            let result = if name == "fan_curve" {
+                Self::parse_od_fan_curve(&contents).map(|curve| controls.curve = Some(curve))
+            } else {
+                Self::parse_od_fan_value(&contents).map(|value| {
+                    controls.values.insert(name.clone(), value);
+                })
+            };
+
+            if let Err(e) = result {
+                log::warn!("Failed to parse gpu_od/fan_ctrl/{}: {:?}", name, e);
+            }
+        }
+
+        if controls.values.is_empty() && controls.curve.is_none() {
+            return Err(GpuControllerError::NotSupported);
+        }
+
+        Ok(controls)
+    }
+
+    /// Parses sections such as `OD_ACOUSTIC_LIMIT:\n2450\nOD_RANGE:\nACOUSTIC_LIMIT: 500 3100`
+    fn parse_od_fan_value(contents: &str) -> Result<OdFanValue, GpuControllerError> {
+        let mut lines = contents
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .skip(1);
+
+        let value = lines
+            .next()
+            .ok_or_else(|| GpuControllerError::ParseError("missing value".to_string()))?
+            .parse()?;
+
+        let range = match lines.skip_while(|line| *line != "OD_RANGE:").nth(1) {
+            Some(line) => {
+                let mut split = line
+                    .split(':')
+                    .nth(1)
+                    .ok_or_else(|| {
+                        GpuControllerError::ParseError(format!("invalid range line {}", line))
+                    })?
+                    .split_whitespace();
+
+                Some(Self::parse_range_values(&mut split, "")?)
+            }
+            None => None,
+        };
+
+        Ok(OdFanValue { value, range })
+    }
+
+    /// Parses the `OD_FAN_CURVE` section, with points such as `0: 25C 15%`
+    fn parse_od_fan_curve(contents: &str) -> Result<OdFanCurve, GpuControllerError> {
+        let mut curve = OdFanCurve::default();
+        let mut in_range = false;
+
+        for line in contents.lines().map(|line| line.trim()) {
+            match line {
+                "" | "OD_FAN_CURVE:" => (),
+                "OD_RANGE:" => in_range = true,
+                line => {
+                    let (name, values) = line.split_once(':').ok_or_else(|| {
+                        GpuControllerError::ParseError(format!("invalid fan curve line {}", line))
+                    })?;
+                    let mut split = values.split_whitespace();
+
+                    if !in_range {
+                        let temperature = Self::parse_unit_value(split.next(), "c")?;
+                        let speed = Self::parse_unit_value(split.next(), "%")?;
+
+                        curve.points.push((temperature, speed));
+                    } else if name.to_lowercase().contains("temp") {
+                        curve.temperature_range = Some(Self::parse_range_values(&mut split, "c")?);
+                    } else if name.to_lowercase().contains("speed") {
+                        curve.speed_range = Some(Self::parse_range_values(&mut split, "%")?);
+                    }
+                }
+            }
+        }
+
+        Ok(curve)
+    }
+
     fn parse_clocks_table(table: &str) -> Result<ClocksTable, GpuControllerError> {
-        if table.contains("CURVE") {
-            Ok(ClocksTable::New(Self::parse_clocks_table_new(table)?))
-        } else {
+        // Only the tables before Vega20 have a voltage for every power level
+        let is_old = table
+            .lines()
+            .map(|line| line.trim())
+            .skip_while(|line| *line != "OD_SCLK:")
+            .nth(1)
+            .map(|line| line.to_lowercase().ends_with("mv"))
+            .unwrap_or(false);
+
+        if is_old {
             Ok(ClocksTable::Old(Self::parse_clocks_table_old(table)?))
+        } else {
+            Ok(ClocksTable::New(Self::parse_clocks_table_new(table)?))
         }
     }
 
@@ -620,7 +1149,7 @@ impl GpuController {
 
         let mut clocks_table = ClocksTableNew::default();
 
-        let mut lines_iter = table.trim().split("\n").into_iter();
+        let mut lines_iter = table.trim().split("\n").peekable();
 
         log::trace!("Reading clocks table");
 
@@ -629,151 +1158,147 @@ impl GpuController {
             log::trace!("Parsing line {}", line);
 
             match line {
-                "OD_SCLK:" => {
-                    let min_clock_line = lines_iter
+                "OD_SCLK:" | "OD_MCLK:" => {
+                    // Vega20 and Navi10 only list the maximum VRAM clock, newer generations list both
+                    let mut clocks = BTreeMap::new();
+
+                    while let Some(&line) = lines_iter.peek() {
+                        let line = line.trim();
+
+                        if !line.starts_with(|c: char| c.is_ascii_digit()) {
+                            break;
+                        }
+
+                        let (num, clock) = Self::parse_clock_line(line)?;
+                        log::trace!("Clock level {}: {}MHz", num, clock);
+
+                        clocks.insert(num, clock);
+                        lines_iter.next();
+                    }
+
+                    let max_clock = *clocks.get(&1).ok_or_else(|| {
+                        GpuControllerError::ParseError(format!("missing maximum clock in {}", line))
+                    })?;
+                    let min_clock = clocks.get(&0).copied();
+
+                    if line == "OD_SCLK:" {
+                        let min_clock = min_clock.ok_or_else(|| {
+                            GpuControllerError::ParseError("missing minimum GPU clock".to_string())
+                        })?;
+
+                        clocks_table.current_gpu_clocks = (min_clock, max_clock);
+                    } else {
+                        clocks_table.current_max_mem_clock = max_clock;
+                        clocks_table.current_min_mem_clock = min_clock;
+                    }
+                }
+                "OD_VDDGFX_OFFSET:" => {
+                    let offset_line = lines_iter
                         .next()
                         .ok_or_else(|| {
-                            GpuControllerError::ParseError(
-                                "unexpeceted clocks file end".to_string(),
-                            )
+                            GpuControllerError::ParseError("unexpected clocks file end".to_string())
                         })?
                         .trim()
                         .to_lowercase();
 
-                    let min_clock: i64 = min_clock_line
-                        .strip_prefix("0:")
-                        .ok_or_else(|| {
-                            GpuControllerError::ParseError(format!(
-                                "invalid clock line prefix in {}",
-                                min_clock_line
-                            ))
-                        })?
-                        .strip_suffix("mhz")
+                    let offset = offset_line
+                        .strip_suffix("mv")
                         .ok_or_else(|| {
                             GpuControllerError::ParseError(format!(
-                                "invalid clock line suffix in {}",
-                                min_clock_line
+                                "invalid voltage offset suffix in {}",
+                                offset_line
                             ))
                         })?
                         .trim()
                         .parse()?;
 
-                    let max_clock_line = lines_iter
-                        .next()
-                        .ok_or_else(|| {
-                            GpuControllerError::ParseError(
-                                "unexpeceted clocks file end".to_string(),
-                            )
-                        })?
-                        .trim()
-                        .to_lowercase();
+                    clocks_table.voltage_offset = Some(offset);
+                }
+                "OD_VDDC_CURVE:" => {
+                    let mut vddc_curve = [(0, 0); 3];
 
-                    let max_clock: i64 = max_clock_line
-                        .strip_prefix("1:")
-                        .ok_or_else(|| {
-                            GpuControllerError::ParseError(format!(
-                                "invalid clock line prefix in {}",
-                                min_clock_line
-                            ))
-                        })?
-                        .strip_suffix("mhz")
-                        .ok_or_else(|| {
-                            GpuControllerError::ParseError(format!(
-                                "invalid clock line suffix in {}",
-                                min_clock_line
-                            ))
-                        })?
-                        .trim()
-                        .parse()?;
-
-                    clocks_table.current_gpu_clocks = (min_clock, max_clock);
-                }
-                "OD_MCLK:" => {
-                    let max_clock_line = lines_iter
-                        .next()
-                        .ok_or_else(|| {
+                    for _ in 0..vddc_curve.len() {
+                        let line = lines_iter.next().ok_or_else(|| {
                             GpuControllerError::ParseError("unexpected clocks file end".to_string())
-                        })?
-                        .trim()
-                        .to_lowercase();
+                        })?;
 
-                    let max_clock = max_clock_line
-                        .strip_prefix("1:")
-                        .ok_or_else(|| {
-                            GpuControllerError::ParseError(format!(
-                                "invalid clock line prefix in {}",
-                                max_clock_line
-                            ))
-                        })?
-                        .strip_suffix("mhz")
-                        .ok_or_else(|| {
+                        let (num, clock, voltage) =
+                            GpuController::parse_clock_voltage_line(line.trim())?;
+
+                        log::trace!("VDDC curve point {}: {}MHz {}mV", num, clock, voltage);
+
+                        let point = vddc_curve.get_mut(num as usize).ok_or_else(|| {
                             GpuControllerError::ParseError(format!(
-                                "invalid clock line suffix in {}",
-                                max_clock_line
+                                "invalid VDDC curve point {}",
+                                num
                             ))
-                        })?
-                        .trim()
-                        .parse()?;
+                        })?;
+
+                        *point = (clock, voltage);
+                    }
 
-                    clocks_table.current_max_mem_clock = max_clock;
+                    clocks_table.vddc_curve = Some(vddc_curve);
                 }
                 "OD_RANGE:" => {
                     while let Some(line) = &lines_iter.next() {
                         let line = line.trim();
                         log::trace!("Parsing OD_RANGE line {}", &line);
 
-                        match &line[..5] {
-                            "SCLK:" => {
-                                let mut split = line.split_whitespace();
+                        let mut split = line.split_whitespace();
 
-                                // Skips the 'SCLK'
-                                split.next().unwrap();
+                        let name = split.next().unwrap_or_default().trim_end_matches(':');
 
-                                let min_clock = split
-                                    .next()
-                                    .unwrap()
-                                    .strip_suffix("Mhz")
-                                    .ok_or_else(|| {
-                                        GpuControllerError::ParseError("missing suffix".to_string())
-                                    })?
-                                    .parse()?;
-
-                                let max_clock = split
-                                    .next()
-                                    .unwrap()
-                                    .strip_suffix("Mhz")
+                        match name {
+                            "SCLK" => {
+                                clocks_table.gpu_clocks_range =
+                                    Self::parse_range_values(&mut split, "mhz")?;
+                            }
+                            "MCLK" => {
+                                clocks_table.mem_clocks_range =
+                                    Self::parse_range_values(&mut split, "mhz")?;
+                            }
+                            "VDDGFX_OFFSET" => {
+                                clocks_table.voltage_offset_range =
+                                    Some(Self::parse_range_values(&mut split, "mv")?);
+                            }
+                            _ if name.starts_with("VDDC_CURVE_SCLK[") => {
+                                let num = Self::parse_curve_point_index(name)?;
+
+                                let range = clocks_table
+                                    .vddc_curve_clocks_range
+                                    .get_mut(num)
                                     .ok_or_else(|| {
-                                        GpuControllerError::ParseError("missing suffix".to_string())
-                                    })?
-                                    .parse()?;
+                                        GpuControllerError::ParseError(format!(
+                                            "invalid VDDC curve point {}",
+                                            num
+                                        ))
+                                    })?;
 
-                                clocks_table.gpu_clocks_range = (min_clock, max_clock);
+                                *range = Self::parse_range_values(&mut split, "mhz")?;
                             }
-                            "MCLK:" => {
-                                let mut split = line.split_whitespace();
+                            _ if name.starts_with("VDDC_CURVE_VOLT[") => {
+                                let num = Self::parse_curve_point_index(name)?;
 
-                                // Skips the 'SCLK'
-                                split.next().unwrap();
+                                let (min_voltage, max_voltage) =
+                                    Self::parse_range_values(&mut split, "mv")?;
 
-                                let min_clock = split
-                                    .next()
-                                    .unwrap()
-                                    .strip_suffix("Mhz")
+                                let range = clocks_table
+                                    .vddc_curve_voltage_range
+                                    .get_mut(num)
                                     .ok_or_else(|| {
-                                        GpuControllerError::ParseError("missing suffix".to_string())
-                                    })?
-                                    .parse()?;
-
-                                let max_clock = split
-                                    .next()
-                                    .unwrap()
-                                    .strip_suffix("Mhz")
-                                    .ok_or_else(|| {
-                                        GpuControllerError::ParseError("missing suffix".to_string())
-                                    })?
-                                    .parse()?;
-
-                                clocks_table.mem_clocks_range = (min_clock, max_clock);
+                                        GpuControllerError::ParseError(format!(
+                                            "invalid VDDC curve point {}",
+                                            num
+                                        ))
+                                    })?;
+
+                                *range = (min_voltage, max_voltage);
+
+                                // The overall voltage range covers all of the curve points
+                                clocks_table.voltage_range = match clocks_table.voltage_range {
+                                    (0, 0) => (min_voltage, max_voltage),
+                                    (min, max) => (min.min(min_voltage), max.max(max_voltage)),
+                                };
                             }
                             _ => {
                                 log::trace!("OD_RANGE ended");
@@ -824,6 +1349,17 @@ impl GpuController {
     ) -> Result<(), GpuControllerError> {
         match self.get_clocks_table()? {
             ClocksTable::Old(clocks_table) => {
+                check_range(
+                    "GPU clock",
+                    clockspeed,
+                    clocks_table.gpu_clocks_range,
+                    "MHz",
+                )?;
+
+                if let Some(voltage) = voltage {
+                    check_range("GPU voltage", voltage, clocks_table.voltage_range, "mV")?;
+                }
+
                 let profile = { clocks_table.gpu_power_levels.iter().next_back().unwrap().0 };
 
                 let mut line = format!("s {} {}", profile, clockspeed);
@@ -833,32 +1369,102 @@ impl GpuController {
                 }
                 line.push_str("\n");
 
-                log::info!("Writing {} to pp_od_clk_voltage", line);
-
-                fs::write(self.hw_path.join("pp_od_clk_voltage"), line)?;
+                self.write_clocks_line(&line)?;
 
                 self.config.gpu_max_clock = clockspeed;
                 self.config.gpu_max_voltage = voltage;
             }
-            ClocksTable::New(_) => {
-                let s_line = format!("s 1 {}\n", clockspeed);
+            ClocksTable::New(clocks_table) => {
+                // Navi2x and newer don't have a voltage curve, only an offset
+                if voltage.is_some() && clocks_table.vddc_curve.is_none() {
+                    return Err(GpuControllerError::NotSupported);
+                }
 
-                fs::write(self.hw_path.join("pp_od_clk_voltage"), s_line)?;
+                check_range(
+                    "GPU clock",
+                    clockspeed,
+                    clocks_table.gpu_clocks_range,
+                    "MHz",
+                )?;
 
                 if let Some(voltage) = voltage {
-                    let vc_line = format!("vc 2 {} {}\n", clockspeed, voltage);
+                    clocks_table.check_vddc_curve_point(2, clockspeed, voltage)?;
+                }
+
+                // The last curve point is the max clock too, so a `s 1` line before it would be overridden
+                let line = match voltage {
+                    Some(voltage) => format!("vc 2 {} {}\n", clockspeed, voltage),
+                    None => format!("s 1 {}\n", clockspeed),
+                };
 
-                    fs::write(self.hw_path.join("pp_od_clk_voltage"), vc_line)?;
+                self.write_clocks_line(&line)?;
+
+                if let Some(voltage) = voltage {
+                    self.config.vddc_curve.insert(2, (clockspeed, voltage));
                 }
+
+                self.config.gpu_max_clock = clockspeed;
+                self.config.gpu_max_voltage = voltage;
             }
         }
 
         Ok(())
     }
 
+    pub fn set_gpu_min_clockspeed(&mut self, clockspeed: i64) -> Result<(), GpuControllerError> {
+        match self.get_clocks_table()? {
+            ClocksTable::New(clocks_table) => {
+                check_range(
+                    "GPU min clock",
+                    clockspeed,
+                    clocks_table.gpu_clocks_range,
+                    "MHz",
+                )?;
+
+                let line = format!("s 0 {}\n", clockspeed);
+
+                self.write_clocks_line(&line)?;
+
+                self.config.gpu_min_clock = Some(clockspeed);
+
+                Ok(())
+            }
+            ClocksTable::Old(_) => Err(GpuControllerError::NotSupported),
+        }
+    }
+
+    pub fn set_vddc_curve_point(
+        &mut self,
+        num: u32,
+        clockspeed: i64,
+        voltage: i64,
+    ) -> Result<(), GpuControllerError> {
+        match self.get_clocks_table()? {
+            ClocksTable::New(clocks_table) => {
+                clocks_table.check_vddc_curve_point(num as usize, clockspeed, voltage)?;
+
+                let line = format!("vc {} {} {}\n", num, clockspeed, voltage);
+
+                self.write_clocks_line(&line)?;
+
+                self.config.vddc_curve.insert(num, (clockspeed, voltage));
+
+                Ok(())
+            }
+            ClocksTable::Old(_) => Err(GpuControllerError::NotSupported),
+        }
+    }
+
     pub fn set_vram_max_clockspeed(&mut self, clockspeed: i64) -> Result<(), GpuControllerError> {
         match self.get_clocks_table()? {
             ClocksTable::Old(clocks_table) => {
+                check_range(
+                    "VRAM clock",
+                    clockspeed,
+                    clocks_table.mem_clocks_range,
+                    "MHz",
+                )?;
+
                 let (profile, voltage) = {
                     let power_level = clocks_table.mem_power_levels.iter().next_back().unwrap();
                     (power_level.0, power_level.1 .1)
@@ -866,34 +1472,309 @@ impl GpuController {
 
                 let line = format!("m {} {} {}\n", profile, clockspeed, voltage);
 
-                log::info!("Writing {} to pp_od_clk_voltage", line);
+                self.write_clocks_line(&line)?;
+            }
+            ClocksTable::New(clocks_table) => {
+                check_range(
+                    "VRAM clock",
+                    clockspeed,
+                    clocks_table.mem_clocks_range,
+                    "MHz",
+                )?;
+
+                let line = format!("m 1 {}\n", clockspeed);
+
+                self.write_clocks_line(&line)?;
+            }
+        }
+
+        self.config.vram_max_clock = clockspeed;
+
+        Ok(())
+    }
+
+    pub fn set_vram_min_clockspeed(&mut self, clockspeed: i64) -> Result<(), GpuControllerError> {
+        match self.get_clocks_table()? {
+            ClocksTable::New(clocks_table) if clocks_table.current_min_mem_clock.is_some() => {
+                // The default minimum is usually below the overdrive range, so it can't be written back
+                if clocks_table.current_min_mem_clock != Some(clockspeed) {
+                    check_range(
+                        "VRAM min clock",
+                        clockspeed,
+                        clocks_table.mem_clocks_range,
+                        "MHz",
+                    )?;
+                }
+
+                let line = format!("m 0 {}\n", clockspeed);
+
+                self.write_clocks_line(&line)?;
+
+                self.config.vram_min_clock = Some(clockspeed);
+
+                Ok(())
+            }
+            _ => Err(GpuControllerError::NotSupported),
+        }
+    }
+
+    pub fn set_voltage_offset(&mut self, offset: i64) -> Result<(), GpuControllerError> {
+        match self.get_clocks_table()? {
+            ClocksTable::New(clocks_table) if clocks_table.voltage_offset.is_some() => {
+                if let Some(voltage_offset_range) = clocks_table.voltage_offset_range {
+                    check_range("Voltage offset", offset, voltage_offset_range, "mV")?;
+                }
+
+                let line = format!("vo {}\n", offset);
+
+                self.write_clocks_line(&line)?;
+
+                self.config.voltage_offset = Some(offset);
+
+                Ok(())
+            }
+            _ => Err(GpuControllerError::NotSupported),
+        }
+    }
 
-                fs::write(self.hw_path.join("pp_od_clk_voltage"), line)?;
+    /// Sets everything the clocks page of the GUI shows, without committing.
+    /// With a voltage curve the first and last points are the min and max clock,
+    /// so they aren't written on their own as well.
+    pub fn apply_clocks(&mut self, settings: &ClocksSettings) -> Result<(), GpuControllerError> {
+        match &settings.vddc_curve {
+            Some(vddc_curve) => {
+                for (num, (clockspeed, voltage)) in vddc_curve.iter().enumerate() {
+                    self.set_vddc_curve_point(num as u32, *clockspeed, *voltage)?;
+                }
 
-                // self.config
-                // .gpu_power_states
-                // .insert(*profile, (clockspeed, voltage.unwrap()));
+                self.config.gpu_max_clock = 0;
+                self.config.gpu_max_voltage = None;
+                self.config.gpu_min_clock = None;
             }
-            ClocksTable::New(_) => {
-                let s_line = format!("m 1 {}\n", clockspeed);
+            None => {
+                self.set_gpu_max_power_state(settings.gpu_clock, settings.gpu_voltage)?;
 
-                fs::write(self.hw_path.join("pp_od_clk_voltage"), s_line)?;
+                if let Some(clockspeed) = settings.gpu_min_clock {
+                    self.set_gpu_min_clockspeed(clockspeed)?;
+                }
             }
         }
 
+        self.set_vram_max_clockspeed(settings.vram_clock)?;
+
+        if let Some(clockspeed) = settings.vram_min_clock {
+            self.set_vram_min_clockspeed(clockspeed)?;
+        }
+
+        if let Some(offset) = settings.voltage_offset {
+            self.set_voltage_offset(offset)?;
+        }
+
         Ok(())
     }
 
-    pub fn commit_gpu_power_states(&mut self) -> Result<(), GpuControllerError> {
-        fs::write(self.hw_path.join("pp_od_clk_voltage"), b"c\n")?;
+    fn write_clocks_line(&self, line: &str) -> Result<(), GpuControllerError> {
+        log::info!("Writing {} to pp_od_clk_voltage", line.trim_end());
+
+        // sysfs takes every write as one command wherever it goes, appending
+        // lets a plain file keep the table with the commands after it
+        fs::OpenOptions::new()
+            .append(true)
+            .open(self.hw_path.join("pp_od_clk_voltage"))?
+            .write_all(line.as_bytes())?;
+
         Ok(())
     }
 
+    pub fn commit_gpu_power_states(&mut self) -> Result<(), GpuControllerError> {
+        self.write_clocks_line("c\n")?;
+
+        self.verify_clocks_table()
+    }
+
     pub fn reset_gpu_power_states(&mut self) -> Result<(), GpuControllerError> {
-        fs::write(self.hw_path.join("pp_od_clk_voltage"), b"r\n")?;
+        self.write_clocks_line("r\n")?;
+
+        self.config.gpu_max_clock = 0;
+        self.config.gpu_max_voltage = None;
+        self.config.vram_max_clock = 0;
+        self.config.gpu_min_clock = None;
+        self.config.vram_min_clock = None;
+        self.config.voltage_offset = None;
+        self.config.vddc_curve.clear();
+
+        Ok(())
+    }
+
+    /// Writes and commits a file of gpu_od/fan_ctrl. Each file is committed on its own.
+    fn write_od_fan_control(&self, name: &str, line: &str) -> Result<(), GpuControllerError> {
+        let path = self.hw_path.join("gpu_od/fan_ctrl").join(name);
+
+        log::info!("Writing {} to gpu_od/fan_ctrl/{}", line, name);
+
+        fs::write(&path, format!("{}\n", line))?;
+        fs::write(&path, b"c\n")?;
+
+        Ok(())
+    }
+
+    pub fn set_od_fan_value(&mut self, name: &str, value: i64) -> Result<(), GpuControllerError> {
+        self.get_od_fan_controls()?.check_value(name, value)?;
+
+        self.write_od_fan_control(name, &value.to_string())?;
+
+        self.config.od_fan_values.insert(name.to_string(), value);
+
+        Ok(())
+    }
+
+    pub fn set_od_fan_curve_point(
+        &mut self,
+        num: u32,
+        temperature: i64,
+        speed: i64,
+    ) -> Result<(), GpuControllerError> {
+        self.get_od_fan_controls()?
+            .check_curve_point(num as usize, temperature, speed)?;
+
+        self.write_od_fan_control("fan_curve", &format!("{} {} {}", num, temperature, speed))?;
+
+        self.config.od_fan_curve.insert(num, (temperature, speed));
+
+        Ok(())
+    }
+
+    pub fn reset_od_fan_controls(&mut self) -> Result<(), GpuControllerError> {
+        let controls = self.get_od_fan_controls()?;
+
+        let names = controls
+            .values
+            .keys()
+            .map(|name| name.as_str())
+            .chain(controls.curve.as_ref().map(|_| "fan_curve"));
+
+        for name in names {
+            self.write_od_fan_control(name, "r")?;
+        }
+
+        self.config.od_fan_values.clear();
+        self.config.od_fan_curve.clear();
+
         Ok(())
     }
 
+    /// Reads the clocks table back after a commit and checks that the card accepted the configured values.
+    /// Values that were not accepted are replaced in the config with the actual ones.
+    fn verify_clocks_table(&mut self) -> Result<(), GpuControllerError> {
+        let clocks_table = self.get_clocks_table()?;
+
+        let mut mismatches = Vec::new();
+        let config = &mut self.config;
+
+        match clocks_table {
+            ClocksTable::Old(clocks_table) => {
+                if let Some((_, (clockspeed, voltage))) =
+                    clocks_table.gpu_power_levels.iter().next_back()
+                {
+                    if config.gpu_max_clock != 0 {
+                        verify_value(
+                            "GPU clock",
+                            &mut config.gpu_max_clock,
+                            *clockspeed,
+                            &mut mismatches,
+                        );
+                    }
+                    if let Some(expected) = &mut config.gpu_max_voltage {
+                        verify_value("GPU voltage", expected, *voltage, &mut mismatches);
+                    }
+                }
+
+                if let Some((_, (clockspeed, _))) = clocks_table.mem_power_levels.iter().next_back()
+                {
+                    if config.vram_max_clock != 0 {
+                        verify_value(
+                            "VRAM clock",
+                            &mut config.vram_max_clock,
+                            *clockspeed,
+                            &mut mismatches,
+                        );
+                    }
+                }
+            }
+            ClocksTable::New(clocks_table) => {
+                if config.gpu_max_clock != 0 {
+                    verify_value(
+                        "GPU clock",
+                        &mut config.gpu_max_clock,
+                        clocks_table.current_gpu_clocks.1,
+                        &mut mismatches,
+                    );
+                }
+                if let Some(expected) = &mut config.gpu_min_clock {
+                    verify_value(
+                        "GPU min clock",
+                        expected,
+                        clocks_table.current_gpu_clocks.0,
+                        &mut mismatches,
+                    );
+                }
+                if config.vram_max_clock != 0 {
+                    verify_value(
+                        "VRAM clock",
+                        &mut config.vram_max_clock,
+                        clocks_table.current_max_mem_clock,
+                        &mut mismatches,
+                    );
+                }
+                if let (Some(expected), Some(actual)) = (
+                    &mut config.vram_min_clock,
+                    clocks_table.current_min_mem_clock,
+                ) {
+                    verify_value("VRAM min clock", expected, actual, &mut mismatches);
+                }
+                if let (Some(expected), Some(actual)) =
+                    (&mut config.voltage_offset, clocks_table.voltage_offset)
+                {
+                    verify_value("Voltage offset", expected, actual, &mut mismatches);
+                }
+                if let Some(vddc_curve) = clocks_table.vddc_curve {
+                    if let Some(expected) = &mut config.gpu_max_voltage {
+                        verify_value("GPU voltage", expected, vddc_curve[2].1, &mut mismatches);
+                    }
+
+                    for (num, (clockspeed, voltage)) in config.vddc_curve.iter_mut() {
+                        if let Some((actual_clockspeed, actual_voltage)) =
+                            vddc_curve.get(*num as usize)
+                        {
+                            verify_value(
+                                &format!("Voltage curve point {} clock", num),
+                                clockspeed,
+                                *actual_clockspeed,
+                                &mut mismatches,
+                            );
+                            verify_value(
+                                &format!("Voltage curve point {} voltage", num),
+                                voltage,
+                                *actual_voltage,
+                                &mut mismatches,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            log::warn!(
+                "The GPU did not accept all values: {}",
+                mismatches.join(", ")
+            );
+            Err(GpuControllerError::NotApplied(mismatches.join(", ")))
+        }
+    }
+
     fn get_vulkan_info(pci_id: &str) -> VulkanInfo {
         let mut device_name = String::from("Not supported");
         let mut api_version = String::new();
@@ -937,6 +1818,71 @@ impl GpuController {
         }
     }
 
+    /// Parses clock level lines without a voltage, such as `1: 2100Mhz`
+    fn parse_clock_line(line: &str) -> Result<(u32, i64), GpuControllerError> {
+        let line = line.to_lowercase();
+        let mut split = line.split(':');
+
+        let num = split
+            .next()
+            .ok_or_else(|| {
+                GpuControllerError::ParseError("failed to read the clock level number".to_string())
+            })?
+            .trim()
+            .parse()?;
+
+        let clock = split
+            .next()
+            .ok_or_else(|| {
+                GpuControllerError::ParseError("failed to read the clockspeed".to_string())
+            })?
+            .trim()
+            .strip_suffix("mhz")
+            .ok_or_else(|| {
+                GpuControllerError::ParseError(format!("invalid clock line suffix in {}", line))
+            })?
+            .trim()
+            .parse()?;
+
+        Ok((num, clock))
+    }
+
+    /// Parses the `min max` part of an `OD_RANGE` line, stripping the given unit (case insensitive).
+    fn parse_range_values<'a>(
+        split: &mut impl Iterator<Item = &'a str>,
+        unit: &str,
+    ) -> Result<(i64, i64), GpuControllerError> {
+        let min = Self::parse_unit_value(split.next(), unit)?;
+        let max = Self::parse_unit_value(split.next(), unit)?;
+
+        Ok((min, max))
+    }
+
+    /// Parses values such as `25C`, stripping the given unit (case insensitive).
+    fn parse_unit_value(raw: Option<&str>, unit: &str) -> Result<i64, GpuControllerError> {
+        let raw = raw
+            .ok_or_else(|| GpuControllerError::ParseError("missing value".to_string()))?
+            .to_lowercase();
+
+        Ok(raw
+            .strip_suffix(unit)
+            .ok_or_else(|| GpuControllerError::ParseError(format!("missing suffix in {}", raw)))?
+            .parse()?)
+    }
+
+    /// Gets the point number from names such as `VDDC_CURVE_SCLK[1]`
+    fn parse_curve_point_index(name: &str) -> Result<usize, GpuControllerError> {
+        let index = name
+            .split('[')
+            .nth(1)
+            .and_then(|index| index.strip_suffix(']'))
+            .ok_or_else(|| {
+                GpuControllerError::ParseError(format!("invalid curve point name {}", name))
+            })?;
+
+        Ok(index.parse()?)
+    }
+
     fn parse_clock_voltage_line(line: &str) -> Result<(u32, i64, i64), GpuControllerError> {
         log::trace!("Parsing line {}", line);
 
@@ -1042,33 +1988,312 @@ mod tests {
     }
 
     // pp_od_clk_voltage taken from an RX 5700 XT
+    const PP_OD_CLK_VOLTAGE_NAVI: &str = r#"
+        OD_SCLK:
+        0: 800Mhz
+        1: 2100Mhz
+        OD_MCLK:
+        1: 875MHz
+        OD_VDDC_CURVE:
+        0: 800MHz 711mV
+        1: 1450MHz 801mV
+        2: 2100MHz 1191mV
+        OD_RANGE:
+        SCLK:     800Mhz       2150Mhz
+        MCLK:     625Mhz        950Mhz
+        VDDC_CURVE_SCLK[0]:     800Mhz       2150Mhz
+        VDDC_CURVE_VOLT[0]:     750mV        1200mV
+        VDDC_CURVE_SCLK[1]:     800Mhz       2150Mhz
+        VDDC_CURVE_VOLT[1]:     750mV        1200mV
+        VDDC_CURVE_SCLK[2]:     800Mhz       2150Mhz
+        VDDC_CURVE_VOLT[2]:     750mV        1200mV
+    "#;
+
+    /// A controller for a GPU that only has the given clocks table
+    fn controller(name: &str, pp_od_clk_voltage: &str) -> GpuController {
+        let hw_path =
+            std::env::temp_dir().join(format!("lact-gpu-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&hw_path);
+        fs::create_dir_all(&hw_path).unwrap();
+        fs::write(hw_path.join("pp_od_clk_voltage"), pp_od_clk_voltage).unwrap();
+
+        GpuController {
+            hw_path,
+            hw_mon: None,
+            gpu_info: GpuInfo::default(),
+            config: GpuConfig::new(),
+        }
+    }
+
+    /// The commands written after the table, which is then restored
+    fn take_written_clocks_lines(
+        controller: &GpuController,
+        pp_od_clk_voltage: &str,
+    ) -> Vec<String> {
+        let path = controller.hw_path.join("pp_od_clk_voltage");
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, pp_od_clk_voltage).unwrap();
+
+        contents
+            .strip_prefix(pp_od_clk_voltage)
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
     #[test]
     fn parse_clocks_table_navi() {
         init();
 
+        let clocks_table = GpuController::parse_clocks_table(PP_OD_CLK_VOLTAGE_NAVI).unwrap();
+
+        log::info!("{:?}", clocks_table);
+
+        match clocks_table {
+            ClocksTable::New(clocks_table) => {
+                assert_eq!(
+                    clocks_table.vddc_curve,
+                    Some([(800, 711), (1450, 801), (2100, 1191)])
+                );
+                assert_eq!(clocks_table.vddc_curve_clocks_range[2], (800, 2150));
+                assert_eq!(clocks_table.vddc_curve_voltage_range[0], (750, 1200));
+                assert_eq!(clocks_table.voltage_range, (750, 1200));
+                assert_eq!(clocks_table.current_min_mem_clock, None);
+                assert_eq!(clocks_table.voltage_offset, None);
+
+                assert!(clocks_table.check_vddc_curve_point(1, 1450, 800).is_ok());
+                assert!(clocks_table.check_vddc_curve_point(1, 2200, 800).is_err());
+                assert!(clocks_table.check_vddc_curve_point(2, 2100, 700).is_err());
+                assert!(clocks_table.check_vddc_curve_point(3, 2100, 1000).is_err());
+            }
+            ClocksTable::Old(_) => panic!("parsed the Navi clocks table as the old format"),
+        }
+    }
+
+    #[test]
+    fn apply_clocks_navi_writes_the_curve_instead_of_the_max_clock() {
+        init();
+
+        let mut controller = controller("apply-clocks-navi", PP_OD_CLK_VOLTAGE_NAVI);
+
+        // The GPU clock slider is hidden with a curve, so its value is stale
+        let settings = ClocksSettings {
+            gpu_clock: 2100,
+            vram_clock: 900,
+            vddc_curve: Some(vec![(800, 750), (1450, 800), (2000, 1150)]),
+            ..Default::default()
+        };
+        controller.apply_clocks(&settings).unwrap();
+
+        assert_eq!(
+            take_written_clocks_lines(&controller, PP_OD_CLK_VOLTAGE_NAVI),
+            vec!["vc 0 800 750", "vc 1 1450 800", "vc 2 2000 1150", "m 1 900"]
+        );
+
+        let config = controller.get_config();
+        assert_eq!(config.gpu_max_clock, 0);
+        assert_eq!(config.gpu_min_clock, None);
+        assert_eq!(config.vddc_curve[&2], (2000, 1150));
+
+        // Without a voltage the max clock doesn't touch the curve, with one it's only the last point
+        controller.set_gpu_max_power_state(2000, None).unwrap();
+        controller
+            .set_gpu_max_power_state(2050, Some(1100))
+            .unwrap();
+
+        assert_eq!(
+            take_written_clocks_lines(&controller, PP_OD_CLK_VOLTAGE_NAVI),
+            vec!["s 1 2000", "vc 2 2050 1100"]
+        );
+
+        // Replayed after the max clock when the config is loaded, so it has to match
+        assert_eq!(controller.get_config().vddc_curve[&2], (2050, 1100));
+
+        let _ = fs::remove_dir_all(&controller.hw_path);
+    }
+
+    #[test]
+    fn commit_corrects_values_the_gpu_did_not_accept() {
+        init();
+
+        let mut controller = controller("commit-navi", PP_OD_CLK_VOLTAGE_NAVI);
+
+        let settings = ClocksSettings {
+            vram_clock: 900,
+            vddc_curve: Some(vec![(800, 750), (1450, 800), (2000, 1150)]),
+            ..Default::default()
+        };
+        controller.apply_clocks(&settings).unwrap();
+
+        let accepted = PP_OD_CLK_VOLTAGE_NAVI
+            .replace("1: 2100Mhz", "1: 2000Mhz")
+            .replace("1: 875MHz", "1: 900MHz")
+            .replace("0: 800MHz 711mV", "0: 800MHz 750mV")
+            .replace("1: 1450MHz 801mV", "1: 1450MHz 800mV")
+            .replace("2: 2100MHz 1191mV", "2: 2000MHz 1150mV");
+        fs::write(controller.hw_path.join("pp_od_clk_voltage"), &accepted).unwrap();
+
+        controller.commit_gpu_power_states().unwrap();
+        assert_eq!(take_written_clocks_lines(&controller, &accepted), vec!["c"]);
+
+        // The GPU caps the voltage of the last point
+        let capped = accepted.replace("2: 2000MHz 1150mV", "2: 2000MHz 1100mV");
+        fs::write(controller.hw_path.join("pp_od_clk_voltage"), &capped).unwrap();
+
+        match controller.commit_gpu_power_states() {
+            Err(GpuControllerError::NotApplied(msg)) => {
+                assert_eq!(msg, "Voltage curve point 2 voltage is 1100 instead of 1150")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let config = controller.get_config();
+        assert_eq!(config.vddc_curve[&2], (2000, 1100));
+        assert_eq!(config.vram_max_clock, 900);
+
+        let _ = fs::remove_dir_all(&controller.hw_path);
+    }
+
+    // pp_od_clk_voltage taken from an RX 6800
+    #[test]
+    fn parse_clocks_table_navi2x() {
+        init();
+
+        let pp_od_clk_voltage = r#"
+            OD_SCLK:
+            0: 500Mhz
+            1: 2475Mhz
+            OD_MCLK:
+            0: 97Mhz
+            1: 1000MHz
+            OD_VDDGFX_OFFSET:
+            0mV
+            OD_RANGE:
+            SCLK:     500Mhz        2800Mhz
+            MCLK:     674Mhz        1075Mhz
+        "#;
+
+        let clocks_table = GpuController::parse_clocks_table(pp_od_clk_voltage).unwrap();
+
+        log::info!("{:?}", clocks_table);
+
+        match clocks_table {
+            ClocksTable::New(clocks_table) => {
+                assert_eq!(clocks_table.current_gpu_clocks, (500, 2475));
+                assert_eq!(clocks_table.current_min_mem_clock, Some(97));
+                assert_eq!(clocks_table.current_max_mem_clock, 1000);
+                assert_eq!(clocks_table.voltage_offset, Some(0));
+                assert_eq!(clocks_table.voltage_offset_range, None);
+                assert_eq!(clocks_table.vddc_curve, None);
+                assert_eq!(clocks_table.gpu_clocks_range, (500, 2800));
+                assert_eq!(clocks_table.mem_clocks_range, (674, 1075));
+            }
+            ClocksTable::Old(_) => panic!("parsed the Navi2x clocks table as the old format"),
+        }
+    }
+
+    // pp_od_clk_voltage taken from an RX 7900 XTX
+    #[test]
+    fn parse_clocks_table_navi3x() {
+        init();
+
         let pp_od_clk_voltage = r#"
             OD_SCLK:
-            0: 800Mhz
-            1: 2100Mhz
+            0: 500Mhz
+            1: 2500Mhz
             OD_MCLK:
-            1: 875MHz
-            OD_VDDC_CURVE:
-            0: 800MHz 711mV
-            1: 1450MHz 801mV
-            2: 2100MHz 1191mV
+            0: 97Mhz
+            1: 1250MHz
+            OD_VDDGFX_OFFSET:
+            -50mV
             OD_RANGE:
-            SCLK:     800Mhz       2150Mhz
-            MCLK:     625Mhz        950Mhz
-            VDDC_CURVE_SCLK[0]:     800Mhz       2150Mhz
-            VDDC_CURVE_VOLT[0]:     750mV        1200mV
-            VDDC_CURVE_SCLK[1]:     800Mhz       2150Mhz
-            VDDC_CURVE_VOLT[1]:     750mV        1200mV
-            VDDC_CURVE_SCLK[2]:     800Mhz       2150Mhz
-            VDDC_CURVE_VOLT[2]:     750mV        1200mV
+            SCLK:     500Mhz        3000Mhz
+            MCLK:      97Mhz        1500Mhz
+            VDDGFX_OFFSET:    -450mv        0mv
         "#;
 
         let clocks_table = GpuController::parse_clocks_table(pp_od_clk_voltage).unwrap();
 
         log::info!("{:?}", clocks_table);
+
+        match clocks_table {
+            ClocksTable::New(clocks_table) => {
+                assert_eq!(clocks_table.current_gpu_clocks, (500, 2500));
+                assert_eq!(clocks_table.current_min_mem_clock, Some(97));
+                assert_eq!(clocks_table.voltage_offset, Some(-50));
+                assert_eq!(clocks_table.voltage_offset_range, Some((-450, 0)));
+                assert_eq!(clocks_table.vddc_curve, None);
+            }
+            ClocksTable::Old(_) => panic!("parsed the Navi3x clocks table as the old format"),
+        }
+    }
+
+    // gpu_od/fan_ctrl taken from an RX 7900 XTX
+    #[test]
+    fn parse_od_fan_controls() {
+        init();
+
+        let acoustic_limit = "OD_ACOUSTIC_LIMIT:\n2450\nOD_RANGE:\nACOUSTIC_LIMIT: 500 3100\n";
+        let value = GpuController::parse_od_fan_value(acoustic_limit).unwrap();
+        assert_eq!(
+            value,
+            OdFanValue {
+                value: 2450,
+                range: Some((500, 3100)),
+            }
+        );
+
+        let fan_curve = r#"
+            OD_FAN_CURVE:
+            0: 0C 0%
+            1: 45C 30%
+            2: 60C 50%
+            3: 75C 70%
+            4: 90C 100%
+            OD_RANGE:
+            FAN_CURVE(hotspot temp): 25C 100C
+            FAN_CURVE(fan speed): 15% 100%
+        "#;
+        let curve = GpuController::parse_od_fan_curve(fan_curve).unwrap();
+        assert_eq!(
+            curve.points,
+            vec![(0, 0), (45, 30), (60, 50), (75, 70), (90, 100)]
+        );
+        assert_eq!(curve.temperature_range, Some((25, 100)));
+        assert_eq!(curve.speed_range, Some((15, 100)));
+
+        let mut controls = OdFanControls {
+            curve: Some(curve),
+            ..Default::default()
+        };
+        controls
+            .values
+            .insert("acoustic_limit_rpm_threshold".to_string(), value);
+
+        assert!(controls
+            .check_value("acoustic_limit_rpm_threshold", 3000)
+            .is_ok());
+        assert!(controls
+            .check_value("acoustic_limit_rpm_threshold", 3200)
+            .is_err());
+        assert!(controls.check_value("fan_minimum_pwm", 20).is_err());
+        assert!(controls.check_curve_point(4, 95, 100).is_ok());
+        assert!(controls.check_curve_point(1, 20, 30).is_err());
+        assert!(controls.check_curve_point(5, 95, 100).is_err());
+    }
+
+    #[test]
+    fn check_range_rejects_out_of_range_values() {
+        assert!(check_range("GPU clock", 2100, (800, 2150), "MHz").is_ok());
+        assert!(check_range("GPU clock", 800, (800, 2150), "MHz").is_ok());
+
+        match check_range("GPU clock", 2200, (800, 2150), "MHz") {
+            Err(GpuControllerError::InvalidValue(msg)) => assert_eq!(
+                msg,
+                "GPU clock 2200MHz is outside of the allowed range 800-2150MHz"
+            ),
+            other => panic!("unexpected result {:?}", other),
+        }
     }
 }
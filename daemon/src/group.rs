@@ -0,0 +1,283 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fan_control::{validate_curve, FanCurveOptions};
+use crate::gpu_controller::{GpuInfo, PowerProfile};
+
+/// Which GPUs belong to a group
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum GroupMatcher {
+    Model(String),  // Card or GPU model, e.g. "Radeon RX 6800"
+    Vendor(String), // Part of the card or GPU vendor name, e.g. "Sapphire"
+    Ids(Vec<u32>),
+}
+
+impl GroupMatcher {
+    pub fn matches(&self, id: u32, info: &GpuInfo) -> bool {
+        let vendor_data = &info.vendor_data;
+
+        match self {
+            GroupMatcher::Model(model) => vec![&vendor_data.card_model, &vendor_data.gpu_model]
+                .into_iter()
+                .flatten()
+                .any(|name| name.eq_ignore_ascii_case(model)),
+            GroupMatcher::Vendor(vendor) => {
+                let vendor = vendor.to_lowercase();
+
+                vec![&vendor_data.card_vendor, &vendor_data.gpu_vendor]
+                    .into_iter()
+                    .flatten()
+                    .any(|name| name.to_lowercase().contains(&vendor))
+            }
+            GroupMatcher::Ids(ids) => ids.contains(&id),
+        }
+    }
+}
+
+impl fmt::Display for GroupMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GroupMatcher::Model(model) => write!(f, "model {}", model),
+            GroupMatcher::Vendor(vendor) => write!(f, "vendor {}", vendor),
+            GroupMatcher::Ids(ids) => {
+                let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                write!(f, "GPUs {}", ids.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum GroupSetting {
+    FanControl(bool),
+    FanCurve(BTreeMap<i64, f64>), // %
+    PowerCap(i64),
+    PowerProfile(PowerProfile),
+    GpuMaxClock(i64, Option<i64>), // MHz and mV
+    VramMaxClock(i64),
+}
+
+impl GroupSetting {
+    /// Groups and overrides hold one setting of each kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GroupSetting::FanControl(_) => "fan-control",
+            GroupSetting::FanCurve(_) => "fan-curve",
+            GroupSetting::PowerCap(_) => "power-cap",
+            GroupSetting::PowerProfile(_) => "power-profile",
+            GroupSetting::GpuMaxClock(..) => "gpu-max-clock",
+            GroupSetting::VramMaxClock(_) => "vram-max-clock",
+        }
+    }
+
+    /// Checks what can be checked without a card, the cards check their ranges when it's applied
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            GroupSetting::FanCurve(curve) => {
+                validate_curve(curve, &FanCurveOptions::default(), None)
+            }
+            GroupSetting::PowerCap(cap) if *cap <= 0 => {
+                Err("the power cap has to be at least 1W".to_string())
+            }
+            GroupSetting::GpuMaxClock(clock, _) | GroupSetting::VramMaxClock(clock)
+                if *clock <= 0 =>
+            {
+                Err("the clock has to be at least 1MHz".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for GroupSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GroupSetting::FanControl(true) => write!(f, "fan control enabled"),
+            GroupSetting::FanControl(false) => write!(f, "automatic fan control"),
+            GroupSetting::FanCurve(curve) => {
+                let points: Vec<String> = curve
+                    .iter()
+                    .map(|(temp, speed)| format!("{}:{}", temp, speed))
+                    .collect();
+                write!(f, "fan curve {}", points.join(" "))
+            }
+            GroupSetting::PowerCap(cap) => write!(f, "power cap {}W", cap),
+            GroupSetting::PowerProfile(profile) => write!(f, "power profile {:?}", profile),
+            GroupSetting::GpuMaxClock(clock, Some(voltage)) => {
+                write!(f, "GPU max clock {}MHz at {}mV", clock, voltage)
+            }
+            GroupSetting::GpuMaxClock(clock, None) => write!(f, "GPU max clock {}MHz", clock),
+            GroupSetting::VramMaxClock(clock) => write!(f, "VRAM max clock {}MHz", clock),
+        }
+    }
+}
+
+fn set_kind(settings: &mut Vec<GroupSetting>, setting: GroupSetting) {
+    match settings
+        .iter_mut()
+        .find(|other| other.kind() == setting.kind())
+    {
+        Some(other) => *other = setting,
+        None => settings.push(setting),
+    }
+}
+
+/// Settings that are applied to several GPUs at once, e.g. identical cards of a compute box
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GpuGroup {
+    pub name: String,
+    pub matcher: GroupMatcher,
+    #[serde(default)]
+    pub settings: Vec<GroupSetting>,
+    #[serde(default)]
+    pub overrides: HashMap<u32, Vec<GroupSetting>>, // Used instead of the group's settings of the same kind
+}
+
+impl GpuGroup {
+    pub fn new(name: &str, matcher: GroupMatcher) -> Self {
+        GpuGroup {
+            name: name.to_string(),
+            matcher,
+            settings: Vec::new(),
+            overrides: HashMap::new(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("the group name is empty".to_string());
+        }
+
+        match &self.matcher {
+            GroupMatcher::Model(text) | GroupMatcher::Vendor(text) if text.trim().is_empty() => {
+                Err("the group matches every GPU".to_string())
+            }
+            GroupMatcher::Ids(ids) if ids.is_empty() => Err("the group has no GPUs".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets a setting of the whole group, or overrides it for one GPU
+    pub fn set_setting(&mut self, gpu_id: Option<u32>, setting: GroupSetting) {
+        match gpu_id {
+            Some(id) => set_kind(self.overrides.entry(id).or_default(), setting),
+            None => set_kind(&mut self.settings, setting),
+        }
+    }
+
+    /// Removes a setting of the whole group, or the override of one GPU
+    pub fn clear_setting(&mut self, gpu_id: Option<u32>, kind: &str) -> Result<(), String> {
+        let settings = match gpu_id {
+            Some(id) => self.overrides.get_mut(&id),
+            None => Some(&mut self.settings),
+        };
+
+        let removed = match settings {
+            Some(settings) => {
+                let len = settings.len();
+                settings.retain(|setting| setting.kind() != kind);
+                settings.len() != len
+            }
+            None => false,
+        };
+
+        if let Some(id) = gpu_id {
+            if self
+                .overrides
+                .get(&id)
+                .map_or(false, |settings| settings.is_empty())
+            {
+                self.overrides.remove(&id);
+            }
+        }
+
+        match removed {
+            true => Ok(()),
+            false => Err(format!("there is no {} setting", kind)),
+        }
+    }
+
+    /// The settings of a member, with its overrides taking the place of the group's settings
+    pub fn get_settings(&self, gpu_id: u32) -> Vec<GroupSetting> {
+        let mut settings = self.settings.clone();
+
+        if let Some(overrides) = self.overrides.get(&gpu_id) {
+            for setting in overrides {
+                set_kind(&mut settings, setting.clone());
+            }
+        }
+
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pciid_parser::VendorData;
+
+    #[test]
+    fn matches_gpus() {
+        let mut info = GpuInfo::default();
+        info.vendor_data = VendorData {
+            gpu_vendor: Some("Advanced Micro Devices, Inc. [AMD/ATI]".to_string()),
+            gpu_model: Some("Navi 21 [Radeon RX 6800/6800 XT / 6900 XT]".to_string()),
+            card_vendor: Some("Sapphire Technology Limited".to_string()),
+            card_model: Some("Radeon RX 6800".to_string()),
+        };
+
+        assert!(GroupMatcher::Model("radeon rx 6800".to_string()).matches(1, &info));
+        assert!(!GroupMatcher::Model("Radeon RX 6800 XT".to_string()).matches(1, &info));
+        assert!(GroupMatcher::Vendor("sapphire".to_string()).matches(1, &info));
+        assert!(GroupMatcher::Vendor("AMD".to_string()).matches(1, &info));
+        assert!(GroupMatcher::Ids(vec![1, 2]).matches(2, &info));
+        assert!(!GroupMatcher::Ids(vec![1, 2]).matches(3, &info));
+    }
+
+    #[test]
+    fn overrides_replace_group_settings() {
+        let mut group = GpuGroup::new("compute", GroupMatcher::Ids(vec![1, 2]));
+
+        group.set_setting(None, GroupSetting::PowerCap(180));
+        group.set_setting(None, GroupSetting::VramMaxClock(1000));
+        group.set_setting(None, GroupSetting::PowerCap(200));
+        group.set_setting(Some(2), GroupSetting::PowerCap(150));
+
+        assert_eq!(
+            group.get_settings(1),
+            vec![
+                GroupSetting::PowerCap(200),
+                GroupSetting::VramMaxClock(1000)
+            ]
+        );
+        assert_eq!(
+            group.get_settings(2),
+            vec![
+                GroupSetting::PowerCap(150),
+                GroupSetting::VramMaxClock(1000)
+            ]
+        );
+
+        group.clear_setting(Some(2), "power-cap").unwrap();
+        assert!(group.overrides.is_empty());
+        assert_eq!(group.get_settings(2), group.get_settings(1));
+        assert!(group.clear_setting(Some(2), "power-cap").is_err());
+    }
+
+    #[test]
+    fn validates_settings() {
+        let mut curve = BTreeMap::new();
+        curve.insert(40, 30.0);
+        curve.insert(80, 100.0);
+        assert!(GroupSetting::FanCurve(curve.clone()).validate().is_ok());
+
+        curve.insert(60, 20.0);
+        assert!(GroupSetting::FanCurve(curve).validate().is_err());
+        assert!(GroupSetting::FanCurve(BTreeMap::new()).validate().is_err());
+        assert!(GroupSetting::PowerCap(0).validate().is_err());
+    }
+}
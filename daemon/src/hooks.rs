@@ -0,0 +1,440 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Read,
+    os::unix::process::{CommandExt, ExitStatusExt},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+// Only the end of the output is kept, that's where errors usually are
+const OUTPUT_LIMIT: usize = 1024;
+// How much the temperature has to fall below the threshold before it counts as crossed again
+const TEMPERATURE_HYSTERESIS: i64 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HookEvent {
+    ProfileSwitched,
+    TemperatureThreshold,
+    FanFault,
+    SettingsApplied,
+    GpuAdded,
+    GpuRemoved,
+}
+
+impl HookEvent {
+    pub fn from_str(event: &str) -> Option<Self> {
+        match event {
+            "profile-switched" => Some(HookEvent::ProfileSwitched),
+            "temperature-threshold" => Some(HookEvent::TemperatureThreshold),
+            "fan-fault" => Some(HookEvent::FanFault),
+            "settings-applied" => Some(HookEvent::SettingsApplied),
+            "gpu-added" => Some(HookEvent::GpuAdded),
+            "gpu-removed" => Some(HookEvent::GpuRemoved),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookEvent::ProfileSwitched => write!(f, "profile-switched"),
+            HookEvent::TemperatureThreshold => write!(f, "temperature-threshold"),
+            HookEvent::FanFault => write!(f, "fan-fault"),
+            HookEvent::SettingsApplied => write!(f, "settings-applied"),
+            HookEvent::GpuAdded => write!(f, "gpu-added"),
+            HookEvent::GpuRemoved => write!(f, "gpu-removed"),
+        }
+    }
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+/// A command that gets run with `sh -c` when an event happens.
+/// The event details are passed in `LACT_*` environment variables.
+/// Hooks run as the daemon's user, so only root or members of the configured group can change them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub event: HookEvent,
+    pub gpu_id: Option<u32>, // Runs for all GPUs if not set
+    pub command: String,
+    #[serde(default = "default_timeout")]
+    pub timeout: u64, // Seconds until the command gets killed
+    #[serde(default)]
+    pub temperature: Option<i64>, // °C, the threshold of temperature-threshold hooks
+}
+
+impl Hook {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.command.trim().is_empty() {
+            return Err("the command is empty".to_string());
+        }
+        if self.timeout == 0 {
+            return Err("the timeout has to be at least 1 second".to_string());
+        }
+        match (self.event, self.temperature) {
+            (HookEvent::TemperatureThreshold, None) => {
+                Err("temperature-threshold hooks need a temperature".to_string())
+            }
+            (HookEvent::TemperatureThreshold, Some(_)) | (_, None) => Ok(()),
+            (event, Some(_)) => Err(format!("{} hooks don't take a temperature", event)),
+        }
+    }
+
+    fn matches(&self, event: HookEvent, gpu_id: u32) -> bool {
+        self.event == event && self.gpu_id.map_or(true, |id| id == gpu_id)
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.event)?;
+        if let Some(temperature) = self.temperature {
+            write!(f, " at {}°C", temperature)?;
+        }
+        match self.gpu_id {
+            Some(id) => write!(f, " of GPU {}", id)?,
+            None => write!(f, " of any GPU")?,
+        }
+        write!(f, ": {}", self.command)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum HookStatus {
+    Running,
+    Exited(i32),
+    Killed(i32), // By a signal other than the timeout
+    TimedOut,
+    Failed(String), // The command couldn't be started
+}
+
+impl fmt::Display for HookStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookStatus::Running => write!(f, "running"),
+            HookStatus::Exited(code) => write!(f, "exited with {}", code),
+            HookStatus::Killed(signal) => write!(f, "killed by signal {}", signal),
+            HookStatus::TimedOut => write!(f, "timed out"),
+            HookStatus::Failed(e) => write!(f, "failed to start: {}", e),
+        }
+    }
+}
+
+/// The last run of a hook
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HookResult {
+    pub gpu_id: u32,
+    pub started: u64,  // Unix time in seconds
+    pub duration: u64, // ms
+    pub status: HookStatus,
+    pub output: String, // The end of stdout and stderr
+}
+
+/// Runs the hooks on their own threads, so that slow commands don't hold up the daemon or the fan control
+#[derive(Clone)]
+pub struct HookRunner {
+    hooks: Arc<RwLock<Vec<Hook>>>,
+    results: Arc<RwLock<HashMap<usize, HookResult>>>,
+    // Hook index and GPU ID to whether the temperature is above the threshold
+    temperatures: Arc<Mutex<HashMap<(usize, u32), bool>>>,
+    // Increased when the hooks change, so that runs of removed hooks don't report to the new indices
+    generation: Arc<AtomicU64>,
+}
+
+impl HookRunner {
+    pub fn new(hooks: Vec<Hook>) -> Self {
+        HookRunner {
+            hooks: Arc::new(RwLock::new(hooks)),
+            results: Arc::new(RwLock::new(HashMap::new())),
+            temperatures: Arc::new(Mutex::new(HashMap::new())),
+            generation: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn set_hooks(&self, hooks: Vec<Hook>) {
+        let mut current = self.hooks.write().unwrap();
+        if *current != hooks {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            self.results.write().unwrap().clear();
+            self.temperatures.lock().unwrap().clear();
+            *current = hooks;
+        }
+    }
+
+    pub fn get_results(&self) -> HashMap<usize, HookResult> {
+        self.results.read().unwrap().clone()
+    }
+
+    /// Runs the hooks of an event in the background. `LACT_EVENT` and `LACT_GPU_ID` are always set.
+    pub fn fire(&self, event: HookEvent, gpu_id: u32, vars: &[(&str, String)]) {
+        let hooks = self.hooks.read().unwrap();
+
+        for (index, hook) in hooks.iter().enumerate() {
+            if hook.matches(event, gpu_id) {
+                self.run(index, hook.clone(), gpu_id, vars);
+            }
+        }
+    }
+
+    /// Runs the temperature-threshold hooks whose threshold the temperature crossed since the last check
+    pub fn check_temperature(&self, gpu_id: u32, temperature: i64) {
+        let hooks = self.hooks.read().unwrap();
+        let mut temperatures = self.temperatures.lock().unwrap();
+
+        for (index, hook) in hooks.iter().enumerate() {
+            let threshold = match hook.temperature {
+                Some(threshold) if hook.matches(HookEvent::TemperatureThreshold, gpu_id) => {
+                    threshold
+                }
+                _ => continue,
+            };
+
+            let above = temperatures.entry((index, gpu_id)).or_insert(false);
+            let crossed = match *above {
+                false => temperature >= threshold,
+                true => temperature <= threshold - TEMPERATURE_HYSTERESIS,
+            };
+
+            if crossed {
+                *above = !*above;
+
+                let direction = match *above {
+                    true => "above",
+                    false => "below",
+                };
+                log::info!(
+                    "The temperature of GPU {} is {} {}°C now",
+                    gpu_id,
+                    direction,
+                    threshold
+                );
+
+                self.run(
+                    index,
+                    hook.clone(),
+                    gpu_id,
+                    &[
+                        ("LACT_TEMPERATURE", temperature.to_string()),
+                        ("LACT_THRESHOLD", threshold.to_string()),
+                        ("LACT_DIRECTION", direction.to_string()),
+                    ],
+                );
+            }
+        }
+    }
+
+    fn run(&self, index: usize, hook: Hook, gpu_id: u32, vars: &[(&str, String)]) {
+        {
+            let mut results = self.results.write().unwrap();
+
+            // Events can come in faster than a slow command finishes
+            if let Some(HookResult {
+                status: HookStatus::Running,
+                ..
+            }) = results.get(&index)
+            {
+                log::warn!("Hook #{} is still running, skipping {}", index, hook.event);
+                return;
+            }
+
+            results.insert(
+                index,
+                HookResult {
+                    gpu_id,
+                    started: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|time| time.as_secs())
+                        .unwrap_or_default(),
+                    duration: 0,
+                    status: HookStatus::Running,
+                    output: String::new(),
+                },
+            );
+        }
+
+        log::info!("Running hook #{}: {}", index, hook);
+
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("exec 2>&1\n{}", hook.command))
+            .env("LACT_EVENT", hook.event.to_string())
+            .env("LACT_GPU_ID", gpu_id.to_string())
+            .envs(vars.iter().map(|(name, value)| (*name, value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            // Its own process group, so that the timeout also kills what the command started
+            .process_group(0);
+
+        let results = self.results.clone();
+        let generation = self.generation.clone();
+        let current_generation = generation.load(Ordering::SeqCst);
+
+        thread::spawn(move || {
+            let start = Instant::now();
+            let (status, output) = run_command(command, Duration::from_secs(hook.timeout));
+
+            match &status {
+                HookStatus::Exited(0) => (),
+                status => log::warn!("Hook #{} {}", index, status),
+            }
+
+            if generation.load(Ordering::SeqCst) == current_generation {
+                if let Some(result) = results.write().unwrap().get_mut(&index) {
+                    result.duration = start.elapsed().as_millis() as u64;
+                    result.status = status;
+                    result.output = output;
+                }
+            }
+        });
+    }
+}
+
+fn run_command(mut command: Command, timeout: Duration) -> (HookStatus, String) {
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return (HookStatus::Failed(e.to_string()), String::new()),
+    };
+
+    // Read on a separate thread, a full pipe would block the command otherwise
+    let (sender, receiver) = mpsc::channel();
+    if let Some(mut stdout) = child.stdout.take() {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = stdout.read_to_end(&mut output);
+            let _ = sender.send(output);
+        });
+    }
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                break match (status.code(), status.signal()) {
+                    (Some(code), _) => HookStatus::Exited(code),
+                    (None, Some(signal)) => HookStatus::Killed(signal),
+                    (None, None) => HookStatus::Failed("unknown exit status".to_string()),
+                }
+            }
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = signal::killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+                let _ = child.wait();
+                break HookStatus::TimedOut;
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(50)),
+            Err(e) => break HookStatus::Failed(e.to_string()),
+        }
+    };
+
+    // Something that left the process group might still hold on to the pipe
+    let output = receiver
+        .recv_timeout(Duration::from_secs(1))
+        .unwrap_or_default();
+    let output = String::from_utf8_lossy(&output);
+    let output = output.trim_end();
+
+    let mut start = output.len().saturating_sub(OUTPUT_LIMIT);
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+
+    (status, output[start..].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(event: HookEvent, command: &str) -> Hook {
+        Hook {
+            event,
+            gpu_id: None,
+            command: command.to_string(),
+            timeout: 1,
+            temperature: None,
+        }
+    }
+
+    fn wait_for_result(runner: &HookRunner, index: usize) -> HookResult {
+        for _ in 0..100 {
+            if let Some(result) = runner.get_results().get(&index) {
+                if result.status != HookStatus::Running {
+                    return result.clone();
+                }
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("hook #{} didn't finish", index);
+    }
+
+    #[test]
+    fn runs_hooks_with_event_details() {
+        let runner = HookRunner::new(vec![
+            hook(HookEvent::FanFault, "exit 1"),
+            hook(
+                HookEvent::ProfileSwitched,
+                "echo $LACT_EVENT $LACT_GPU_ID $LACT_PROFILE; echo oops >&2; exit 3",
+            ),
+        ]);
+
+        runner.fire(
+            HookEvent::ProfileSwitched,
+            7,
+            &[("LACT_PROFILE", "gaming".to_string())],
+        );
+
+        let result = wait_for_result(&runner, 1);
+        assert_eq!(result.gpu_id, 7);
+        assert_eq!(result.status, HookStatus::Exited(3));
+        assert_eq!(result.output, "profile-switched 7 gaming\noops");
+        assert!(runner.get_results().get(&0).is_none());
+    }
+
+    #[test]
+    fn kills_hooks_after_the_timeout() {
+        let runner = HookRunner::new(vec![hook(HookEvent::GpuAdded, "echo started; sleep 10")]);
+
+        let start = Instant::now();
+        runner.fire(HookEvent::GpuAdded, 0, &[]);
+
+        let result = wait_for_result(&runner, 0);
+        assert_eq!(result.status, HookStatus::TimedOut);
+        assert_eq!(result.output, "started");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn fires_temperature_hooks_on_crossings() {
+        let runner = HookRunner::new(vec![Hook {
+            temperature: Some(80),
+            ..hook(HookEvent::TemperatureThreshold, "echo $LACT_DIRECTION")
+        }]);
+
+        runner.check_temperature(0, 70);
+        assert!(runner.get_results().is_empty());
+
+        runner.check_temperature(0, 81);
+        assert_eq!(wait_for_result(&runner, 0).output, "above");
+
+        // Hovering around the threshold doesn't fire again
+        runner.check_temperature(0, 79);
+        runner.check_temperature(0, 82);
+        assert_eq!(wait_for_result(&runner, 0).output, "above");
+
+        runner.check_temperature(0, 75);
+        assert_eq!(wait_for_result(&runner, 0).output, "below");
+    }
+}
@@ -1,7 +1,7 @@
 use std::{
     collections::BTreeMap,
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc, RwLock,
@@ -10,6 +10,21 @@ use std::{
     time::Duration,
 };
 
+use crate::config::GpuConfig;
+use crate::fan_calibration::{
+    calibrate, CalibrationFan, CalibrationSettings, FanCalibration, FanCalibrationStatus,
+};
+use crate::fan_control::{
+    curve_speed, rpm_step, FanCurveInterpolation, FanCurveOptions, FanFailsafe, FanFault,
+    FanLimits, FanMode, FanSmoother, FanSmoothing, FanSpeedUnit, FanTempSource, SensorWatchdog,
+};
+use crate::fan_health::{FanHealth, FanHealthMonitor, FanHealthSettings};
+use crate::fan_pid::FanPid;
+use crate::gpu_controller::GpuStats;
+use crate::idle::{IdlePolicy, IdleSettings, IdleStatus, IdleTransition};
+use crate::power_guard::{PowerGuard, PowerGuardReadings, PowerGuardSettings, PowerGuardStatus};
+use crate::schedule::LocalTime;
+use crate::script::{Script, ScriptOutput, ScriptStatus};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -25,37 +40,189 @@ pub struct HWMon {
     hwmon_path: PathBuf,
     fan_control: Arc<AtomicBool>,
     fan_curve: Arc<RwLock<BTreeMap<i64, f64>>>,
+    fan_curve_options: Arc<RwLock<FanCurveOptions>>,
+    fan_smoothing: Arc<RwLock<FanSmoothing>>,
+    fan_temp_source: Arc<RwLock<FanTempSource>>,
+    fan_limits: Arc<RwLock<FanLimits>>,
+    fan_mode: Arc<RwLock<FanMode>>,
+    fan_fault: Arc<RwLock<Option<FanFault>>>,
+    fan_calibration: Arc<RwLock<Option<FanCalibration>>>,
+    fan_calibration_status: Arc<RwLock<FanCalibrationStatus>>,
+    fan_health_settings: Arc<RwLock<FanHealthSettings>>,
+    power_guard_settings: Arc<RwLock<PowerGuardSettings>>,
+    idle_settings: Arc<RwLock<IdleSettings>>,
+    idle_fan_curve: Arc<RwLock<Option<BTreeMap<i64, f64>>>>, // Replaces the fan mode while idle
+    script: Arc<RwLock<Option<String>>>,
+}
+
+/// State of the fan control loop that is kept between ticks
+pub struct FanControlState {
+    smoother: FanSmoother,
+    watchdog: SensorWatchdog,
+    rpm_speed: f64, // Speed used by the closed loop RPM fallback
+    pid: FanPid,
+    native_rpm_enabled: bool,
+    last_pwm: Option<i64>,
+    last_temp: Option<f64>,
+    suspended: bool, // Whether the failsafe has the fan until the sensors update again
+}
+
+impl FanControlState {
+    pub fn new() -> Self {
+        FanControlState {
+            smoother: FanSmoother::new(),
+            watchdog: SensorWatchdog::new(),
+            rpm_speed: 0.0,
+            pid: FanPid::new(),
+            native_rpm_enabled: false,
+            last_pwm: None,
+            last_temp: None,
+            suspended: false,
+        }
+    }
+
+    /// The PWM value written on the last tick
+    pub fn get_last_pwm(&self) -> Option<i64> {
+        self.last_pwm
+    }
+
+    /// The (averaged) fan curve temperature of the last tick
+    pub fn get_last_temp(&self) -> Option<f64> {
+        self.last_temp
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+}
+
+/// State of the fan health check that is kept between ticks
+pub struct FanHealthState {
+    monitor: FanHealthMonitor,
+    limited_power_cap: Option<i64>, // The power cap from before the fan failed
+}
+
+impl FanHealthState {
+    pub fn new() -> Self {
+        FanHealthState {
+            monitor: FanHealthMonitor::new(),
+            limited_power_cap: None,
+        }
+    }
+}
+
+/// State of the idle policy that is kept between ticks
+pub struct IdleState {
+    policy: IdlePolicy,
+    restore: Option<IdleRestore>,
+}
+
+/// What was changed when entering low power, to be undone when leaving it
+struct IdleRestore {
+    power_cap: Option<(i64, i64)>, // Previous cap and the idle cap written instead
+    level: Option<String>,         // Previous power_dpm_force_performance_level
+}
+
+impl IdleState {
+    pub fn new() -> Self {
+        IdleState {
+            policy: IdlePolicy::new(),
+            restore: None,
+        }
+    }
+}
+
+/// State of the control script that is kept between ticks
+pub struct ScriptState {
+    script: Option<(String, Script)>, // Source and compiled script
+    fan_pwm: Option<i64>,             // Set while the script controls the fan
+    power_cap: Option<(i64, i64)>,    // Previous cap and the cap written by the script
+    last_error: Option<String>,
+}
+
+impl ScriptState {
+    pub fn new() -> Self {
+        ScriptState {
+            script: None,
+            fan_pwm: None,
+            power_cap: None,
+            last_error: None,
+        }
+    }
+
+    /// Whether the script currently drives the fan instead of the fan control loop
+    pub fn controls_fan(&self) -> bool {
+        self.fan_pwm.is_some()
+    }
 }
 
 impl HWMon {
-    pub fn new(
-        hwmon_path: &PathBuf,
-        fan_control_enabled: bool,
-        fan_curve: BTreeMap<i64, f64>,
-        power_cap: Option<i64>,
-    ) -> HWMon {
-        let mut mon = HWMon {
+    pub fn new(hwmon_path: &PathBuf, config: &GpuConfig) -> HWMon {
+        let mon = HWMon {
             hwmon_path: hwmon_path.clone(),
             fan_control: Arc::new(AtomicBool::new(false)),
-            fan_curve: Arc::new(RwLock::new(fan_curve)),
+            fan_curve: Arc::new(RwLock::new(BTreeMap::new())),
+            fan_curve_options: Arc::new(RwLock::new(FanCurveOptions::default())),
+            fan_smoothing: Arc::new(RwLock::new(FanSmoothing::default())),
+            fan_temp_source: Arc::new(RwLock::new(FanTempSource::default())),
+            fan_limits: Arc::new(RwLock::new(FanLimits::default())),
+            fan_mode: Arc::new(RwLock::new(FanMode::default())),
+            fan_fault: Arc::new(RwLock::new(None)),
+            fan_calibration: Arc::new(RwLock::new(None)),
+            fan_calibration_status: Arc::new(RwLock::new(FanCalibrationStatus::Idle)),
+            fan_health_settings: Arc::new(RwLock::new(FanHealthSettings::default())),
+            power_guard_settings: Arc::new(RwLock::new(PowerGuardSettings::default())),
+            idle_settings: Arc::new(RwLock::new(IdleSettings::default())),
+            idle_fan_curve: Arc::new(RwLock::new(None)),
+            script: Arc::new(RwLock::new(None)),
         };
 
-        if fan_control_enabled {
-            mon.start_fan_control().unwrap();
-        }
-        if let Some(cap) = power_cap {
-            #[allow(unused_must_use)]
-            {
-                mon.set_power_cap(cap);
+        mon.load_config(config);
+
+        mon
+    }
+
+    /// Switches to the settings of a config in place. The fan scheduler shares the settings,
+    /// so it keeps its state and carries on with the new ones on its next tick.
+    pub fn load_config(&self, config: &GpuConfig) {
+        *self.fan_curve.write().unwrap() = config.fan_curve.clone();
+        *self.fan_curve_options.write().unwrap() = config.fan_curve_options.clone();
+        *self.fan_smoothing.write().unwrap() = config.fan_smoothing.clone();
+        *self.fan_temp_source.write().unwrap() = config.fan_temp_source.clone();
+        *self.fan_limits.write().unwrap() = config.fan_limits.clone();
+        *self.fan_mode.write().unwrap() = config.fan_mode.clone();
+        *self.fan_calibration.write().unwrap() = config.fan_calibration.clone();
+        *self.fan_health_settings.write().unwrap() = config.fan_health.clone();
+        *self.power_guard_settings.write().unwrap() = config.power_guard.clone();
+        *self.idle_settings.write().unwrap() = config.idle.clone();
+        *self.script.write().unwrap() = config.script.clone();
+
+        if config.fan_control_enabled {
+            if let Err(e) = self.start_fan_control() {
+                log::error!("Failed to start fan control: {:?}", e);
             }
         }
 
-        mon
+        #[allow(unused_must_use)]
+        {
+            self.set_power_cap(config.power_cap);
+        }
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.hwmon_path
     }
 
     pub fn get_fan_max_speed(&self) -> Option<i64> {
         match fs::read_to_string(self.hwmon_path.join("fan1_max")) {
-            Ok(speed) => Some(speed.trim().parse().unwrap()),
+            Ok(speed) => speed.trim().parse().ok(),
+            Err(_) => None,
+        }
+    }
+
+    pub fn get_fan_min_speed(&self) -> Option<i64> {
+        match fs::read_to_string(self.hwmon_path.join("fan1_min")) {
+            Ok(speed) => speed.trim().parse().ok(),
             Err(_) => None,
         }
     }
@@ -78,11 +245,19 @@ impl HWMon {
                 .unwrap()
         }*/
         match fs::read_to_string(self.hwmon_path.join("fan1_input")) {
-            Ok(a) => Some(a.trim().parse::<i64>().unwrap()),
+            Ok(a) => a.trim().parse::<i64>().ok(),
             _ => None,
         }
     }
 
+    /// The PWM value the fan is currently driven with, 0-255
+    pub fn get_fan_pwm(&self) -> Option<i64> {
+        match fs::read_to_string(self.hwmon_path.join("pwm1")) {
+            Ok(pwm) => pwm.trim().parse().ok(),
+            Err(_) => None,
+        }
+    }
+
     pub fn get_mem_freq(&self) -> Option<i64> {
         let filename = self.hwmon_path.join("freq2_input");
 
@@ -105,11 +280,65 @@ impl HWMon {
         let filename = self.hwmon_path.join("temp1_input");
 
         match fs::read_to_string(filename) {
-            Ok(temp) => Some(temp.trim().parse::<i64>().unwrap() / 1000),
+            Ok(temp) => temp.trim().parse::<i64>().ok().map(|temp| temp / 1000),
             Err(_) => None,
         }
     }
 
+    pub fn get_junction_temp(&self) -> Option<i64> {
+        self.get_labeled_temp("junction")
+    }
+
+    pub fn get_mem_temp(&self) -> Option<i64> {
+        self.get_labeled_temp("mem")
+    }
+
+    // amdgpu exposes edge, junction and mem temperatures as temp1-3, identified by their labels
+    fn get_labeled_temp(&self, label: &str) -> Option<i64> {
+        for i in 1..=3 {
+            if let Ok(temp_label) =
+                fs::read_to_string(self.hwmon_path.join(format!("temp{}_label", i)))
+            {
+                if temp_label.trim() == label {
+                    return match fs::read_to_string(
+                        self.hwmon_path.join(format!("temp{}_input", i)),
+                    ) {
+                        Ok(temp) => temp.trim().parse::<i64>().ok().map(|temp| temp / 1000),
+                        Err(_) => None,
+                    };
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the temperature used as the fan curve input
+    pub fn get_fan_curve_temp(&self) -> Option<f64> {
+        self.fan_temp_source.read().unwrap().select(
+            self.get_gpu_temp(),
+            self.get_junction_temp(),
+            self.get_mem_temp(),
+        )
+    }
+
+    /// Unrounded readings of the temperatures, power usage and load, which all keep moving
+    /// a little on a working card even when it idles
+    fn get_raw_sensor_readings(&self) -> Vec<Option<i64>> {
+        let read = |path: PathBuf| fs::read_to_string(path).ok()?.trim().parse().ok();
+
+        let mut readings: Vec<Option<i64>> = (1..=3)
+            .map(|i| read(self.hwmon_path.join(format!("temp{}_input", i))))
+            .collect();
+        readings.push(read(self.hwmon_path.join("power1_average")));
+        readings.push(
+            self.device_path()
+                .and_then(|path| read(path.join("gpu_busy_percent"))),
+        );
+
+        readings
+    }
+
     pub fn get_voltage(&self) -> Option<i64> {
         let filename = self.hwmon_path.join("in0_input");
 
@@ -128,6 +357,32 @@ impl HWMon {
         }
     }
 
+    pub fn get_power_cap_default(&self) -> Option<i64> {
+        let filename = self.hwmon_path.join("power1_cap_default");
+
+        match fs::read_to_string(filename) {
+            Ok(power_cap) => power_cap
+                .trim()
+                .parse::<i64>()
+                .ok()
+                .map(|power_cap| power_cap / 1000000),
+            _ => None,
+        }
+    }
+
+    pub fn get_power_cap_min(&self) -> Option<i64> {
+        let filename = self.hwmon_path.join("power1_cap_min");
+
+        match fs::read_to_string(filename) {
+            Ok(power_cap) => power_cap
+                .trim()
+                .parse::<i64>()
+                .ok()
+                .map(|power_cap| power_cap / 1000000),
+            _ => None,
+        }
+    }
+
     pub fn get_power_cap(&self) -> Option<i64> {
         let filename = self.hwmon_path.join("power1_cap");
 
@@ -137,7 +392,7 @@ impl HWMon {
         }
     }
 
-    pub fn set_power_cap(&mut self, cap: i64) -> Result<(), HWMonError> {
+    pub fn set_power_cap(&self, cap: i64) -> Result<(), HWMonError> {
         if cap
             > self
                 .get_power_cap_max()
@@ -145,6 +400,9 @@ impl HWMon {
         {
             return Err(HWMonError::InvalidValue);
         }
+        if cap < self.get_power_cap_min().unwrap_or(0) {
+            return Err(HWMonError::InvalidValue);
+        }
 
         let cap = cap * 1000000;
         log::trace!("setting power cap to {}", cap);
@@ -175,55 +433,908 @@ impl HWMon {
         log::trace!("set curve to {:?}", current);
     }
 
-    pub fn start_fan_control(&self) -> Result<(), HWMonError> {
-        if self.fan_control.load(Ordering::SeqCst) {
+    pub fn set_fan_curve_options(&self, options: FanCurveOptions) {
+        log::trace!("set fan curve options to {:?}", options);
+        *self.fan_curve_options.write().unwrap() = options;
+    }
+
+    pub fn get_fan_curve_options(&self) -> FanCurveOptions {
+        self.fan_curve_options.read().unwrap().clone()
+    }
+
+    pub fn set_fan_smoothing(&self, smoothing: FanSmoothing) {
+        log::trace!("set fan smoothing to {:?}", smoothing);
+        *self.fan_smoothing.write().unwrap() = smoothing;
+    }
+
+    pub fn get_fan_smoothing(&self) -> FanSmoothing {
+        self.fan_smoothing.read().unwrap().clone()
+    }
+
+    pub fn set_fan_temp_source(&self, source: FanTempSource) {
+        log::trace!("set fan temperature source to {}", source);
+        *self.fan_temp_source.write().unwrap() = source;
+    }
+
+    pub fn get_fan_temp_source(&self) -> FanTempSource {
+        self.fan_temp_source.read().unwrap().clone()
+    }
+
+    pub fn set_fan_limits(&self, limits: FanLimits) {
+        log::trace!("set fan limits to {:?}", limits);
+        *self.fan_limits.write().unwrap() = limits;
+    }
+
+    pub fn get_fan_limits(&self) -> FanLimits {
+        self.fan_limits.read().unwrap().clone()
+    }
+
+    pub fn get_fan_calibration(&self) -> Option<FanCalibration> {
+        self.fan_calibration.read().unwrap().clone()
+    }
+
+    pub fn get_fan_calibration_status(&self) -> FanCalibrationStatus {
+        self.fan_calibration_status.read().unwrap().clone()
+    }
+
+    /// The fan control loop has to leave the fan alone while this is true
+    pub fn is_calibrating(&self) -> bool {
+        matches!(
+            *self.fan_calibration_status.read().unwrap(),
+            FanCalibrationStatus::Running(_)
+        )
+    }
+
+    /// Measures the speed of the fan over its PWM range in the background.
+    /// The fan is handed back to the fan control loop or the firmware once it's done.
+    pub fn start_fan_calibration(&self) -> Result<(), HWMonError> {
+        if self.is_calibrating() {
             return Ok(());
         }
-        self.fan_control.store(true, Ordering::SeqCst);
 
-        match fs::write(self.hwmon_path.join("pwm1_enable"), "1") {
-            Ok(_) => {
-                let s = self.clone();
-
-                thread::spawn(move || {
-                    while s.fan_control.load(Ordering::SeqCst) {
-                        let curve = s.fan_curve.read().unwrap();
-
-                        let temp = s.get_gpu_temp().unwrap();
-                        log::trace!("Current gpu temp: {}", temp);
-
-                        for (t_low, s_low) in curve.iter() {
-                            match curve.range(t_low..).nth(1) {
-                                Some((t_high, s_high)) => {
-                                    if (t_low..t_high).contains(&&temp) {
-                                        let speed_ratio =
-                                            (temp - t_low) as f64 / (t_high - t_low) as f64; //The ratio of which speed to choose within the range of current lower and upper speeds
-                                        let speed_percent =
-                                            s_low + ((s_high - s_low) * speed_ratio);
-                                        let pwm = (255f64 * (speed_percent / 100f64)) as i64;
-                                        log::trace!("pwm: {}", pwm);
-
-                                        fs::write(s.hwmon_path.join("pwm1"), pwm.to_string())
-                                            .expect("Failed to write to pwm1");
-
-                                        log::trace!("In the range of {}..{}c {}..{}%, setting speed {}% ratio {}", t_low, t_high, s_low, s_high, speed_percent, speed_ratio);
-                                        break;
-                                    }
+        if self.get_fan_speed().is_none() {
+            return Err(HWMonError::Unsupported);
+        }
+
+        if fs::write(self.hwmon_path.join("pwm1_enable"), "1").is_err() {
+            return Err(HWMonError::PermissionDenied);
+        }
+
+        *self.fan_calibration_status.write().unwrap() = FanCalibrationStatus::Running(0.0);
+
+        let hw_mon = self.clone();
+
+        thread::spawn(move || {
+            log::info!("Calibrating fan {:?}", hw_mon.hwmon_path);
+
+            let result = calibrate(
+                &mut HWMonCalibrationFan { hw_mon: &hw_mon },
+                &CalibrationSettings::default(),
+                &|progress| {
+                    *hw_mon.fan_calibration_status.write().unwrap() =
+                        FanCalibrationStatus::Running(progress);
+                },
+            );
+
+            if !hw_mon.is_fan_control_enabled() && hw_mon.write_file("pwm1_enable", "2").is_err() {
+                log::error!("Failed to switch the fan back to automatic control after calibration");
+            }
+
+            *hw_mon.fan_calibration_status.write().unwrap() = match result {
+                Ok(calibration) => {
+                    log::info!("Fan calibration finished: {:?}", calibration);
+                    *hw_mon.fan_calibration.write().unwrap() = Some(calibration.clone());
+                    FanCalibrationStatus::Finished(calibration)
+                }
+                Err(e) => {
+                    log::error!("Fan calibration failed: {}", e);
+                    FanCalibrationStatus::Failed(e)
+                }
+            };
+        });
+
+        Ok(())
+    }
+
+    pub fn set_fan_health_settings(&self, settings: FanHealthSettings) {
+        log::trace!("set fan health settings to {:?}", settings);
+        *self.fan_health_settings.write().unwrap() = settings;
+    }
+
+    pub fn get_fan_health_settings(&self) -> FanHealthSettings {
+        self.fan_health_settings.read().unwrap().clone()
+    }
+
+    /// Checks that the fan spins as fast as its PWM value says it should and protects the card if it doesn't.
+    /// Returns `None` when the check is disabled or the fan can't be monitored.
+    pub fn check_fan_health(
+        &self,
+        state: &mut FanHealthState,
+        elapsed: Duration,
+    ) -> Option<FanHealth> {
+        let settings = self.get_fan_health_settings();
+
+        let readings = match settings.enabled {
+            true => self.get_fan_pwm().zip(self.get_fan_speed()),
+            false => None,
+        };
+
+        let (pwm, rpm) = match readings {
+            Some(readings) => readings,
+            None => {
+                self.restore_fan_protection(state);
+                return None;
+            }
+        };
+
+        let calibration = self.get_fan_calibration();
+        let health = state
+            .monitor
+            .update(&settings, pwm, rpm, calibration.as_ref(), elapsed);
+
+        match &health.fault {
+            Some(fault) if fault.needs_action() => {
+                if settings.action.stop_fan_control && self.is_fan_control_enabled() {
+                    self.fail_fan_control(FanFault::FanFailure(fault.clone()));
+                }
+
+                if let Some(cap) = settings.action.power_cap {
+                    if state.limited_power_cap.is_none() {
+                        match self.get_power_cap() {
+                            Some(current) if current > cap => {
+                                log::warn!(
+                                    "Fan failure: {}, lowering the power cap to {}W",
+                                    fault,
+                                    cap
+                                );
+
+                                match self.set_power_cap(cap) {
+                                    Ok(()) => state.limited_power_cap = Some(current),
+                                    Err(e) => log::error!("Failed to lower the power cap: {:?}", e),
                                 }
-                                None => (),
                             }
+                            _ => (),
                         }
-                        drop(curve); //needed to release rwlock so that the curve can be changed
+                    }
+                }
+            }
+            _ => self.restore_fan_protection(state),
+        }
+
+        Some(health)
+    }
+
+    /// Undoes the power cap limit applied because of a fan failure
+    pub fn restore_fan_protection(&self, state: &mut FanHealthState) {
+        if let Some(power_cap) = state.limited_power_cap.take() {
+            log::info!("Restoring the power cap to {}W", power_cap);
+
+            if let Err(e) = self.set_power_cap(power_cap) {
+                log::error!("Failed to restore the power cap: {:?}", e);
+            }
+        }
+    }
+
+    pub fn set_power_guard_settings(&self, settings: PowerGuardSettings) {
+        log::trace!("set power guard settings to {:?}", settings);
+        *self.power_guard_settings.write().unwrap() = settings;
+    }
+
+    pub fn get_power_guard_settings(&self) -> PowerGuardSettings {
+        self.power_guard_settings.read().unwrap().clone()
+    }
+
+    /// Lowers the power cap while the card is too hot or its fan too fast, and raises it again once it cooled down.
+    /// Returns `None` when the guard is disabled or the power cap can't be read.
+    pub fn power_guard_tick(
+        &self,
+        guard: &mut PowerGuard,
+        elapsed: Duration,
+    ) -> Option<PowerGuardStatus> {
+        let settings = self.get_power_guard_settings();
+
+        let cap = match settings.enabled {
+            true => self.get_power_cap(),
+            false => None,
+        };
+        let (cap, max_cap) = match cap.zip(self.get_power_cap_max()) {
+            Some(caps) => caps,
+            None => {
+                self.restore_power_guard(guard);
+                return None;
+            }
+        };
+
+        // The hottest sensor, as any of them reaching its limit throttles the card
+        let temp = vec![
+            self.get_gpu_temp(),
+            self.get_junction_temp(),
+            self.get_mem_temp(),
+        ]
+        .into_iter()
+        .flatten()
+        .max();
+        let fan_speed = self
+            .get_fan_speed()
+            .zip(self.get_fan_max_speed())
+            .filter(|(_, max)| *max > 0)
+            .map(|(speed, max)| speed as f64 / max as f64 * 100.0);
+
+        let readings = PowerGuardReadings {
+            temp,
+            fan_speed,
+            cap,
+            cap_range: (self.get_power_cap_min().unwrap_or(0), max_cap),
+        };
+        let (new_cap, status) = guard.update(&settings, &readings, elapsed);
+
+        if let Some(new_cap) = new_cap {
+            match &status.reason {
+                Some(reason) => log::warn!(
+                    "Power guard: {}, setting the power cap to {}W",
+                    reason,
+                    new_cap
+                ),
+                None => log::info!("Power guard: restoring the power cap to {}W", new_cap),
+            }
+
+            if let Err(e) = self.set_power_cap(new_cap) {
+                log::error!("Failed to set the power cap: {:?}", e);
+            }
+        }
+
+        Some(status)
+    }
+
+    /// Sets the power cap back to what it was before the power guard lowered it
+    pub fn restore_power_guard(&self, guard: &mut PowerGuard) {
+        if let Some(power_cap) = guard.restore() {
+            log::info!(
+                "Power guard stopped, restoring the power cap to {}W",
+                power_cap
+            );
+
+            if let Err(e) = self.set_power_cap(power_cap) {
+                log::error!("Failed to restore the power cap: {:?}", e);
+            }
+        }
+    }
+
+    pub fn set_idle_settings(&self, settings: IdleSettings) {
+        log::trace!("set idle settings to {:?}", settings);
+        *self.idle_settings.write().unwrap() = settings;
+    }
+
+    pub fn get_idle_settings(&self) -> IdleSettings {
+        self.idle_settings.read().unwrap().clone()
+    }
+
+    /// The device directory with the GPU-wide files, which contains the hwmon directory
+    fn device_path(&self) -> Option<&Path> {
+        self.hwmon_path.parent().and_then(|path| path.parent())
+    }
+
+    pub fn get_busy_percent(&self) -> Option<u8> {
+        let path = self.device_path()?.join("gpu_busy_percent");
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
+    fn get_performance_level(&self) -> Option<String> {
+        let path = self
+            .device_path()?
+            .join("power_dpm_force_performance_level");
+        Some(fs::read_to_string(path).ok()?.trim().to_string())
+    }
+
+    fn set_performance_level(&self, level: &str) -> Result<(), HWMonError> {
+        let path = self
+            .device_path()
+            .ok_or(HWMonError::Unsupported)?
+            .join("power_dpm_force_performance_level");
+
+        fs::write(path, level).map_err(|_| HWMonError::PermissionDenied)
+    }
 
-                        thread::sleep(Duration::from_millis(1000));
+    /// Switches to the low power settings once the GPU was idle for long enough, and back as soon as it's busy.
+    /// Returns `None` when the policy is disabled or the load can't be read.
+    pub fn idle_tick(&self, state: &mut IdleState, elapsed: Duration) -> Option<IdleStatus> {
+        let settings = self.get_idle_settings();
+
+        let busy_percent = match settings.enabled {
+            true => self.get_busy_percent(),
+            false => None,
+        };
+        let busy_percent = match busy_percent {
+            Some(busy_percent) => busy_percent,
+            None => {
+                self.restore_idle(state);
+                return None;
+            }
+        };
+
+        match state.policy.update(&settings, busy_percent, elapsed) {
+            Some(IdleTransition::Enter) => {
+                log::info!(
+                    "GPU idle for {}s, switching to low power",
+                    state.policy.get_idle_for().as_secs()
+                );
+                state.restore = Some(self.enter_low_power(&settings));
+            }
+            Some(IdleTransition::Leave) => {
+                log::info!("GPU busy at {}%, leaving low power", busy_percent);
+                self.restore_idle(state);
+            }
+            None => (),
+        }
+
+        Some(IdleStatus {
+            busy_percent,
+            idle_for: state.policy.get_idle_for().as_secs(),
+            low_power: state.policy.is_low_power(),
+            previous_power_cap: state
+                .restore
+                .as_ref()
+                .and_then(|restore| restore.power_cap)
+                .map(|(previous_cap, _)| previous_cap),
+        })
+    }
+
+    fn enter_low_power(&self, settings: &IdleSettings) -> IdleRestore {
+        let mut restore = IdleRestore {
+            power_cap: None,
+            level: None,
+        };
+
+        if let Some((idle_cap, cap)) = settings.power_cap.zip(self.get_power_cap()) {
+            let idle_cap = idle_cap.max(self.get_power_cap_min().unwrap_or(0)).min(cap);
+
+            match self.set_power_cap(idle_cap) {
+                Ok(()) => restore.power_cap = Some((cap, idle_cap)),
+                Err(e) => log::error!("Failed to set the idle power cap: {:?}", e),
+            }
+        }
+
+        if settings.force_low {
+            if let Some(level) = self.get_performance_level() {
+                match self.set_performance_level("low") {
+                    Ok(()) => restore.level = Some(level),
+                    Err(e) => log::error!("Failed to force the low performance level: {:?}", e),
+                }
+            }
+        }
+
+        *self.idle_fan_curve.write().unwrap() = settings.fan_curve.clone();
+
+        restore
+    }
+
+    /// Puts back what the low power mode changed, unless it was changed again in the meantime
+    pub fn restore_idle(&self, state: &mut IdleState) {
+        state.policy.reset();
+        *self.idle_fan_curve.write().unwrap() = None;
+
+        let restore = match state.restore.take() {
+            Some(restore) => restore,
+            None => return,
+        };
+
+        if let Some((cap, idle_cap)) = restore.power_cap {
+            if self.get_power_cap() == Some(idle_cap) {
+                log::info!("Restoring the power cap to {}W", cap);
+
+                if let Err(e) = self.set_power_cap(cap) {
+                    log::error!("Failed to restore the power cap: {:?}", e);
+                }
+            }
+        }
+
+        if let Some(level) = restore.level {
+            if self.get_performance_level().as_deref() == Some("low") {
+                log::info!("Restoring the performance level {}", level);
+
+                if let Err(e) = self.set_performance_level(&level) {
+                    log::error!("Failed to restore the performance level: {:?}", e);
+                }
+            }
+        }
+    }
+
+    pub fn set_script(&self, script: Option<String>) {
+        log::trace!("set control script to {:?}", script);
+        *self.script.write().unwrap() = script;
+    }
+
+    pub fn get_script(&self) -> Option<String> {
+        self.script.read().unwrap().clone()
+    }
+
+    fn read_vram_mb(&self, file: &str) -> Option<u64> {
+        let path = self.device_path()?.join(file);
+        let bytes: u64 = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+        Some(bytes / 1024 / 1024)
+    }
+
+    pub fn get_stats(&self) -> GpuStats {
+        GpuStats {
+            mem_total: self.read_vram_mb("mem_info_vram_total"),
+            mem_used: self.read_vram_mb("mem_info_vram_used"),
+            mem_freq: self.get_mem_freq(),
+            gpu_freq: self.get_gpu_freq(),
+            gpu_temp: self.get_gpu_temp(),
+            junction_temp: self.get_junction_temp(),
+            mem_temp: self.get_mem_temp(),
+            power_avg: self.get_power_avg(),
+            power_cap: self.get_power_cap(),
+            power_cap_min: self.get_power_cap_min(),
+            power_cap_max: self.get_power_cap_max(),
+            power_guard: None,
+            fan_speed: self.get_fan_speed(),
+            max_fan_speed: self.get_fan_max_speed(),
+            fan_pwm: self.get_fan_pwm(),
+            fan_calibration: self.get_fan_calibration_status(),
+            fan_health: None,
+            voltage: self.get_voltage(),
+            gpu_usage: self.get_busy_percent(),
+            idle: None,
+            process_rule: None,
+            script: None,
+        }
+    }
+
+    /// Runs the control script and applies what it returned.
+    /// Returns `None` when there is no script.
+    pub fn script_tick(&self, state: &mut ScriptState) -> Option<ScriptStatus> {
+        let source = match self.get_script() {
+            Some(source) => source,
+            None => {
+                self.restore_script(state);
+                return None;
+            }
+        };
+
+        // Also starts over with an empty state when the script was changed
+        if state.script.as_ref().map(|(current, _)| current) != Some(&source) {
+            self.restore_script(state);
+
+            match Script::compile(&source) {
+                Ok(script) => state.script = Some((source, script)),
+                Err(e) => return Some(self.fail_script(state, e)),
+            }
+        }
+
+        let stats = self.get_stats();
+        let result = match &mut state.script {
+            Some((_, script)) => script.run(&stats, LocalTime::now()),
+            None => unreachable!(),
+        };
+
+        match result {
+            Ok(output) => {
+                if state.last_error.take().is_some() {
+                    log::info!("The control script works again");
+                }
+                self.apply_script_output(state, &output);
+
+                Some(ScriptStatus {
+                    output: Some(output),
+                    error: None,
+                    previous_power_cap: state.power_cap.map(|(previous_cap, _)| previous_cap),
+                })
+            }
+            Err(e) => Some(self.fail_script(state, e)),
+        }
+    }
+
+    fn apply_script_output(&self, state: &mut ScriptState, output: &ScriptOutput) {
+        // A failing fan stays in its failsafe
+        match output.fan_pwm.filter(|_| self.get_fan_fault().is_none()) {
+            Some(pwm) => {
+                let result = match state.fan_pwm {
+                    Some(_) => Ok(()),
+                    None => self.write_file("pwm1_enable", "1"),
+                }
+                .and_then(|_| self.write_file("pwm1", &pwm.to_string()));
+
+                match result {
+                    Ok(()) => state.fan_pwm = Some(pwm),
+                    Err(e) => log::error!("Failed to set the fan speed from the script: {}", e),
+                }
+            }
+            None => self.release_script_fan(state),
+        }
+
+        match output.power_cap {
+            Some(cap) => {
+                let cap = cap
+                    .max(self.get_power_cap_min().unwrap_or(0))
+                    .min(self.get_power_cap_max().unwrap_or(cap));
+
+                // Only written when the script changes its mind, so that the power guard can still lower it
+                if state.power_cap.map(|(_, script_cap)| script_cap) != Some(cap) {
+                    let previous = match state.power_cap {
+                        Some((previous, _)) => Some(previous),
+                        None => self.get_power_cap(),
+                    };
+
+                    match previous {
+                        Some(previous) => match self.set_power_cap(cap) {
+                            Ok(()) => state.power_cap = Some((previous, cap)),
+                            Err(e) => {
+                                log::error!("Failed to set the power cap from the script: {:?}", e)
+                            }
+                        },
+                        None => log::error!("Failed to read the power cap"),
                     }
-                });
+                }
+            }
+            None => self.release_script_power_cap(state),
+        }
+    }
+
+    fn fail_script(&self, state: &mut ScriptState, error: String) -> ScriptStatus {
+        if state.last_error.as_ref() != Some(&error) {
+            log::error!(
+                "Control script failed, falling back to automatic control: {}",
+                error
+            );
+            state.last_error = Some(error.clone());
+        }
+
+        self.release_script_fan(state);
+        self.release_script_power_cap(state);
+
+        ScriptStatus {
+            output: None,
+            error: Some(error),
+            previous_power_cap: None,
+        }
+    }
+
+    /// Hands the fan back to the fan control loop if it's enabled, or to the firmware otherwise
+    fn release_script_fan(&self, state: &mut ScriptState) {
+        if state.fan_pwm.take().is_some() && !self.is_fan_control_enabled() {
+            if let Err(e) = self.write_file("pwm1_enable", "2") {
+                log::error!("Failed to give the fan back to the firmware: {}", e);
+            }
+        }
+    }
+
+    /// Puts back the power cap from before the script changed it, unless it was changed again in the meantime
+    fn release_script_power_cap(&self, state: &mut ScriptState) {
+        if let Some((cap, script_cap)) = state.power_cap.take() {
+            if self.get_power_cap() == Some(script_cap) {
+                log::info!("Restoring the power cap to {}W", cap);
+
+                if let Err(e) = self.set_power_cap(cap) {
+                    log::error!("Failed to restore the power cap: {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Undoes what the script changed and forgets its state
+    pub fn restore_script(&self, state: &mut ScriptState) {
+        self.release_script_fan(state);
+        self.release_script_power_cap(state);
+        *state = ScriptState::new();
+    }
+
+    pub fn start_fan_control(&self) -> Result<(), HWMonError> {
+        if self.fan_control.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        // The control loop itself is run by the daemon's fan scheduler
+        match fs::write(self.hwmon_path.join("pwm1_enable"), "1") {
+            Ok(_) => {
+                *self.fan_fault.write().unwrap() = None;
+                self.fan_control.store(true, Ordering::SeqCst);
                 Ok(())
             }
             Err(_) => Err(HWMonError::PermissionDenied),
         }
     }
 
+    pub fn is_fan_control_enabled(&self) -> bool {
+        self.fan_control.load(Ordering::SeqCst)
+    }
+
+    /// Runs one iteration of the fan control loop
+    pub fn fan_control_tick(
+        &self,
+        state: &mut FanControlState,
+        interval: Duration,
+        elapsed: Duration,
+    ) -> Result<(), FanFault> {
+        let mode = self.fan_mode.read().unwrap().clone();
+        let idle_fan_curve = self.idle_fan_curve.read().unwrap().clone();
+
+        // Modes without a temperature input take the fan back from the failsafe right away
+        let reads_temp =
+            idle_fan_curve.is_some() || matches!(mode, FanMode::Curve | FanMode::Pid(_));
+        if state.suspended && !reads_temp {
+            self.resume_fan_control(state)?;
+        }
+
+        // Switching from RPM to PWM control needs the PWM mode to be set again
+        let native_rpm = match mode {
+            FanMode::TargetRpm(_) => idle_fan_curve.is_none() && self.supports_fan_target(),
+            _ => false,
+        };
+        if native_rpm != state.native_rpm_enabled {
+            let file = match native_rpm {
+                true => "fan1_enable",
+                false => "pwm1_enable",
+            };
+            self.write_file(file, "1")?;
+            state.native_rpm_enabled = native_rpm;
+        }
+
+        // The PID controller starts over when it's selected again
+        if !matches!(mode, FanMode::Pid(_)) {
+            state.pid.reset();
+        }
+
+        // The relaxed curve of the low power mode takes over from any mode
+        if let Some(curve) = idle_fan_curve {
+            return self.curve_tick(
+                state,
+                &curve,
+                FanCurveInterpolation::Linear,
+                FanSpeedUnit::Percent,
+                interval,
+                elapsed,
+            );
+        }
+
+        match mode {
+            FanMode::Curve => {
+                let FanCurveOptions {
+                    interpolation,
+                    unit,
+                    ..
+                } = self.fan_curve_options.read().unwrap().clone();
+                let curve = self.fan_curve.read().unwrap().clone();
+
+                self.curve_tick(state, &curve, interpolation, unit, interval, elapsed)
+            }
+            FanMode::Pid(settings) => {
+                let temp = self.read_fan_control_temp(state, interval, elapsed)?;
+                let pwm = state.pid.update(&settings, temp, elapsed);
+
+                log::trace!("Average temp {}c, PID output PWM {}", temp, pwm);
+
+                self.write_pwm(state, pwm)
+            }
+            FanMode::Static(speed) => self.write_fan_speed(state, speed),
+            FanMode::TargetRpm(rpm) if native_rpm => {
+                state.last_pwm = None;
+                self.write_file("fan1_target", &rpm.to_string())
+            }
+            FanMode::TargetRpm(rpm) => {
+                let limits = self.fan_limits.read().unwrap().clone();
+
+                let fan_speed = self
+                    .get_fan_speed()
+                    .ok_or_else(|| FanFault::SensorReadFailed(String::from("fan1_input")))?;
+                let max_fan_speed = self
+                    .get_fan_max_speed()
+                    .ok_or_else(|| FanFault::SensorReadFailed(String::from("fan1_max")))?;
+
+                state.rpm_speed = rpm_step(state.rpm_speed, rpm, fan_speed, max_fan_speed)
+                    .max(limits.min_speed)
+                    .min(limits.max_speed);
+
+                let speed = state.rpm_speed;
+                self.write_fan_speed(state, speed)
+            }
+        }
+    }
+
+    /// Puts the fan into a safe state after the control loop failed
+    pub fn fail_fan_control(&self, fault: FanFault) {
+        log::error!("Fan control failed: {}", fault);
+
+        self.fan_control.store(false, Ordering::SeqCst);
+        self.apply_fan_failsafe(fault);
+    }
+
+    /// Puts the fan into a safe state while the sensors are stale, keeping fan control enabled
+    /// so that it takes over again once they update
+    pub fn suspend_fan_control(&self, state: &mut FanControlState, fault: FanFault) {
+        log::error!("Fan control suspended: {}", fault);
+
+        state.suspended = true;
+        state.last_pwm = None;
+        self.apply_fan_failsafe(fault);
+    }
+
+    fn resume_fan_control(&self, state: &mut FanControlState) -> Result<(), FanFault> {
+        log::info!("Sensors are updating again, resuming fan control");
+
+        self.write_file("pwm1_enable", "1")?;
+        state.suspended = false;
+        state.native_rpm_enabled = false;
+        *self.fan_fault.write().unwrap() = None;
+
+        Ok(())
+    }
+
+    fn apply_fan_failsafe(&self, fault: FanFault) {
+        let failsafe = self.fan_limits.read().unwrap().failsafe.clone();
+        let result = match failsafe {
+            FanFailsafe::Automatic => self.write_file("pwm1_enable", "2"),
+            FanFailsafe::FullSpeed => self
+                .write_file("pwm1_enable", "1")
+                .and_then(|_| self.write_file("pwm1", "255")),
+        };
+
+        match result {
+            Ok(()) => log::warn!("Fan failsafe applied: {:?}", failsafe),
+            Err(e) => {
+                log::error!("Failed to apply the fan failsafe: {}", e);
+
+                if self.write_file("pwm1", "255").is_err() {
+                    log::error!("Failed to set the fan to full speed, the fan is left as is");
+                }
+            }
+        }
+
+        *self.fan_fault.write().unwrap() = Some(fault);
+    }
+
+    pub fn get_fan_fault(&self) -> Option<FanFault> {
+        self.fan_fault.read().unwrap().clone()
+    }
+
+    fn curve_tick(
+        &self,
+        state: &mut FanControlState,
+        curve: &BTreeMap<i64, f64>,
+        interpolation: FanCurveInterpolation,
+        unit: FanSpeedUnit,
+        interval: Duration,
+        elapsed: Duration,
+    ) -> Result<(), FanFault> {
+        let smoothing = self.fan_smoothing.read().unwrap().clone();
+        let limits = self.fan_limits.read().unwrap().clone();
+
+        let temp = self.read_fan_control_temp(state, interval, elapsed)?;
+
+        if let Some(target) = curve_speed(curve, temp, interpolation) {
+            let target_percent = self.curve_speed_percent(target, unit)?;
+
+            let speed_percent = state
+                .smoother
+                .update(&smoothing, temp, target_percent, elapsed);
+            let speed_percent = state.smoother.apply_limits(
+                &limits,
+                smoothing.hysteresis,
+                temp,
+                speed_percent,
+                elapsed,
+            );
+
+            log::trace!(
+                "Average temp {}c, curve speed {}%, setting speed {}%",
+                temp,
+                target_percent,
+                speed_percent
+            );
+
+            self.write_fan_speed(state, speed_percent)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the selected temperature source and averages it over the configured window
+    fn read_fan_control_temp(
+        &self,
+        state: &mut FanControlState,
+        interval: Duration,
+        elapsed: Duration,
+    ) -> Result<f64, FanFault> {
+        let average_window = self.fan_smoothing.read().unwrap().temp_average_window;
+        let temp_source = self.fan_temp_source.read().unwrap().clone();
+
+        let temp = state.watchdog.check(
+            &temp_source.to_string(),
+            self.get_fan_curve_temp(),
+            self.get_raw_sensor_readings(),
+            elapsed,
+        )?;
+        log::trace!("Current fan curve temp: {}", temp);
+
+        if state.suspended {
+            self.resume_fan_control(state)?;
+        }
+
+        let samples = (average_window * 1000 / interval.as_millis().max(1) as u64).max(1);
+        let temp = state.smoother.push_temp(temp, samples as usize);
+        state.last_temp = Some(temp);
+
+        Ok(temp)
+    }
+
+    /// Converts a speed from the fan curve to a percentage of the maximum PWM
+    fn curve_speed_percent(&self, speed: f64, unit: FanSpeedUnit) -> Result<f64, FanFault> {
+        match unit {
+            FanSpeedUnit::Percent => Ok(speed),
+            FanSpeedUnit::Rpm => match &*self.fan_calibration.read().unwrap() {
+                Some(calibration) => Ok(calibration.rpm_to_percent(speed)),
+                // Without a calibration the fan is assumed to be linear up to its maximum speed
+                None => {
+                    let max_fan_speed = self
+                        .get_fan_max_speed()
+                        .ok_or_else(|| FanFault::SensorReadFailed(String::from("fan1_max")))?;
+
+                    Ok((speed / max_fan_speed as f64 * 100.0).min(100.0))
+                }
+            },
+        }
+    }
+
+    fn write_fan_speed(
+        &self,
+        state: &mut FanControlState,
+        speed_percent: f64,
+    ) -> Result<(), FanFault> {
+        let pwm = (255f64 * (speed_percent / 100f64)) as i64;
+        self.write_pwm(state, pwm)
+    }
+
+    fn write_pwm(&self, state: &mut FanControlState, pwm: i64) -> Result<(), FanFault> {
+        log::trace!("pwm: {}", pwm);
+
+        self.write_file("pwm1", &pwm.to_string())?;
+        state.last_pwm = Some(pwm);
+
+        Ok(())
+    }
+
+    fn write_file(&self, file: &str, value: &str) -> Result<(), FanFault> {
+        fs::write(self.hwmon_path.join(file), value)
+            .map_err(|_| FanFault::WriteFailed(file.to_string()))
+    }
+
+    /// Whether the card can regulate the fan to an RPM target by itself
+    pub fn supports_fan_target(&self) -> bool {
+        self.hwmon_path.join("fan1_target").exists() && self.hwmon_path.join("fan1_enable").exists()
+    }
+
+    pub fn set_fan_mode(&self, mode: FanMode) -> Result<(), HWMonError> {
+        match mode {
+            FanMode::Static(speed) if !(0.0..=100.0).contains(&speed) => {
+                return Err(HWMonError::InvalidValue)
+            }
+            FanMode::TargetRpm(rpm) => {
+                let max = self.get_fan_max_speed().ok_or(HWMonError::Unsupported)?;
+                let min = self.get_fan_min_speed().unwrap_or(0);
+
+                if rpm < min || rpm > max {
+                    return Err(HWMonError::InvalidValue);
+                }
+            }
+            FanMode::Pid(ref settings) => {
+                if let Err(e) = settings.validate() {
+                    log::warn!("Invalid PID fan settings: {}", e);
+                    return Err(HWMonError::InvalidValue);
+                }
+            }
+            _ => (),
+        }
+
+        log::trace!("set fan mode to {}", mode);
+        *self.fan_mode.write().unwrap() = mode;
+
+        Ok(())
+    }
+
+    pub fn get_fan_mode(&self) -> FanMode {
+        self.fan_mode.read().unwrap().clone()
+    }
+
     pub fn stop_fan_control(&self) -> Result<(), HWMonError> {
         match fs::write(self.hwmon_path.join("pwm1_enable"), "2") {
             Ok(_) => {
@@ -243,3 +1354,32 @@ impl HWMon {
         )
     }
 }
+
+/// Drives the fan directly for the calibration routine
+struct HWMonCalibrationFan<'a> {
+    hw_mon: &'a HWMon,
+}
+
+impl CalibrationFan for HWMonCalibrationFan<'_> {
+    fn set_pwm(&mut self, pwm: i64) -> Result<(), String> {
+        // Something else took the fan over, for example fan control was stopped
+        match fs::read_to_string(self.hw_mon.hwmon_path.join("pwm1_enable")) {
+            Ok(mode) if mode.trim() == "1" => (),
+            _ => return Err(String::from("the fan is no longer in manual mode")),
+        }
+
+        self.hw_mon
+            .write_file("pwm1", &pwm.to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    fn read_rpm(&mut self) -> Result<i64, String> {
+        self.hw_mon
+            .get_fan_speed()
+            .ok_or_else(|| FanFault::SensorReadFailed(String::from("fan1_input")).to_string())
+    }
+
+    fn wait(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
@@ -0,0 +1,221 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// Switches the GPU to low power settings after it wasn't used for a while
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IdleSettings {
+    pub enabled: bool,
+    pub idle_threshold: u8, // gpu_busy_percent at or below which the GPU counts as idle
+    pub busy_threshold: u8, // gpu_busy_percent above which the previous settings are restored
+    pub idle_time: u64,     // Seconds the GPU has to be idle before switching
+    pub power_cap: Option<i64>, // W while idle
+    pub force_low: bool,    // Forces the lowest performance level while idle
+    pub fan_curve: Option<BTreeMap<i64, f64>>, // Relaxed curve in % while idle, if fan control is enabled
+}
+
+impl Default for IdleSettings {
+    fn default() -> Self {
+        IdleSettings {
+            enabled: false,
+            idle_threshold: 5,
+            busy_threshold: 20,
+            idle_time: 300,
+            power_cap: None,
+            force_low: true,
+            fan_curve: None,
+        }
+    }
+}
+
+impl IdleSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.busy_threshold > 100 || self.idle_threshold >= self.busy_threshold {
+            return Err(format!(
+                "the idle threshold of {}% has to be below the busy threshold of {}%",
+                self.idle_threshold, self.busy_threshold
+            ));
+        }
+        if self.idle_time == 0 {
+            return Err("the idle time has to be at least 1 second".to_string());
+        }
+        if let Some(power_cap) = self.power_cap {
+            if power_cap <= 0 {
+                return Err("the idle power cap has to be at least 1W".to_string());
+            }
+        }
+        if let Some(fan_curve) = &self.fan_curve {
+            if fan_curve.is_empty() {
+                return Err("the idle fan curve has no points".to_string());
+            }
+            if fan_curve
+                .values()
+                .any(|speed| !(0.0..=100.0).contains(speed))
+            {
+                return Err("the idle fan curve speeds have to be within 0-100%".to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IdleStatus {
+    pub busy_percent: u8,
+    pub idle_for: u64, // Seconds
+    pub low_power: bool,
+    pub previous_power_cap: Option<i64>, // W, the cap to go back to while in low power
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IdleTransition {
+    Enter,
+    Leave,
+}
+
+/// Decides when the GPU enters and leaves low power from its load
+pub struct IdlePolicy {
+    idle_for: Duration,
+    low_power: bool,
+}
+
+impl IdlePolicy {
+    pub fn new() -> Self {
+        IdlePolicy {
+            idle_for: Duration::from_secs(0),
+            low_power: false,
+        }
+    }
+
+    pub fn is_low_power(&self) -> bool {
+        self.low_power
+    }
+
+    pub fn get_idle_for(&self) -> Duration {
+        self.idle_for
+    }
+
+    pub fn update(
+        &mut self,
+        settings: &IdleSettings,
+        busy_percent: u8,
+        elapsed: Duration,
+    ) -> Option<IdleTransition> {
+        // Loads between the thresholds neither count as idle nor end the low power mode
+        if busy_percent <= settings.idle_threshold {
+            self.idle_for += elapsed;
+        } else if !self.low_power || busy_percent > settings.busy_threshold {
+            self.idle_for = Duration::from_secs(0);
+        }
+
+        match self.low_power {
+            false if self.idle_for >= Duration::from_secs(settings.idle_time) => {
+                self.low_power = true;
+                Some(IdleTransition::Enter)
+            }
+            true if busy_percent > settings.busy_threshold => {
+                self.low_power = false;
+                Some(IdleTransition::Leave)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = IdlePolicy::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn run(
+        policy: &mut IdlePolicy,
+        settings: &IdleSettings,
+        busy: u8,
+        secs: u32,
+    ) -> Vec<IdleTransition> {
+        (0..secs)
+            .filter_map(|_| policy.update(settings, busy, SECOND))
+            .collect()
+    }
+
+    #[test]
+    fn enters_low_power_after_idle_time() {
+        let settings = IdleSettings {
+            enabled: true,
+            ..IdleSettings::default()
+        };
+        let mut policy = IdlePolicy::new();
+
+        // Short breaks in the load don't count
+        run(&mut policy, &settings, 2, 200);
+        run(&mut policy, &settings, 50, 1);
+        assert!(run(&mut policy, &settings, 2, 299).is_empty());
+        assert!(!policy.is_low_power());
+
+        assert_eq!(
+            run(&mut policy, &settings, 2, 1),
+            vec![IdleTransition::Enter]
+        );
+        assert!(policy.is_low_power());
+        assert!(run(&mut policy, &settings, 0, 600).is_empty());
+    }
+
+    #[test]
+    fn leaves_low_power_on_load_with_hysteresis() {
+        let settings = IdleSettings {
+            enabled: true,
+            idle_time: 60,
+            ..IdleSettings::default()
+        };
+        let mut policy = IdlePolicy::new();
+
+        assert_eq!(
+            run(&mut policy, &settings, 0, 60),
+            vec![IdleTransition::Enter]
+        );
+
+        // Light desktop activity keeps the low power mode
+        assert!(run(&mut policy, &settings, 15, 30).is_empty());
+        assert!(policy.is_low_power());
+
+        // Real load ends it on the first tick
+        assert_eq!(
+            run(&mut policy, &settings, 90, 1),
+            vec![IdleTransition::Leave]
+        );
+        assert_eq!(policy.get_idle_for(), Duration::from_secs(0));
+
+        // And the idle time starts over
+        assert!(run(&mut policy, &settings, 0, 59).is_empty());
+        assert_eq!(
+            run(&mut policy, &settings, 0, 1),
+            vec![IdleTransition::Enter]
+        );
+    }
+
+    #[test]
+    fn validates_settings() {
+        assert!(IdleSettings::default().validate().is_ok());
+        assert!(IdleSettings {
+            idle_threshold: 30,
+            ..IdleSettings::default()
+        }
+        .validate()
+        .is_err());
+
+        let mut fan_curve = BTreeMap::new();
+        fan_curve.insert(60, 120.0);
+        assert!(IdleSettings {
+            fan_curve: Some(fan_curve),
+            ..IdleSettings::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -1,17 +1,47 @@
 pub mod config;
 pub mod daemon_connection;
+pub mod export;
+pub mod fan_calibration;
+pub mod fan_control;
+pub mod fan_health;
+pub mod fan_pid;
+pub mod fan_scheduler;
 pub mod gpu_controller;
+pub mod group;
+pub mod hooks;
 pub mod hw_mon;
+pub mod idle;
+pub mod power_guard;
+pub mod power_source;
+pub mod process_watcher;
+pub mod schedule;
+pub mod script;
 
-use config::{Config, GpuConfig};
-use gpu_controller::PowerProfile;
+use config::{Config, ConfigBackup, ConfigError, GpuConfig, GpuIdentifier};
+use daemon_connection::DaemonConnection;
+use export::SettingsExport;
+use fan_control::{FanCurveOptions, FanLimits, FanMode, FanSmoothing, FanSpeedUnit, FanTempSource};
+use fan_health::FanHealthSettings;
+use fan_scheduler::FanScheduler;
+use gpu_controller::{ClocksSettings, GpuControllerError, PowerProfile};
+use group::{GpuGroup, GroupSetting};
+use hooks::{Hook, HookEvent, HookResult, HookRunner};
+use idle::IdleSettings;
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+use nix::unistd::{Group, Uid, User};
 use pciid_parser::PciDatabase;
+use power_guard::PowerGuardSettings;
+use power_source::{PowerSource, PowerSourceWatcher};
+use process_watcher::{ProcessRule, ProcessRuleMatch, ProcessWatcher};
 use rand::prelude::*;
+use schedule::{Schedule, ScheduleAction, ScheduleWatcher};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
 use std::{
     collections::{BTreeMap, HashMap},
-    fs,
+    fmt, fs, io,
 };
 
 use crate::gpu_controller::GpuController;
@@ -19,36 +49,129 @@ use crate::gpu_controller::GpuController;
 // Abstract socket allows anyone to connect without worrying about permissions
 // https://unix.stackexchange.com/questions/579612/unix-domain-sockets-for-non-root-user
 pub const SOCK_PATH: &str = "amdgpu-configurator.sock";
-pub const BUFFER_SIZE: usize = 4096;
 
 pub struct Daemon {
     gpu_controllers: HashMap<u32, GpuController>,
     listener: std::os::unix::io::RawFd,
     config: Config,
+    fan_scheduler: FanScheduler,
+    hooks: HookRunner,
+    process_watcher: ProcessWatcher,
+    process_rule_restores: HashMap<u32, ProcessRuleRestore>,
+    schedule_watcher: ScheduleWatcher,
+    power_source_watcher: PowerSourceWatcher,
+}
+
+/// What a GPU returns to once no process matches the rule that switched its profile anymore
+struct ProcessRuleRestore {
+    profile: String, // The profile the rule switched to
+    previous_profile: Option<String>,
+    previous_config: GpuConfig,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Action {
     CheckAlive,
     GetConfig,
+    GetConfigBackups,
+    GetConfigLoadError,
+    RestoreConfigBackup(usize), // By the index of `ConfigBackup`
     SetConfig(Config),
+    GetProfiles(u32),
+    CreateProfile(u32, String),
+    RenameProfile(u32, String, String),
+    DeleteProfile(u32, String),
+    ActivateProfile(u32, String),
+    ExportSettings(u32, Option<String>), // The current settings or a profile
+    ImportSettings(u32, SettingsExport, Option<String>, bool), // Applies them or adds them as a profile, forced despite incompatibilities
+    GetProcessRules,
+    AddProcessRule(ProcessRule),
+    RemoveProcessRule(usize),
+    ApplyProcessRules, // Sent by the process watcher when the matched rules change
+    GetGroups,
+    CreateGroup(GpuGroup),
+    DeleteGroup(String),
+    SetGroupSetting(String, Option<u32>, GroupSetting), // For the whole group, or as an override of one GPU
+    ClearGroupSetting(String, Option<u32>, String),     // By the setting kind
+    ApplyGroup(String),
+    GetHooks,
+    AddHook(Hook),
+    RemoveHook(usize),
+    GetSchedules,
+    SetSchedule(Option<usize>, Schedule), // Adds a schedule, or replaces the one at the index
+    RemoveSchedule(usize),
+    ApplySchedules, // Sent by the schedule watcher when schedules are due
+    SetPowerSourceProfile(u32, PowerSource, Option<String>),
+    ApplyPowerSource, // Sent by the power source watcher when the machine was plugged in or unplugged
     GetGpus,
     GetInfo(u32),
     GetStats(u32),
     StartFanControl(u32),
     StopFanControl(u32),
     GetFanControl(u32),
-    SetFanCurve(u32, BTreeMap<i64, f64>),
+    StartFanCalibration(u32),
+    SetFanCurve(u32, BTreeMap<i64, f64>, FanCurveOptions),
+    SetFanSmoothing(u32, FanSmoothing),
+    SetFanTempSource(u32, FanTempSource),
+    SetFanLimits(u32, FanLimits),
+    SetFanHealthSettings(u32, FanHealthSettings),
+    SetFanMode(u32, FanMode),
     SetPowerCap(u32, i64),
+    SetPowerGuard(u32, PowerGuardSettings),
+    SetIdleSettings(u32, IdleSettings),
+    SetScript(u32, Option<String>),
+    ApplyScriptProfiles, // Sent by the fan scheduler when a control script asks for another profile
     SetPowerProfile(u32, PowerProfile),
     // SetGPUPowerState(u32, u32, i64, Option<i64>),
     SetGPUMaxPowerState(u32, i64, Option<i64>),
+    SetVddcCurvePoint(u32, u32, i64, i64),
+    SetGPUMinClock(u32, i64),
     SetVRAMMaxClock(u32, i64),
+    SetVRAMMinClock(u32, i64),
+    SetVoltageOffset(u32, i64),
+    ApplyClocks(u32, ClocksSettings),
     CommitGPUPowerStates(u32),
     ResetGPUPowerStates(u32),
+    SetOdFanValue(u32, String, i64),
+    SetOdFanCurvePoint(u32, u32, i64, i64),
+    ResetOdFanControls(u32),
     Shutdown,
 }
 
+impl Action {
+    /// The GPU whose settings the action changes right away, for the settings-applied hooks.
+    /// Clocks and voltages only take effect when they're committed.
+    fn get_applied_gpu(&self) -> Option<u32> {
+        match self {
+            Action::StartFanControl(id)
+            | Action::StopFanControl(id)
+            | Action::SetFanCurve(id, ..)
+            | Action::SetFanSmoothing(id, _)
+            | Action::SetFanTempSource(id, _)
+            | Action::SetFanLimits(id, _)
+            | Action::SetFanHealthSettings(id, _)
+            | Action::SetFanMode(id, _)
+            | Action::SetPowerCap(id, _)
+            | Action::SetPowerGuard(id, _)
+            | Action::SetIdleSettings(id, _)
+            | Action::SetScript(id, _)
+            | Action::SetPowerProfile(id, _)
+            | Action::CommitGPUPowerStates(id)
+            | Action::ResetGPUPowerStates(id)
+            | Action::SetOdFanValue(id, ..)
+            | Action::SetOdFanCurvePoint(id, ..)
+            | Action::ResetOdFanControls(id) => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// The variant name without the parameters
+    fn get_name(&self) -> String {
+        let name = format!("{:?}", self);
+        name.split('(').next().unwrap_or_default().to_string()
+    }
+}
+
 impl Daemon {
     pub fn new(unprivileged: bool) -> Daemon {
         let addr = nix::sys::socket::SockAddr::Unix(
@@ -73,30 +196,175 @@ impl Daemon {
                     log::info!("Loaded config from {}", c.config_path.to_string_lossy());
                     c
                 }
-                Err(_) => {
+                Err(ConfigError::IoError(e)) if e.kind() == io::ErrorKind::NotFound => {
                     log::info!("Config not found, creating");
                     let c = Config::new(&config_path);
                     //c.save().unwrap();
                     c
                 }
+                Err(e) => {
+                    log::error!(
+                        "FAILED TO LOAD THE CONFIG {}: {}",
+                        config_path.to_string_lossy(),
+                        e
+                    );
+
+                    // Kept in case it gets replaced later on, by setting or restoring a whole config
+                    match Config::back_up_broken(&config_path) {
+                        Ok(backup_path) => log::error!(
+                            "The broken config was copied to {}",
+                            backup_path.to_string_lossy()
+                        ),
+                        Err(e) => log::error!("Failed to back up the broken config: {}", e),
+                    }
+
+                    log::error!("Starting with the default settings, which are not saved over it");
+
+                    let mut c = Config::new(&config_path);
+                    c.load_error = Some(e.to_string());
+                    c
+                }
             }
         };
 
         log::info!("Using config {:?}", config);
 
+        let known_gpus = config
+            .gpu_configs
+            .iter()
+            .map(|(id, (identifier, _))| (*id, identifier.clone()))
+            .collect::<HashMap<u32, GpuIdentifier>>();
         let gpu_controllers = Self::load_gpu_controllers(&mut config);
 
-        if !unprivileged {
-            config.save().unwrap();
+        // The daemon still works when the config can't be written, the error is logged
+        if !unprivileged && config.load_error.is_none() {
+            let _ = save_config(&config);
         }
 
-        Daemon {
+        let hooks = HookRunner::new(config.hooks.clone());
+        let process_watcher = ProcessWatcher::new(config.process_rules.clone());
+        let schedule_watcher = ScheduleWatcher::new(config.schedules.clone());
+
+        let mut daemon = Daemon {
             listener,
             gpu_controllers,
             config,
+            // The scheduler can't wait for the daemon, which might be waiting for the scheduler to stop
+            fan_scheduler: FanScheduler::new(hooks.clone(), || {
+                thread::spawn(|| match DaemonConnection::new() {
+                    Ok(d) => {
+                        if let Err(e) = d.apply_script_profiles() {
+                            log::error!("Failed to apply the script profiles: {}", e);
+                        }
+                    }
+                    Err(e) => log::error!("Failed to connect to the daemon: {:?}", e),
+                });
+            }),
+            hooks,
+            process_watcher,
+            process_rule_restores: HashMap::new(),
+            schedule_watcher,
+            power_source_watcher: PowerSourceWatcher::new(),
+        };
+        daemon.start_fan_scheduler();
+
+        // So that a replaced card gets the settings of its group
+        for id in daemon.gpu_controllers.keys().copied().collect::<Vec<u32>>() {
+            if !unprivileged && !known_gpus.contains_key(&id) {
+                daemon.apply_groups(id);
+            }
+        }
+        daemon.fire_gpu_hooks(known_gpus);
+
+        // Switching profiles needs the daemon itself, so the watcher asks it to over the socket
+        daemon.process_watcher.start(
+            PathBuf::from("/proc"),
+            process_watcher::DEFAULT_INTERVAL,
+            || match DaemonConnection::new() {
+                Ok(d) => {
+                    if let Err(e) = d.apply_process_rules() {
+                        log::error!("Failed to apply the process rules: {}", e);
+                    }
+                }
+                Err(e) => log::error!("Failed to connect to the daemon: {:?}", e),
+            },
+        );
+        daemon
+            .schedule_watcher
+            .start(schedule::DEFAULT_INTERVAL, || {
+                match DaemonConnection::new() {
+                    Ok(d) => {
+                        if let Err(e) = d.apply_schedules() {
+                            log::error!("Failed to apply the schedules: {}", e);
+                        }
+                    }
+                    Err(e) => log::error!("Failed to connect to the daemon: {:?}", e),
+                }
+            });
+        daemon.power_source_watcher.start(
+            PathBuf::from("/sys/class/power_supply"),
+            power_source::DEFAULT_INTERVAL,
+            || match DaemonConnection::new() {
+                Ok(d) => {
+                    if let Err(e) = d.apply_power_source() {
+                        log::error!("Failed to apply the power source profiles: {}", e);
+                    }
+                }
+                Err(e) => log::error!("Failed to connect to the daemon: {:?}", e),
+            },
+        );
+
+        daemon
+    }
+
+    /// Runs the hooks of the GPUs that were added or removed since the given ones were known.
+    /// GPUs that are in the config but weren't found count as removed.
+    fn fire_gpu_hooks(&self, known_gpus: HashMap<u32, GpuIdentifier>) {
+        fn identifier_vars(identifier: &GpuIdentifier) -> Vec<(&'static str, String)> {
+            vec![
+                ("LACT_PCI_ID", identifier.pci_id.clone()),
+                (
+                    "LACT_CARD_MODEL",
+                    identifier.card_model.clone().unwrap_or_default(),
+                ),
+                (
+                    "LACT_GPU_MODEL",
+                    identifier.gpu_model.clone().unwrap_or_default(),
+                ),
+                ("LACT_PATH", identifier.path.to_string_lossy().to_string()),
+            ]
+        }
+
+        for (id, controller) in &self.gpu_controllers {
+            if !known_gpus.contains_key(id) {
+                let vars = identifier_vars(&controller.get_identifier());
+                self.hooks.fire(HookEvent::GpuAdded, *id, &vars);
+            }
+        }
+        for (id, identifier) in &known_gpus {
+            if !self.gpu_controllers.contains_key(id) {
+                self.hooks
+                    .fire(HookEvent::GpuRemoved, *id, &identifier_vars(identifier));
+            }
         }
     }
 
+    fn start_fan_scheduler(&mut self) {
+        let hw_mons = self
+            .gpu_controllers
+            .iter()
+            .filter_map(|(id, controller)| controller.get_hw_mon().map(|hw_mon| (*id, hw_mon)))
+            .collect();
+
+        let interval = self
+            .config
+            .fan_control_interval
+            .unwrap_or(fan_scheduler::DEFAULT_INTERVAL_MS);
+
+        self.fan_scheduler
+            .start(hw_mons, Duration::from_millis(interval));
+    }
+
     fn load_gpu_controllers(config: &mut Config) -> HashMap<u32, GpuController> {
         let pci_db = match config.allow_online_update {
             Some(true) => match Self::get_pci_db_online() {
@@ -184,37 +452,634 @@ impl Daemon {
                 break;
             } else {
                 Daemon::handle_connection(&mut self, stream);
+                let _ = nix::unistd::close(stream);
             }
         }
     }
 
-    pub fn read_buffer(stream: i32) -> Vec<u8> {
-        log::trace!("Reading buffer");
-        let mut buffer = Vec::<u8>::new();
-        buffer.resize(BUFFER_SIZE, 0);
-        loop {
-            match nix::unistd::read(stream, &mut buffer) {
-                Ok(0) => {
-                    break;
+    /// Persists fan calibrations that finished in the background since the last request
+    fn save_fan_calibrations(&mut self) {
+        let mut changed = false;
+
+        for (id, controller) in self.gpu_controllers.iter_mut() {
+            if controller.update_fan_calibration() {
+                self.config.set_gpu_config(
+                    *id,
+                    controller.get_identifier(),
+                    controller.get_config(),
+                );
+                changed = true;
+            }
+        }
+
+        // A failed save is logged and tried again with the next one
+        if changed {
+            let _ = save_config(&self.config);
+        }
+    }
+
+    fn activate_profile(&mut self, id: u32, name: &str) -> Result<(), DaemonError> {
+        let controller = match self.gpu_controllers.get_mut(&id) {
+            Some(controller) => controller,
+            None => return Err(DaemonError::InvalidID),
+        };
+        let profile = match self.config.get_profile(id, name) {
+            Some(profile) => profile,
+            None => {
+                return Err(DaemonError::ProfileError(format!(
+                    "there is no profile named {}",
+                    name
+                )))
+            }
+        };
+
+        log::info!("Activating profile {} of GPU {}", name, id);
+
+        let mut vars = vec![("LACT_PROFILE", name.to_string())];
+        if let Some(previous) = self
+            .config
+            .gpu_profiles
+            .get(&id)
+            .and_then(|gpu_profiles| gpu_profiles.active.clone())
+        {
+            vars.push(("LACT_PREVIOUS_PROFILE", previous));
+        }
+
+        controller.apply_profile(&profile);
+
+        self.config.set_active_profile(id, name).unwrap();
+        self.config
+            .set_gpu_config(id, controller.get_identifier(), controller.get_config());
+        let saved = save_config(&self.config);
+
+        self.hooks.fire(HookEvent::ProfileSwitched, id, &vars);
+        saved
+    }
+
+    /// Switches to another config, applying it to the GPUs.
+    /// An invalid config is rejected as a whole, keeping the current one.
+    fn replace_config(&mut self, mut config: Config) -> Result<(), DaemonError> {
+        let problems = config.validate();
+        if !problems.is_empty() {
+            return Err(DaemonError::ConfigError(format!(
+                "invalid config: {}",
+                problems.join(", ")
+            )));
+        }
+
+        self.fan_scheduler.stop();
+
+        // The new controllers take over the fans again if the config says so
+        for controller in self.gpu_controllers.values_mut() {
+            if controller.get_config().fan_control_enabled {
+                #[allow(unused_must_use)]
+                {
+                    controller.stop_fan_control();
+                }
+            }
+        }
+
+        let known_gpus = self
+            .gpu_controllers
+            .iter()
+            .map(|(id, controller)| (*id, controller.get_identifier()))
+            .collect();
+
+        self.config = config;
+        self.hooks.set_hooks(self.config.hooks.clone());
+        self.process_rule_restores.clear();
+        self.process_watcher
+            .set_rules(self.config.process_rules.clone());
+        self.schedule_watcher
+            .set_schedules(self.config.schedules.clone());
+        self.gpu_controllers.clear();
+        self.gpu_controllers = Self::load_gpu_controllers(&mut self.config);
+        let saved = save_config(&self.config);
+        self.start_fan_scheduler();
+        self.fire_gpu_hooks(known_gpus);
+        saved
+    }
+
+    fn export_settings(
+        &self,
+        id: u32,
+        profile: Option<String>,
+    ) -> Result<SettingsExport, DaemonError> {
+        let controller = match self.gpu_controllers.get(&id) {
+            Some(controller) => controller,
+            None => return Err(DaemonError::InvalidID),
+        };
+
+        let settings = match &profile {
+            Some(name) => match self.config.get_profile(id, name) {
+                Some(settings) => settings,
+                None => {
+                    return Err(DaemonError::ProfileError(format!(
+                        "there is no profile named {}",
+                        name
+                    )))
+                }
+            },
+            None => controller.get_config(),
+        };
+
+        Ok(SettingsExport::new(
+            &controller.get_info(),
+            profile,
+            &settings,
+        ))
+    }
+
+    fn import_settings(
+        &mut self,
+        id: u32,
+        export: SettingsExport,
+        profile: Option<String>,
+        force: bool,
+    ) -> Result<(), DaemonError> {
+        let controller = match self.gpu_controllers.get_mut(&id) {
+            Some(controller) => controller,
+            None => return Err(DaemonError::InvalidID),
+        };
+
+        let fan_calibrated = controller.get_config().fan_calibration.is_some();
+        let problems = export.check_compatibility(&controller.get_info(), fan_calibrated);
+
+        if !problems.is_empty() {
+            match force {
+                true => log::warn!(
+                    "Importing settings for GPU {} despite: {}",
+                    id,
+                    problems.join(", ")
+                ),
+                false => return Err(DaemonError::IncompatibleSettings(problems)),
+            }
+        }
+
+        match profile {
+            Some(name) => {
+                log::info!(
+                    "Importing the settings of a {} as profile {} of GPU {}",
+                    export.model,
+                    name,
+                    id
+                );
+
+                self.config
+                    .add_profile(id, &name, export.settings)
+                    .map_err(DaemonError::ProfileError)?;
+            }
+            None => {
+                log::info!("Applying the settings of a {} to GPU {}", export.model, id);
+
+                controller.apply_profile(&export.settings);
+
+                self.config.set_gpu_config(
+                    id,
+                    controller.get_identifier(),
+                    controller.get_config(),
+                );
+            }
+        }
+
+        save_config(&self.config)
+    }
+
+    /// Switches the GPUs to the profiles of the rules matching the running processes,
+    /// and back to what they had before once the processes are gone
+    fn apply_process_rules(&mut self) {
+        let matches = self.process_watcher.get_matches();
+        let ids: Vec<u32> = self.gpu_controllers.keys().copied().collect();
+
+        for id in ids {
+            let profile = matches.get(&id).map(|rule_match| &rule_match.rule.profile);
+            let restore = self.process_rule_restores.remove(&id);
+
+            match (profile, restore) {
+                (Some(profile), Some(restore)) if *profile == restore.profile => {
+                    self.process_rule_restores.insert(id, restore);
                 }
-                Ok(n) => {
-                    assert!(n < buffer.len());
-                    if n < buffer.len() {
-                        buffer.resize(n, 0);
+                (Some(profile), restore) => {
+                    let restore = match restore {
+                        Some(restore) => restore,
+                        None => ProcessRuleRestore {
+                            profile: String::new(),
+                            previous_profile: self
+                                .config
+                                .gpu_profiles
+                                .get(&id)
+                                .and_then(|gpu_profiles| gpu_profiles.active.clone()),
+                            previous_config: self.config.gpu_configs[&id].1.clone(),
+                        },
+                    };
+
+                    match self.activate_profile(id, profile) {
+                        Ok(()) => {
+                            self.process_rule_restores.insert(
+                                id,
+                                ProcessRuleRestore {
+                                    profile: profile.clone(),
+                                    ..restore
+                                },
+                            );
+                        }
+                        Err(e) => {
+                            log::error!("Failed to switch GPU {} to profile {}: {}", id, profile, e)
+                        }
                     }
-                    break;
                 }
-                Err(e) => {
-                    panic!("Error reading from socket: {}", e);
+                (None, Some(restore)) => self.restore_process_rule(id, restore),
+                (None, None) => (),
+            }
+        }
+    }
+
+    fn apply_schedule(&mut self, schedule: Schedule) {
+        log::info!("Applying the schedule {}", schedule);
+        let id = schedule.gpu_id;
+
+        let result = match schedule.action {
+            ScheduleAction::Profile(name) => self.activate_profile(id, &name),
+            action => match self.gpu_controllers.get_mut(&id) {
+                Some(controller) => {
+                    let result = match action {
+                        ScheduleAction::PowerCap(cap) => controller
+                            .set_power_cap(cap)
+                            .map_err(|_| DaemonError::HWMonError),
+                        ScheduleAction::FanCurve(curve) => {
+                            let options = FanCurveOptions {
+                                unit: FanSpeedUnit::Percent,
+                                ..controller.get_config().fan_curve_options
+                            };
+                            controller
+                                .set_fan_curve(curve, options)
+                                .map_err(DaemonError::from)
+                        }
+                        ScheduleAction::PowerProfile(profile) => controller
+                            .set_power_profile(profile)
+                            .map_err(DaemonError::from),
+                        ScheduleAction::Profile(_) => unreachable!(),
+                    };
+
+                    if result.is_ok() {
+                        self.config.set_gpu_config(
+                            id,
+                            controller.get_identifier(),
+                            controller.get_config(),
+                        );
+                        self.hooks.fire(
+                            HookEvent::SettingsApplied,
+                            id,
+                            &[("LACT_ACTION", "Schedule".to_string())],
+                        );
+                    }
+
+                    result.and_then(|_| save_config(&self.config))
+                }
+                None => Err(DaemonError::InvalidID),
+            },
+        };
+
+        if let Err(e) = result {
+            log::error!("Failed to apply the schedule: {}", e);
+        }
+    }
+
+    /// Activates the profile each GPU has for the current power source
+    fn apply_power_source(&mut self) {
+        let source = match self.power_source_watcher.get() {
+            Some(source) => source,
+            None => return,
+        };
+
+        let mut switches: Vec<(u32, String)> = self
+            .config
+            .power_source_profiles
+            .iter()
+            .filter_map(|(id, profiles)| profiles.get(source).map(|name| (*id, name.clone())))
+            .collect();
+        switches.sort();
+
+        for (id, profile) in switches {
+            // A matching process rule wins, its restore switches back to the previous profile instead
+            if let Some(restore) = self.process_rule_restores.get_mut(&id) {
+                restore.previous_profile = Some(profile);
+                continue;
+            }
+
+            let active = self
+                .config
+                .gpu_profiles
+                .get(&id)
+                .and_then(|gpu_profiles| gpu_profiles.active.clone());
+            if active.as_ref() == Some(&profile) {
+                continue;
+            }
+
+            log::info!(
+                "Switching GPU {} to profile {} on {} power",
+                id,
+                profile,
+                source
+            );
+            if let Err(e) = self.activate_profile(id, &profile) {
+                log::error!("Failed to switch GPU {} to profile {}: {}", id, profile, e);
+            }
+        }
+    }
+
+    fn get_group_members(&self, group: &GpuGroup) -> Vec<u32> {
+        let mut members: Vec<u32> = self
+            .gpu_controllers
+            .iter()
+            .filter(|(id, controller)| group.matcher.matches(**id, &controller.get_info()))
+            .map(|(id, _)| *id)
+            .collect();
+        members.sort();
+        members
+    }
+
+    fn apply_group_setting(&mut self, id: u32, setting: GroupSetting) -> Result<(), DaemonError> {
+        let controller = match self.gpu_controllers.get_mut(&id) {
+            Some(controller) => controller,
+            None => return Err(DaemonError::InvalidID),
+        };
+
+        match setting {
+            GroupSetting::FanControl(true) => controller
+                .start_fan_control()
+                .map_err(|_| DaemonError::HWMonError),
+            GroupSetting::FanControl(false) => controller
+                .stop_fan_control()
+                .map_err(|_| DaemonError::HWMonError),
+            GroupSetting::FanCurve(curve) => {
+                let options = FanCurveOptions {
+                    unit: FanSpeedUnit::Percent,
+                    ..controller.get_config().fan_curve_options
+                };
+                controller
+                    .set_fan_curve(curve, options)
+                    .map_err(DaemonError::from)
+            }
+            GroupSetting::PowerCap(cap) => controller
+                .set_power_cap(cap)
+                .map_err(|_| DaemonError::HWMonError),
+            GroupSetting::PowerProfile(profile) => controller
+                .set_power_profile(profile)
+                .map_err(DaemonError::from),
+            GroupSetting::GpuMaxClock(clock, voltage) => controller
+                .set_gpu_max_power_state(clock, voltage)
+                .and_then(|_| controller.commit_gpu_power_states())
+                .map_err(DaemonError::from),
+            GroupSetting::VramMaxClock(clock) => controller
+                .set_vram_max_clockspeed(clock)
+                .and_then(|_| controller.commit_gpu_power_states())
+                .map_err(DaemonError::from),
+        }
+    }
+
+    /// Applies the settings of a group to its members, or to one of them, and saves them to their configs.
+    /// Only the settings of the given kind if there is one.
+    fn apply_group(
+        &mut self,
+        group: &GpuGroup,
+        gpu_id: Option<u32>,
+        kind: Option<&str>,
+    ) -> Result<(), DaemonError> {
+        let members = match gpu_id {
+            Some(id) => vec![id],
+            None => self.get_group_members(group),
+        };
+        let errors = self.apply_group_to(group, &members, kind);
+        let saved = save_config(&self.config);
+
+        match errors.is_empty() {
+            true => saved,
+            false => Err(DaemonError::GroupError(format!(
+                "failed to apply group {} to some GPUs: {}",
+                group.name,
+                errors.join(", ")
+            ))),
+        }
+    }
+
+    /// Applies the settings of a group to some of its members without saving, returning what failed
+    fn apply_group_to(
+        &mut self,
+        group: &GpuGroup,
+        members: &[u32],
+        kind: Option<&str>,
+    ) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        for &id in members {
+            for setting in group.get_settings(id) {
+                if kind.map_or(false, |kind| kind != setting.kind()) {
+                    continue;
+                }
+
+                let description = setting.to_string();
+                if let Err(e) = self.apply_group_setting(id, setting) {
+                    errors.push(format!("GPU {}: {}: {}", id, description, e));
+                }
+            }
+
+            let controller = &self.gpu_controllers[&id];
+            self.config
+                .set_gpu_config(id, controller.get_identifier(), controller.get_config());
+        }
+
+        errors
+    }
+
+    fn set_group_setting(
+        &mut self,
+        name: &str,
+        gpu_id: Option<u32>,
+        setting: GroupSetting,
+    ) -> Result<(), DaemonError> {
+        setting.validate().map_err(DaemonError::GroupError)?;
+
+        let mut group = self
+            .config
+            .get_group_mut(name)
+            .map_err(DaemonError::GroupError)?
+            .clone();
+        let all_members = self.get_group_members(&group);
+
+        let members = match gpu_id {
+            Some(id) if !all_members.contains(&id) => {
+                return Err(DaemonError::GroupError(format!(
+                    "GPU {} is not a member of group {}",
+                    id, name
+                )))
+            }
+            Some(id) => vec![id],
+            None => all_members,
+        };
+
+        let kind = setting.kind();
+        group.set_setting(gpu_id, setting);
+
+        // Members hold one setting of each kind, so every member failing means none took it
+        // and it's not kept, otherwise it would fail again on every start
+        let errors = self.apply_group_to(&group, &members, Some(kind));
+        if members.is_empty() || errors.len() < members.len() {
+            *self.config.get_group_mut(name).unwrap() = group;
+        }
+        save_config(&self.config)?;
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(DaemonError::GroupError(format!(
+                "failed to apply group {} to some GPUs: {}",
+                name,
+                errors.join(", ")
+            ))),
+        }
+    }
+
+    /// Applies the settings of all groups a GPU is a member of
+    fn apply_groups(&mut self, id: u32) {
+        for group in self.config.groups.clone() {
+            if self.get_group_members(&group).contains(&id) {
+                log::info!(
+                    "Applying the settings of group {} to GPU {}",
+                    group.name,
+                    id
+                );
+
+                if let Err(e) = self.apply_group(&group, Some(id), None) {
+                    log::error!("{}", e);
                 }
             }
         }
+    }
+
+    /// Activates the profiles the control scripts ask for
+    fn apply_script_profiles(&mut self) {
+        let mut ids: Vec<u32> = self.gpu_controllers.keys().copied().collect();
+        ids.sort();
+
+        for id in ids {
+            let profile = self
+                .fan_scheduler
+                .get_script(id)
+                .and_then(|status| status.output)
+                .and_then(|output| output.profile);
+            let profile = match profile {
+                Some(profile) => profile,
+                None => continue,
+            };
+
+            let active = self
+                .config
+                .gpu_profiles
+                .get(&id)
+                .and_then(|gpu_profiles| gpu_profiles.active.clone());
+            if active.as_ref() == Some(&profile) {
+                continue;
+            }
+
+            log::info!(
+                "The control script switches GPU {} to profile {}",
+                id,
+                profile
+            );
+            if let Err(e) = self.activate_profile(id, &profile) {
+                log::error!("Failed to switch GPU {} to profile {}: {}", id, profile, e);
+            }
+        }
+    }
+
+    fn restore_process_rule(&mut self, id: u32, restore: ProcessRuleRestore) {
+        log::info!("No process matches the rules of GPU {} anymore", id);
+
+        // The previous profile might have been deleted in the meantime
+        let result = match restore.previous_profile {
+            Some(name) if self.config.get_profile(id, &name).is_some() => {
+                self.activate_profile(id, &name)
+            }
+            _ => match self.gpu_controllers.get_mut(&id) {
+                Some(controller) => {
+                    controller.apply_profile(&restore.previous_config);
+
+                    self.config.clear_active_profile(id);
+                    self.config.set_gpu_config(
+                        id,
+                        controller.get_identifier(),
+                        controller.get_config(),
+                    );
+                    save_config(&self.config)
+                }
+                None => Err(DaemonError::InvalidID),
+            },
+        };
+
+        if let Err(e) = result {
+            log::error!("Failed to restore the settings of GPU {}: {}", id, e);
+        }
+    }
+
+    /// Whether the user on the other end of the socket may change the commands the daemon runs,
+    /// which is root, the user the daemon runs as, or a member of the configured group.
+    fn is_privileged(&self, stream: i32) -> bool {
+        let credentials = match getsockopt(stream, PeerCredentials) {
+            Ok(credentials) => credentials,
+            Err(e) => {
+                log::error!("Failed to get the credentials of the client: {}", e);
+                return false;
+            }
+        };
+
+        if credentials.uid() == 0 || credentials.uid() == Uid::effective().as_raw() {
+            return true;
+        }
+
+        let group = match Group::from_name(&self.config.group) {
+            Ok(Some(group)) => group,
+            _ => {
+                log::warn!("Group {} does not exist", self.config.group);
+                return false;
+            }
+        };
+
+        if credentials.gid() == group.gid.as_raw() {
+            return true;
+        }
 
-        buffer
+        match User::from_uid(Uid::from_raw(credentials.uid())) {
+            Ok(Some(user)) => user.gid == group.gid || group.mem.contains(&user.name),
+            _ => false,
+        }
+    }
+
+    /// Replacing the config can add hooks or move the config file,
+    /// so that needs the same rights as changing the hooks directly.
+    fn check_config_change(&self, stream: i32, config: &Config) -> Result<(), DaemonError> {
+        let unchanged = config.hooks == self.config.hooks
+            && config.group == self.config.group
+            && config.config_path == self.config.config_path;
+
+        match unchanged || self.is_privileged(stream) {
+            true => Ok(()),
+            false => Err(DaemonError::PermissionDenied(format!(
+                "only root or members of {} can change hooks",
+                self.config.group
+            ))),
+        }
     }
 
     fn handle_connection(&mut self, stream: i32) {
-        let buffer = Self::read_buffer(stream);
+        self.save_fan_calibrations();
+
+        let buffer = match daemon_connection::read_message(stream) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                log::error!("Failed to read the request: {}", e);
+                return;
+            }
+        };
 
         //log::trace!("finished reading, buffer size {}", buffer.len());
         log::trace!("Attempting to deserialize {:?}", &buffer);
@@ -223,6 +1088,8 @@ impl Daemon {
         match bincode::deserialize::<Action>(&buffer) {
             Ok(action) => {
                 log::trace!("Executing action {:?}", action);
+                let applied = action.get_applied_gpu().map(|id| (id, action.get_name()));
+
                 let response: Result<DaemonResponse, DaemonError> = match action {
                     Action::CheckAlive => Ok(DaemonResponse::OK),
                     Action::GetGpus => {
@@ -234,26 +1101,66 @@ impl Daemon {
                     }
                     Action::GetStats(i) => match self.gpu_controllers.get(&i) {
                         Some(controller) => match controller.get_stats() {
-                            Ok(stats) => Ok(DaemonResponse::GpuStats(stats)),
+                            Ok(mut stats) => {
+                                stats.fan_health = self.fan_scheduler.get_health(i);
+                                stats.power_guard = self.fan_scheduler.get_power_guard(i);
+                                stats.idle = self.fan_scheduler.get_idle(i);
+                                stats.script = self.fan_scheduler.get_script(i);
+                                stats.process_rule = self.process_watcher.get_match(i);
+                                Ok(DaemonResponse::GpuStats(stats))
+                            }
                             Err(_) => Err(DaemonError::HWMonError),
                         },
                         None => Err(DaemonError::InvalidID),
                     },
                     Action::GetInfo(i) => match self.gpu_controllers.get(&i) {
                         Some(controller) => {
-                            Ok(DaemonResponse::GpuInfo(controller.get_info().clone()))
+                            let mut info = controller.get_info();
+
+                            // Report the cap the user set rather than the one lowered by the power guard
+                            if let Some(status) = self.fan_scheduler.get_power_guard(i) {
+                                info.power_cap = Some(status.configured_cap);
+                            }
+
+                            // Same for the low power settings while idle
+                            if let Some(status) = self.fan_scheduler.get_idle(i) {
+                                if status.low_power {
+                                    if let Some(cap) = status.previous_power_cap {
+                                        info.power_cap = Some(cap);
+                                    }
+                                    info.power_profile =
+                                        Some(controller.get_config().power_profile);
+                                }
+                            }
+
+                            // And for the one set by the control script
+                            if let Some(status) = self.fan_scheduler.get_script(i) {
+                                if let Some(cap) = status.previous_power_cap {
+                                    info.power_cap = Some(cap);
+                                }
+                            }
+
+                            info.power_source = self.power_source_watcher.get();
+                            info.power_source_profiles = self
+                                .config
+                                .power_source_profiles
+                                .get(&i)
+                                .cloned()
+                                .unwrap_or_default();
+
+                            Ok(DaemonResponse::GpuInfo(info))
                         }
                         None => Err(DaemonError::InvalidID),
                     },
                     Action::StartFanControl(i) => match self.gpu_controllers.get_mut(&i) {
                         Some(controller) => match controller.start_fan_control() {
                             Ok(_) => {
-                                self.config.gpu_configs.insert(
+                                self.config.set_gpu_config(
                                     i,
-                                    (controller.get_identifier(), controller.get_config()),
+                                    controller.get_identifier(),
+                                    controller.get_config(),
                                 );
-                                self.config.save().unwrap();
-                                Ok(DaemonResponse::OK)
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
                             }
                             Err(_) => Err(DaemonError::HWMonError),
                         },
@@ -262,12 +1169,12 @@ impl Daemon {
                     Action::StopFanControl(i) => match self.gpu_controllers.get_mut(&i) {
                         Some(controller) => match controller.stop_fan_control() {
                             Ok(_) => {
-                                self.config.gpu_configs.insert(
+                                self.config.set_gpu_config(
                                     i,
-                                    (controller.get_identifier(), controller.get_config()),
+                                    controller.get_identifier(),
+                                    controller.get_config(),
                                 );
-                                self.config.save().unwrap();
-                                Ok(DaemonResponse::OK)
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
                             }
                             Err(_) => Err(DaemonError::HWMonError),
                         },
@@ -275,20 +1182,113 @@ impl Daemon {
                     },
                     Action::GetFanControl(i) => match self.gpu_controllers.get(&i) {
                         Some(controller) => match controller.get_fan_control() {
-                            Ok(info) => Ok(DaemonResponse::FanControlInfo(info)),
+                            Ok(mut info) => {
+                                info.status = self.fan_scheduler.get_status(i);
+                                Ok(DaemonResponse::FanControlInfo(info))
+                            }
+                            Err(_) => Err(DaemonError::HWMonError),
+                        },
+                        None => Err(DaemonError::InvalidID),
+                    },
+                    Action::StartFanCalibration(i) => match self.gpu_controllers.get(&i) {
+                        Some(controller) => match controller.start_fan_calibration() {
+                            Ok(_) => Ok(DaemonResponse::OK),
+                            Err(_) => Err(DaemonError::HWMonError),
+                        },
+                        None => Err(DaemonError::InvalidID),
+                    },
+                    Action::SetFanCurve(i, curve, options) => {
+                        match self.gpu_controllers.get_mut(&i) {
+                            Some(controller) => match controller.set_fan_curve(curve, options) {
+                                Ok(_) => {
+                                    self.config.set_gpu_config(
+                                        i,
+                                        controller.get_identifier(),
+                                        controller.get_config(),
+                                    );
+                                    save_config(&self.config).map(|_| DaemonResponse::OK)
+                                }
+                                Err(e) => Err(e.into()),
+                            },
+                            None => Err(DaemonError::InvalidID),
+                        }
+                    }
+                    Action::SetFanSmoothing(i, smoothing) => {
+                        match self.gpu_controllers.get_mut(&i) {
+                            Some(controller) => match controller.set_fan_smoothing(smoothing) {
+                                Ok(_) => {
+                                    self.config.set_gpu_config(
+                                        i,
+                                        controller.get_identifier(),
+                                        controller.get_config(),
+                                    );
+                                    save_config(&self.config).map(|_| DaemonResponse::OK)
+                                }
+                                Err(_) => Err(DaemonError::HWMonError),
+                            },
+                            None => Err(DaemonError::InvalidID),
+                        }
+                    }
+                    Action::SetFanTempSource(i, source) => match self.gpu_controllers.get_mut(&i) {
+                        Some(controller) => match controller.set_fan_temp_source(source) {
+                            Ok(_) => {
+                                self.config.set_gpu_config(
+                                    i,
+                                    controller.get_identifier(),
+                                    controller.get_config(),
+                                );
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
+                            }
                             Err(_) => Err(DaemonError::HWMonError),
                         },
                         None => Err(DaemonError::InvalidID),
                     },
-                    Action::SetFanCurve(i, curve) => match self.gpu_controllers.get_mut(&i) {
-                        Some(controller) => match controller.set_fan_curve(curve) {
+                    Action::SetFanLimits(i, limits) => match self.gpu_controllers.get_mut(&i) {
+                        Some(controller) => match controller.set_fan_limits(limits) {
                             Ok(_) => {
-                                self.config.gpu_configs.insert(
+                                self.config.set_gpu_config(
                                     i,
-                                    (controller.get_identifier(), controller.get_config()),
+                                    controller.get_identifier(),
+                                    controller.get_config(),
                                 );
-                                self.config.save().unwrap();
-                                Ok(DaemonResponse::OK)
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
+                            }
+                            Err(_) => Err(DaemonError::HWMonError),
+                        },
+                        None => Err(DaemonError::InvalidID),
+                    },
+                    Action::SetFanHealthSettings(i, settings) => {
+                        match self.gpu_controllers.get_mut(&i) {
+                            Some(controller) => {
+                                match controller.set_fan_health_settings(settings) {
+                                    Ok(_) => {
+                                        self.config.set_gpu_config(
+                                            i,
+                                            controller.get_identifier(),
+                                            controller.get_config(),
+                                        );
+                                        save_config(&self.config).map(|_| DaemonResponse::OK)
+                                    }
+                                    Err(e) => Err(e.into()),
+                                }
+                            }
+                            None => Err(DaemonError::InvalidID),
+                        }
+                    }
+                    Action::SetFanMode(i, mode) => match self.gpu_controllers.get_mut(&i) {
+                        Some(controller) => match controller.set_fan_mode(mode) {
+                            Ok(_) => {
+                                self.config.set_gpu_config(
+                                    i,
+                                    controller.get_identifier(),
+                                    controller.get_config(),
+                                );
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
+                            }
+                            Err(hw_mon::HWMonError::InvalidValue) => {
+                                Err(DaemonError::InvalidValue(String::from(
+                                    "fan speed is outside of the supported range",
+                                )))
                             }
                             Err(_) => Err(DaemonError::HWMonError),
                         },
@@ -297,26 +1297,74 @@ impl Daemon {
                     Action::SetPowerCap(i, cap) => match self.gpu_controllers.get_mut(&i) {
                         Some(controller) => match controller.set_power_cap(cap) {
                             Ok(_) => {
-                                self.config.gpu_configs.insert(
+                                self.config.set_gpu_config(
                                     i,
-                                    (controller.get_identifier(), controller.get_config()),
+                                    controller.get_identifier(),
+                                    controller.get_config(),
                                 );
-                                self.config.save().unwrap();
-                                Ok(DaemonResponse::OK)
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
                             }
                             Err(_) => Err(DaemonError::HWMonError),
                         },
                         None => Err(DaemonError::InvalidID),
                     },
+                    Action::SetPowerGuard(i, settings) => match self.gpu_controllers.get_mut(&i) {
+                        Some(controller) => match controller.set_power_guard(settings) {
+                            Ok(_) => {
+                                self.config.set_gpu_config(
+                                    i,
+                                    controller.get_identifier(),
+                                    controller.get_config(),
+                                );
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
+                            }
+                            Err(e) => Err(e.into()),
+                        },
+                        None => Err(DaemonError::InvalidID),
+                    },
+                    Action::SetScript(i, script) => match self.gpu_controllers.get_mut(&i) {
+                        Some(controller) => match controller.set_script(script) {
+                            Ok(_) => {
+                                self.config.set_gpu_config(
+                                    i,
+                                    controller.get_identifier(),
+                                    controller.get_config(),
+                                );
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
+                            }
+                            Err(e) => Err(e.into()),
+                        },
+                        None => Err(DaemonError::InvalidID),
+                    },
+                    Action::ApplyScriptProfiles => {
+                        self.apply_script_profiles();
+                        Ok(DaemonResponse::OK)
+                    }
+                    Action::SetIdleSettings(i, settings) => {
+                        match self.gpu_controllers.get_mut(&i) {
+                            Some(controller) => match controller.set_idle_settings(settings) {
+                                Ok(_) => {
+                                    self.config.set_gpu_config(
+                                        i,
+                                        controller.get_identifier(),
+                                        controller.get_config(),
+                                    );
+                                    save_config(&self.config).map(|_| DaemonResponse::OK)
+                                }
+                                Err(e) => Err(e.into()),
+                            },
+                            None => Err(DaemonError::InvalidID),
+                        }
+                    }
                     Action::SetPowerProfile(i, profile) => match self.gpu_controllers.get_mut(&i) {
                         Some(controller) => match controller.set_power_profile(profile) {
                             Ok(_) => {
-                                self.config.gpu_configs.insert(
+                                self.config.set_gpu_config(
                                     i,
-                                    (controller.get_identifier(), controller.get_config()),
+                                    controller.get_identifier(),
+                                    controller.get_config(),
                                 );
-                                self.config.save().unwrap();
-                                Ok(DaemonResponse::OK)
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
                             }
                             Err(_) => Err(DaemonError::ControllerError),
                         },
@@ -327,12 +1375,8 @@ impl Daemon {
                             Some(controller) => {
                                 match controller.set_gpu_power_state(num, clockspeed, voltage) {
                                     Ok(_) => {
-                                        self.config.gpu_configs.insert(
-                                            i,
-                                            (controller.get_identifier(), controller.get_config()),
-                                        );
-                                        self.config.save().unwrap();
-                                        Ok(DaemonResponse::OK)
+                                        self.config.set_gpu_config(i, controller.get_identifier(), controller.get_config());
+                                        save_config(&self.config).map(|_| DaemonResponse::OK)
                                     }
                                     Err(_) => Err(DaemonError::ControllerError),
                                 }
@@ -345,14 +1389,32 @@ impl Daemon {
                             Some(controller) => {
                                 match controller.set_gpu_max_power_state(clockspeed, voltage) {
                                     Ok(()) => {
-                                        self.config.gpu_configs.insert(
+                                        self.config.set_gpu_config(
                                             i,
-                                            (controller.get_identifier(), controller.get_config()),
+                                            controller.get_identifier(),
+                                            controller.get_config(),
                                         );
-                                        self.config.save().unwrap();
-                                        Ok(DaemonResponse::OK)
+                                        save_config(&self.config).map(|_| DaemonResponse::OK)
                                     }
-                                    Err(_) => Err(DaemonError::ControllerError),
+                                    Err(e) => Err(e.into()),
+                                }
+                            }
+                            None => Err(DaemonError::InvalidID),
+                        }
+                    }
+                    Action::SetVddcCurvePoint(i, num, clockspeed, voltage) => {
+                        match self.gpu_controllers.get_mut(&i) {
+                            Some(controller) => {
+                                match controller.set_vddc_curve_point(num, clockspeed, voltage) {
+                                    Ok(()) => {
+                                        self.config.set_gpu_config(
+                                            i,
+                                            controller.get_identifier(),
+                                            controller.get_config(),
+                                        );
+                                        save_config(&self.config).map(|_| DaemonResponse::OK)
+                                    }
+                                    Err(e) => Err(e.into()),
                                 }
                             }
                             None => Err(DaemonError::InvalidID),
@@ -363,48 +1425,174 @@ impl Daemon {
                             Some(controller) => {
                                 match controller.set_vram_max_clockspeed(clockspeed) {
                                     Ok(()) => {
-                                        self.config.gpu_configs.insert(
+                                        self.config.set_gpu_config(
                                             i,
-                                            (controller.get_identifier(), controller.get_config()),
+                                            controller.get_identifier(),
+                                            controller.get_config(),
                                         );
-                                        self.config.save().unwrap();
-                                        Ok(DaemonResponse::OK)
+                                        save_config(&self.config).map(|_| DaemonResponse::OK)
                                     }
-                                    Err(_) => Err(DaemonError::ControllerError),
+                                    Err(e) => Err(e.into()),
                                 }
                             }
                             None => Err(DaemonError::InvalidID),
                         }
                     }
-                    Action::CommitGPUPowerStates(i) => match self.gpu_controllers.get_mut(&i) {
-                        Some(controller) => match controller.commit_gpu_power_states() {
-                            Ok(_) => {
-                                self.config.gpu_configs.insert(
+                    Action::SetGPUMinClock(i, clockspeed) => match self.gpu_controllers.get_mut(&i)
+                    {
+                        Some(controller) => match controller.set_gpu_min_clockspeed(clockspeed) {
+                            Ok(()) => {
+                                self.config.set_gpu_config(
                                     i,
-                                    (controller.get_identifier(), controller.get_config()),
+                                    controller.get_identifier(),
+                                    controller.get_config(),
                                 );
-                                self.config.save().unwrap();
-                                Ok(DaemonResponse::OK)
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
                             }
-                            Err(_) => Err(DaemonError::ControllerError),
+                            Err(e) => Err(e.into()),
                         },
                         None => Err(DaemonError::InvalidID),
                     },
+                    Action::SetVRAMMinClock(i, clockspeed) => {
+                        match self.gpu_controllers.get_mut(&i) {
+                            Some(controller) => {
+                                match controller.set_vram_min_clockspeed(clockspeed) {
+                                    Ok(()) => {
+                                        self.config.set_gpu_config(
+                                            i,
+                                            controller.get_identifier(),
+                                            controller.get_config(),
+                                        );
+                                        save_config(&self.config).map(|_| DaemonResponse::OK)
+                                    }
+                                    Err(e) => Err(e.into()),
+                                }
+                            }
+                            None => Err(DaemonError::InvalidID),
+                        }
+                    }
+                    Action::SetVoltageOffset(i, offset) => match self.gpu_controllers.get_mut(&i) {
+                        Some(controller) => match controller.set_voltage_offset(offset) {
+                            Ok(()) => {
+                                self.config.set_gpu_config(
+                                    i,
+                                    controller.get_identifier(),
+                                    controller.get_config(),
+                                );
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
+                            }
+                            Err(e) => Err(e.into()),
+                        },
+                        None => Err(DaemonError::InvalidID),
+                    },
+                    Action::ApplyClocks(i, settings) => match self.gpu_controllers.get_mut(&i) {
+                        Some(controller) => match controller.apply_clocks(&settings) {
+                            Ok(()) => {
+                                self.config.set_gpu_config(
+                                    i,
+                                    controller.get_identifier(),
+                                    controller.get_config(),
+                                );
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
+                            }
+                            Err(e) => Err(e.into()),
+                        },
+                        None => Err(DaemonError::InvalidID),
+                    },
+                    Action::CommitGPUPowerStates(i) => match self.gpu_controllers.get_mut(&i) {
+                        Some(controller) => {
+                            let result = controller.commit_gpu_power_states();
+
+                            // Saved even if the commit failed, as the config gets updated with the values the GPU actually accepted
+                            self.config.set_gpu_config(
+                                i,
+                                controller.get_identifier(),
+                                controller.get_config(),
+                            );
+                            let saved = save_config(&self.config);
+
+                            match result {
+                                Ok(()) => saved.map(|_| DaemonResponse::OK),
+                                Err(e) => Err(e.into()),
+                            }
+                        }
+                        None => Err(DaemonError::InvalidID),
+                    },
                     Action::ResetGPUPowerStates(i) => match self.gpu_controllers.get_mut(&i) {
                         Some(controller) => match controller.reset_gpu_power_states() {
                             Ok(_) => {
-                                self.config.gpu_configs.insert(
+                                self.config.set_gpu_config(
                                     i,
-                                    (controller.get_identifier(), controller.get_config()),
+                                    controller.get_identifier(),
+                                    controller.get_config(),
                                 );
-                                self.config.save().unwrap();
-                                Ok(DaemonResponse::OK)
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
                             }
-                            Err(_) => Err(DaemonError::ControllerError),
+                            Err(e) => Err(e.into()),
+                        },
+                        None => Err(DaemonError::InvalidID),
+                    },
+                    Action::SetOdFanValue(i, name, value) => match self.gpu_controllers.get_mut(&i)
+                    {
+                        Some(controller) => match controller.set_od_fan_value(&name, value) {
+                            Ok(()) => {
+                                self.config.set_gpu_config(
+                                    i,
+                                    controller.get_identifier(),
+                                    controller.get_config(),
+                                );
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
+                            }
+                            Err(e) => Err(e.into()),
+                        },
+                        None => Err(DaemonError::InvalidID),
+                    },
+                    Action::SetOdFanCurvePoint(i, num, temperature, speed) => {
+                        match self.gpu_controllers.get_mut(&i) {
+                            Some(controller) => {
+                                match controller.set_od_fan_curve_point(num, temperature, speed) {
+                                    Ok(()) => {
+                                        self.config.set_gpu_config(
+                                            i,
+                                            controller.get_identifier(),
+                                            controller.get_config(),
+                                        );
+                                        save_config(&self.config).map(|_| DaemonResponse::OK)
+                                    }
+                                    Err(e) => Err(e.into()),
+                                }
+                            }
+                            None => Err(DaemonError::InvalidID),
+                        }
+                    }
+                    Action::ResetOdFanControls(i) => match self.gpu_controllers.get_mut(&i) {
+                        Some(controller) => match controller.reset_od_fan_controls() {
+                            Ok(()) => {
+                                self.config.set_gpu_config(
+                                    i,
+                                    controller.get_identifier(),
+                                    controller.get_config(),
+                                );
+                                save_config(&self.config).map(|_| DaemonResponse::OK)
+                            }
+                            Err(e) => Err(e.into()),
                         },
                         None => Err(DaemonError::InvalidID),
                     },
                     Action::Shutdown => {
+                        self.process_watcher.stop();
+                        self.schedule_watcher.stop();
+                        self.power_source_watcher.stop();
+
+                        // So that the settings from before the rules matched are the ones loaded next time
+                        let restores: Vec<(u32, ProcessRuleRestore)> =
+                            self.process_rule_restores.drain().collect();
+                        for (id, restore) in restores {
+                            self.restore_process_rule(id, restore);
+                        }
+
+                        self.fan_scheduler.stop();
+
                         for (id, controller) in &mut self.gpu_controllers {
                             #[allow(unused_must_use)]
                             {
@@ -426,24 +1614,274 @@ impl Daemon {
                         }
                         std::process::exit(0);
                     }
-                    Action::SetConfig(config) => {
-                        self.config = config;
-                        self.gpu_controllers.clear();
-                        self.gpu_controllers = Self::load_gpu_controllers(&mut self.config);
-                        self.config.save().expect("Failed to save config");
-                        Ok(DaemonResponse::OK)
+                    Action::SetConfig(config) => self
+                        .check_config_change(stream, &config)
+                        .and_then(|_| self.replace_config(config))
+                        .map(|_| DaemonResponse::OK),
+                    Action::GetConfigLoadError => Ok(DaemonResponse::ConfigLoadError(
+                        self.config.load_error.clone(),
+                    )),
+                    Action::GetConfigBackups => Ok(DaemonResponse::ConfigBackups(
+                        Config::list_backups(&self.config.config_path),
+                    )),
+                    Action::RestoreConfigBackup(index) => {
+                        let restored = Config::read_backup(&self.config.config_path, index)
+                            .map_err(|e| DaemonError::ConfigError(e.to_string()))
+                            .and_then(|config| self.check_config_change(stream, &config));
+
+                        match restored.and_then(|_| {
+                            Config::restore_backup(&self.config.config_path, index)
+                                .map_err(|e| DaemonError::ConfigError(e.to_string()))
+                        }) {
+                            Ok(config) => {
+                                log::info!("Restoring config backup {}", index);
+                                self.replace_config(config).map(|_| DaemonResponse::OK)
+                            }
+                            Err(e) => Err(e),
+                        }
                     }
                     Action::GetConfig => Ok(DaemonResponse::Config(self.config.clone())),
+                    Action::GetProfiles(i) => match self.gpu_controllers.contains_key(&i) {
+                        true => {
+                            let gpu_profiles = self
+                                .config
+                                .gpu_profiles
+                                .get(&i)
+                                .cloned()
+                                .unwrap_or_default();
+
+                            Ok(DaemonResponse::Profiles(
+                                gpu_profiles.profiles.keys().cloned().collect(),
+                                gpu_profiles.active,
+                            ))
+                        }
+                        false => Err(DaemonError::InvalidID),
+                    },
+                    Action::CreateProfile(i, name) => match self.gpu_controllers.contains_key(&i) {
+                        true => match self.config.create_profile(i, &name) {
+                            Ok(()) => save_config(&self.config).map(|_| DaemonResponse::OK),
+                            Err(e) => Err(DaemonError::ProfileError(e)),
+                        },
+                        false => Err(DaemonError::InvalidID),
+                    },
+                    Action::RenameProfile(i, name, new_name) => {
+                        match self.gpu_controllers.contains_key(&i) {
+                            true => match self.config.rename_profile(i, &name, &new_name) {
+                                Ok(()) => save_config(&self.config).map(|_| DaemonResponse::OK),
+                                Err(e) => Err(DaemonError::ProfileError(e)),
+                            },
+                            false => Err(DaemonError::InvalidID),
+                        }
+                    }
+                    Action::DeleteProfile(i, name) => match self.gpu_controllers.contains_key(&i) {
+                        true => match self.config.delete_profile(i, &name) {
+                            Ok(()) => save_config(&self.config).map(|_| DaemonResponse::OK),
+                            Err(e) => Err(DaemonError::ProfileError(e)),
+                        },
+                        false => Err(DaemonError::InvalidID),
+                    },
+                    Action::ActivateProfile(i, name) => {
+                        self.activate_profile(i, &name).map(|_| DaemonResponse::OK)
+                    }
+                    Action::ExportSettings(i, profile) => self
+                        .export_settings(i, profile)
+                        .map(DaemonResponse::SettingsExport),
+                    Action::ImportSettings(i, export, profile, force) => self
+                        .import_settings(i, export, profile, force)
+                        .map(|_| DaemonResponse::OK),
+                    Action::GetProcessRules => {
+                        let mut matches: Vec<ProcessRuleMatch> = self
+                            .process_watcher
+                            .get_matches()
+                            .values()
+                            .cloned()
+                            .collect();
+                        matches.sort_by_key(|rule_match| rule_match.index);
+
+                        Ok(DaemonResponse::ProcessRules(
+                            self.config.process_rules.clone(),
+                            matches,
+                        ))
+                    }
+                    Action::AddProcessRule(rule) => {
+                        match self.gpu_controllers.contains_key(&rule.gpu_id) {
+                            true => match self.config.add_process_rule(rule) {
+                                Ok(()) => {
+                                    let saved = save_config(&self.config);
+                                    self.process_watcher
+                                        .set_rules(self.config.process_rules.clone());
+                                    saved.map(|_| DaemonResponse::OK)
+                                }
+                                Err(e) => Err(DaemonError::ProfileError(e)),
+                            },
+                            false => Err(DaemonError::InvalidID),
+                        }
+                    }
+                    Action::RemoveProcessRule(index) => {
+                        match self.config.remove_process_rule(index) {
+                            Ok(_) => {
+                                let saved = save_config(&self.config);
+                                self.process_watcher
+                                    .set_rules(self.config.process_rules.clone());
+                                saved.map(|_| DaemonResponse::OK)
+                            }
+                            Err(e) => Err(DaemonError::ProfileError(e)),
+                        }
+                    }
+                    Action::ApplyProcessRules => {
+                        self.apply_process_rules();
+                        Ok(DaemonResponse::OK)
+                    }
+                    Action::GetGroups => Ok(DaemonResponse::Groups(
+                        self.config
+                            .groups
+                            .iter()
+                            .map(|group| (group.clone(), self.get_group_members(group)))
+                            .collect(),
+                    )),
+                    Action::CreateGroup(group) => match self.config.create_group(group) {
+                        Ok(()) => save_config(&self.config).map(|_| DaemonResponse::OK),
+                        Err(e) => Err(DaemonError::GroupError(e)),
+                    },
+                    Action::DeleteGroup(name) => match self.config.delete_group(&name) {
+                        Ok(_) => save_config(&self.config).map(|_| DaemonResponse::OK),
+                        Err(e) => Err(DaemonError::GroupError(e)),
+                    },
+                    Action::SetGroupSetting(name, gpu_id, setting) => self
+                        .set_group_setting(&name, gpu_id, setting)
+                        .map(|_| DaemonResponse::OK),
+                    Action::ClearGroupSetting(name, gpu_id, kind) => {
+                        let group = self.config.get_group_mut(&name).and_then(|group| {
+                            group.clear_setting(gpu_id, &kind)?;
+                            Ok(group.clone())
+                        });
+
+                        match group {
+                            // The GPU goes back to the setting of the group, if it has one
+                            Ok(group) => match gpu_id {
+                                Some(id) if self.gpu_controllers.contains_key(&id) => self
+                                    .apply_group(&group, Some(id), Some(&kind))
+                                    .map(|_| DaemonResponse::OK),
+                                _ => save_config(&self.config).map(|_| DaemonResponse::OK),
+                            },
+                            Err(e) => Err(DaemonError::GroupError(e)),
+                        }
+                    }
+                    Action::ApplyGroup(name) => {
+                        match self.config.groups.iter().find(|group| group.name == name) {
+                            Some(group) => {
+                                let group = group.clone();
+                                self.apply_group(&group, None, None)
+                                    .map(|_| DaemonResponse::OK)
+                            }
+                            None => Err(DaemonError::GroupError(format!(
+                                "there is no group named {}",
+                                name
+                            ))),
+                        }
+                    }
+                    Action::GetHooks => Ok(DaemonResponse::Hooks(
+                        self.config.hooks.clone(),
+                        self.hooks.get_results(),
+                    )),
+                    Action::AddHook(_) | Action::RemoveHook(_) if !self.is_privileged(stream) => {
+                        Err(DaemonError::PermissionDenied(format!(
+                            "only root or members of {} can change hooks",
+                            self.config.group
+                        )))
+                    }
+                    Action::AddHook(hook) => {
+                        let known_gpu = hook
+                            .gpu_id
+                            .map_or(true, |id| self.gpu_controllers.contains_key(&id));
+
+                        match known_gpu {
+                            true => match self.config.add_hook(hook) {
+                                Ok(()) => {
+                                    let saved = save_config(&self.config);
+                                    self.hooks.set_hooks(self.config.hooks.clone());
+                                    saved.map(|_| DaemonResponse::OK)
+                                }
+                                Err(e) => Err(DaemonError::InvalidValue(e)),
+                            },
+                            false => Err(DaemonError::InvalidID),
+                        }
+                    }
+                    Action::RemoveHook(index) => match self.config.remove_hook(index) {
+                        Ok(_) => {
+                            let saved = save_config(&self.config);
+                            self.hooks.set_hooks(self.config.hooks.clone());
+                            saved.map(|_| DaemonResponse::OK)
+                        }
+                        Err(e) => Err(DaemonError::InvalidValue(e)),
+                    },
+                    Action::GetSchedules => Ok(DaemonResponse::Schedules(
+                        self.config.schedules.clone(),
+                        self.schedule_watcher.get_applied(),
+                    )),
+                    Action::SetSchedule(index, schedule) => {
+                        match self.gpu_controllers.contains_key(&schedule.gpu_id) {
+                            true => match self.config.set_schedule(index, schedule) {
+                                Ok(()) => {
+                                    let saved = save_config(&self.config);
+                                    self.schedule_watcher
+                                        .set_schedules(self.config.schedules.clone());
+                                    saved.map(|_| DaemonResponse::OK)
+                                }
+                                Err(e) => Err(DaemonError::InvalidValue(e)),
+                            },
+                            false => Err(DaemonError::InvalidID),
+                        }
+                    }
+                    Action::RemoveSchedule(index) => match self.config.remove_schedule(index) {
+                        Ok(_) => {
+                            let saved = save_config(&self.config);
+                            self.schedule_watcher
+                                .set_schedules(self.config.schedules.clone());
+                            saved.map(|_| DaemonResponse::OK)
+                        }
+                        Err(e) => Err(DaemonError::InvalidValue(e)),
+                    },
+                    Action::ApplySchedules => {
+                        for schedule in self.schedule_watcher.take_pending() {
+                            self.apply_schedule(schedule);
+                        }
+                        Ok(DaemonResponse::OK)
+                    }
+                    Action::SetPowerSourceProfile(i, source, profile) => {
+                        match self.gpu_controllers.contains_key(&i) {
+                            true => {
+                                match self.config.set_power_source_profile(i, source, profile) {
+                                    Ok(()) => {
+                                        let saved = save_config(&self.config);
+                                        // Takes effect right away if the machine is on that source
+                                        self.apply_power_source();
+                                        saved.map(|_| DaemonResponse::OK)
+                                    }
+                                    Err(e) => Err(DaemonError::ProfileError(e)),
+                                }
+                            }
+                            false => Err(DaemonError::InvalidID),
+                        }
+                    }
+                    Action::ApplyPowerSource => {
+                        self.apply_power_source();
+                        Ok(DaemonResponse::OK)
+                    }
                 };
 
+                if let (Some((id, name)), Ok(_)) = (applied, &response) {
+                    self.hooks
+                        .fire(HookEvent::SettingsApplied, id, &[("LACT_ACTION", name)]);
+                }
+
                 let buffer = bincode::serialize(&response).unwrap();
 
                 log::trace!("Responding, buffer length {}", buffer.len());
-                nix::unistd::write(stream, &buffer).expect("Writing response to socket failed");
-                //stream
-                //    .shutdown(std::net::Shutdown::Write)
-                //    .expect("Could not shut down");
-                log::trace!("Finished responding");
+                match daemon_connection::write_message(stream, &buffer) {
+                    Ok(()) => log::trace!("Finished responding"),
+                    Err(e) => log::error!("Failed to write the response: {}", e),
+                }
             }
             Err(_) => {
                 println!("Failed deserializing action");
@@ -461,6 +1899,14 @@ pub enum DaemonResponse {
     PowerCap((i64, i64)),
     FanControlInfo(gpu_controller::FanControlInfo),
     Config(Config),
+    ConfigBackups(Vec<ConfigBackup>),
+    ConfigLoadError(Option<String>),
+    Profiles(Vec<String>, Option<String>), // Names and the active profile
+    ProcessRules(Vec<ProcessRule>, Vec<ProcessRuleMatch>), // All rules and the ones matching right now
+    SettingsExport(SettingsExport),
+    Groups(Vec<(GpuGroup, Vec<u32>)>), // All groups with their members
+    Hooks(Vec<Hook>, HashMap<usize, HookResult>), // All hooks and the last results by index
+    Schedules(Vec<Schedule>, Vec<Schedule>), // All schedules and the ones in effect
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -469,4 +1915,48 @@ pub enum DaemonError {
     InvalidID,
     HWMonError,
     ControllerError,
+    InvalidValue(String),
+    NotApplied(String),
+    ProfileError(String),
+    GroupError(String),
+    IncompatibleSettings(Vec<String>),
+    ConfigError(String),
+    PermissionDenied(String),
+}
+
+/// Saves the config, handing the error to the client instead of taking the daemon down with it
+fn save_config(config: &Config) -> Result<(), DaemonError> {
+    config.save().map_err(|e| {
+        log::error!("Failed to save the config: {}", e);
+        DaemonError::ConfigError(format!("failed to save the config: {}", e))
+    })
+}
+
+impl From<GpuControllerError> for DaemonError {
+    fn from(err: GpuControllerError) -> DaemonError {
+        match err {
+            GpuControllerError::InvalidValue(msg) => DaemonError::InvalidValue(msg),
+            GpuControllerError::NotApplied(msg) => DaemonError::NotApplied(msg),
+            _ => DaemonError::ControllerError,
+        }
+    }
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DaemonError::InvalidValue(msg) => write!(f, "invalid value: {}", msg),
+            DaemonError::NotApplied(msg) => write!(f, "the GPU did not accept the values: {}", msg),
+            DaemonError::ProfileError(msg) => write!(f, "{}", msg),
+            DaemonError::GroupError(msg) => write!(f, "{}", msg),
+            DaemonError::ConfigError(msg) => write!(f, "{}", msg),
+            DaemonError::PermissionDenied(msg) => write!(f, "permission denied: {}", msg),
+            DaemonError::IncompatibleSettings(problems) => write!(
+                f,
+                "the settings don't fit this GPU: {}",
+                problems.join(", ")
+            ),
+            _ => write!(f, "{:?}", self),
+        }
+    }
 }
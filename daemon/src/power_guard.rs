@@ -0,0 +1,346 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// Lowers the power cap while the card is too hot or the fan too loud
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PowerGuardSettings {
+    pub enabled: bool,
+    pub max_temp: i64,              // °C of the hottest sensor
+    pub max_fan_speed: Option<f64>, // % of the maximum fan RPM
+    pub hysteresis: i64, // °C the temperature has to drop below the limit before the cap is raised again
+    pub step: i64,       // W the cap is lowered by at once
+    pub step_interval: u64, // Seconds between lowering steps, so that the temperature can react
+    pub restore_step: i64, // W the cap is raised by at once
+    pub restore_interval: u64, // Seconds it has to stay cool before each raising step
+}
+
+impl Default for PowerGuardSettings {
+    fn default() -> Self {
+        PowerGuardSettings {
+            enabled: false,
+            max_temp: 90,
+            max_fan_speed: None,
+            hysteresis: 5,
+            step: 10,
+            step_interval: 5,
+            restore_step: 5,
+            restore_interval: 30,
+        }
+    }
+}
+
+impl PowerGuardSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0..=150).contains(&self.max_temp) {
+            return Err(format!(
+                "the temperature limit of {}°C is outside of 0-150°C",
+                self.max_temp
+            ));
+        }
+        if let Some(max_fan_speed) = self.max_fan_speed {
+            if !(0.0..=100.0).contains(&max_fan_speed) {
+                return Err(format!(
+                    "the fan speed limit of {}% is outside of 0-100%",
+                    max_fan_speed
+                ));
+            }
+        }
+        if self.hysteresis < 0 {
+            return Err("the hysteresis can't be negative".to_string());
+        }
+        if self.step <= 0 || self.restore_step <= 0 {
+            return Err("the power cap steps have to be at least 1W".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// Percentage points the fan has to slow down below its limit before the cap is raised again
+const FAN_SPEED_HYSTERESIS: f64 = 5.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PowerGuardReason {
+    Temperature(i64), // °C
+    FanSpeed(f64),    // %
+}
+
+impl fmt::Display for PowerGuardReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowerGuardReason::Temperature(temp) => write!(f, "temperature reached {}°C", temp),
+            PowerGuardReason::FanSpeed(speed) => write!(f, "fan speed reached {:.0}%", speed),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PowerGuardStatus {
+    pub configured_cap: i64,              // W
+    pub effective_cap: i64,               // W
+    pub reason: Option<PowerGuardReason>, // Why the cap is lowered, if it is
+}
+
+/// What the guard sees on a tick
+pub struct PowerGuardReadings {
+    pub temp: Option<i64>,
+    pub fan_speed: Option<f64>, // %
+    pub cap: i64,
+    pub cap_range: (i64, i64), // power1_cap_min-power1_cap_max
+}
+
+pub struct PowerGuard {
+    configured_cap: Option<i64>, // The cap from before it was lowered, set while limiting
+    written_cap: Option<i64>,
+    reason: Option<PowerGuardReason>,
+    since_step: Option<Duration>, // None before the first step
+    cool_for: Duration,
+}
+
+impl PowerGuard {
+    pub fn new() -> Self {
+        PowerGuard {
+            configured_cap: None,
+            written_cap: None,
+            reason: None,
+            since_step: None,
+            cool_for: Duration::from_secs(0),
+        }
+    }
+
+    /// Returns the cap that should be written, if it changed, and the current status
+    pub fn update(
+        &mut self,
+        settings: &PowerGuardSettings,
+        readings: &PowerGuardReadings,
+        elapsed: Duration,
+    ) -> (Option<i64>, PowerGuardStatus) {
+        if let Some(since_step) = &mut self.since_step {
+            *since_step += elapsed;
+        }
+
+        // Someone else set the cap in the meantime, which is then the cap to return to
+        if self.written_cap.is_some() && self.written_cap != Some(readings.cap) {
+            log::info!(
+                "The power cap was changed to {}W, using it as the configured cap",
+                readings.cap
+            );
+            *self = PowerGuard {
+                since_step: self.since_step,
+                ..PowerGuard::new()
+            };
+        }
+
+        let configured_cap = self.configured_cap.unwrap_or(readings.cap);
+        let (min_cap, max_cap) = readings.cap_range;
+
+        let hot = match (readings.temp, readings.fan_speed, settings.max_fan_speed) {
+            (Some(temp), _, _) if temp >= settings.max_temp => {
+                Some(PowerGuardReason::Temperature(temp))
+            }
+            (_, Some(speed), Some(max_speed)) if speed >= max_speed => {
+                Some(PowerGuardReason::FanSpeed(speed))
+            }
+            _ => None,
+        };
+        let cool = readings
+            .temp
+            .map_or(true, |temp| temp <= settings.max_temp - settings.hysteresis)
+            && match (readings.fan_speed, settings.max_fan_speed) {
+                (Some(speed), Some(max_speed)) => speed < max_speed - FAN_SPEED_HYSTERESIS,
+                _ => true,
+            };
+
+        if cool {
+            self.cool_for += elapsed;
+        } else {
+            self.cool_for = Duration::from_secs(0);
+        }
+
+        let mut new_cap = None;
+
+        if let Some(reason) = hot {
+            let step_due = self.since_step.map_or(true, |since_step| {
+                since_step >= Duration::from_secs(settings.step_interval)
+            });
+
+            if step_due {
+                let cap = (readings.cap - settings.step).max(min_cap);
+
+                if cap < readings.cap {
+                    new_cap = Some(cap);
+                    self.configured_cap = Some(configured_cap);
+                    self.since_step = Some(Duration::from_secs(0));
+                }
+            }
+
+            self.reason = Some(reason);
+        } else if self.configured_cap.is_some()
+            && self.cool_for >= Duration::from_secs(settings.restore_interval)
+        {
+            let cap = (readings.cap + settings.restore_step)
+                .min(configured_cap)
+                .min(max_cap);
+
+            new_cap = Some(cap);
+            self.since_step = Some(Duration::from_secs(0));
+            self.cool_for = Duration::from_secs(0);
+
+            if cap >= configured_cap {
+                self.configured_cap = None;
+                self.reason = None;
+            }
+        }
+
+        if new_cap.is_some() {
+            self.written_cap = match self.configured_cap {
+                Some(_) => new_cap,
+                None => None,
+            };
+        }
+
+        let status = PowerGuardStatus {
+            configured_cap,
+            effective_cap: new_cap.unwrap_or(readings.cap),
+            reason: match self.configured_cap {
+                Some(_) => self.reason.clone(),
+                None => None,
+            },
+        };
+
+        (new_cap, status)
+    }
+
+    /// Returns the cap to go back to if it's currently lowered
+    pub fn restore(&mut self) -> Option<i64> {
+        let configured_cap = self.configured_cap;
+        *self = PowerGuard::new();
+        configured_cap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn readings(temp: i64, cap: i64) -> PowerGuardReadings {
+        PowerGuardReadings {
+            temp: Some(temp),
+            fan_speed: None,
+            cap,
+            cap_range: (150, 250),
+        }
+    }
+
+    /// Runs the guard for the given number of seconds, applying the caps it sets
+    fn run(
+        guard: &mut PowerGuard,
+        settings: &PowerGuardSettings,
+        temp: i64,
+        cap: &mut i64,
+        secs: u32,
+    ) -> PowerGuardStatus {
+        let mut status = None;
+
+        for _ in 0..secs {
+            let (new_cap, s) = guard.update(settings, &readings(temp, *cap), SECOND);
+            if let Some(new_cap) = new_cap {
+                *cap = new_cap;
+            }
+            status = Some(s);
+        }
+
+        status.unwrap()
+    }
+
+    #[test]
+    fn lowers_cap_in_steps_down_to_minimum() {
+        let settings = PowerGuardSettings {
+            enabled: true,
+            ..PowerGuardSettings::default()
+        };
+        let mut guard = PowerGuard::new();
+        let mut cap = 200;
+
+        // Below the limit nothing happens
+        let status = run(&mut guard, &settings, 85, &mut cap, 60);
+        assert_eq!(cap, 200);
+        assert_eq!(status.reason, None);
+
+        // One step right away, then one every 5 seconds
+        run(&mut guard, &settings, 92, &mut cap, 1);
+        assert_eq!(cap, 190);
+        let status = run(&mut guard, &settings, 92, &mut cap, 10);
+        assert_eq!(cap, 170);
+        assert_eq!(
+            status,
+            PowerGuardStatus {
+                configured_cap: 200,
+                effective_cap: 170,
+                reason: Some(PowerGuardReason::Temperature(92)),
+            }
+        );
+
+        // Never below power1_cap_min
+        run(&mut guard, &settings, 95, &mut cap, 600);
+        assert_eq!(cap, 150);
+    }
+
+    #[test]
+    fn restores_cap_gradually() {
+        let settings = PowerGuardSettings {
+            enabled: true,
+            ..PowerGuardSettings::default()
+        };
+        let mut guard = PowerGuard::new();
+        let mut cap = 200;
+
+        run(&mut guard, &settings, 92, &mut cap, 11);
+        assert_eq!(cap, 170);
+
+        // Within the hysteresis the cap is held
+        run(&mut guard, &settings, 88, &mut cap, 120);
+        assert_eq!(cap, 170);
+
+        // Raised by 5W every 30 seconds once it's cool
+        run(&mut guard, &settings, 80, &mut cap, 30);
+        assert_eq!(cap, 175);
+        run(&mut guard, &settings, 80, &mut cap, 60);
+        assert_eq!(cap, 185);
+
+        // Up to the configured cap and no further
+        let status = run(&mut guard, &settings, 80, &mut cap, 600);
+        assert_eq!(cap, 200);
+        assert_eq!(status.reason, None);
+        assert_eq!(guard.restore(), None);
+    }
+
+    #[test]
+    fn limits_fan_speed_and_follows_external_changes() {
+        let settings = PowerGuardSettings {
+            enabled: true,
+            max_fan_speed: Some(70.0),
+            ..PowerGuardSettings::default()
+        };
+        let mut guard = PowerGuard::new();
+
+        let fan_readings = PowerGuardReadings {
+            temp: Some(70),
+            fan_speed: Some(80.0),
+            cap: 200,
+            cap_range: (150, 250),
+        };
+        let (new_cap, status) = guard.update(&settings, &fan_readings, SECOND);
+        assert_eq!(new_cap, Some(190));
+        assert_eq!(status.reason, Some(PowerGuardReason::FanSpeed(80.0)));
+
+        // The user set a new cap, which is what gets restored later on
+        let (new_cap, status) = guard.update(&settings, &readings(70, 220), SECOND);
+        assert_eq!(new_cap, None);
+        assert_eq!(status.configured_cap, 220);
+        assert_eq!(guard.restore(), None);
+    }
+}
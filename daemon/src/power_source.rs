@@ -0,0 +1,223 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PowerSource {
+    Ac,
+    Battery,
+}
+
+impl PowerSource {
+    pub fn from_str(source: &str) -> Option<Self> {
+        match source.to_lowercase().as_str() {
+            "ac" => Some(PowerSource::Ac),
+            "battery" => Some(PowerSource::Battery),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PowerSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowerSource::Ac => write!(f, "AC"),
+            PowerSource::Battery => write!(f, "battery"),
+        }
+    }
+}
+
+/// The profiles a GPU switches to when the power source changes
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PowerSourceProfiles {
+    pub ac: Option<String>,
+    pub battery: Option<String>,
+}
+
+impl PowerSourceProfiles {
+    pub fn get(&self, source: PowerSource) -> Option<&String> {
+        match source {
+            PowerSource::Ac => self.ac.as_ref(),
+            PowerSource::Battery => self.battery.as_ref(),
+        }
+    }
+
+    pub fn set(&mut self, source: PowerSource, profile: Option<String>) {
+        match source {
+            PowerSource::Ac => self.ac = profile,
+            PowerSource::Battery => self.battery = profile,
+        }
+    }
+}
+
+fn read_property(supply: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(supply.join(name))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+/// Works out the power source from a power_supply class directory.
+/// Returns `None` on machines without a system battery, where there is nothing to switch between.
+pub fn read_power_source(power_supply_path: &Path) -> Option<PowerSource> {
+    let entries = match fs::read_dir(power_supply_path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::debug!("Failed to read {:?}: {}", power_supply_path, e);
+            return None;
+        }
+    };
+
+    let mut has_battery = false;
+
+    for entry in entries.flatten() {
+        let supply = entry.path();
+
+        // Batteries of wireless mice and the like are devices, not the system's
+        if read_property(&supply, "scope").as_deref() == Some("Device") {
+            continue;
+        }
+
+        match read_property(&supply, "type").as_deref() {
+            Some("Battery") => {
+                has_battery |= read_property(&supply, "present").as_deref() != Some("0")
+            }
+            // Mains, USB and USB-C chargers
+            Some(_) if read_property(&supply, "online").as_deref() == Some("1") => {
+                return Some(PowerSource::Ac)
+            }
+            _ => (),
+        }
+    }
+
+    match has_battery {
+        true => Some(PowerSource::Battery),
+        false => None,
+    }
+}
+
+/// Periodically checks the power source on a separate thread
+pub struct PowerSourceWatcher {
+    power_source: Arc<RwLock<Option<PowerSource>>>,
+    stop_sender: Option<Sender<()>>,
+}
+
+impl PowerSourceWatcher {
+    pub fn new() -> Self {
+        PowerSourceWatcher {
+            power_source: Arc::new(RwLock::new(None)),
+            stop_sender: None,
+        }
+    }
+
+    /// Starts watching, `on_change` gets called when the machine was plugged in or unplugged
+    pub fn start<F>(&mut self, power_supply_path: PathBuf, interval: Duration, on_change: F)
+    where
+        F: Fn() + Send + 'static,
+    {
+        self.stop();
+
+        let (sender, receiver) = mpsc::channel();
+        let power_source = self.power_source.clone();
+
+        // The first check happens right away, so that the profile of the current source gets applied on startup
+        thread::spawn(move || loop {
+            let new_source = read_power_source(&power_supply_path);
+
+            let changed = {
+                let mut power_source = power_source.write().unwrap();
+                let changed = *power_source != new_source;
+                *power_source = new_source;
+                changed
+            };
+
+            if changed {
+                match new_source {
+                    Some(source) => log::info!("Running on {} power now", source),
+                    None => log::info!("The power source is unknown now"),
+                }
+                on_change();
+            }
+
+            // Anything other than a timeout means that a stop was requested
+            if let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                continue;
+            }
+            break;
+        });
+
+        self.stop_sender = Some(sender);
+    }
+
+    /// Stops watching, without waiting for the thread like the process watcher
+    pub fn stop(&mut self) {
+        if let Some(sender) = self.stop_sender.take() {
+            let _ = sender.send(());
+        }
+    }
+
+    pub fn get(&self) -> Option<PowerSource> {
+        *self.power_source.read().unwrap()
+    }
+}
+
+impl Drop for PowerSourceWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_supply(path: &Path, name: &str, properties: &[(&str, &str)]) {
+        let supply = path.join(name);
+        fs::create_dir_all(&supply).unwrap();
+
+        for (property, value) in properties {
+            fs::write(supply.join(property), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    #[test]
+    fn reads_power_source() {
+        let path = std::env::temp_dir().join(format!("lact-power-supply-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        // A desktop with a wireless mouse
+        add_supply(
+            &path,
+            "hidpp_battery_0",
+            &[("type", "Battery"), ("scope", "Device"), ("present", "1")],
+        );
+        assert_eq!(read_power_source(&path), None);
+
+        // A laptop
+        add_supply(&path, "BAT0", &[("type", "Battery"), ("present", "1")]);
+        add_supply(&path, "AC", &[("type", "Mains"), ("online", "0")]);
+        add_supply(
+            &path,
+            "ucsi-source-psy-USBC000:001",
+            &[("type", "USB"), ("online", "0")],
+        );
+        assert_eq!(read_power_source(&path), Some(PowerSource::Battery));
+
+        // Charging over USB-C
+        add_supply(&path, "ucsi-source-psy-USBC000:001", &[("online", "1")]);
+        assert_eq!(read_power_source(&path), Some(PowerSource::Ac));
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,483 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What a process has to look like for a rule to match
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ProcessMatcher {
+    Exe(String),     // File name of the executable, or its full path if it contains a slash
+    Cmdline(String), // Part of the command line, e.g. the name of a game started through Wine
+    Cgroup(String),  // Part of the cgroup path, e.g. the scope of a flatpak app
+}
+
+impl fmt::Display for ProcessMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessMatcher::Exe(pattern) => write!(f, "exe {}", pattern),
+            ProcessMatcher::Cmdline(pattern) => write!(f, "cmdline contains {}", pattern),
+            ProcessMatcher::Cgroup(pattern) => write!(f, "cgroup contains {}", pattern),
+        }
+    }
+}
+
+/// Switches a GPU to a profile while a matching process runs
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProcessRule {
+    pub gpu_id: u32,
+    pub matcher: ProcessMatcher,
+    pub profile: String,
+}
+
+impl ProcessRule {
+    pub fn validate(&self) -> Result<(), String> {
+        let pattern = match &self.matcher {
+            ProcessMatcher::Exe(pattern)
+            | ProcessMatcher::Cmdline(pattern)
+            | ProcessMatcher::Cgroup(pattern) => pattern,
+        };
+
+        match pattern.is_empty() {
+            true => Err("the pattern can't be empty".to_string()),
+            false => Ok(()),
+        }
+    }
+
+    pub fn matches(&self, process: &ProcessInfo) -> bool {
+        match &self.matcher {
+            ProcessMatcher::Exe(pattern) => match &process.exe {
+                Some(exe) if pattern.contains('/') => exe == Path::new(pattern),
+                Some(exe) => exe
+                    .file_name()
+                    .map_or(false, |name| name == pattern.as_str()),
+                None => false,
+            },
+            ProcessMatcher::Cmdline(pattern) => process.cmdline.contains(pattern.as_str()),
+            ProcessMatcher::Cgroup(pattern) => process
+                .cgroups
+                .iter()
+                .any(|cgroup| cgroup.contains(pattern.as_str())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub exe: Option<PathBuf>,
+    pub cmdline: String,      // Arguments separated by spaces
+    pub cgroups: Vec<String>, // Paths of the cgroups the process is in
+}
+
+impl ProcessInfo {
+    /// Short name to show to the user
+    pub fn name(&self) -> String {
+        match self.exe.as_ref().and_then(|exe| exe.file_name()) {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => self
+                .cmdline
+                .split(' ')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+}
+
+/// The rule that currently applies to a GPU and one of the processes it matched
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProcessRuleMatch {
+    pub index: usize, // Position of the rule in the config
+    pub rule: ProcessRule,
+    pub pid: u32,
+    pub process: String,
+}
+
+/// Reads the processes from a proc filesystem. Processes that exit while being read or can't be read at all are skipped.
+pub fn read_processes(proc_root: &Path) -> Vec<ProcessInfo> {
+    let entries = match fs::read_dir(proc_root) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("Failed to read {:?}: {}", proc_root, e);
+            return Vec::new();
+        }
+    };
+
+    let mut processes = Vec::new();
+
+    for entry in entries.flatten() {
+        let pid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+        let path = entry.path();
+
+        // Executables that were replaced while running are marked as deleted
+        let exe = fs::read_link(path.join("exe")).ok().map(|exe| {
+            let exe = exe.to_string_lossy();
+            PathBuf::from(exe.trim_end_matches(" (deleted)"))
+        });
+
+        let cmdline = match fs::read(path.join("cmdline")) {
+            Ok(cmdline) => cmdline
+                .split(|b| *b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).to_string())
+                .collect::<Vec<String>>()
+                .join(" "),
+            Err(_) => continue,
+        };
+
+        // Lines look like hierarchy-ID:controller-list:cgroup-path
+        let cgroups = fs::read_to_string(path.join("cgroup"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.splitn(3, ':').nth(2))
+            .map(|cgroup| cgroup.to_string())
+            .collect();
+
+        processes.push(ProcessInfo {
+            pid,
+            exe,
+            cmdline,
+            cgroups,
+        });
+    }
+
+    processes
+}
+
+/// Finds the rule that applies to each GPU. Rules that come first take precedence over later ones for the same GPU.
+pub fn match_rules(
+    rules: &[ProcessRule],
+    processes: &[ProcessInfo],
+) -> HashMap<u32, ProcessRuleMatch> {
+    let mut matches = HashMap::new();
+
+    for (index, rule) in rules.iter().enumerate() {
+        if matches.contains_key(&rule.gpu_id) {
+            continue;
+        }
+
+        if let Some(process) = processes.iter().find(|process| rule.matches(process)) {
+            matches.insert(
+                rule.gpu_id,
+                ProcessRuleMatch {
+                    index,
+                    rule: rule.clone(),
+                    pid: process.pid,
+                    process: process.name(),
+                },
+            );
+        }
+    }
+
+    matches
+}
+
+/// Periodically checks the running processes against the rules on a separate thread
+pub struct ProcessWatcher {
+    rules: Arc<RwLock<Vec<ProcessRule>>>,
+    matches: Arc<RwLock<HashMap<u32, ProcessRuleMatch>>>,
+    generation: Arc<AtomicU64>, // Bumped on every stop, so that the thread of a stopped watcher can tell
+    stop_sender: Option<Sender<()>>,
+}
+
+/// One run of the watcher thread, which does nothing anymore once the watcher was stopped
+struct ProcessScanner {
+    rules: Arc<RwLock<Vec<ProcessRule>>>,
+    matches: Arc<RwLock<HashMap<u32, ProcessRuleMatch>>>,
+    generation: Arc<AtomicU64>,
+    started_generation: u64,
+}
+
+impl ProcessScanner {
+    fn is_stopped(&self) -> bool {
+        self.generation.load(Ordering::SeqCst) != self.started_generation
+    }
+
+    /// Checks the processes once, returning whether the rules that match changed
+    fn scan(&self, proc_root: &Path) -> bool {
+        let rules = self.rules.read().unwrap().clone();
+
+        let new_matches = match rules.is_empty() {
+            true => HashMap::new(),
+            false => match_rules(&rules, &read_processes(proc_root)),
+        };
+
+        let mut matches = self.matches.write().unwrap();
+
+        // Checked under the lock that stopping takes as well, so nothing is stored after a stop
+        if self.is_stopped() {
+            return false;
+        }
+
+        // Another process matching the same rule doesn't change anything
+        let changed = matches.len() != new_matches.len()
+            || new_matches.iter().any(|(id, new_match)| {
+                matches.get(id).map_or(true, |old_match| {
+                    old_match.index != new_match.index || old_match.rule != new_match.rule
+                })
+            });
+        *matches = new_matches;
+
+        changed
+    }
+}
+
+impl ProcessWatcher {
+    pub fn new(rules: Vec<ProcessRule>) -> Self {
+        ProcessWatcher {
+            rules: Arc::new(RwLock::new(rules)),
+            matches: Arc::new(RwLock::new(HashMap::new())),
+            generation: Arc::new(AtomicU64::new(0)),
+            stop_sender: None,
+        }
+    }
+
+    /// Starts watching, `on_change` gets called whenever a GPU starts or stops matching a rule
+    pub fn start<F>(&mut self, proc_root: PathBuf, interval: Duration, on_change: F)
+    where
+        F: Fn() + Send + 'static,
+    {
+        self.stop();
+
+        let (sender, receiver) = mpsc::channel();
+        let scanner = self.scanner();
+
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                if scanner.scan(&proc_root) && !scanner.is_stopped() {
+                    log::info!("The processes matching the profile rules changed");
+                    on_change();
+                }
+
+                if scanner.is_stopped() {
+                    break;
+                }
+            }
+        });
+
+        self.stop_sender = Some(sender);
+    }
+
+    fn scanner(&self) -> ProcessScanner {
+        ProcessScanner {
+            rules: self.rules.clone(),
+            matches: self.matches.clone(),
+            generation: self.generation.clone(),
+            started_generation: self.generation.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Stops watching. The thread isn't waited for, as it might be waiting for the daemon that is stopping it,
+    /// but it doesn't touch the matches or report changes anymore.
+    pub fn stop(&mut self) {
+        if let Some(sender) = self.stop_sender.take() {
+            let _ = sender.send(());
+        }
+
+        let mut matches = self.matches.write().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        matches.clear();
+    }
+
+    /// Replaces the rules, which take effect on the next check
+    pub fn set_rules(&self, rules: Vec<ProcessRule>) {
+        *self.rules.write().unwrap() = rules;
+    }
+
+    pub fn get_matches(&self) -> HashMap<u32, ProcessRuleMatch> {
+        self.matches.read().unwrap().clone()
+    }
+
+    pub fn get_match(&self, gpu_id: u32) -> Option<ProcessRuleMatch> {
+        self.matches.read().unwrap().get(&gpu_id).cloned()
+    }
+}
+
+impl Drop for ProcessWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// Creates a proc directory with the given processes, as (pid, exe, cmdline, cgroup)
+    fn fake_proc(name: &str, processes: &[(u32, &str, &str, &str)]) -> PathBuf {
+        let proc_root =
+            std::env::temp_dir().join(format!("lact-proc-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&proc_root);
+        fs::create_dir_all(&proc_root).unwrap();
+
+        // Not a process
+        fs::create_dir_all(proc_root.join("sys")).unwrap();
+
+        for (pid, exe, cmdline, cgroup) in processes {
+            add_process(&proc_root, *pid, exe, cmdline, cgroup);
+        }
+
+        proc_root
+    }
+
+    fn add_process(proc_root: &Path, pid: u32, exe: &str, cmdline: &str, cgroup: &str) {
+        let path = proc_root.join(pid.to_string());
+        fs::create_dir_all(&path).unwrap();
+
+        if !exe.is_empty() {
+            symlink(exe, path.join("exe")).unwrap();
+        }
+        fs::write(path.join("cmdline"), cmdline.replace(' ', "\0") + "\0").unwrap();
+        fs::write(path.join("cgroup"), format!("0::{}\n", cgroup)).unwrap();
+    }
+
+    fn rule(gpu_id: u32, matcher: ProcessMatcher, profile: &str) -> ProcessRule {
+        ProcessRule {
+            gpu_id,
+            matcher,
+            profile: profile.to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_and_matches_processes() {
+        let proc_root = fake_proc(
+            "match",
+            &[
+                (1, "/usr/lib/systemd/systemd", "/sbin/init", "/init.scope"),
+                (
+                    120,
+                    "/usr/bin/wine64-preloader (deleted)",
+                    "C:\\Games\\Game.exe -dx12",
+                    "/user.slice/app-steam.scope",
+                ),
+                (
+                    300,
+                    "/usr/bin/blender",
+                    "blender scene.blend",
+                    "/user.slice/app-flatpak-org.blender.Blender-1.scope",
+                ),
+                // Kernel threads have neither an executable nor arguments
+                (2, "", "", "/"),
+            ],
+        );
+
+        let mut processes = read_processes(&proc_root);
+        processes.sort_by_key(|process| process.pid);
+        assert_eq!(processes.len(), 4);
+        assert_eq!(
+            processes[2].exe,
+            Some(PathBuf::from("/usr/bin/wine64-preloader"))
+        );
+        assert_eq!(processes[2].cmdline, "C:\\Games\\Game.exe -dx12");
+        assert_eq!(processes[2].cgroups, vec!["/user.slice/app-steam.scope"]);
+
+        assert!(rule(0, ProcessMatcher::Exe("blender".to_string()), "p").matches(&processes[3]));
+        assert!(
+            rule(0, ProcessMatcher::Exe("/usr/bin/blender".to_string()), "p")
+                .matches(&processes[3])
+        );
+        assert!(!rule(0, ProcessMatcher::Exe("blend".to_string()), "p").matches(&processes[3]));
+        assert!(!rule(0, ProcessMatcher::Exe("blender".to_string()), "p").matches(&processes[1]));
+
+        let rules = vec![
+            rule(0, ProcessMatcher::Cmdline("Game.exe".to_string()), "gaming"),
+            rule(0, ProcessMatcher::Exe("blender".to_string()), "compute"),
+            rule(
+                1,
+                ProcessMatcher::Cgroup("org.blender".to_string()),
+                "compute",
+            ),
+            rule(2, ProcessMatcher::Exe("steam".to_string()), "gaming"),
+        ];
+        let matches = match_rules(&rules, &processes);
+
+        // The first rule of a GPU wins
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[&0].index, 0);
+        assert_eq!(matches[&0].pid, 120);
+        assert_eq!(matches[&0].process, "wine64-preloader");
+        assert_eq!(matches[&1].rule.profile, "compute");
+
+        fs::remove_dir_all(&proc_root).unwrap();
+    }
+
+    #[test]
+    fn watcher_reports_changes() {
+        let proc_root = fake_proc("watch", &[(1, "/sbin/init", "/sbin/init", "/")]);
+
+        let watcher = ProcessWatcher::new(vec![rule(
+            0,
+            ProcessMatcher::Exe("game".to_string()),
+            "gaming",
+        )]);
+        let scanner = watcher.scanner();
+
+        assert!(!scanner.scan(&proc_root));
+        assert_eq!(watcher.get_match(0), None);
+
+        // Two instances of the game are reported as a single change
+        add_process(&proc_root, 200, "/opt/game/game", "game", "/");
+        add_process(&proc_root, 201, "/opt/game/game", "game --server", "/");
+        assert!(scanner.scan(&proc_root));
+        assert_eq!(watcher.get_match(0).unwrap().rule.profile, "gaming");
+
+        // The rule still matches until the last instance exits
+        fs::remove_dir_all(proc_root.join("200")).unwrap();
+        assert!(!scanner.scan(&proc_root));
+        assert_eq!(watcher.get_match(0).unwrap().pid, 201);
+
+        fs::remove_dir_all(proc_root.join("201")).unwrap();
+        assert!(scanner.scan(&proc_root));
+        assert_eq!(watcher.get_match(0), None);
+
+        fs::remove_dir_all(&proc_root).unwrap();
+    }
+
+    #[test]
+    fn stopped_watcher_keeps_quiet() {
+        let proc_root = fake_proc("stopped", &[(300, "/opt/game/game", "game", "/")]);
+
+        let mut watcher = ProcessWatcher::new(vec![rule(
+            0,
+            ProcessMatcher::Exe("game".to_string()),
+            "gaming",
+        )]);
+        let old_scanner = watcher.scanner();
+
+        watcher.stop();
+
+        // The thread from before the stop neither brings back the matches nor reports a change
+        assert!(old_scanner.is_stopped());
+        assert!(!old_scanner.scan(&proc_root));
+        assert_eq!(watcher.get_match(0), None);
+
+        // While the one started afterwards does
+        let scanner = watcher.scanner();
+        assert!(scanner.scan(&proc_root));
+        assert_eq!(watcher.get_match(0).unwrap().pid, 300);
+        assert!(!old_scanner.scan(&proc_root));
+
+        fs::remove_dir_all(&proc_root).unwrap();
+    }
+}
@@ -0,0 +1,532 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fan_control::{validate_curve, FanCurveOptions};
+use crate::gpu_controller::PowerProfile;
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(20);
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// What a schedule changes when it triggers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ScheduleAction {
+    Profile(String),
+    PowerCap(i64),                // W
+    FanCurve(BTreeMap<i64, f64>), // %
+    PowerProfile(PowerProfile),
+}
+
+impl ScheduleAction {
+    /// Actions of the same kind replace each other, different kinds are independent
+    fn kind(&self) -> u8 {
+        match self {
+            ScheduleAction::Profile(_) => 0,
+            ScheduleAction::PowerCap(_) => 1,
+            ScheduleAction::FanCurve(_) => 2,
+            ScheduleAction::PowerProfile(_) => 3,
+        }
+    }
+}
+
+impl fmt::Display for ScheduleAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleAction::Profile(name) => write!(f, "profile {}", name),
+            ScheduleAction::PowerCap(cap) => write!(f, "power cap {}W", cap),
+            ScheduleAction::FanCurve(curve) => write!(
+                f,
+                "fan curve {}",
+                curve
+                    .iter()
+                    .map(|(temp, speed)| format!("{}:{}", temp, speed))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+            ScheduleAction::PowerProfile(profile) => {
+                write!(f, "power profile {}", profile.to_string())
+            }
+        }
+    }
+}
+
+/// Applies an action to a GPU at a time of day, on some or all days of the week
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub gpu_id: u32,
+    pub hour: u8,
+    pub minute: u8,
+    pub weekdays: Vec<u8>, // 0 is Monday, every day when empty
+    pub action: ScheduleAction,
+}
+
+impl Schedule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.hour > 23 || self.minute > 59 {
+            return Err(format!(
+                "{:02}:{:02} is not a time of day",
+                self.hour, self.minute
+            ));
+        }
+        if self.weekdays.iter().any(|day| *day > 6) {
+            return Err("weekdays have to be within 0-6".to_string());
+        }
+        match &self.action {
+            ScheduleAction::PowerCap(cap) if *cap <= 0 => {
+                Err("the power cap has to be at least 1W".to_string())
+            }
+            ScheduleAction::FanCurve(curve) => {
+                validate_curve(curve, &FanCurveOptions::default(), None)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn runs_on(&self, weekday: u8) -> bool {
+        self.weekdays.is_empty() || self.weekdays.contains(&weekday)
+    }
+
+    fn minute_of_day(&self) -> u32 {
+        self.hour as u32 * 60 + self.minute as u32
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:02}:{:02} GPU {}: {}",
+            format_weekdays(&self.weekdays),
+            self.hour,
+            self.minute,
+            self.gpu_id,
+            self.action
+        )
+    }
+}
+
+/// Parses a time of day like `07:30`
+pub fn parse_time(time: &str) -> Option<(u8, u8)> {
+    let mut parts = time.trim().splitn(2, ':');
+
+    let hour = parts.next()?.parse().ok()?;
+    let minute = parts.next()?.parse().ok()?;
+
+    match hour <= 23 && minute <= 59 {
+        true => Some((hour, minute)),
+        false => None,
+    }
+}
+
+/// Parses weekdays like `*`, `mon-fri` or `sat,sun` the way cron does
+pub fn parse_weekdays(weekdays: &str) -> Option<Vec<u8>> {
+    let weekdays = weekdays.trim().to_lowercase();
+
+    if weekdays == "*" {
+        return Some(Vec::new());
+    }
+
+    let day = |name: &str| {
+        WEEKDAYS
+            .iter()
+            .position(|day| *day == name)
+            .map(|i| i as u8)
+    };
+    let mut days = Vec::new();
+
+    for part in weekdays.split(',') {
+        let mut range = part.splitn(2, '-');
+        let first = day(range.next()?)?;
+
+        match range.next() {
+            Some(last) => {
+                let last = day(last)?;
+
+                // Ranges can wrap around the end of the week, like fri-mon
+                let mut current = first;
+                loop {
+                    days.push(current);
+                    if current == last {
+                        break;
+                    }
+                    current = (current + 1) % 7;
+                }
+            }
+            None => days.push(first),
+        }
+    }
+
+    days.sort_unstable();
+    days.dedup();
+
+    match days.len() {
+        7 => Some(Vec::new()),
+        _ => Some(days),
+    }
+}
+
+pub fn format_weekdays(weekdays: &[u8]) -> String {
+    match weekdays.is_empty() {
+        true => "*".to_string(),
+        false => weekdays
+            .iter()
+            .filter_map(|day| WEEKDAYS.get(*day as usize))
+            .cloned()
+            .collect::<Vec<&str>>()
+            .join(","),
+    }
+}
+
+/// A point in local time, as far as schedules are concerned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTime {
+    pub day: i64,    // Days since 1970-01-01
+    pub weekday: u8, // 0 is Monday
+    pub minute: u32, // Minute of the day
+}
+
+impl LocalTime {
+    pub fn now() -> Option<Self> {
+        // There is no way to get the local time zone from the standard library
+        let mut tm: nix::libc::tm = unsafe { std::mem::zeroed() };
+
+        unsafe {
+            let time = nix::libc::time(std::ptr::null_mut());
+            if nix::libc::localtime_r(&time, &mut tm).is_null() {
+                return None;
+            }
+        }
+
+        Some(LocalTime {
+            day: days_from_civil(
+                tm.tm_year as i64 + 1900,
+                tm.tm_mon as i64 + 1,
+                tm.tm_mday as i64,
+            ),
+            weekday: ((tm.tm_wday + 6) % 7) as u8,
+            minute: tm.tm_hour as u32 * 60 + tm.tm_min as u32,
+        })
+    }
+
+    fn days_ago(&self, days: i64) -> Self {
+        LocalTime {
+            day: self.day - days,
+            weekday: ((self.weekday as i64 - days).rem_euclid(7)) as u8,
+            minute: 24 * 60 - 1,
+        }
+    }
+}
+
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// The last time a schedule triggered
+#[derive(Debug, Clone, PartialEq)]
+struct Trigger {
+    day: i64,
+    minute: u32,
+    schedule: Schedule,
+}
+
+/// Keeps track of which schedules were applied. Instead of waiting for the exact minute of a schedule,
+/// it works out which schedule should be in effect right now, so that jumps of the clock
+/// from suspending or adjusting it apply the right settings as well.
+pub struct ScheduleState {
+    applied: HashMap<(u32, u8), Trigger>, // By GPU and action kind
+}
+
+impl ScheduleState {
+    pub fn new() -> Self {
+        ScheduleState {
+            applied: HashMap::new(),
+        }
+    }
+
+    /// Returns the schedules that have to be applied now, in the order they triggered
+    pub fn update(&mut self, schedules: &[Schedule], now: &LocalTime) -> Vec<Schedule> {
+        let mut latest: HashMap<(u32, u8), Trigger> = HashMap::new();
+
+        // A schedule that runs on any day triggered within the last week
+        for days_ago in 0..=7 {
+            let day = match days_ago {
+                0 => *now,
+                _ => now.days_ago(days_ago),
+            };
+
+            for schedule in schedules {
+                if !schedule.runs_on(day.weekday) || schedule.minute_of_day() > day.minute {
+                    continue;
+                }
+
+                let key = (schedule.gpu_id, schedule.action.kind());
+                let trigger = Trigger {
+                    day: day.day,
+                    minute: schedule.minute_of_day(),
+                    schedule: schedule.clone(),
+                };
+
+                let is_later = latest.get(&key).map_or(true, |other| {
+                    (trigger.day, trigger.minute) > (other.day, other.minute)
+                });
+                if is_later {
+                    latest.insert(key, trigger);
+                }
+            }
+        }
+
+        let mut due = Vec::new();
+
+        for (key, trigger) in latest {
+            if self.applied.get(&key) != Some(&trigger) {
+                self.applied.insert(key, trigger.clone());
+                due.push(trigger);
+            }
+        }
+        due.sort_by_key(|trigger| (trigger.day, trigger.minute));
+
+        due.into_iter().map(|trigger| trigger.schedule).collect()
+    }
+
+    /// The schedules whose settings are currently in effect
+    pub fn get_applied(&self) -> Vec<Schedule> {
+        self.applied
+            .values()
+            .map(|trigger| trigger.schedule.clone())
+            .collect()
+    }
+}
+
+/// Evaluates the schedules periodically on a separate thread
+pub struct ScheduleWatcher {
+    schedules: Arc<RwLock<Vec<Schedule>>>,
+    state: Arc<RwLock<ScheduleState>>,
+    pending: Arc<RwLock<Vec<Schedule>>>,
+    stop_sender: Option<Sender<()>>,
+}
+
+impl ScheduleWatcher {
+    pub fn new(schedules: Vec<Schedule>) -> Self {
+        ScheduleWatcher {
+            schedules: Arc::new(RwLock::new(schedules)),
+            state: Arc::new(RwLock::new(ScheduleState::new())),
+            pending: Arc::new(RwLock::new(Vec::new())),
+            stop_sender: None,
+        }
+    }
+
+    /// Starts checking the schedules, `on_due` gets called when there are schedules to apply.
+    /// The first check happens right away, so that the settings in effect get applied on startup.
+    pub fn start<F>(&mut self, interval: Duration, on_due: F)
+    where
+        F: Fn() + Send + 'static,
+    {
+        self.stop();
+
+        let (sender, receiver) = mpsc::channel();
+        let schedules = self.schedules.clone();
+        let state = self.state.clone();
+        let pending = self.pending.clone();
+
+        thread::spawn(move || loop {
+            if let Some(now) = LocalTime::now() {
+                let schedules = schedules.read().unwrap().clone();
+                let due = state.write().unwrap().update(&schedules, &now);
+
+                if !due.is_empty() {
+                    log::info!("{} schedules are due", due.len());
+                    pending.write().unwrap().extend(due);
+                    on_due();
+                }
+            }
+
+            // Anything other than a timeout means that a stop was requested
+            if let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                continue;
+            }
+            break;
+        });
+
+        self.stop_sender = Some(sender);
+    }
+
+    /// Stops checking, without waiting for the thread like the process watcher
+    pub fn stop(&mut self) {
+        if let Some(sender) = self.stop_sender.take() {
+            let _ = sender.send(());
+        }
+    }
+
+    /// Replaces the schedules, which take effect on the next check
+    pub fn set_schedules(&self, schedules: Vec<Schedule>) {
+        *self.schedules.write().unwrap() = schedules;
+    }
+
+    /// Takes the schedules that are due
+    pub fn take_pending(&self) -> Vec<Schedule> {
+        self.pending.write().unwrap().drain(..).collect()
+    }
+
+    pub fn get_applied(&self) -> Vec<Schedule> {
+        self.state.read().unwrap().get_applied()
+    }
+}
+
+impl Drop for ScheduleWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(weekdays: &str, time: &str, action: ScheduleAction) -> Schedule {
+        let (hour, minute) = parse_time(time).unwrap();
+
+        Schedule {
+            gpu_id: 0,
+            hour,
+            minute,
+            weekdays: parse_weekdays(weekdays).unwrap(),
+            action,
+        }
+    }
+
+    fn profile(name: &str) -> ScheduleAction {
+        ScheduleAction::Profile(name.to_string())
+    }
+
+    // 2024-01-01 was a Monday
+    fn at(day: i64, time: &str) -> LocalTime {
+        let (hour, minute) = parse_time(time).unwrap();
+        let day = days_from_civil(2024, 1, 1) + day;
+
+        LocalTime {
+            day,
+            weekday: (day - days_from_civil(2024, 1, 1)).rem_euclid(7) as u8,
+            minute: hour as u32 * 60 + minute as u32,
+        }
+    }
+
+    #[test]
+    fn parses_times_and_weekdays() {
+        assert_eq!(parse_time("07:30"), Some((7, 30)));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_weekdays("*"), Some(vec![]));
+        assert_eq!(parse_weekdays("mon-fri"), Some(vec![0, 1, 2, 3, 4]));
+        assert_eq!(parse_weekdays("Sat,sun"), Some(vec![5, 6]));
+        assert_eq!(parse_weekdays("fri-mon"), Some(vec![0, 4, 5, 6]));
+        assert_eq!(parse_weekdays("mon-sun"), Some(vec![]));
+        assert_eq!(parse_weekdays("mon-fr"), None);
+        assert_eq!(format_weekdays(&[0, 4, 5, 6]), "mon,fri,sat,sun");
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+    }
+
+    #[test]
+    fn validates_the_fan_curve() {
+        let mut curve = BTreeMap::new();
+        curve.insert(40, 30.0);
+        curve.insert(80, 100.0);
+        assert!(
+            schedule("*", "08:00", ScheduleAction::FanCurve(curve.clone()))
+                .validate()
+                .is_ok()
+        );
+
+        curve.insert(90, 150.0);
+        assert!(schedule("*", "08:00", ScheduleAction::FanCurve(curve))
+            .validate()
+            .is_err());
+        assert!(
+            schedule("*", "08:00", ScheduleAction::FanCurve(BTreeMap::new()))
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn applies_the_schedule_in_effect() {
+        let schedules = vec![
+            schedule("mon-fri", "08:00", profile("performance")),
+            schedule("*", "20:00", profile("quiet")),
+            schedule("*", "22:30", ScheduleAction::PowerCap(100)),
+        ];
+        let mut state = ScheduleState::new();
+
+        // On startup the settings of the evening before apply
+        let due = state.update(&schedules, &at(0, "07:00"));
+        assert_eq!(due, vec![schedules[1].clone(), schedules[2].clone()]);
+
+        assert!(state.update(&schedules, &at(0, "07:59")).is_empty());
+        assert_eq!(
+            state.update(&schedules, &at(0, "08:00")),
+            vec![schedules[0].clone()]
+        );
+        assert!(state.update(&schedules, &at(0, "12:00")).is_empty());
+
+        // Only checked again on saturday evening
+        assert_eq!(
+            state.update(&schedules, &at(5, "21:00")),
+            vec![schedules[2].clone(), schedules[1].clone()]
+        );
+        assert_eq!(
+            state.update(&schedules, &at(5, "23:00")),
+            vec![schedules[2].clone()]
+        );
+
+        // Nothing happens on the weekend mornings
+        assert!(state.update(&schedules, &at(6, "09:00")).is_empty());
+    }
+
+    #[test]
+    fn catches_up_after_suspend_and_clock_changes() {
+        let schedules = vec![
+            schedule("*", "08:00", profile("performance")),
+            schedule("*", "20:00", profile("quiet")),
+        ];
+        let mut state = ScheduleState::new();
+
+        assert_eq!(
+            state.update(&schedules, &at(0, "10:00")),
+            vec![schedules[0].clone()]
+        );
+
+        // Suspended over night, only the last schedule matters
+        assert_eq!(
+            state.update(&schedules, &at(1, "07:00")),
+            vec![schedules[1].clone()]
+        );
+
+        assert_eq!(
+            state.update(&schedules, &at(1, "09:00")),
+            vec![schedules[0].clone()]
+        );
+
+        // The clock was set back past a schedule
+        assert_eq!(
+            state.update(&schedules, &at(0, "21:00")),
+            vec![schedules[1].clone()]
+        );
+        assert!(state.update(&schedules, &at(0, "21:01")).is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+use rhai::{module_resolvers::DummyModuleResolver, Dynamic, Engine, Map, Scope, AST};
+use serde::{Deserialize, Serialize};
+
+use crate::gpu_controller::GpuStats;
+use crate::schedule::LocalTime;
+
+// Runs every tick, so a script that doesn't finish quickly is considered broken
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// What a control script asks for on a tick. Settings it leaves out are controlled automatically.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ScriptOutput {
+    pub fan_pwm: Option<i64>,   // 0-255
+    pub power_cap: Option<i64>, // W
+    pub profile: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScriptStatus {
+    pub output: Option<ScriptOutput>, // `None` when the script failed
+    pub error: Option<String>,
+    pub previous_power_cap: Option<i64>, // W, the cap to go back to while the script sets it
+}
+
+/// A per-GPU control script, written in Rhai.
+///
+/// The script sees the current `stats`, the local `time` and a `state` map that is kept between ticks,
+/// and returns a map with any of `fan_pwm`, `power_cap` and `profile`. Scripts can't access files,
+/// processes or the network, and are stopped when they run for too long.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+}
+
+impl Script {
+    pub fn compile(source: &str) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        engine
+            .set_module_resolver(DummyModuleResolver::new())
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(32)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(4096)
+            .set_max_array_size(1024)
+            .set_max_map_size(256)
+            .on_print(|text| log::info!("Control script: {}", text))
+            .on_debug(|text, _, position| log::debug!("Control script {}: {}", position, text));
+
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+
+        let mut scope = Scope::new();
+        scope.push("state", Map::new());
+
+        Ok(Script { engine, ast, scope })
+    }
+
+    pub fn run(
+        &mut self,
+        stats: &GpuStats,
+        time: Option<LocalTime>,
+    ) -> Result<ScriptOutput, String> {
+        let stats = rhai::serde::to_dynamic(stats).map_err(|e| e.to_string())?;
+        self.scope.set_value("stats", stats);
+
+        let mut time_map = Map::new();
+        if let Some(time) = time {
+            time_map.insert("hour".into(), ((time.minute / 60) as i64).into());
+            time_map.insert("minute".into(), ((time.minute % 60) as i64).into());
+            time_map.insert("weekday".into(), (time.weekday as i64).into());
+        }
+        self.scope.set_value("time", time_map);
+
+        let value = self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut self.scope, &self.ast)
+            .map_err(|e| e.to_string())?;
+
+        parse_output(value)
+    }
+}
+
+fn parse_number(name: &str, value: &Dynamic) -> Result<f64, String> {
+    value
+        .as_int()
+        .map(|value| value as f64)
+        .or_else(|_| value.as_float())
+        .map_err(|_| format!("{} has to be a number, not {}", name, value.type_name()))
+}
+
+fn parse_output(value: Dynamic) -> Result<ScriptOutput, String> {
+    // Returning nothing leaves everything to the automatic control
+    if value.is_unit() {
+        return Ok(ScriptOutput::default());
+    }
+
+    let type_name = value.type_name();
+    let map = value
+        .try_cast::<Map>()
+        .ok_or_else(|| format!("the script has to return a map, not {}", type_name))?;
+
+    let mut output = ScriptOutput::default();
+
+    for (name, value) in map {
+        if value.is_unit() {
+            continue;
+        }
+
+        match name.as_str() {
+            "fan_pwm" => {
+                let pwm = parse_number("fan_pwm", &value)?.round();
+                if !(0.0..=255.0).contains(&pwm) {
+                    return Err(format!("fan_pwm {} is outside of 0-255", pwm));
+                }
+                output.fan_pwm = Some(pwm as i64);
+            }
+            "power_cap" => {
+                let cap = parse_number("power_cap", &value)?.round();
+                if cap < 1.0 {
+                    return Err(format!("power_cap {} has to be at least 1W", cap));
+                }
+                output.power_cap = Some(cap as i64);
+            }
+            "profile" => {
+                let type_name = value.type_name();
+                let profile = value
+                    .into_string()
+                    .map_err(|_| format!("profile has to be a string, not {}", type_name))?;
+                output.profile = Some(profile);
+            }
+            name => return Err(format!("unknown output {}", name)),
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> GpuStats {
+        GpuStats {
+            junction_temp: Some(85),
+            mem_temp: Some(90),
+            gpu_usage: Some(40),
+            ..GpuStats::default()
+        }
+    }
+
+    fn run(source: &str) -> Result<ScriptOutput, String> {
+        Script::compile(source)?.run(&stats(), None)
+    }
+
+    #[test]
+    fn returns_settings_from_stats() {
+        let output = run(r#"
+            let temp = max(stats.junction_temp, stats.mem_temp) + 5;
+            #{ fan_pwm: temp * 2, power_cap: 150.4, profile: "hot" }
+        "#)
+        .unwrap();
+
+        assert_eq!(
+            output,
+            ScriptOutput {
+                fan_pwm: Some(190),
+                power_cap: Some(150),
+                profile: Some("hot".to_string()),
+            }
+        );
+
+        // Missing sensors are () and nothing means automatic control
+        assert_eq!(
+            run("if stats.gpu_temp == () { () } else { #{ fan_pwm: 0 } }").unwrap(),
+            ScriptOutput::default()
+        );
+    }
+
+    #[test]
+    fn keeps_state_between_ticks() {
+        let mut script = Script::compile(
+            r#"
+            state.ticks = (state.ticks ?? 0) + 1;
+            #{ fan_pwm: state.ticks }
+        "#,
+        )
+        .unwrap();
+
+        for ticks in 1..=3 {
+            assert_eq!(script.run(&stats(), None).unwrap().fan_pwm, Some(ticks));
+        }
+    }
+
+    #[test]
+    fn reports_broken_scripts() {
+        assert!(run("#{ fan_pwm: ").is_err());
+        assert!(run("#{ fan_pwm: 300 }").is_err());
+        assert!(run("#{ fan_speed: 100 }").is_err());
+        assert!(run("#{ profile: 1 }").is_err());
+        assert!(run("42").is_err());
+        assert!(run("stats.gpu_usage / 0").is_err());
+
+        // Endless loops and the file system are off limits
+        assert!(run("loop {}").is_err());
+        assert!(run(r#"import "/etc/passwd" as x; ()"#).is_err());
+    }
+}
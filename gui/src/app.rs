@@ -14,12 +14,14 @@ use std::{
 
 use apply_revealer::ApplyRevealer;
 use daemon::daemon_connection::DaemonConnection;
+use daemon::fan_control::FanSpeedUnit;
 use daemon::gpu_controller::GpuStats;
+use daemon::group::GroupSetting;
 use daemon::DaemonError;
 use gtk::*;
 
 use header::Header;
-use root_stack::RootStack;
+use root_stack::{ClocksSettings, RootStack};
 
 #[derive(Clone)]
 pub struct App {
@@ -85,6 +87,39 @@ impl App {
             });
         }
 
+        {
+            let current_gpu_id = current_gpu_id.clone();
+            let app = self.clone();
+
+            self.header.connect_profile_selected(move |name| {
+                let gpu_id = current_gpu_id.load(Ordering::SeqCst);
+                log::info!("Activating profile {}", name);
+
+                if let Err(e) = app.daemon_connection.activate_profile(gpu_id, &name) {
+                    show_error(&format!("Failed to activate profile: {}", e));
+                }
+
+                app.set_info(gpu_id);
+            });
+        }
+
+        {
+            let current_gpu_id = current_gpu_id.clone();
+            let app = self.clone();
+
+            self.header.connect_new_profile_clicked(move || {
+                let gpu_id = current_gpu_id.load(Ordering::SeqCst);
+
+                if let Some(name) = ask_profile_name(&app.window) {
+                    if let Err(e) = app.daemon_connection.create_profile(gpu_id, &name) {
+                        show_error(&format!("Failed to create profile: {}", e));
+                    }
+
+                    app.set_info(gpu_id);
+                }
+            });
+        }
+
         let gpus = self.daemon_connection.get_gpus()?;
 
         self.header.set_gpus(gpus);
@@ -127,6 +162,24 @@ impl App {
             })
         }
 
+        {
+            let app = self.clone();
+            let current_gpu_id = current_gpu_id.clone();
+
+            self.root_stack
+                .thermals_page
+                .connect_calibrate_clicked(move || {
+                    let gpu_id = current_gpu_id.load(Ordering::SeqCst);
+
+                    log::info!("Starting fan calibration");
+
+                    if let Err(e) = app.daemon_connection.start_fan_calibration(gpu_id) {
+                        log::error!("Failed to start fan calibration: {}", e);
+                        show_error(&format!("Failed to start fan calibration: {}", e));
+                    }
+                });
+        }
+
         // Apply settings
         {
             let current_gpu_id = current_gpu_id.clone();
@@ -136,6 +189,7 @@ impl App {
                 log::info!("Applying settings");
 
                 let gpu_id = current_gpu_id.load(Ordering::SeqCst);
+                let group = app.apply_revealer.get_group();
 
                 {
                     let thermals_settings = app.root_stack.thermals_page.get_thermals_settings();
@@ -150,39 +204,86 @@ impl App {
                             .expect("Failed to start fan control");
                     }
 
-                    app.daemon_connection
-                        .set_fan_curve(gpu_id, thermals_settings.curve)
-                        .expect("Failed to set fan curve");
-                }
+                    if let Err(e) = app.daemon_connection.set_fan_curve(
+                        gpu_id,
+                        thermals_settings.curve.clone(),
+                        thermals_settings.curve_options,
+                    ) {
+                        log::error!("Failed to set fan curve: {}", e);
+                        show_error(&format!("Failed to set fan curve: {}", e));
+                    }
 
-                if let Some(clocks_settings) = app.root_stack.oc_page.get_clocks() {
                     app.daemon_connection
-                        .set_gpu_max_power_state(
-                            gpu_id,
-                            clocks_settings.gpu_clock,
-                            Some(clocks_settings.gpu_voltage),
-                        )
-                        .expect("Failed to set GPU clockspeed/voltage");
+                        .set_fan_smoothing(gpu_id, thermals_settings.smoothing)
+                        .expect("Failed to set fan smoothing");
 
                     app.daemon_connection
-                        .set_vram_max_clock(gpu_id, clocks_settings.vram_clock)
-                        .expect("Failed to set VRAM Clock");
+                        .set_fan_temp_source(gpu_id, thermals_settings.temp_source)
+                        .expect("Failed to set fan temperature source");
 
                     app.daemon_connection
-                        .commit_gpu_power_states(gpu_id)
-                        .expect("Failed to commit power states");
+                        .set_fan_limits(gpu_id, thermals_settings.limits)
+                        .expect("Failed to set fan limits");
+
+                    if let Err(e) = app
+                        .daemon_connection
+                        .set_fan_mode(gpu_id, thermals_settings.mode)
+                    {
+                        log::error!("Failed to set fan mode: {}", e);
+                        show_error(&format!("Failed to set fan mode: {}", e));
+                    }
+
+                    if let Some(group) = &group {
+                        let mut settings = vec![GroupSetting::FanControl(
+                            !thermals_settings.automatic_fan_control_enabled,
+                        )];
+                        // Group curves are in %, a curve in RPM only fits this card
+                        if thermals_settings.curve_options.unit == FanSpeedUnit::Percent {
+                            settings.push(GroupSetting::FanCurve(thermals_settings.curve));
+                        }
+
+                        app.apply_group_settings(group, settings);
+                    }
+                }
+
+                if let Some(clocks_settings) = app.root_stack.oc_page.get_clocks() {
+                    if let Some(group) = &group {
+                        app.apply_group_settings(
+                            group,
+                            vec![
+                                GroupSetting::GpuMaxClock(
+                                    clocks_settings.gpu_clock,
+                                    clocks_settings.gpu_voltage,
+                                ),
+                                GroupSetting::VramMaxClock(clocks_settings.vram_clock),
+                            ],
+                        );
+                    }
+
+                    if let Err(e) = app.apply_clocks(gpu_id, clocks_settings) {
+                        log::error!("Failed to apply clocks: {}", e);
+                        show_error(&format!("Failed to apply clocks: {}", e));
+                    }
                 }
 
                 if let Some(profile) = app.root_stack.oc_page.get_power_profile() {
                     app.daemon_connection
-                        .set_power_profile(gpu_id, profile)
+                        .set_power_profile(gpu_id, profile.clone())
                         .expect("Failed to set power profile");
+
+                    if let Some(group) = &group {
+                        app.apply_group_settings(group, vec![GroupSetting::PowerProfile(profile)]);
+                    }
                 }
 
                 if let Some(cap) = app.root_stack.oc_page.get_power_cap() {
                     app.daemon_connection
                         .set_power_cap(gpu_id, cap)
                         .expect("Failed to set power cap");
+
+                    if let Some(group) = &group {
+                        app.apply_group_settings(group, vec![GroupSetting::PowerCap(cap)]);
+                    }
                 }
 
                 app.set_info(gpu_id);
@@ -194,6 +295,33 @@ impl App {
         Ok(gtk::main())
     }
 
+    fn apply_clocks(
+        &self,
+        gpu_id: u32,
+        clocks_settings: ClocksSettings,
+    ) -> Result<(), DaemonError> {
+        self.daemon_connection
+            .apply_clocks(gpu_id, clocks_settings)?;
+
+        self.daemon_connection.commit_gpu_power_states(gpu_id)
+    }
+
+    /// Saves settings for the whole group, which applies them to all of its GPUs
+    fn apply_group_settings(&self, group: &str, settings: Vec<GroupSetting>) {
+        for setting in settings {
+            if let Err(e) = self
+                .daemon_connection
+                .set_group_setting(group, None, setting)
+            {
+                log::error!("Failed to apply settings to group {}: {}", group, e);
+                show_error(&format!(
+                    "Failed to apply settings to group {}: {}",
+                    group, e
+                ));
+            }
+        }
+    }
+
     fn set_info(&self, gpu_id: u32) {
         let gpu_info = self.daemon_connection.get_gpu_info(gpu_id).unwrap();
         log::trace!("Setting info {:?}", &gpu_info);
@@ -208,6 +336,25 @@ impl App {
             .oc_page
             .set_power_profile(&gpu_info.power_profile);
 
+        log::trace!("Setting profiles");
+        match self.daemon_connection.get_profiles(gpu_id) {
+            Ok((profiles, active)) => self.header.set_profiles(&profiles, active.as_deref()),
+            Err(e) => log::error!("Failed to get profiles: {:?}", e),
+        }
+
+        log::trace!("Setting groups");
+        match self.daemon_connection.get_groups() {
+            Ok(groups) => {
+                let names: Vec<String> = groups
+                    .into_iter()
+                    .filter(|(_, members)| members.contains(&gpu_id))
+                    .map(|(group, _)| group.name)
+                    .collect();
+                self.apply_revealer.set_groups(&names);
+            }
+            Err(e) => log::error!("Failed to get groups: {:?}", e),
+        }
+
         log::trace!("Setting fan control info");
         match self.daemon_connection.get_fan_control(gpu_id) {
             Ok(fan_control_info) => self
@@ -273,6 +420,51 @@ impl App {
     }
 }
 
+fn show_error(message: &str) {
+    let diag = MessageDialog::new(
+        None::<&Window>,
+        DialogFlags::empty(),
+        MessageType::Error,
+        ButtonsType::Ok,
+        message,
+    );
+    diag.run();
+    diag.hide();
+}
+
+fn ask_profile_name(parent: &Window) -> Option<String> {
+    let diag = Dialog::with_buttons(
+        Some("New profile"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[
+            ("Cancel", ResponseType::Cancel),
+            ("Create", ResponseType::Ok),
+        ],
+    );
+    diag.set_default_response(ResponseType::Ok);
+
+    let entry = Entry::new();
+    entry.set_placeholder_text(Some("Profile name"));
+    entry.set_activates_default(true);
+    entry.set_margin_start(10);
+    entry.set_margin_end(10);
+    entry.set_margin_top(10);
+    entry.set_margin_bottom(10);
+
+    diag.get_content_area().add(&entry);
+    diag.show_all();
+
+    let response = diag.run();
+    let name = entry.get_text().trim().to_string();
+    diag.hide();
+
+    match response {
+        ResponseType::Ok if !name.is_empty() => Some(name),
+        _ => None,
+    }
+}
+
 enum GuiUpdateMsg {
     // FanControlInfo(FanControlInfo),
     GpuStats(GpuStats),
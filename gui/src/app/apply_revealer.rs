@@ -4,6 +4,7 @@ use gtk::*;
 pub struct ApplyRevealer {
     pub container: Revealer,
     apply_button: Button,
+    target_combo: ComboBoxText,
 }
 
 impl ApplyRevealer {
@@ -12,15 +13,27 @@ impl ApplyRevealer {
 
         container.set_transition_duration(150);
 
+        let root_box = Box::new(Orientation::Horizontal, 5);
+
+        // Where the settings go, the current GPU or every member of one of its groups
+        let target_combo = ComboBoxText::new();
+
+        target_combo.set_no_show_all(true);
+
+        root_box.pack_start(&target_combo, false, false, 0);
+
         let apply_button = Button::new();
 
         apply_button.set_label("Apply");
 
-        container.add(&apply_button);
+        root_box.pack_start(&apply_button, true, true, 0);
+
+        container.add(&root_box);
 
         Self {
             container,
             apply_button,
+            target_combo,
         }
     }
 
@@ -32,6 +45,28 @@ impl ApplyRevealer {
         self.container.set_reveal_child(false);
     }
 
+    /// Sets the groups the current GPU is a member of
+    pub fn set_groups(&self, groups: &[String]) {
+        self.target_combo.remove_all();
+
+        self.target_combo.append(None, "This GPU");
+
+        for name in groups {
+            self.target_combo
+                .append(Some(name), &format!("Group {}", name));
+        }
+
+        self.target_combo.set_active(Some(0));
+        self.target_combo.set_visible(!groups.is_empty());
+    }
+
+    /// The group to apply the settings to, `None` for only the current GPU
+    pub fn get_group(&self) -> Option<String> {
+        self.target_combo
+            .get_active_id()
+            .map(|name| name.to_string())
+    }
+
     pub fn connect_apply_button_clicked<F: Fn() + 'static>(&self, f: F) {
         self.apply_button.connect_clicked(move |_| {
             f();
@@ -1,13 +1,18 @@
 use gtk::prelude::{ComboBoxExtManual, ObjectExt};
 use gtk::*;
 use pango::EllipsizeMode;
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 #[derive(Clone)]
 pub struct Header {
     pub container: HeaderBar,
     gpu_selector: ComboBoxText,
     switcher: StackSwitcher,
+    profile_selector: ComboBoxText,
+    new_profile_button: Button,
+    updating_profiles: Rc<Cell<bool>>, // Set while the profile list is refilled, so that it doesn't activate a profile
 }
 
 impl Header {
@@ -24,10 +29,22 @@ impl Header {
         let switcher = StackSwitcher::new();
         container.pack_start(&switcher);
 
+        let new_profile_button =
+            Button::from_icon_name(Some("list-add-symbolic"), IconSize::Button);
+        new_profile_button.set_tooltip_text(Some("Save the current settings as a new profile"));
+        container.pack_end(&new_profile_button);
+
+        let profile_selector = ComboBoxText::new();
+        profile_selector.set_tooltip_text(Some("Settings profile"));
+        container.pack_end(&profile_selector);
+
         Header {
             container,
             gpu_selector,
             switcher,
+            profile_selector,
+            new_profile_button,
+            updating_profiles: Rc::new(Cell::new(false)),
         }
     }
 
@@ -56,4 +73,34 @@ impl Header {
             f(selected_id.parse().unwrap());
         });
     }
+
+    pub fn set_profiles(&self, profiles: &[String], active: Option<&str>) {
+        self.updating_profiles.set(true);
+
+        self.profile_selector.remove_all();
+        for name in profiles {
+            self.profile_selector.append(Some(name), name);
+        }
+        self.profile_selector.set_active_id(active);
+
+        self.updating_profiles.set(false);
+    }
+
+    pub fn connect_profile_selected<F: Fn(String) + 'static>(&self, f: F) {
+        let updating_profiles = self.updating_profiles.clone();
+
+        self.profile_selector
+            .connect_changed(move |profile_selector| {
+                if updating_profiles.get() {
+                    return;
+                }
+                if let Some(name) = profile_selector.get_active_id() {
+                    f(name.to_string());
+                }
+            });
+    }
+
+    pub fn connect_new_profile_clicked<F: Fn() + 'static>(&self, f: F) {
+        self.new_profile_button.connect_clicked(move |_| f());
+    }
 }
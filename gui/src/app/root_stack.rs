@@ -4,6 +4,8 @@ mod thermals_page;
 
 use gtk::*;
 
+pub use oc_page::ClocksSettings;
+
 use info_page::InformationPage;
 use oc_page::OcPage;
 use thermals_page::ThermalsPage;
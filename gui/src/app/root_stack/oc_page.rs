@@ -2,16 +2,18 @@ mod clocks_frame;
 mod power_cap_frame;
 mod power_profile_frame;
 mod stats_grid;
+mod vddc_curve_frame;
 mod warning_frame;
 
-use clocks_frame::ClocksSettings;
-use daemon::gpu_controller::{GpuInfo, GpuStats, PowerProfile};
+pub use clocks_frame::ClocksSettings;
+use daemon::gpu_controller::{ClocksTable, GpuInfo, GpuStats, PowerProfile};
 use gtk::*;
 
 use clocks_frame::ClocksFrame;
 use power_cap_frame::PowerCapFrame;
 use power_profile_frame::PowerProfileFrame;
 use stats_grid::StatsGrid;
+use vddc_curve_frame::VddcCurveFrame;
 use warning_frame::WarningFrame;
 
 #[derive(Clone)]
@@ -21,6 +23,7 @@ pub struct OcPage {
     power_profile_frame: PowerProfileFrame,
     power_cap_frame: PowerCapFrame,
     clocks_frame: ClocksFrame,
+    vddc_curve_frame: VddcCurveFrame,
     pub warning_frame: WarningFrame,
 }
 
@@ -48,11 +51,16 @@ impl OcPage {
 
         container.pack_start(&clocks_frame.container, false, true, 0);
 
+        let vddc_curve_frame = VddcCurveFrame::new();
+
+        container.pack_start(&vddc_curve_frame.container, false, true, 0);
+
         Self {
             container,
             stats_grid,
             power_profile_frame,
             clocks_frame,
+            vddc_curve_frame,
             warning_frame,
             power_cap_frame,
         }
@@ -82,6 +90,12 @@ impl OcPage {
                 f();
             })
         }
+        {
+            let f = f.clone();
+            self.vddc_curve_frame.connect_curve_changed(move || {
+                f();
+            })
+        }
         {
             self.power_cap_frame.connect_cap_changed(move || {
                 f();
@@ -115,13 +129,40 @@ impl OcPage {
             None => self.clocks_frame.hide(),
         }
 
+        match &info.clocks_table {
+            Some(ClocksTable::New(clocks_table)) if clocks_table.vddc_curve.is_some() => {
+                self.vddc_curve_frame.show();
+                self.vddc_curve_frame.set_curve(clocks_table);
+            }
+            _ => self.vddc_curve_frame.hide(),
+        }
+
         self.power_cap_frame
-            .set_data(info.power_cap, info.power_cap_max);
+            .set_data(info.power_cap, info.power_cap_min, info.power_cap_max);
     }
 
     pub fn get_clocks(&self) -> Option<ClocksSettings> {
         match self.clocks_frame.get_visibility() {
-            true => Some(self.clocks_frame.get_settings()),
+            true => {
+                let mut settings = self.clocks_frame.get_settings();
+
+                // The last curve point is the max clock, which is what groups get
+                settings.vddc_curve = self.get_vddc_curve();
+                if let Some((clockspeed, _)) =
+                    settings.vddc_curve.as_ref().and_then(|curve| curve.last())
+                {
+                    settings.gpu_clock = *clockspeed;
+                }
+
+                Some(settings)
+            }
+            false => None,
+        }
+    }
+
+    pub fn get_vddc_curve(&self) -> Option<Vec<(i64, i64)>> {
+        match self.vddc_curve_frame.get_visibility() {
+            true => Some(self.vddc_curve_frame.get_curve()),
             false => None,
         }
     }
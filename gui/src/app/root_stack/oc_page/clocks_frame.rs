@@ -1,18 +1,29 @@
+pub use daemon::gpu_controller::ClocksSettings;
 use daemon::gpu_controller::ClocksTable;
 use gtk::*;
 
-pub struct ClocksSettings {
-    pub gpu_clock: i64,
-    pub vram_clock: i64,
-    pub gpu_voltage: i64,
-}
+// Used when the kernel doesn't report the allowed voltage offset range
+const DEFAULT_VOLTAGE_OFFSET_RANGE: (i64, i64) = (-200, 0);
 
 #[derive(Clone)]
 pub struct ClocksFrame {
     pub container: Frame,
     gpu_clock_adjustment: Adjustment,
+    gpu_clock_label: Label,
+    gpu_clock_scale: Scale,
+    gpu_min_clock_adjustment: Adjustment,
+    gpu_min_clock_label: Label,
+    gpu_min_clock_scale: Scale,
     gpu_voltage_adjustment: Adjustment,
+    gpu_voltage_label: Label,
+    gpu_voltage_scale: Scale,
+    voltage_offset_adjustment: Adjustment,
+    voltage_offset_label: Label,
+    voltage_offset_scale: Scale,
     vram_clock_adjustment: Adjustment,
+    vram_min_clock_adjustment: Adjustment,
+    vram_min_clock_label: Label,
+    vram_min_clock_scale: Scale,
     apply_button: Button,
 }
 
@@ -27,63 +38,123 @@ impl ClocksFrame {
 
         container.set_label_widget(Some(&{
             let label = Label::new(None);
-            label.set_markup("<span font_desc='11'><b>Maximum Clocks</b></span>");
+            label.set_markup("<span font_desc='11'><b>Clocks</b></span>");
             label
         }));
         container.set_label_align(0.2, 0.0);
 
         let gpu_clock_adjustment = Adjustment::new(0.0, 0.0, 0.0, 1.0, 0.0, 0.0);
 
+        let gpu_min_clock_adjustment = Adjustment::new(0.0, 0.0, 0.0, 1.0, 0.0, 0.0);
+
         let gpu_voltage_adjustment = Adjustment::new(1.0, 0.0, 0.0, 0.05, 0.0, 0.0);
 
+        let voltage_offset_adjustment = Adjustment::new(0.0, 0.0, 0.0, 1.0, 0.0, 0.0);
+
         let vram_clock_adjustment = Adjustment::new(0.0, 0.0, 0.0, 1.0, 0.0, 0.0);
 
+        let vram_min_clock_adjustment = Adjustment::new(0.0, 0.0, 0.0, 1.0, 0.0, 0.0);
+
         let root_grid = Grid::new();
 
         root_grid.set_row_spacing(5);
         root_grid.set_column_spacing(10);
 
+        let gpu_clock_label = Label::new(Some("GPU Clock (MHz)"));
+
+        let gpu_clock_scale = Scale::new(Orientation::Horizontal, Some(&gpu_clock_adjustment));
+
+        let gpu_min_clock_label = Label::new(Some("GPU Min Clock (MHz)"));
+
+        let gpu_min_clock_scale =
+            Scale::new(Orientation::Horizontal, Some(&gpu_min_clock_adjustment));
+
+        let gpu_voltage_label = Label::new(Some("GPU Voltage (V)"));
+
+        let gpu_voltage_scale = Scale::new(Orientation::Horizontal, Some(&gpu_voltage_adjustment));
+
+        let voltage_offset_label = Label::new(Some("GPU Voltage Offset (mV)"));
+
+        let voltage_offset_scale =
+            Scale::new(Orientation::Horizontal, Some(&voltage_offset_adjustment));
+
+        let vram_min_clock_label = Label::new(Some("VRAM Min Clock (MHz)"));
+
+        let vram_min_clock_scale =
+            Scale::new(Orientation::Horizontal, Some(&vram_min_clock_adjustment));
+
         {
-            let gpu_clock_scale = Scale::new(Orientation::Horizontal, Some(&gpu_clock_adjustment));
+            gpu_min_clock_scale.set_value_pos(PositionType::Right);
+
+            root_grid.attach(&gpu_min_clock_scale, 1, 0, 1, 1);
+
+            root_grid.attach_next_to(
+                &gpu_min_clock_label,
+                Some(&gpu_min_clock_scale),
+                PositionType::Left,
+                1,
+                1,
+            );
 
             gpu_clock_scale.set_hexpand(true); // Affects the grid column and all scales
 
             gpu_clock_scale.set_value_pos(PositionType::Right);
 
-            root_grid.attach(&gpu_clock_scale, 1, 0, 1, 1);
+            root_grid.attach(&gpu_clock_scale, 1, 1, 1, 1);
 
             root_grid.attach_next_to(
-                &Label::new(Some("GPU Clock (MHz)")),
+                &gpu_clock_label,
                 Some(&gpu_clock_scale),
                 PositionType::Left,
                 1,
                 1,
             );
 
-            let gpu_voltage_scale =
-                Scale::new(Orientation::Horizontal, Some(&gpu_voltage_adjustment));
-
             gpu_voltage_scale.set_value_pos(PositionType::Right);
 
             gpu_voltage_scale.set_digits(3);
             gpu_voltage_scale.set_round_digits(3);
 
-            root_grid.attach(&gpu_voltage_scale, 1, 1, 1, 1);
+            root_grid.attach(&gpu_voltage_scale, 1, 2, 1, 1);
 
             root_grid.attach_next_to(
-                &Label::new(Some("GPU Voltage (V)")),
+                &gpu_voltage_label,
                 Some(&gpu_voltage_scale),
                 PositionType::Left,
                 1,
                 1,
             );
 
+            voltage_offset_scale.set_value_pos(PositionType::Right);
+
+            root_grid.attach(&voltage_offset_scale, 1, 3, 1, 1);
+
+            root_grid.attach_next_to(
+                &voltage_offset_label,
+                Some(&voltage_offset_scale),
+                PositionType::Left,
+                1,
+                1,
+            );
+
+            vram_min_clock_scale.set_value_pos(PositionType::Right);
+
+            root_grid.attach(&vram_min_clock_scale, 1, 4, 1, 1);
+
+            root_grid.attach_next_to(
+                &vram_min_clock_label,
+                Some(&vram_min_clock_scale),
+                PositionType::Left,
+                1,
+                1,
+            );
+
             let vram_clock_scale =
                 Scale::new(Orientation::Horizontal, Some(&vram_clock_adjustment));
 
             vram_clock_scale.set_value_pos(PositionType::Right);
 
-            root_grid.attach(&vram_clock_scale, 1, 2, 1, 1);
+            root_grid.attach(&vram_clock_scale, 1, 5, 1, 1);
 
             root_grid.attach_next_to(
                 &Label::new(Some("VRAM Clock (MHz)")),
@@ -99,7 +170,7 @@ impl ClocksFrame {
         {
             apply_button.set_label("Reset");
 
-            root_grid.attach(&apply_button, 0, 3, 2, 1);
+            root_grid.attach(&apply_button, 0, 6, 2, 1);
 
             container.add(&root_grid);
         }
@@ -107,8 +178,21 @@ impl ClocksFrame {
         Self {
             container,
             gpu_clock_adjustment,
+            gpu_clock_label,
+            gpu_clock_scale,
+            gpu_min_clock_adjustment,
+            gpu_min_clock_label,
+            gpu_min_clock_scale,
             gpu_voltage_adjustment,
+            gpu_voltage_label,
+            gpu_voltage_scale,
+            voltage_offset_adjustment,
+            voltage_offset_label,
+            voltage_offset_scale,
             vram_clock_adjustment,
+            vram_min_clock_adjustment,
+            vram_min_clock_label,
+            vram_min_clock_scale,
             apply_button,
         }
     }
@@ -120,6 +204,21 @@ impl ClocksFrame {
     pub fn set_clocks(&self, clocks_table: &ClocksTable) {
         match clocks_table {
             ClocksTable::Old(clocks_table) => {
+                self.gpu_clock_label.set_visible(true);
+                self.gpu_clock_scale.set_visible(true);
+
+                self.gpu_voltage_label.set_visible(true);
+                self.gpu_voltage_scale.set_visible(true);
+
+                self.gpu_min_clock_label.set_visible(false);
+                self.gpu_min_clock_scale.set_visible(false);
+
+                self.voltage_offset_label.set_visible(false);
+                self.voltage_offset_scale.set_visible(false);
+
+                self.vram_min_clock_label.set_visible(false);
+                self.vram_min_clock_scale.set_visible(false);
+
                 self.gpu_clock_adjustment
                     .set_lower(clocks_table.gpu_clocks_range.0 as f64);
                 self.gpu_clock_adjustment
@@ -150,29 +249,83 @@ impl ClocksFrame {
                     .set_value(*vram_clockspeed as f64);
             }
             ClocksTable::New(clocks_table) => {
+                // The voltage is set through the curve points or the offset instead
+                self.gpu_voltage_label.set_visible(false);
+                self.gpu_voltage_scale.set_visible(false);
+
+                // The first and last points of the voltage curve are the min and max clock
+                let has_curve = clocks_table.vddc_curve.is_some();
+
+                self.gpu_clock_label.set_visible(!has_curve);
+                self.gpu_clock_scale.set_visible(!has_curve);
+
+                self.gpu_min_clock_label.set_visible(!has_curve);
+                self.gpu_min_clock_scale.set_visible(!has_curve);
+
                 self.gpu_clock_adjustment
                     .set_lower(clocks_table.gpu_clocks_range.0 as f64);
                 self.gpu_clock_adjustment
                     .set_upper(clocks_table.gpu_clocks_range.1 as f64);
 
-                /* self.gpu_voltage_adjustment
-                   .set_lower(clocks_table.voltage_range.0 as f64 / 1000.0);
-                self.gpu_voltage_adjustment
-                   .set_upper(clocks_table.voltage_range.1 as f64 / 1000.0);*/
+                self.gpu_min_clock_adjustment
+                    .set_lower(clocks_table.gpu_clocks_range.0 as f64);
+                self.gpu_min_clock_adjustment
+                    .set_upper(clocks_table.gpu_clocks_range.1 as f64);
 
                 self.vram_clock_adjustment
                     .set_lower(clocks_table.mem_clocks_range.0 as f64);
                 self.vram_clock_adjustment
                     .set_upper(clocks_table.mem_clocks_range.1 as f64);
 
+                self.gpu_min_clock_adjustment
+                    .set_value(clocks_table.current_gpu_clocks.0 as f64);
+
                 self.gpu_clock_adjustment
                     .set_value(clocks_table.current_gpu_clocks.1 as f64);
 
-                // self.gpu_voltage_adjustment
-                // .set_value(*clocks_table.gpu_voltage as f64 / 1000.0);
-
                 self.vram_clock_adjustment
                     .set_value(clocks_table.current_max_mem_clock as f64);
+
+                match clocks_table.current_min_mem_clock {
+                    Some(min_mem_clock) => {
+                        self.vram_min_clock_label.set_visible(true);
+                        self.vram_min_clock_scale.set_visible(true);
+
+                        // The current minimum can be below the overdrive range
+                        self.vram_min_clock_adjustment
+                            .set_lower(clocks_table.mem_clocks_range.0.min(min_mem_clock) as f64);
+                        self.vram_min_clock_adjustment
+                            .set_upper(clocks_table.mem_clocks_range.1 as f64);
+
+                        self.vram_min_clock_adjustment
+                            .set_value(min_mem_clock as f64);
+                    }
+                    None => {
+                        self.vram_min_clock_label.set_visible(false);
+                        self.vram_min_clock_scale.set_visible(false);
+                    }
+                }
+
+                match clocks_table.voltage_offset {
+                    Some(voltage_offset) => {
+                        self.voltage_offset_label.set_visible(true);
+                        self.voltage_offset_scale.set_visible(true);
+
+                        let (min_offset, max_offset) = clocks_table
+                            .voltage_offset_range
+                            .unwrap_or(DEFAULT_VOLTAGE_OFFSET_RANGE);
+
+                        self.voltage_offset_adjustment.set_lower(min_offset as f64);
+                        self.voltage_offset_adjustment.set_upper(max_offset as f64);
+
+                        self.voltage_offset_adjustment
+                            .set_value(voltage_offset as f64);
+                    }
+                    None => {
+                        self.voltage_offset_label.set_visible(false);
+                        self.voltage_offset_scale.set_visible(false);
+                    }
+                }
             }
         }
     }
@@ -182,12 +335,34 @@ impl ClocksFrame {
 
         let vram_clock = self.vram_clock_adjustment.get_value() as i64;
 
-        let gpu_voltage = (self.gpu_voltage_adjustment.get_value() * 1000.0) as i64;
+        let gpu_voltage = match self.gpu_voltage_scale.get_visible() {
+            true => Some((self.gpu_voltage_adjustment.get_value() * 1000.0) as i64),
+            false => None,
+        };
+
+        let gpu_min_clock = match self.gpu_min_clock_scale.get_visible() {
+            true => Some(self.gpu_min_clock_adjustment.get_value() as i64),
+            false => None,
+        };
+
+        let vram_min_clock = match self.vram_min_clock_scale.get_visible() {
+            true => Some(self.vram_min_clock_adjustment.get_value() as i64),
+            false => None,
+        };
+
+        let voltage_offset = match self.voltage_offset_scale.get_visible() {
+            true => Some(self.voltage_offset_adjustment.get_value() as i64),
+            false => None,
+        };
 
         ClocksSettings {
             gpu_clock,
             vram_clock,
             gpu_voltage,
+            gpu_min_clock,
+            vram_min_clock,
+            voltage_offset,
+            vddc_curve: None, // Filled in from the voltage curve frame
         }
     }
 
@@ -211,10 +386,31 @@ impl ClocksFrame {
             });
         }
         {
+            let f = f.clone();
             self.gpu_voltage_adjustment.connect_value_changed(move |_| {
                 f();
             });
         }
+        {
+            let f = f.clone();
+            self.gpu_min_clock_adjustment
+                .connect_value_changed(move |_| {
+                    f();
+                });
+        }
+        {
+            let f = f.clone();
+            self.vram_min_clock_adjustment
+                .connect_value_changed(move |_| {
+                    f();
+                });
+        }
+        {
+            self.voltage_offset_adjustment
+                .connect_value_changed(move |_| {
+                    f();
+                });
+        }
     }
 
     pub fn hide(&self) {
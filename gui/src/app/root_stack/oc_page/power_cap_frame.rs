@@ -53,7 +53,15 @@ impl PowerCapFrame {
         }
     }
 
-    pub fn set_data(&self, power_cap: Option<i64>, power_cap_max: Option<i64>) {
+    pub fn set_data(
+        &self,
+        power_cap: Option<i64>,
+        power_cap_min: Option<i64>,
+        power_cap_max: Option<i64>,
+    ) {
+        if let Some(power_cap_min) = power_cap_min {
+            self.adjustment.set_lower(power_cap_min as f64);
+        }
         if let Some(power_cap_max) = power_cap_max {
             self.adjustment.set_upper(power_cap_max as f64);
         } else {
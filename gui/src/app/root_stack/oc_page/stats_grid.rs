@@ -168,11 +168,21 @@ impl StatsGrid {
             stats.voltage.unwrap_or_else(|| 0) as f64 / 1000f64
         ));
 
-        self.power_usage_label.set_markup(&format!(
+        let mut power_usage = format!(
             "<b>{}/{}W</b>",
             stats.power_avg.unwrap_or_else(|| 0),
             stats.power_cap.unwrap_or_else(|| 0)
-        ));
+        );
+        if let Some(power_guard) = &stats.power_guard {
+            if let Some(reason) = &power_guard.reason {
+                power_usage.push_str(&format!(
+                    " (lowered from {}W, {})",
+                    power_guard.configured_cap,
+                    glib::markup_escape_text(&reason.to_string())
+                ));
+            }
+        }
+        self.power_usage_label.set_markup(&power_usage);
         self.gpu_temperature_label
             .set_markup(&format!("<b>{}°C</b>", stats.gpu_temp.unwrap_or_default()));
         self.gpu_usage_label
@@ -0,0 +1,135 @@
+use daemon::gpu_controller::ClocksTableNew;
+use gtk::*;
+
+#[derive(Clone)]
+pub struct VddcCurveFrame {
+    pub container: Frame,
+    points: Vec<(Adjustment, Adjustment)>, //<(clockspeed, voltage)>
+}
+
+impl VddcCurveFrame {
+    pub fn new() -> Self {
+        let container = Frame::new(None);
+
+        container.set_margin_start(10);
+        container.set_margin_end(10);
+
+        container.set_shadow_type(ShadowType::None);
+
+        container.set_label_widget(Some(&{
+            let label = Label::new(None);
+            label.set_markup("<span font_desc='11'><b>Voltage Curve</b></span>");
+            label
+        }));
+        container.set_label_align(0.2, 0.0);
+
+        let root_grid = Grid::new();
+
+        root_grid.set_row_spacing(5);
+        root_grid.set_column_spacing(10);
+
+        root_grid.attach(&Label::new(Some("Clock (MHz)")), 1, 0, 1, 1);
+        root_grid.attach(&Label::new(Some("Voltage (V)")), 2, 0, 1, 1);
+
+        let mut points = Vec::new();
+
+        // Vega20 and Navi10 expose exactly 3 curve points
+        for i in 0..3 {
+            let clock_adjustment = Adjustment::new(0.0, 0.0, 0.0, 1.0, 0.0, 0.0);
+
+            let voltage_adjustment = Adjustment::new(1.0, 0.0, 0.0, 0.005, 0.0, 0.0);
+
+            // The first and last points replace the min and max clock of the clocks frame
+            let name = match i {
+                0 => "Point 0 (min clock)".to_string(),
+                2 => "Point 2 (max clock)".to_string(),
+                i => format!("Point {}", i),
+            };
+
+            root_grid.attach(&Label::new(Some(&name)), 0, i + 1, 1, 1);
+
+            let clock_scale = Scale::new(Orientation::Horizontal, Some(&clock_adjustment));
+
+            clock_scale.set_hexpand(true);
+            clock_scale.set_value_pos(PositionType::Right);
+
+            root_grid.attach(&clock_scale, 1, i + 1, 1, 1);
+
+            let voltage_scale = Scale::new(Orientation::Horizontal, Some(&voltage_adjustment));
+
+            voltage_scale.set_hexpand(true);
+            voltage_scale.set_value_pos(PositionType::Right);
+
+            voltage_scale.set_digits(3);
+            voltage_scale.set_round_digits(3);
+
+            root_grid.attach(&voltage_scale, 2, i + 1, 1, 1);
+
+            points.push((clock_adjustment, voltage_adjustment));
+        }
+
+        container.add(&root_grid);
+
+        Self { container, points }
+    }
+
+    pub fn set_curve(&self, clocks_table: &ClocksTableNew) {
+        let vddc_curve = clocks_table.vddc_curve.unwrap_or_default();
+
+        for (i, (clock_adjustment, voltage_adjustment)) in self.points.iter().enumerate() {
+            let clocks_range = clocks_table.vddc_curve_clocks_range[i];
+            let voltage_range = clocks_table.vddc_curve_voltage_range[i];
+            let (clockspeed, voltage) = vddc_curve[i];
+
+            clock_adjustment.set_lower(clocks_range.0 as f64);
+            clock_adjustment.set_upper(clocks_range.1 as f64);
+
+            voltage_adjustment.set_lower(voltage_range.0 as f64 / 1000.0);
+            voltage_adjustment.set_upper(voltage_range.1 as f64 / 1000.0);
+
+            clock_adjustment.set_value(clockspeed as f64);
+            voltage_adjustment.set_value(voltage as f64 / 1000.0);
+        }
+    }
+
+    pub fn get_curve(&self) -> Vec<(i64, i64)> {
+        self.points
+            .iter()
+            .map(|(clock_adjustment, voltage_adjustment)| {
+                (
+                    clock_adjustment.get_value() as i64,
+                    (voltage_adjustment.get_value() * 1000.0).round() as i64,
+                )
+            })
+            .collect()
+    }
+
+    pub fn connect_curve_changed<F: Fn() + 'static + Clone>(&self, f: F) {
+        for (clock_adjustment, voltage_adjustment) in &self.points {
+            {
+                let f = f.clone();
+                clock_adjustment.connect_value_changed(move |_| {
+                    f();
+                });
+            }
+            {
+                let f = f.clone();
+                voltage_adjustment.connect_value_changed(move |_| {
+                    f();
+                });
+            }
+        }
+    }
+
+    pub fn get_visibility(&self) -> bool {
+        self.container.get_visible()
+    }
+
+    pub fn hide(&self) {
+        self.container.set_visible(false);
+    }
+
+    pub fn show(&self) {
+        self.container.set_visible(true);
+    }
+}
@@ -1,15 +1,30 @@
 mod fan_curve_frame;
+mod fan_limits_frame;
+mod fan_mode_frame;
+mod fan_smoothing_frame;
 
+use daemon::fan_calibration::FanCalibrationStatus;
+use daemon::fan_control::{FanCurveOptions, FanLimits, FanMode, FanSmoothing, FanTempSource};
 use daemon::gpu_controller::{FanControlInfo, GpuStats};
 use gtk::prelude::*;
 use gtk::*;
+use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::rc::Rc;
 
 use fan_curve_frame::FanCurveFrame;
+use fan_limits_frame::FanLimitsFrame;
+use fan_mode_frame::FanModeFrame;
+use fan_smoothing_frame::FanSmoothingFrame;
 
 pub struct ThermalsSettings {
     pub automatic_fan_control_enabled: bool,
     pub curve: BTreeMap<i64, f64>,
+    pub curve_options: FanCurveOptions,
+    pub smoothing: FanSmoothing,
+    pub temp_source: FanTempSource,
+    pub limits: FanLimits,
+    pub mode: FanMode,
 }
 
 #[derive(Clone)]
@@ -17,8 +32,20 @@ pub struct ThermalsPage {
     pub container: Box,
     temp_label: Label,
     fan_speed_label: Label,
+    fan_fault_label: Label,
+    fan_calibration_title_label: Label,
+    fan_calibration_label: Label,
+    fan_calibration_button: Button,
+    fan_health_title_label: Label,
+    fan_health_label: Label,
     fan_control_enabled_switch: Switch,
+    temp_source_label: Label,
+    temp_source_combo_box: ComboBoxText,
+    weighted_temp_source: Rc<RefCell<FanTempSource>>, // Keeps the configured weights, which are only editable from the CLI
+    fan_mode_frame: FanModeFrame,
     fan_curve_frame: FanCurveFrame,
+    fan_smoothing_frame: FanSmoothingFrame,
+    fan_limits_frame: FanLimitsFrame,
 }
 
 impl ThermalsPage {
@@ -83,6 +110,11 @@ impl ThermalsPage {
             1,
         );
 
+        let fan_fault_label = Label::new(None);
+        fan_fault_label.set_line_wrap(true);
+
+        grid.attach(&fan_fault_label, 0, 4, 3, 1);
+
         let fan_control_enabled_switch = Switch::new();
 
         fan_control_enabled_switch.set_active(true);
@@ -90,57 +122,241 @@ impl ThermalsPage {
 
         grid.attach(&fan_control_enabled_switch, 2, 2, 1, 1);
 
+        let temp_source_label = Label::new(Some("Fan curve temperature:"));
+        temp_source_label.set_halign(Align::End);
+
+        grid.attach(&temp_source_label, 0, 3, 1, 1);
+
+        let temp_source_combo_box = ComboBoxText::new();
+
+        temp_source_combo_box.append(Some("edge"), "Edge");
+        temp_source_combo_box.append(Some("junction"), "Junction");
+        temp_source_combo_box.append(Some("memory"), "Memory");
+        temp_source_combo_box.append(Some("max"), "Hottest sensor");
+        temp_source_combo_box.append(Some("weighted"), "Weighted average");
+
+        temp_source_combo_box.set_halign(Align::Start);
+
+        grid.attach(&temp_source_combo_box, 2, 3, 1, 1);
+
+        let fan_calibration_title_label = Label::new(Some("Fan calibration:"));
+        fan_calibration_title_label.set_halign(Align::End);
+
+        grid.attach(&fan_calibration_title_label, 0, 5, 1, 1);
+
+        let fan_calibration_label = Label::new(None);
+        fan_calibration_label.set_halign(Align::Start);
+        fan_calibration_label.set_line_wrap(true);
+
+        let fan_calibration_button = Button::with_label("Calibrate");
+        fan_calibration_button.set_tooltip_text(Some(
+            "Measures the fan speed over the whole PWM range, which allows fan curves in RPM. This takes a few minutes.",
+        ));
+
+        {
+            let calibration_box = Box::new(Orientation::Horizontal, 10);
+
+            calibration_box.pack_start(&fan_calibration_label, false, false, 0);
+            calibration_box.pack_start(&fan_calibration_button, false, false, 0);
+
+            grid.attach(&calibration_box, 2, 5, 1, 1);
+        }
+
+        let fan_health_title_label = Label::new(Some("Fan health:"));
+        fan_health_title_label.set_halign(Align::End);
+
+        grid.attach(&fan_health_title_label, 0, 6, 1, 1);
+
+        let fan_health_label = Label::new(None);
+        fan_health_label.set_halign(Align::Start);
+        fan_health_label.set_line_wrap(true);
+
+        grid.attach(&fan_health_label, 2, 6, 1, 1);
+
         container.pack_start(&grid, false, false, 5);
 
+        let fan_mode_frame = FanModeFrame::new();
+
+        container.pack_start(&fan_mode_frame.container, false, false, 5);
+
         let fan_curve_frame = FanCurveFrame::new();
 
         container.pack_start(&fan_curve_frame.container, true, true, 5);
 
-        // Show/hide fan curve when the switch is toggled
-        {
-            let fan_curve_frame = fan_curve_frame.clone();
-            fan_control_enabled_switch.connect_changed_active(move |switch| {
-                log::trace!("Fan control switch toggled");
-                if switch.get_active() {
-                    {
-                        glib::idle_add(|| {
-                            let diag = MessageDialog::new(None::<&Window>, DialogFlags::empty(), MessageType::Warning, ButtonsType::Ok,
-                            "Warning! Due to a driver bug, a reboot may be required for fan control to properly switch back to automatic.");
-                            diag.run();
-                            diag.hide();
-                            glib::Continue(false)
-                        });
-                    }
+        let fan_smoothing_frame = FanSmoothingFrame::new();
 
-                    fan_curve_frame.hide();
-                } else {
-                    fan_curve_frame.show();
-                }
-            });
-        }
+        container.pack_start(&fan_smoothing_frame.container, false, false, 5);
+
+        let fan_limits_frame = FanLimitsFrame::new();
+
+        container.pack_start(&fan_limits_frame.container, false, false, 5);
 
-        Self {
+        fan_control_enabled_switch.connect_changed_active(move |switch| {
+            log::trace!("Fan control switch toggled");
+            if switch.get_active() {
+                glib::idle_add(|| {
+                    let diag = MessageDialog::new(None::<&Window>, DialogFlags::empty(), MessageType::Warning, ButtonsType::Ok,
+                    "Warning! Due to a driver bug, a reboot may be required for fan control to properly switch back to automatic.");
+                    diag.run();
+                    diag.hide();
+                    glib::Continue(false)
+                });
+            }
+        });
+
+        let page = Self {
             container,
             temp_label,
             fan_speed_label,
+            fan_fault_label,
+            fan_calibration_title_label,
+            fan_calibration_label,
+            fan_calibration_button,
+            fan_health_title_label,
+            fan_health_label,
             fan_control_enabled_switch,
+            temp_source_label,
+            temp_source_combo_box,
+            weighted_temp_source: Rc::new(RefCell::new(FanTempSource::Weighted {
+                edge: 1.0,
+                junction: 1.0,
+                memory: 1.0,
+            })),
+            fan_mode_frame,
             fan_curve_frame,
+            fan_smoothing_frame,
+            fan_limits_frame,
+        };
+
+        // Show/hide the fan settings when the switch is toggled or the mode is changed
+        {
+            let page_clone = page.clone();
+            page.fan_control_enabled_switch
+                .connect_changed_active(move |_| {
+                    page_clone.update_fan_frames();
+                });
+        }
+        {
+            let page_clone = page.clone();
+            page.fan_mode_frame.connect_mode_changed(move || {
+                page_clone.update_fan_frames();
+            });
         }
+
+        page
+    }
+
+    fn update_fan_frames(&self) {
+        let manual = !self.fan_control_enabled_switch.get_active();
+        let mode = self.fan_mode_frame.get_mode();
+        let curve = manual && mode == FanMode::Curve;
+        // The PID mode regulates the same temperature the curve uses
+        let uses_temp = manual && matches!(mode, FanMode::Curve | FanMode::Pid(_));
+
+        if manual {
+            self.fan_mode_frame.show();
+            self.fan_limits_frame.show();
+        } else {
+            self.fan_mode_frame.hide();
+            self.fan_limits_frame.hide();
+        }
+
+        if curve {
+            self.fan_curve_frame.show();
+            self.fan_smoothing_frame.show();
+        } else {
+            self.fan_curve_frame.hide();
+            self.fan_smoothing_frame.hide();
+        }
+
+        self.temp_source_label.set_visible(uses_temp);
+        self.temp_source_combo_box.set_visible(uses_temp);
     }
 
     pub fn set_thermals_info(&self, stats: &GpuStats) {
         match stats.gpu_temp {
-            Some(temp) => self.temp_label.set_markup(&format!("<b>{}°C</b>", temp)),
+            Some(temp) => {
+                let mut markup = format!("<b>{}°C</b>", temp);
+
+                if let Some(junction_temp) = stats.junction_temp {
+                    markup.push_str(&format!(" (junction <b>{}°C</b>", junction_temp));
+
+                    if let Some(mem_temp) = stats.mem_temp {
+                        markup.push_str(&format!(", memory <b>{}°C</b>", mem_temp));
+                    }
+
+                    markup.push(')');
+                }
+
+                self.temp_label.set_markup(&markup)
+            }
             None => self.temp_label.set_text("Sensor not found"),
         }
 
-        match stats.fan_speed {
-            Some(fan_speed) => self.fan_speed_label.set_markup(&format!(
+        match (stats.fan_speed, stats.fan_pwm) {
+            // The PWM is what the fan curve sets, the RPM what the fan actually does
+            (Some(fan_speed), Some(fan_pwm)) => self.fan_speed_label.set_markup(&format!(
+                "<b>{} RPM</b> (PWM <b>{}%</b>)",
+                fan_speed,
+                (fan_pwm as f64 / 255.0 * 100.0).round()
+            )),
+            (Some(fan_speed), None) => self.fan_speed_label.set_markup(&format!(
                 "<b>{} RPM ({}%)</b>",
                 fan_speed,
                 (fan_speed as f64 / stats.max_fan_speed.unwrap() as f64 * 100.0).round()
             )),
-            None => self.fan_speed_label.set_text("No fan detected"),
+            (None, _) => self.fan_speed_label.set_text("No fan detected"),
+        }
+
+        match &stats.fan_health {
+            Some(fan_health) => {
+                match &fan_health.fault {
+                    Some(fault) => self.fan_health_label.set_markup(&format!(
+                        "<span foreground='red'>{}</span>",
+                        glib::markup_escape_text(&fault.to_string())
+                    )),
+                    None => match fan_health.speed_ratio {
+                        Some(ratio) => self.fan_health_label.set_markup(&format!(
+                            "OK, <b>{:.0}%</b> of the calibrated speed",
+                            ratio * 100.0
+                        )),
+                        None => self.fan_health_label.set_text("OK"),
+                    },
+                }
+                self.fan_health_title_label.show();
+                self.fan_health_label.show();
+            }
+            None => {
+                self.fan_health_title_label.hide();
+                self.fan_health_label.hide();
+            }
+        }
+
+        match &stats.fan_calibration {
+            FanCalibrationStatus::Idle => (),
+            FanCalibrationStatus::Running(progress) => {
+                self.fan_calibration_label
+                    .set_text(&format!("Calibrating... {:.0}%", progress * 100.0));
+                self.fan_calibration_button.set_sensitive(false);
+            }
+            FanCalibrationStatus::Finished(calibration) => {
+                self.fan_calibration_label.set_markup(&format!(
+                    "Up to <b>{} RPM</b>, starts at PWM <b>{}</b>, stops below PWM <b>{}</b>",
+                    calibration.max_rpm(),
+                    calibration.start_pwm,
+                    calibration.stop_pwm
+                ));
+                self.fan_calibration_button.set_sensitive(true);
+                self.fan_curve_frame
+                    .set_calibration(Some(calibration.clone()));
+            }
+            FanCalibrationStatus::Failed(e) => {
+                self.fan_calibration_label.set_markup(&format!(
+                    "<span foreground='red'>Calibration failed: {}</span>",
+                    glib::markup_escape_text(e)
+                ));
+                self.fan_calibration_button.set_sensitive(true);
+            }
         }
     }
 
@@ -149,16 +365,57 @@ impl ThermalsPage {
 
         self.fan_control_enabled_switch.set_visible(true);
 
+        match &fan_control_info.fault {
+            Some(fault) => {
+                self.fan_fault_label.set_markup(&format!(
+                    "<span foreground='red'><b>Fan control was stopped: {}</b></span>",
+                    glib::markup_escape_text(&fault.to_string())
+                ));
+                self.fan_fault_label.set_visible(true);
+            }
+            None => self.fan_fault_label.set_visible(false),
+        }
+
         self.fan_control_enabled_switch
             .set_active(!fan_control_info.enabled);
 
-        if !fan_control_info.enabled {
-            self.fan_curve_frame.hide();
-        } else {
-            self.fan_curve_frame.show();
+        self.fan_mode_frame
+            .set_mode(&fan_control_info.mode, fan_control_info.speed_range);
+        match &fan_control_info.calibration {
+            Some(calibration) => self.fan_calibration_label.set_markup(&format!(
+                "Up to <b>{} RPM</b>, starts at PWM <b>{}</b>, stops below PWM <b>{}</b>",
+                calibration.max_rpm(),
+                calibration.start_pwm,
+                calibration.stop_pwm
+            )),
+            None => self.fan_calibration_label.set_text("Not calibrated"),
         }
-
-        self.fan_curve_frame.set_curve(&fan_control_info.curve);
+        self.fan_calibration_title_label.set_visible(true);
+        self.fan_calibration_label.set_visible(true);
+        self.fan_calibration_button.set_visible(true);
+
+        // The calibration decides which units the curve can use, so it goes first
+        self.fan_curve_frame
+            .set_calibration(fan_control_info.calibration.clone());
+        self.fan_curve_frame
+            .set_curve(&fan_control_info.curve, &fan_control_info.curve_options);
+        self.fan_smoothing_frame
+            .set_smoothing(&fan_control_info.smoothing);
+        self.fan_limits_frame.set_limits(&fan_control_info.limits);
+
+        let id = match fan_control_info.temp_source {
+            FanTempSource::Edge => "edge",
+            FanTempSource::Junction => "junction",
+            FanTempSource::Memory => "memory",
+            FanTempSource::Max => "max",
+            FanTempSource::Weighted { .. } => {
+                *self.weighted_temp_source.borrow_mut() = fan_control_info.temp_source.clone();
+                "weighted"
+            }
+        };
+        self.temp_source_combo_box.set_active_id(Some(id));
+
+        self.update_fan_frames();
     }
 
     pub fn connect_settings_changed<F: Fn() + 'static + Clone>(&self, f: F) {
@@ -171,6 +428,14 @@ impl ThermalsPage {
                 });
         }
 
+        // Fan mode changed
+        {
+            let f = f.clone();
+            self.fan_mode_frame.connect_mode_changed(move || {
+                f();
+            });
+        }
+
         // Fan curve adjusted
         {
             let f = f.clone();
@@ -178,20 +443,94 @@ impl ThermalsPage {
                 f();
             });
         }
+
+        // Temperature source changed
+        {
+            let f = f.clone();
+            self.temp_source_combo_box.connect_changed(move |_| {
+                f();
+            });
+        }
+
+        // Fan smoothing adjusted
+        {
+            let f = f.clone();
+            self.fan_smoothing_frame.connect_adjusted(move || {
+                f();
+            });
+        }
+
+        // Fan limits adjusted
+        {
+            let f = f.clone();
+            self.fan_limits_frame.connect_adjusted(move || {
+                f();
+            });
+        }
+    }
+
+    /// The calibration is started right away, it's not a setting that waits for apply
+    pub fn connect_calibrate_clicked<F: Fn() + 'static>(&self, f: F) {
+        self.fan_calibration_button.connect_clicked(move |_| {
+            f();
+        });
     }
 
     pub fn get_thermals_settings(&self) -> ThermalsSettings {
         let automatic_fan_control_enabled = self.fan_control_enabled_switch.get_active();
         let curve = self.fan_curve_frame.get_curve();
+        let curve_options = self.fan_curve_frame.get_curve_options();
+        let smoothing = self.fan_smoothing_frame.get_smoothing();
+        let limits = self.fan_limits_frame.get_limits();
+        let mode = self.fan_mode_frame.get_mode();
+
+        let temp_source = match self.temp_source_combo_box.get_active_id() {
+            Some(id) => match id.as_str() {
+                "junction" => FanTempSource::Junction,
+                "memory" => FanTempSource::Memory,
+                "max" => FanTempSource::Max,
+                "weighted" => self.weighted_temp_source.borrow().clone(),
+                _ => FanTempSource::Edge,
+            },
+            None => FanTempSource::Edge,
+        };
 
         ThermalsSettings {
             automatic_fan_control_enabled,
             curve,
+            curve_options,
+            smoothing,
+            temp_source,
+            limits,
+            mode,
         }
     }
 
     pub fn hide_fan_controls(&self) {
         self.fan_control_enabled_switch.set_visible(false);
+        self.fan_fault_label.set_visible(false);
+        self.fan_calibration_title_label.set_visible(false);
+        self.fan_calibration_label.set_visible(false);
+        self.fan_calibration_button.set_visible(false);
+        self.temp_source_label.set_visible(false);
+        self.temp_source_combo_box.set_visible(false);
+        self.fan_mode_frame.hide();
         self.fan_curve_frame.hide();
+        self.fan_smoothing_frame.hide();
+        self.fan_limits_frame.hide();
     }
 }
+
+fn attach_spin_row(grid: &Grid, row: i32, text: &str, adjustment: &Adjustment, digits: u32) {
+    let label = Label::new(Some(text));
+    label.set_halign(Align::End);
+    label.set_hexpand(true);
+
+    grid.attach(&label, 0, row, 1, 1);
+
+    let spin_button = SpinButton::new(Some(adjustment), 1.0, digits);
+    spin_button.set_halign(Align::Start);
+    spin_button.set_hexpand(true);
+
+    grid.attach(&spin_button, 1, row, 1, 1);
+}
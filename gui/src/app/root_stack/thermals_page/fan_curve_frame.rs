@@ -1,15 +1,37 @@
+use std::cell::{Cell, RefCell};
 use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::rc::Rc;
 
+use daemon::fan_calibration::FanCalibration;
+use daemon::fan_control::{curve_speed, FanCurveInterpolation, FanCurveOptions, FanSpeedUnit};
+use gtk::prelude::*;
 use gtk::*;
 
+// Upper bound of curves in RPM when the fan hasn't been calibrated
+const DEFAULT_MAX_RPM: f64 = 5000.0;
+
+#[derive(Clone)]
+struct CurvePoint {
+    row: Box,
+    temp_adjustment: Adjustment,
+    speed_adjustment: Adjustment,
+    converted_label: Label, // The speed in the other unit
+}
+
 #[derive(Clone)]
 pub struct FanCurveFrame {
     pub container: Frame,
-    adjustment_1: Adjustment,
-    adjustment_2: Adjustment,
-    adjustment_3: Adjustment,
-    adjustment_4: Adjustment,
-    adjustment_5: Adjustment,
+    interpolation_combo_box: ComboBoxText,
+    unit_combo_box: ComboBoxText,
+    non_decreasing_check_button: CheckButton,
+    preview: DrawingArea,
+    speed_header_label: Label,
+    points_box: Box,
+    points: Rc<RefCell<Vec<CurvePoint>>>,
+    unit: Rc<Cell<FanSpeedUnit>>, // The unit the points are currently in
+    calibration: Rc<RefCell<Option<FanCalibration>>>,
+    adjusted_callbacks: Rc<RefCell<Vec<Rc<dyn Fn()>>>>,
 }
 
 impl FanCurveFrame {
@@ -23,170 +45,420 @@ impl FanCurveFrame {
 
         container.set_label_align(0.35, 0.5);
 
-        // container.set_shadow_type(ShadowType::None);
-        //
-        let root_grid = Grid::new();
+        let root_box = Box::new(Orientation::Vertical, 5);
+
+        root_box.set_margin_start(5);
+        root_box.set_margin_end(5);
+        root_box.set_margin_bottom(5);
+        root_box.set_margin_top(5);
+
+        // Interpolation and validation options
+        let options_box = Box::new(Orientation::Horizontal, 10);
+
+        options_box.pack_start(&Label::new(Some("Interpolation:")), false, false, 0);
+
+        let interpolation_combo_box = ComboBoxText::new();
+
+        interpolation_combo_box.append(Some("step"), "Step");
+        interpolation_combo_box.append(Some("linear"), "Linear");
+        interpolation_combo_box.append(Some("smooth"), "Smooth");
+
+        interpolation_combo_box.set_active_id(Some("linear"));
+
+        options_box.pack_start(&interpolation_combo_box, false, false, 0);
+
+        options_box.pack_start(&Label::new(Some("Unit:")), false, false, 0);
+
+        let unit_combo_box = ComboBoxText::new();
+
+        unit_combo_box.append(Some("percent"), "%");
+        unit_combo_box.set_active_id(Some("percent"));
+
+        options_box.pack_start(&unit_combo_box, false, false, 0);
+
+        let non_decreasing_check_button =
+            CheckButton::with_label("Don't allow the speed to drop as the temperature rises");
+
+        options_box.pack_end(&non_decreasing_check_button, false, false, 0);
 
-        // PWM Percentage Labels
+        root_box.pack_start(&options_box, false, false, 0);
+
+        // Preview of the speed the fan will run at for every temperature
+        let preview = DrawingArea::new();
+        preview.set_size_request(-1, 150);
+        preview.set_vexpand(true);
+
+        root_box.pack_start(&preview, true, true, 0);
+
+        // Header of the point list
+        let speed_header_label = Label::new(Some("PWM %"));
         {
-            root_grid.attach(
-                &{
-                    let label = Label::new(Some("PWM %"));
-                    label.set_angle(90.0);
-                    label.set_vexpand(true); // This expands the entire top section of the grid, including the scales
-                    label
-                },
-                0,
-                0,
-                1,
-                5,
-            );
+            let header_box = Box::new(Orientation::Horizontal, 10);
 
-            root_grid.attach(
-                &{
-                    let label = Label::new(Some("0"));
-                    label.set_angle(90.0);
-                    label
-                },
-                1,
-                4,
-                1,
-                1,
-            );
-            root_grid.attach(
-                &{
-                    let label = Label::new(Some("25"));
-                    label.set_angle(90.0);
-                    label
-                },
-                1,
-                3,
-                1,
-                1,
-            );
-            root_grid.attach(
-                &{
-                    let label = Label::new(Some("50"));
-                    label.set_angle(90.0);
-                    label
-                },
-                1,
-                2,
-                1,
-                1,
-            );
-            root_grid.attach(
-                &{
-                    let label = Label::new(Some("75"));
-                    label.set_angle(90.0);
-                    label
-                },
-                1,
-                1,
-                1,
-                1,
-            );
-            root_grid.attach(
-                &{
-                    let label = Label::new(Some("100"));
-                    label.set_angle(90.0);
-                    label
-                },
-                1,
-                0,
-                1,
-                1,
-            );
+            header_box.pack_start(&Label::new(Some("Temperature °C")), false, false, 0);
+            header_box.pack_start(&speed_header_label, true, true, 0);
+
+            root_box.pack_start(&header_box, false, false, 0);
+        }
+
+        let points_box = Box::new(Orientation::Vertical, 5);
+
+        root_box.pack_start(&points_box, false, false, 0);
+
+        let add_button = Button::with_label("Add point");
+        add_button.set_halign(Align::Start);
+
+        root_box.pack_start(&add_button, false, false, 0);
+
+        container.add(&root_box);
+
+        let frame = Self {
+            container,
+            interpolation_combo_box,
+            unit_combo_box,
+            non_decreasing_check_button,
+            preview,
+            speed_header_label,
+            points_box,
+            points: Rc::new(RefCell::new(Vec::new())),
+            unit: Rc::new(Cell::new(FanSpeedUnit::Percent)),
+            calibration: Rc::new(RefCell::new(None)),
+            adjusted_callbacks: Rc::new(RefCell::new(Vec::new())),
+        };
+
+        {
+            let frame_clone = frame.clone();
+            frame.preview.connect_draw(move |area, cr| {
+                let curve = frame_clone.get_curve();
+                let interpolation = frame_clone.get_curve_options().interpolation;
+
+                let width = area.get_allocated_width() as f64;
+                let height = area.get_allocated_height() as f64;
+
+                // Always show at least 20-100°C so that small curves don't get stretched out
+                let min_temp = curve.keys().next().map_or(20, |t| (*t).min(20)) as f64;
+                let max_temp = curve.keys().next_back().map_or(100, |t| (*t).max(100)) as f64;
+
+                let max_speed = frame_clone.get_max_speed();
+
+                let to_x = |temp: f64| (temp - min_temp) / (max_temp - min_temp) * width;
+                let to_y = |speed: f64| height - speed / max_speed * height;
+
+                let color = area.get_style_context().get_color(StateFlags::NORMAL);
+
+                // Lines at every 25% and 20°C
+                cr.set_source_rgba(color.red, color.green, color.blue, 0.2);
+                cr.set_line_width(1.0);
+
+                for quarter in 0..=4 {
+                    let speed = max_speed * quarter as f64 / 4.0;
+                    cr.move_to(0.0, to_y(speed));
+                    cr.line_to(width, to_y(speed));
+                }
+                for temp in (min_temp as i64..=max_temp as i64).filter(|t| t % 20 == 0) {
+                    cr.move_to(to_x(temp as f64), 0.0);
+                    cr.line_to(to_x(temp as f64), height);
+                }
+                cr.stroke();
+
+                // The curve itself, using the same interpolation as the daemon
+                cr.set_source_rgba(color.red, color.green, color.blue, color.alpha);
+                cr.set_line_width(2.0);
+
+                for x in 0..=width as i64 {
+                    let temp = min_temp + x as f64 / width * (max_temp - min_temp);
+
+                    if let Some(speed) = curve_speed(&curve, temp, interpolation) {
+                        if x == 0 {
+                            cr.move_to(x as f64, to_y(speed));
+                        } else {
+                            cr.line_to(x as f64, to_y(speed));
+                        }
+                    }
+                }
+                cr.stroke();
+
+                for (temp, speed) in &curve {
+                    cr.arc(to_x(*temp as f64), to_y(*speed), 4.0, 0.0, 2.0 * PI);
+                    cr.fill();
+                }
+
+                Inhibit(false)
+            });
         }
 
-        // Temperature threshold labels
         {
-            root_grid.attach(
-                &{
-                    let label = Label::new(Some("Temperature °C"));
-                    label.set_hexpand(true);
-                    label
-                },
-                2,
-                7,
-                5,
-                1,
-            );
+            let frame_clone = frame.clone();
+            add_button.connect_clicked(move |_| {
+                // Continue from the last point
+                let (temp, speed) = frame_clone
+                    .get_curve()
+                    .into_iter()
+                    .next_back()
+                    .map_or((50, frame_clone.get_max_speed() / 2.0), |(temp, speed)| {
+                        ((temp + 10).min(150), speed)
+                    });
+
+                frame_clone.add_point(temp, speed);
+                frame_clone.adjusted();
+            });
+        }
+
+        {
+            let frame_clone = frame.clone();
+            frame.interpolation_combo_box.connect_changed(move |_| {
+                frame_clone.adjusted();
+            });
+        }
 
-            root_grid.attach(&Label::new(Some("20")), 2, 6, 1, 1);
-            root_grid.attach(&Label::new(Some("40")), 3, 6, 1, 1);
-            root_grid.attach(&Label::new(Some("60")), 4, 6, 1, 1);
-            root_grid.attach(&Label::new(Some("80")), 5, 6, 1, 1);
-            root_grid.attach(&Label::new(Some("100")), 6, 6, 1, 1);
+        {
+            let frame_clone = frame.clone();
+            frame.unit_combo_box.connect_changed(move |_| {
+                frame_clone.convert_points();
+                frame_clone.adjusted();
+            });
+        }
+
+        {
+            let frame_clone = frame.clone();
+            frame.non_decreasing_check_button.connect_toggled(move |_| {
+                frame_clone.adjusted();
+            });
         }
 
-        // The actual adjustments
-        let adjustment_1 = Adjustment::new(0.0, 0.0, 100.0, 1.0, 0.0, 0.0); // 20 °C
-        let adjustment_2 = Adjustment::new(0.0, 0.0, 100.0, 1.0, 0.0, 0.0); // 40 °C
-        let adjustment_3 = Adjustment::new(0.0, 0.0, 100.0, 1.0, 0.0, 0.0); // 60 °C
-        let adjustment_4 = Adjustment::new(0.0, 0.0, 100.0, 1.0, 0.0, 0.0); // 80 °C
-        let adjustment_5 = Adjustment::new(0.0, 0.0, 100.0, 1.0, 0.0, 0.0); // 100 °C
+        frame
+    }
+
+    fn add_point(&self, temp: i64, speed: f64) {
+        let row = Box::new(Orientation::Horizontal, 10);
+
+        let temp_adjustment = Adjustment::new(temp as f64, -20.0, 150.0, 1.0, 5.0, 0.0);
+        let temp_spin_button = SpinButton::new(Some(&temp_adjustment), 1.0, 0);
+
+        row.pack_start(&temp_spin_button, false, false, 0);
+
+        let speed_adjustment = Adjustment::new(0.0, 0.0, 100.0, 1.0, 5.0, 0.0);
+        self.set_speed_range(&speed_adjustment);
+        speed_adjustment.set_value(speed);
+
+        let speed_scale = Scale::new(Orientation::Horizontal, Some(&speed_adjustment));
+        speed_scale.set_digits(0);
+        speed_scale.set_value_pos(PositionType::Right);
+
+        row.pack_start(&speed_scale, true, true, 0);
+
+        let converted_label = Label::new(None);
+        converted_label.set_width_chars(10);
+
+        row.pack_start(&converted_label, false, false, 0);
+
+        let remove_button = Button::from_icon_name(Some("list-remove-symbolic"), IconSize::Button);
+        remove_button.set_tooltip_text(Some("Remove point"));
+
+        row.pack_start(&remove_button, false, false, 0);
+
+        let point = CurvePoint {
+            row,
+            temp_adjustment,
+            speed_adjustment,
+            converted_label,
+        };
+
+        for adjustment in [&point.temp_adjustment, &point.speed_adjustment].iter() {
+            let frame = self.clone();
+            adjustment.connect_value_changed(move |_| {
+                frame.adjusted();
+            });
+        }
 
-        // Scales for the adjustments
         {
-            let adjustments = [
-                &adjustment_1,
-                &adjustment_2,
-                &adjustment_3,
-                &adjustment_4,
-                &adjustment_5,
-            ];
-
-            for i in 0..adjustments.len() {
-                let adj = adjustments[i];
-
-                root_grid.attach(
-                    &{
-                        let scale = Scale::new(Orientation::Vertical, Some(adj));
-                        scale.set_draw_value(false);
-                        scale.set_inverted(true);
-                        scale
-                    },
-                    i as i32 + 2,
-                    0,
-                    1,
-                    5,
-                );
+            let frame = self.clone();
+            let row = point.row.clone();
+            remove_button.connect_clicked(move |_| {
+                frame.points_box.remove(&row);
+                frame.points.borrow_mut().retain(|point| point.row != row);
+                frame.adjusted();
+            });
+        }
+
+        self.points_box.pack_start(&point.row, false, false, 0);
+        point.row.show_all();
+
+        self.points.borrow_mut().push(point);
+    }
+
+    /// The highest speed a point can have in the current unit
+    fn get_max_speed(&self) -> f64 {
+        match self.unit.get() {
+            FanSpeedUnit::Percent => 100.0,
+            FanSpeedUnit::Rpm => match &*self.calibration.borrow() {
+                Some(calibration) => calibration.max_rpm() as f64,
+                None => DEFAULT_MAX_RPM,
+            },
+        }
+    }
+
+    fn set_speed_range(&self, adjustment: &Adjustment) {
+        let max_speed = self.get_max_speed();
+
+        adjustment.set_upper(max_speed);
+        adjustment.set_step_increment((max_speed / 100.0).round());
+        adjustment.set_page_increment((max_speed / 20.0).round());
+    }
+
+    /// Converts the points to the unit selected in the combo box
+    fn convert_points(&self) {
+        let unit = match self.unit_combo_box.get_active_id().as_deref() {
+            Some("rpm") => FanSpeedUnit::Rpm,
+            Some(_) => FanSpeedUnit::Percent,
+            None => return, // The combo box is being rebuilt
+        };
+        let previous_unit = self.unit.replace(unit);
+
+        self.speed_header_label.set_text(match unit {
+            FanSpeedUnit::Percent => "PWM %",
+            FanSpeedUnit::Rpm => "Speed RPM",
+        });
+
+        if unit == previous_unit {
+            return;
+        }
+
+        let curve = self.get_curve();
+        let converted = match &*self.calibration.borrow() {
+            Some(calibration) => calibration.convert_curve(&curve, previous_unit, unit),
+            None => curve,
+        };
+
+        for point in self.points.borrow().iter() {
+            let temp = point.temp_adjustment.get_value() as i64;
+
+            // Raise the limit first, otherwise the new value would get clamped
+            self.set_speed_range(&point.speed_adjustment);
+            if let Some(speed) = converted.get(&temp) {
+                point.speed_adjustment.set_value(*speed);
             }
         }
+    }
 
-        container.add(&root_grid);
+    /// RPM is only offered when the fan has been calibrated or the curve already uses it
+    fn update_unit_combo_box(&self) {
+        let unit = self.unit.get();
 
-        Self {
-            container,
-            adjustment_1,
-            adjustment_2,
-            adjustment_3,
-            adjustment_4,
-            adjustment_5,
+        self.unit_combo_box.remove_all();
+        self.unit_combo_box.append(Some("percent"), "%");
+        if self.calibration.borrow().is_some() || unit == FanSpeedUnit::Rpm {
+            self.unit_combo_box.append(Some("rpm"), "RPM");
         }
+
+        self.unit_combo_box.set_active_id(Some(match unit {
+            FanSpeedUnit::Percent => "percent",
+            FanSpeedUnit::Rpm => "rpm",
+        }));
     }
 
-    pub fn set_curve(&self, curve: &BTreeMap<i64, f64>) {
-        self.adjustment_1.set_value(*curve.get(&20).unwrap());
-        self.adjustment_2.set_value(*curve.get(&40).unwrap());
-        self.adjustment_3.set_value(*curve.get(&60).unwrap());
-        self.adjustment_4.set_value(*curve.get(&80).unwrap());
-        self.adjustment_5.set_value(*curve.get(&100).unwrap());
+    fn update_converted_labels(&self) {
+        let calibration = self.calibration.borrow();
+
+        for point in self.points.borrow().iter() {
+            let speed = point.speed_adjustment.get_value();
+
+            let text = match (&*calibration, self.unit.get()) {
+                (Some(calibration), FanSpeedUnit::Percent) => {
+                    format!("~{} RPM", calibration.percent_to_rpm(speed).round())
+                }
+                (Some(calibration), FanSpeedUnit::Rpm) => {
+                    format!("~{}%", calibration.rpm_to_percent(speed).round())
+                }
+                (None, _) => String::new(),
+            };
+
+            point.converted_label.set_text(&text);
+        }
     }
 
+    /// Curves can only be in RPM when the fan has been calibrated
+    pub fn set_calibration(&self, calibration: Option<FanCalibration>) {
+        if *self.calibration.borrow() == calibration {
+            return;
+        }
+
+        *self.calibration.borrow_mut() = calibration;
+
+        self.update_unit_combo_box();
+
+        for point in self.points.borrow().iter() {
+            self.set_speed_range(&point.speed_adjustment);
+        }
+
+        self.update_converted_labels();
+        self.preview.queue_draw();
+    }
+
+    fn adjusted(&self) {
+        self.preview.queue_draw();
+        self.update_converted_labels();
+
+        // Cloned so that a callback can't hold the borrow while the curve gets rebuilt
+        let callbacks = self.adjusted_callbacks.borrow().clone();
+
+        for f in callbacks {
+            f();
+        }
+    }
+
+    pub fn set_curve(&self, curve: &BTreeMap<i64, f64>, options: &FanCurveOptions) {
+        for point in self.points.borrow_mut().drain(..) {
+            self.points_box.remove(&point.row);
+        }
+
+        // Set before the combo box, so that switching it doesn't convert the new points
+        self.unit.set(options.unit);
+        self.update_unit_combo_box();
+
+        for (temp, speed) in curve {
+            self.add_point(*temp, *speed);
+        }
+
+        self.interpolation_combo_box
+            .set_active_id(Some(match options.interpolation {
+                FanCurveInterpolation::Step => "step",
+                FanCurveInterpolation::Linear => "linear",
+                FanCurveInterpolation::Smooth => "smooth",
+            }));
+        self.non_decreasing_check_button
+            .set_active(options.non_decreasing);
+
+        self.update_converted_labels();
+        self.preview.queue_draw();
+    }
+
+    /// Points with the same temperature are merged, the last one wins
     pub fn get_curve(&self) -> BTreeMap<i64, f64> {
         let mut curve = BTreeMap::new();
 
-        curve.insert(20, self.adjustment_1.get_value());
-        curve.insert(40, self.adjustment_2.get_value());
-        curve.insert(60, self.adjustment_3.get_value());
-        curve.insert(80, self.adjustment_4.get_value());
-        curve.insert(100, self.adjustment_5.get_value());
+        for point in self.points.borrow().iter() {
+            curve.insert(
+                point.temp_adjustment.get_value() as i64,
+                point.speed_adjustment.get_value(),
+            );
+        }
 
         curve
     }
 
+    pub fn get_curve_options(&self) -> FanCurveOptions {
+        FanCurveOptions {
+            interpolation: match self.interpolation_combo_box.get_active_id().as_deref() {
+                Some("step") => FanCurveInterpolation::Step,
+                Some("smooth") => FanCurveInterpolation::Smooth,
+                _ => FanCurveInterpolation::Linear,
+            },
+            non_decreasing: self.non_decreasing_check_button.get_active(),
+            unit: self.unit.get(),
+        }
+    }
+
     pub fn show(&self) {
         log::info!("Manual fan control enaged, showing fan curve");
         self.container.set_visible(true);
@@ -198,19 +470,6 @@ impl FanCurveFrame {
     }
 
     pub fn connect_adjusted<F: Fn() + 'static + Clone>(&self, f: F) {
-        let adjustments = [
-            &self.adjustment_1,
-            &self.adjustment_2,
-            &self.adjustment_3,
-            &self.adjustment_4,
-            &self.adjustment_5,
-        ];
-
-        for adj in adjustments.iter() {
-            let f = f.clone();
-            adj.connect_value_changed(move |_| {
-                f();
-            });
-        }
+        self.adjusted_callbacks.borrow_mut().push(Rc::new(f));
     }
 }
@@ -0,0 +1,190 @@
+use daemon::fan_control::{FanFailsafe, FanLimits};
+use gtk::prelude::*;
+use gtk::*;
+
+use super::attach_spin_row;
+
+#[derive(Clone)]
+pub struct FanLimitsFrame {
+    pub container: Frame,
+    min_speed_adjustment: Adjustment,
+    max_speed_adjustment: Adjustment,
+    zero_rpm_check_button: CheckButton,
+    zero_rpm_threshold_adjustment: Adjustment,
+    kick_speed_adjustment: Adjustment,
+    kick_duration_adjustment: Adjustment,
+    failsafe_combo_box: ComboBoxText,
+}
+
+impl FanLimitsFrame {
+    pub fn new() -> Self {
+        let container = Frame::new(Some("Fan Limits"));
+
+        container.set_margin_start(10);
+        container.set_margin_end(10);
+        container.set_margin_bottom(10);
+
+        container.set_label_align(0.35, 0.5);
+
+        let root_grid = Grid::new();
+
+        root_grid.set_margin_start(5);
+        root_grid.set_margin_end(5);
+        root_grid.set_margin_bottom(5);
+        root_grid.set_margin_top(5);
+
+        root_grid.set_row_spacing(5);
+        root_grid.set_column_spacing(10);
+
+        let min_speed_adjustment = Adjustment::new(0.0, 0.0, 100.0, 1.0, 5.0, 0.0);
+        attach_spin_row(
+            &root_grid,
+            0,
+            "Minimum speed (%):",
+            &min_speed_adjustment,
+            0,
+        );
+
+        let max_speed_adjustment = Adjustment::new(100.0, 0.0, 100.0, 1.0, 5.0, 0.0);
+        attach_spin_row(
+            &root_grid,
+            1,
+            "Maximum speed (%):",
+            &max_speed_adjustment,
+            0,
+        );
+
+        let zero_rpm_check_button = CheckButton::with_label("Stop the fan at low temperatures");
+        zero_rpm_check_button.set_halign(Align::Start);
+
+        root_grid.attach(&zero_rpm_check_button, 1, 2, 1, 1);
+
+        let zero_rpm_threshold_adjustment = Adjustment::new(50.0, 0.0, 100.0, 1.0, 5.0, 0.0);
+        attach_spin_row(
+            &root_grid,
+            3,
+            "Zero RPM below (°C):",
+            &zero_rpm_threshold_adjustment,
+            0,
+        );
+
+        let kick_speed_adjustment = Adjustment::new(50.0, 0.0, 100.0, 1.0, 5.0, 0.0);
+        attach_spin_row(
+            &root_grid,
+            4,
+            "Start-up speed (%):",
+            &kick_speed_adjustment,
+            0,
+        );
+
+        let kick_duration_adjustment = Adjustment::new(2.0, 0.0, 30.0, 1.0, 5.0, 0.0);
+        attach_spin_row(
+            &root_grid,
+            5,
+            "Start-up duration (s):",
+            &kick_duration_adjustment,
+            0,
+        );
+
+        root_grid.attach(
+            &{
+                let label = Label::new(Some("On failure:"));
+                label.set_halign(Align::End);
+                label
+            },
+            0,
+            6,
+            1,
+            1,
+        );
+
+        let failsafe_combo_box = ComboBoxText::new();
+
+        failsafe_combo_box.append(Some("automatic"), "Automatic fan control");
+        failsafe_combo_box.append(Some("full-speed"), "Full speed");
+
+        failsafe_combo_box.set_halign(Align::Start);
+
+        root_grid.attach(&failsafe_combo_box, 1, 6, 1, 1);
+
+        container.add(&root_grid);
+
+        Self {
+            container,
+            min_speed_adjustment,
+            max_speed_adjustment,
+            zero_rpm_check_button,
+            zero_rpm_threshold_adjustment,
+            kick_speed_adjustment,
+            kick_duration_adjustment,
+            failsafe_combo_box,
+        }
+    }
+
+    pub fn set_limits(&self, limits: &FanLimits) {
+        self.min_speed_adjustment.set_value(limits.min_speed);
+        self.max_speed_adjustment.set_value(limits.max_speed);
+        self.zero_rpm_check_button.set_active(limits.zero_rpm);
+        self.zero_rpm_threshold_adjustment
+            .set_value(limits.zero_rpm_threshold as f64);
+        self.kick_speed_adjustment.set_value(limits.kick_speed);
+        self.kick_duration_adjustment
+            .set_value(limits.kick_duration as f64);
+        self.failsafe_combo_box
+            .set_active_id(Some(match limits.failsafe {
+                FanFailsafe::Automatic => "automatic",
+                FanFailsafe::FullSpeed => "full-speed",
+            }));
+    }
+
+    pub fn get_limits(&self) -> FanLimits {
+        FanLimits {
+            min_speed: self.min_speed_adjustment.get_value(),
+            max_speed: self.max_speed_adjustment.get_value(),
+            zero_rpm: self.zero_rpm_check_button.get_active(),
+            zero_rpm_threshold: self.zero_rpm_threshold_adjustment.get_value() as i64,
+            kick_speed: self.kick_speed_adjustment.get_value(),
+            kick_duration: self.kick_duration_adjustment.get_value() as u64,
+            failsafe: match self.failsafe_combo_box.get_active_id().as_deref() {
+                Some("full-speed") => FanFailsafe::FullSpeed,
+                _ => FanFailsafe::Automatic,
+            },
+        }
+    }
+
+    pub fn connect_adjusted<F: Fn() + 'static + Clone>(&self, f: F) {
+        let adjustments = [
+            &self.min_speed_adjustment,
+            &self.max_speed_adjustment,
+            &self.zero_rpm_threshold_adjustment,
+            &self.kick_speed_adjustment,
+            &self.kick_duration_adjustment,
+        ];
+
+        for adj in adjustments.iter() {
+            let f = f.clone();
+            adj.connect_value_changed(move |_| {
+                f();
+            });
+        }
+
+        {
+            let f = f.clone();
+            self.zero_rpm_check_button.connect_toggled(move |_| {
+                f();
+            });
+        }
+
+        self.failsafe_combo_box.connect_changed(move |_| {
+            f();
+        });
+    }
+
+    pub fn hide(&self) {
+        self.container.set_visible(false);
+    }
+
+    pub fn show(&self) {
+        self.container.set_visible(true);
+    }
+}
@@ -0,0 +1,266 @@
+use super::attach_spin_row;
+use daemon::fan_control::FanMode;
+use daemon::fan_pid::FanPidSettings;
+use gtk::*;
+use std::cell::Cell;
+use std::rc::Rc;
+
+#[derive(Clone)]
+pub struct FanModeFrame {
+    pub container: Frame,
+    combo_box: ComboBoxText,
+    speed_label: Label,
+    speed_spin_button: SpinButton,
+    speed_adjustment: Adjustment,
+    pid_grid: Grid,
+    target_temp_adjustment: Adjustment,
+    kp_adjustment: Adjustment,
+    ki_adjustment: Adjustment,
+    kd_adjustment: Adjustment,
+    min_pwm_adjustment: Adjustment,
+    max_pwm_adjustment: Adjustment,
+    rpm_range: Rc<Cell<(i64, i64)>>,
+}
+
+impl FanModeFrame {
+    pub fn new() -> Self {
+        let container = Frame::new(Some("Fan Mode"));
+
+        container.set_margin_start(10);
+        container.set_margin_end(10);
+
+        container.set_label_align(0.35, 0.5);
+
+        let root_grid = Grid::new();
+
+        root_grid.set_margin_start(5);
+        root_grid.set_margin_end(5);
+        root_grid.set_margin_bottom(5);
+        root_grid.set_margin_top(5);
+
+        root_grid.set_row_spacing(5);
+        root_grid.set_column_spacing(10);
+
+        let combo_box = ComboBoxText::new();
+
+        combo_box.append(Some("curve"), "Fan curve");
+        combo_box.append(Some("static"), "Static speed");
+        combo_box.append(Some("rpm"), "Target RPM");
+        combo_box.append(Some("pid"), "Target temperature");
+
+        combo_box.set_hexpand(true);
+
+        root_grid.attach(&combo_box, 0, 0, 2, 1);
+
+        let speed_label = Label::new(None);
+        speed_label.set_halign(Align::End);
+        speed_label.set_hexpand(true);
+
+        root_grid.attach(&speed_label, 0, 1, 1, 1);
+
+        let speed_adjustment = Adjustment::new(0.0, 0.0, 100.0, 1.0, 10.0, 0.0);
+
+        let speed_spin_button = SpinButton::new(Some(&speed_adjustment), 1.0, 0);
+        speed_spin_button.set_halign(Align::Start);
+        speed_spin_button.set_hexpand(true);
+
+        root_grid.attach(&speed_spin_button, 1, 1, 1, 1);
+
+        let pid_grid = Grid::new();
+
+        pid_grid.set_row_spacing(5);
+        pid_grid.set_column_spacing(10);
+
+        let target_temp_adjustment = Adjustment::new(75.0, 0.0, 150.0, 1.0, 5.0, 0.0);
+        attach_spin_row(
+            &pid_grid,
+            0,
+            "Target temperature (°C):",
+            &target_temp_adjustment,
+            0,
+        );
+
+        let kp_adjustment = Adjustment::new(0.0, 0.0, 255.0, 0.5, 5.0, 0.0);
+        attach_spin_row(
+            &pid_grid,
+            1,
+            "Proportional gain (PWM/°C):",
+            &kp_adjustment,
+            1,
+        );
+
+        let ki_adjustment = Adjustment::new(0.0, 0.0, 50.0, 0.05, 1.0, 0.0);
+        attach_spin_row(&pid_grid, 2, "Integral gain (PWM/°C·s):", &ki_adjustment, 2);
+
+        let kd_adjustment = Adjustment::new(0.0, 0.0, 255.0, 0.5, 5.0, 0.0);
+        attach_spin_row(
+            &pid_grid,
+            3,
+            "Derivative gain (PWM·s/°C):",
+            &kd_adjustment,
+            1,
+        );
+
+        let min_pwm_adjustment = Adjustment::new(0.0, 0.0, 255.0, 1.0, 10.0, 0.0);
+        attach_spin_row(&pid_grid, 4, "Minimum PWM:", &min_pwm_adjustment, 0);
+
+        let max_pwm_adjustment = Adjustment::new(255.0, 0.0, 255.0, 1.0, 10.0, 0.0);
+        attach_spin_row(&pid_grid, 5, "Maximum PWM:", &max_pwm_adjustment, 0);
+
+        root_grid.attach(&pid_grid, 0, 2, 2, 1);
+
+        container.add(&root_grid);
+
+        let frame = Self {
+            container,
+            combo_box,
+            speed_label,
+            speed_spin_button,
+            speed_adjustment,
+            pid_grid,
+            target_temp_adjustment,
+            kp_adjustment,
+            ki_adjustment,
+            kd_adjustment,
+            min_pwm_adjustment,
+            max_pwm_adjustment,
+            rpm_range: Rc::new(Cell::new((0, 0))),
+        };
+
+        {
+            let frame_clone = frame.clone();
+            frame.combo_box.connect_changed(move |_| {
+                frame_clone.update_speed_row();
+            });
+        }
+
+        frame
+    }
+
+    /// `speed_range` is the supported fan speed range in RPM
+    pub fn set_mode(&self, mode: &FanMode, speed_range: Option<(i64, i64)>) {
+        self.rpm_range.set(speed_range.unwrap_or((0, 0)));
+
+        // The RPM mode is only usable when the fan speed can be read
+        self.combo_box.remove_all();
+        self.combo_box.append(Some("curve"), "Fan curve");
+        self.combo_box.append(Some("static"), "Static speed");
+        if speed_range.is_some() {
+            self.combo_box.append(Some("rpm"), "Target RPM");
+        }
+        self.combo_box.append(Some("pid"), "Target temperature");
+
+        // Selecting the PID mode starts from the defaults unless it's already configured
+        let pid_settings = match mode {
+            FanMode::Pid(settings) => settings.clone(),
+            _ => FanPidSettings::default(),
+        };
+        self.target_temp_adjustment
+            .set_value(pid_settings.target_temp);
+        self.kp_adjustment.set_value(pid_settings.kp);
+        self.ki_adjustment.set_value(pid_settings.ki);
+        self.kd_adjustment.set_value(pid_settings.kd);
+        self.min_pwm_adjustment
+            .set_value(pid_settings.min_pwm as f64);
+        self.max_pwm_adjustment
+            .set_value(pid_settings.max_pwm as f64);
+
+        match mode {
+            FanMode::Curve => {
+                self.combo_box.set_active_id(Some("curve"));
+            }
+            FanMode::Static(speed) => {
+                self.combo_box.set_active_id(Some("static"));
+                self.speed_adjustment.set_value(*speed);
+            }
+            FanMode::TargetRpm(rpm) => {
+                self.combo_box.set_active_id(Some("rpm"));
+                self.speed_adjustment.set_value(*rpm as f64);
+            }
+            FanMode::Pid(_) => {
+                self.combo_box.set_active_id(Some("pid"));
+            }
+        }
+
+        self.update_speed_row();
+    }
+
+    pub fn get_mode(&self) -> FanMode {
+        match self.combo_box.get_active_id() {
+            Some(id) => match id.as_str() {
+                "static" => FanMode::Static(self.speed_adjustment.get_value()),
+                "rpm" => FanMode::TargetRpm(self.speed_adjustment.get_value() as i64),
+                "pid" => FanMode::Pid(FanPidSettings {
+                    target_temp: self.target_temp_adjustment.get_value(),
+                    kp: self.kp_adjustment.get_value(),
+                    ki: self.ki_adjustment.get_value(),
+                    kd: self.kd_adjustment.get_value(),
+                    min_pwm: self.min_pwm_adjustment.get_value() as i64,
+                    max_pwm: self.max_pwm_adjustment.get_value() as i64,
+                }),
+                _ => FanMode::Curve,
+            },
+            None => FanMode::Curve,
+        }
+    }
+
+    pub fn connect_mode_changed<F: Fn() + 'static + Clone>(&self, f: F) {
+        {
+            let f = f.clone();
+            self.combo_box.connect_changed(move |_| {
+                f();
+            });
+        }
+
+        for adjustment in &[
+            &self.speed_adjustment,
+            &self.target_temp_adjustment,
+            &self.kp_adjustment,
+            &self.ki_adjustment,
+            &self.kd_adjustment,
+            &self.min_pwm_adjustment,
+            &self.max_pwm_adjustment,
+        ] {
+            let f = f.clone();
+            adjustment.connect_value_changed(move |_| {
+                f();
+            });
+        }
+    }
+
+    fn update_speed_row(&self) {
+        self.pid_grid
+            .set_visible(self.combo_box.get_active_id().as_deref() == Some("pid"));
+
+        match self.combo_box.get_active_id().as_deref() {
+            Some("static") => {
+                self.speed_label.set_text("Speed (%):");
+                self.speed_adjustment.set_lower(0.0);
+                self.speed_adjustment.set_upper(100.0);
+                self.speed_label.set_visible(true);
+                self.speed_spin_button.set_visible(true);
+            }
+            Some("rpm") => {
+                let (min_rpm, max_rpm) = self.rpm_range.get();
+
+                self.speed_label.set_text("Speed (RPM):");
+                self.speed_adjustment.set_lower(min_rpm as f64);
+                self.speed_adjustment.set_upper(max_rpm as f64);
+                self.speed_label.set_visible(true);
+                self.speed_spin_button.set_visible(true);
+            }
+            _ => {
+                self.speed_label.set_visible(false);
+                self.speed_spin_button.set_visible(false);
+            }
+        }
+    }
+
+    pub fn hide(&self) {
+        self.container.set_visible(false);
+    }
+
+    pub fn show(&self) {
+        self.container.set_visible(true);
+    }
+}
@@ -0,0 +1,128 @@
+use daemon::fan_control::FanSmoothing;
+use gtk::*;
+
+use super::attach_spin_row;
+
+#[derive(Clone)]
+pub struct FanSmoothingFrame {
+    pub container: Frame,
+    hysteresis_adjustment: Adjustment,
+    ramp_up_adjustment: Adjustment,
+    ramp_down_adjustment: Adjustment,
+    average_window_adjustment: Adjustment,
+    spin_down_delay_adjustment: Adjustment,
+}
+
+impl FanSmoothingFrame {
+    pub fn new() -> Self {
+        let container = Frame::new(Some("Fan Smoothing"));
+
+        container.set_margin_start(10);
+        container.set_margin_end(10);
+        container.set_margin_bottom(10);
+
+        container.set_label_align(0.35, 0.5);
+
+        let root_grid = Grid::new();
+
+        root_grid.set_margin_start(5);
+        root_grid.set_margin_end(5);
+        root_grid.set_margin_bottom(5);
+        root_grid.set_margin_top(5);
+
+        root_grid.set_row_spacing(5);
+        root_grid.set_column_spacing(10);
+
+        let hysteresis_adjustment = Adjustment::new(0.0, 0.0, 20.0, 1.0, 5.0, 0.0);
+        attach_spin_row(&root_grid, 0, "Hysteresis (°C):", &hysteresis_adjustment, 0);
+
+        // Rates are in %/s, 0 means unlimited
+        let ramp_up_adjustment = Adjustment::new(0.0, 0.0, 100.0, 0.5, 5.0, 0.0);
+        attach_spin_row(&root_grid, 1, "Ramp up rate (%/s):", &ramp_up_adjustment, 1);
+
+        let ramp_down_adjustment = Adjustment::new(0.0, 0.0, 100.0, 0.5, 5.0, 0.0);
+        attach_spin_row(
+            &root_grid,
+            2,
+            "Ramp down rate (%/s):",
+            &ramp_down_adjustment,
+            1,
+        );
+
+        let average_window_adjustment = Adjustment::new(1.0, 1.0, 60.0, 1.0, 5.0, 0.0);
+        attach_spin_row(
+            &root_grid,
+            3,
+            "Temperature averaging (s):",
+            &average_window_adjustment,
+            0,
+        );
+
+        let spin_down_delay_adjustment = Adjustment::new(0.0, 0.0, 120.0, 1.0, 5.0, 0.0);
+        attach_spin_row(
+            &root_grid,
+            4,
+            "Spin down delay (s):",
+            &spin_down_delay_adjustment,
+            0,
+        );
+
+        container.add(&root_grid);
+
+        Self {
+            container,
+            hysteresis_adjustment,
+            ramp_up_adjustment,
+            ramp_down_adjustment,
+            average_window_adjustment,
+            spin_down_delay_adjustment,
+        }
+    }
+
+    pub fn set_smoothing(&self, smoothing: &FanSmoothing) {
+        self.hysteresis_adjustment
+            .set_value(smoothing.hysteresis as f64);
+        self.ramp_up_adjustment.set_value(smoothing.ramp_up_rate);
+        self.ramp_down_adjustment
+            .set_value(smoothing.ramp_down_rate);
+        self.average_window_adjustment
+            .set_value(smoothing.temp_average_window as f64);
+        self.spin_down_delay_adjustment
+            .set_value(smoothing.spin_down_delay as f64);
+    }
+
+    pub fn get_smoothing(&self) -> FanSmoothing {
+        FanSmoothing {
+            hysteresis: self.hysteresis_adjustment.get_value() as i64,
+            ramp_up_rate: self.ramp_up_adjustment.get_value(),
+            ramp_down_rate: self.ramp_down_adjustment.get_value(),
+            temp_average_window: self.average_window_adjustment.get_value() as u64,
+            spin_down_delay: self.spin_down_delay_adjustment.get_value() as u64,
+        }
+    }
+
+    pub fn connect_adjusted<F: Fn() + 'static + Clone>(&self, f: F) {
+        let adjustments = [
+            &self.hysteresis_adjustment,
+            &self.ramp_up_adjustment,
+            &self.ramp_down_adjustment,
+            &self.average_window_adjustment,
+            &self.spin_down_delay_adjustment,
+        ];
+
+        for adj in adjustments.iter() {
+            let f = f.clone();
+            adj.connect_value_changed(move |_| {
+                f();
+            });
+        }
+    }
+
+    pub fn hide(&self) {
+        self.container.set_visible(false);
+    }
+
+    pub fn show(&self) {
+        self.container.set_visible(true);
+    }
+}